@@ -0,0 +1,153 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RumiError, RumiResult};
+use crate::http::post_json;
+use crate::monitor::{CheckResult, HostResources};
+
+/// One alert rule from the config, checked by `monitor check` and the daemon.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum AlertRule {
+    /// A deployment failed this many consecutive checks.
+    SiteDown {
+        #[serde(default = "default_consecutive_checks")]
+        consecutive_checks: u32,
+    },
+    /// Any mount on a host is fuller than this percentage.
+    DiskUsageAbove { percent: u8 },
+    /// A certificate expires within this many days.
+    CertExpiresWithin { days: i64 },
+}
+
+fn default_consecutive_checks() -> u32 {
+    1
+}
+
+/// Where alerts get delivered.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NotificationConfig {
+    /// Urls that receive a json POST when alerts fire.
+    #[serde(default)]
+    pub webhooks: Vec<String>,
+}
+
+/// One fired alert, serialized into the webhook payload.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule: String,
+    /// The deployment or host the alert is about.
+    pub subject: String,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    source: &'static str,
+    fired_at: String,
+    alerts: &'a [Alert],
+}
+
+/// Evaluate the site-down and certificate rules against check results.
+/// `down_streaks` carries how many consecutive failures each deployment has,
+/// which is 1 for a one-shot check and grows in daemon mode.
+pub fn evaluate_check_rules(
+    rules: &[AlertRule],
+    results: &[CheckResult],
+    down_streak: impl Fn(&str) -> u32,
+) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for rule in rules {
+        match rule {
+            AlertRule::SiteDown { consecutive_checks } => {
+                for result in results.iter().filter(|r| !r.healthy) {
+                    let streak = down_streak(&result.name);
+                    if streak >= *consecutive_checks {
+                        alerts.push(Alert {
+                            rule: "site_down".to_string(),
+                            subject: result.name.clone(),
+                            message: format!(
+                                "{} ({}) failed {} consecutive check(s)",
+                                result.name, result.domain, streak
+                            ),
+                        });
+                    }
+                }
+            }
+            AlertRule::CertExpiresWithin { days } => {
+                for result in results {
+                    if let Some(left) = result.tls_days_left {
+                        if left < *days {
+                            alerts.push(Alert {
+                                rule: "cert_expires_within".to_string(),
+                                subject: result.name.clone(),
+                                message: format!(
+                                    "certificate for {} expires in {} day(s)",
+                                    result.domain, left
+                                ),
+                            });
+                        }
+                    }
+                }
+            }
+            AlertRule::DiskUsageAbove { .. } => {} // needs resource data
+        }
+    }
+    alerts
+}
+
+/// Evaluate the disk usage rules against gathered host resources.
+pub fn evaluate_resource_rules(rules: &[AlertRule], results: &[HostResources]) -> Vec<Alert> {
+    let mut alerts = Vec::new();
+    for rule in rules {
+        if let AlertRule::DiskUsageAbove { percent } = rule {
+            for resources in results {
+                for disk in &resources.disks {
+                    let used: u8 = disk
+                        .use_percent
+                        .trim_end_matches('%')
+                        .parse()
+                        .unwrap_or(0);
+                    if used > *percent {
+                        alerts.push(Alert {
+                            rule: "disk_usage_above".to_string(),
+                            subject: resources.host.clone(),
+                            message: format!(
+                                "{} on {} is {} full",
+                                disk.mount, resources.host, disk.use_percent
+                            ),
+                        });
+                    }
+                }
+            }
+        }
+    }
+    alerts
+}
+
+/// Deliver fired alerts to every configured webhook with a structured payload.
+pub fn fire_webhooks(notifications: &NotificationConfig, alerts: &[Alert]) -> RumiResult<()> {
+    if alerts.is_empty() || notifications.webhooks.is_empty() {
+        return Ok(());
+    }
+    let payload = serde_json::to_string(&WebhookPayload {
+        source: "rumi2",
+        fired_at: Utc::now().to_rfc3339(),
+        alerts,
+    })?;
+    let mut failures = Vec::new();
+    for webhook in &notifications.webhooks {
+        match post_json(webhook, &payload) {
+            Ok(status) if status < 300 => {}
+            Ok(status) => failures.push(format!("{} answered {}", webhook, status)),
+            Err(e) => failures.push(format!("{}: {}", webhook, e)),
+        }
+    }
+    if !failures.is_empty() {
+        return Err(RumiError::Network(format!(
+            "webhook delivery failed: {}",
+            failures.join("; ")
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+//! Deploy sources that are URLs instead of local paths: CI uploads an
+//! artifact once and every deploy pulls it from there, instead of
+//! re-uploading from the runner each time.
+
+use std::path::{Path, PathBuf};
+
+use openssl::hash::{Hasher, MessageDigest};
+
+use crate::error::{RumiError, RumiResult};
+
+/// Whether a dist_path/binary_path is a remote artifact to fetch first.
+pub fn is_remote(source: &str) -> bool {
+    source.starts_with("https://") || source.starts_with("http://") || source.starts_with("s3://")
+}
+
+fn cache_dir() -> RumiResult<PathBuf> {
+    let home = crate::utils::home_dir()
+        .ok_or_else(|| RumiError::Config("no home directory, nowhere to cache artifacts".to_string()))?;
+    let dir = home.join(".cache/rumi/artifacts");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn sha256_hex(data: &[u8]) -> RumiResult<String> {
+    let mut hasher = Hasher::new(MessageDigest::sha256())
+        .map_err(|e| RumiError::Tls(format!("sha256 unavailable: {}", e)))?;
+    hasher
+        .update(data)
+        .map_err(|e| RumiError::Tls(format!("hashing failed: {}", e)))?;
+    let digest = hasher
+        .finish()
+        .map_err(|e| RumiError::Tls(format!("hashing failed: {}", e)))?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn sha256_of_file(path: &Path) -> RumiResult<String> {
+    sha256_hex(&std::fs::read(path)?)
+}
+
+fn run(program: &str, args: &[&str], what: &str) -> RumiResult<()> {
+    let output = std::process::Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|e| RumiError::CommandFailed(format!("could not run {}: {}", program, e)))?;
+    if !output.status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "{} failed: {}",
+            what,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(())
+}
+
+/// Fetch a remote artifact into the local cache and hand back a path that
+/// drops in where the local one would have been: archives (.tar.gz, .tgz,
+/// .zip) are unpacked and the folder returned, anything else the file. A
+/// `#sha256=<hex>` fragment on the url pins the content; a pinned artifact
+/// already in the cache is not downloaded again.
+pub fn fetch(source: &str) -> RumiResult<PathBuf> {
+    let (url, expected) = match source.split_once("#sha256=") {
+        Some((url, hex)) => (url, Some(hex.to_ascii_lowercase())),
+        None => (source, None),
+    };
+    let filename = url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .filter(|name| !name.is_empty() && !name.contains("://"))
+        .unwrap_or("artifact");
+    let target = cache_dir()?.join(format!("{}-{}", &sha256_hex(url.as_bytes())?[..16], filename));
+
+    let cached = expected.as_deref().is_some_and(|expected| {
+        sha256_of_file(&target).map(|have| have == expected).unwrap_or(false)
+    });
+    if !cached {
+        let target_str = target.to_string_lossy();
+        if url.starts_with("s3://") {
+            run("aws", &["s3", "cp", "--only-show-errors", url, &target_str],
+                &format!("downloading {}", url))?;
+        } else {
+            run("curl", &["-fsSL", "-o", &target_str, url],
+                &format!("downloading {}", url))?;
+        }
+        if let Some(expected) = &expected {
+            let have = sha256_of_file(&target)?;
+            if &have != expected {
+                let _ = std::fs::remove_file(&target);
+                return Err(RumiError::Config(format!(
+                    "checksum mismatch for {}: expected sha256 {}, got {}",
+                    url, expected, have
+                )));
+            }
+        }
+    }
+
+    if filename.ends_with(".tar.gz") || filename.ends_with(".tgz") || filename.ends_with(".zip") {
+        let unpacked = target.with_file_name(format!(
+            "{}-unpacked",
+            target.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        // unpack fresh each time, the archive is the source of truth
+        if unpacked.exists() {
+            std::fs::remove_dir_all(&unpacked)?;
+        }
+        std::fs::create_dir_all(&unpacked)?;
+        let (target_str, unpacked_str) = (target.to_string_lossy(), unpacked.to_string_lossy());
+        if filename.ends_with(".zip") {
+            run("unzip", &["-q", &target_str, "-d", &unpacked_str],
+                &format!("unpacking {}", filename))?;
+        } else {
+            run("tar", &["-xzf", &target_str, "-C", &unpacked_str],
+                &format!("unpacking {}", filename))?;
+        }
+        return Ok(unpacked);
+    }
+    Ok(target)
+}
+
+/// fetch() when the source is remote, the path as given otherwise.
+pub fn materialize(source: &str) -> RumiResult<String> {
+    if is_remote(source) {
+        Ok(fetch(source)?.display().to_string())
+    } else {
+        Ok(source.to_string())
+    }
+}
@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::{DatabaseEngine, DeploymentConfig, DeploymentType, RumiConfig, SshConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// Where backups and their metadata live on the remote host.
+pub const BACKUP_ROOT: &str = "/var/backups/rumi";
+
+/// How many hosts `backup cleanup` works on at once.
+const MAX_CONCURRENT_HOSTS: usize = 4;
+
+/// How long a locally cached backup index stays fresh.
+const CACHE_TTL_SECS: u64 = 900;
+
+/// What a backup contains.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackupType {
+    Website,
+    Config,
+    Database,
+    Chaindata,
+}
+
+/// The sidecar json written next to every backup archive.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupMetadata {
+    pub id: String,
+    pub backup_type: BackupType,
+    pub deployment: String,
+    pub created_at: String,
+    pub archive_path: String,
+    pub size_bytes: u64,
+}
+
+/// Creates and lists backups on one host over an existing session.
+pub struct BackupManager<'a> {
+    session: &'a RumiSession,
+}
+
+impl<'a> BackupManager<'a> {
+    pub fn new(session: &'a RumiSession) -> Self {
+        BackupManager { session }
+    }
+
+    fn write_metadata(&self, metadata: &BackupMetadata) -> RumiResult<()> {
+        let staging_path = format!("/tmp/rumi-backup-{}.json", metadata.id);
+        let sftp = self.session.sftp()?;
+        let mut file = sftp.create(Path::new(&staging_path))?;
+        file.write_all(serde_json::to_string_pretty(metadata)?.as_bytes())?;
+        drop(file);
+        self.session.execute_checked(&format!(
+            "sudo mv {} {}",
+            crate::session::quote_arg(&staging_path),
+            crate::session::quote_arg(&format!(
+                "{}/{}/{}.json",
+                BACKUP_ROOT, metadata.deployment, metadata.id
+            ))
+        ))?;
+        Ok(())
+    }
+
+    fn remote_file_size(&self, path: &str) -> RumiResult<u64> {
+        let output = self
+            .session
+            .execute_checked(&format!("sudo stat -c %s {}", crate::session::quote_arg(path)))?;
+        output.stdout.trim().parse().map_err(|_| {
+            RumiError::CommandFailed(format!("could not read size of {}", path))
+        })
+    }
+
+    /// Dump the deployment's database into a gzipped archive under the
+    /// backup root and record its metadata.
+    pub fn create_database_backup(
+        &self,
+        deployment: &DeploymentConfig,
+    ) -> RumiResult<BackupMetadata> {
+        let (engine, db_name) = match &deployment.deployment_type {
+            DeploymentType::Database {
+                engine, db_name, ..
+            } => (*engine, db_name.as_str()),
+            other => {
+                return Err(RumiError::Config(format!(
+                    "deployment '{}' is a {}, only databases have database backups",
+                    deployment.name,
+                    other.kind()
+                )))
+            }
+        };
+        let id = Uuid::new_v4().to_string();
+        let backup_dir = format!("{}/{}", BACKUP_ROOT, deployment.name);
+        let archive_path = format!("{}/{}.sql.gz", backup_dir, id);
+        self.session
+            .execute_checked(&format!("sudo mkdir -p {}", crate::session::quote_arg(&backup_dir)))?;
+        let dump = format!(
+            "{} {} | gzip > {}",
+            match engine {
+                DatabaseEngine::Postgres => "pg_dump",
+                DatabaseEngine::Mysql => "mysqldump",
+            },
+            crate::session::quote_arg(db_name),
+            crate::session::quote_arg(&archive_path)
+        );
+        let dump_command = match engine {
+            DatabaseEngine::Postgres => {
+                format!("sudo -u postgres sh -c {}", crate::session::shell_quote(&dump))
+            }
+            DatabaseEngine::Mysql => format!("sudo sh -c {}", crate::session::shell_quote(&dump)),
+        };
+        self.session.execute_checked(&dump_command)?;
+        let metadata = BackupMetadata {
+            id,
+            backup_type: BackupType::Database,
+            deployment: deployment.name.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            size_bytes: self.remote_file_size(&archive_path)?,
+            archive_path,
+        };
+        self.write_metadata(&metadata)?;
+        Ok(metadata)
+    }
+
+    /// Snapshot an ethereum node's chaindata into a tarball under the backup
+    /// root and record its metadata. geth is stopped around the tar — leveldb
+    /// files copied under a running node do not restore into a usable chain —
+    /// and started again whether or not the tar succeeded.
+    pub fn create_chaindata_backup(
+        &self,
+        deployment: &DeploymentConfig,
+        datadir: &str,
+    ) -> RumiResult<BackupMetadata> {
+        let id = Uuid::new_v4().to_string();
+        let backup_dir = format!("{}/{}", BACKUP_ROOT, deployment.name);
+        let archive_path = format!("{}/{}.tar.gz", backup_dir, id);
+        self.session
+            .execute_checked(&format!("sudo mkdir -p {}", crate::session::quote_arg(&backup_dir)))?;
+        self.session
+            .execute_command("sudo systemctl stop geth 2>/dev/null; sudo pkill -x geth; true")?;
+        let tar = self.session.execute_command(&format!(
+            "sudo tar -czf {} -C {} geth/chaindata",
+            crate::session::quote_arg(&archive_path),
+            crate::session::quote_arg(datadir)
+        ))?;
+        self.session
+            .execute_command("sudo systemctl start geth 2>/dev/null; true")?;
+        if !tar.success() {
+            return Err(RumiError::CommandFailed(format!(
+                "could not snapshot {}/geth/chaindata: {}",
+                datadir,
+                tar.stderr.trim()
+            )));
+        }
+        let metadata = BackupMetadata {
+            id,
+            backup_type: BackupType::Chaindata,
+            deployment: deployment.name.clone(),
+            created_at: chrono::Utc::now().to_rfc3339(),
+            size_bytes: self.remote_file_size(&archive_path)?,
+            archive_path,
+        };
+        self.write_metadata(&metadata)?;
+        Ok(metadata)
+    }
+
+    /// Read every metadata file on the host, optionally only one deployment's.
+    pub fn list_backups(&self, deployment: Option<&str>) -> RumiResult<Vec<BackupMetadata>> {
+        // the name is quoted, the glob part stays bare so it still expands
+        let pattern = match deployment {
+            Some(name) => format!("{}/{}/*.json", BACKUP_ROOT, crate::session::quote_arg(name)),
+            None => format!("{}/*/*.json", BACKUP_ROOT),
+        };
+        let output = self.session.execute_command(&format!(
+            "sudo sh -c {}",
+            crate::session::shell_quote(&format!("cat {} 2>/dev/null", pattern))
+        ))?;
+        let mut backups = Vec::new();
+        let mut stream =
+            serde_json::Deserializer::from_str(&output.stdout).into_iter::<BackupMetadata>();
+        for entry in &mut stream {
+            match entry {
+                Ok(metadata) => backups.push(metadata),
+                Err(_) => break, // trailing garbage, stop parsing
+            }
+        }
+        backups.sort_by(|a, b| a.created_at.cmp(&b.created_at));
+        Ok(backups)
+    }
+
+    /// Download a backup archive into a local directory over sftp. The
+    /// archive under the backup root belongs to root, so it is staged
+    /// world-readable in /tmp for the transfer and removed afterwards.
+    pub fn download(&self, backup: &BackupMetadata, local_dir: &Path) -> RumiResult<PathBuf> {
+        let archive_name = Path::new(&backup.archive_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .ok_or_else(|| {
+                RumiError::CommandFailed(format!(
+                    "backup {} records no archive file name",
+                    backup.id
+                ))
+            })?;
+        let staging_path = format!("/tmp/rumi-restore-{}", archive_name);
+        let quoted_staging = crate::session::quote_arg(&staging_path);
+        self.session.execute_checked(&format!(
+            "sudo cp {} {} && sudo chmod 644 {}",
+            crate::session::quote_arg(&backup.archive_path),
+            quoted_staging,
+            quoted_staging
+        ))?;
+        let sftp = self.session.sftp()?;
+        let mut remote_file = sftp.open(Path::new(&staging_path))?;
+        let mut content = Vec::new();
+        std::io::Read::read_to_end(&mut remote_file, &mut content)?;
+        drop(remote_file);
+        self.session
+            .execute_command(&format!("rm -f {}", crate::session::quote_arg(&staging_path)))?;
+        let local_path = local_dir.join(archive_name);
+        std::fs::write(&local_path, content)?;
+        Ok(local_path)
+    }
+
+    /// Delete everything beyond the newest `keep` backups per deployment,
+    /// archive and metadata sidecar both. Returns how many backups went and
+    /// the bytes they occupied.
+    pub fn cleanup(&self, deployment: Option<&str>, keep: usize) -> RumiResult<(usize, u64)> {
+        let backups = self.list_backups(deployment)?;
+        let mut by_deployment: HashMap<&str, Vec<&BackupMetadata>> = HashMap::new();
+        for backup in &backups {
+            by_deployment
+                .entry(backup.deployment.as_str())
+                .or_default()
+                .push(backup);
+        }
+        let mut deleted = 0;
+        let mut freed = 0;
+        for list in by_deployment.into_values() {
+            if list.len() <= keep {
+                continue;
+            }
+            // list_backups sorts oldest first, so the excess is at the front
+            for backup in &list[..list.len() - keep] {
+                let sidecar = format!(
+                    "{}/{}/{}.json",
+                    BACKUP_ROOT, backup.deployment, backup.id
+                );
+                self.session.execute_checked(&format!(
+                    "sudo rm -f {} {}",
+                    crate::session::quote_arg(&backup.archive_path),
+                    crate::session::quote_arg(&sidecar)
+                ))?;
+                deleted += 1;
+                freed += backup.size_bytes;
+            }
+        }
+        Ok((deleted, freed))
+    }
+}
+
+/// One host's backup index as cached on the local machine, with when it was
+/// fetched so staleness can be judged.
+#[derive(Serialize, Deserialize)]
+struct CachedIndex {
+    fetched_at: u64,
+    backups: Vec<BackupMetadata>,
+}
+
+fn cache_path(host: &str) -> Option<PathBuf> {
+    let home = crate::utils::home_dir()?;
+    Some(home.join(".cache/rumi").join(format!("backups-{}.json", host)))
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The cached index for a host, or None when there is none or it went stale.
+fn load_cached_index(host: &str) -> Option<Vec<BackupMetadata>> {
+    let content = std::fs::read_to_string(cache_path(host)?).ok()?;
+    let cached: CachedIndex = serde_json::from_str(&content).ok()?;
+    (unix_now().saturating_sub(cached.fetched_at) < CACHE_TTL_SECS).then_some(cached.backups)
+}
+
+/// Cache a freshly fetched index. Failing to write is not worth failing the
+/// command over, the cache is only an optimisation.
+fn store_cached_index(host: &str, backups: &[BackupMetadata]) {
+    let Some(path) = cache_path(host) else {
+        return;
+    };
+    if let Some(parent) = path.parent() {
+        let _ = std::fs::create_dir_all(parent);
+    }
+    let cached = CachedIndex {
+        fetched_at: unix_now(),
+        backups: backups.to_vec(),
+    };
+    if let Ok(content) = serde_json::to_string(&cached) {
+        let _ = std::fs::write(path, content);
+    }
+}
+
+/// Every backup id in the local per-host caches, stale or not, for shell
+/// completion — which must answer instantly and never ssh.
+pub fn cached_backup_ids() -> Vec<String> {
+    let Some(home) = crate::utils::home_dir() else {
+        return Vec::new();
+    };
+    let mut ids = Vec::new();
+    let Ok(entries) = std::fs::read_dir(home.join(".cache/rumi")) else {
+        return ids;
+    };
+    for entry in entries.flatten() {
+        if !entry.file_name().to_string_lossy().starts_with("backups-") {
+            continue;
+        }
+        if let Some(cached) = std::fs::read_to_string(entry.path())
+            .ok()
+            .and_then(|content| serde_json::from_str::<CachedIndex>(&content).ok())
+        {
+            ids.extend(cached.backups.into_iter().map(|b| b.id));
+        }
+    }
+    ids
+}
+
+/// Drop a host's cached index after anything changes its backups.
+pub fn invalidate_cache(host: &str) {
+    if let Some(path) = cache_path(host) {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// The local filters and sort order of `backup list`, applied after the
+/// metadata index is loaded so they cost nothing on the hosts.
+pub struct ListFilter {
+    /// Only this kind of backup: "website", "config", "database" or
+    /// "chaindata".
+    pub backup_type: Option<String>,
+    /// Only backups created at or after this date.
+    pub since: Option<String>,
+    /// Only backups created at or before this date.
+    pub until: Option<String>,
+    /// Only backups of at least this many bytes.
+    pub min_size: Option<u64>,
+    /// "date" (oldest first), "size" (biggest first) or "name".
+    pub sort: String,
+}
+
+impl Default for ListFilter {
+    fn default() -> Self {
+        ListFilter {
+            backup_type: None,
+            since: None,
+            until: None,
+            min_size: None,
+            sort: "date".to_string(),
+        }
+    }
+}
+
+/// A --since/--until value: a plain date or a full rfc3339 timestamp. Plain
+/// dates cover the whole day on the --until side.
+fn parse_filter_time(value: &str, end_of_day: bool) -> RumiResult<DateTime<Utc>> {
+    if let Ok(parsed) = DateTime::parse_from_rfc3339(value) {
+        return Ok(parsed.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").map_err(|_| {
+        RumiError::Config(format!(
+            "'{}' is not a date, use yyyy-mm-dd or a full rfc3339 timestamp",
+            value
+        ))
+    })?;
+    let time = if end_of_day {
+        date.and_hms_opt(23, 59, 59).unwrap()
+    } else {
+        date.and_hms_opt(0, 0, 0).unwrap()
+    };
+    Ok(time.and_utc())
+}
+
+fn created_at(backup: &BackupMetadata) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(&backup.created_at)
+        .ok()
+        .map(|t| t.with_timezone(&Utc))
+}
+
+impl ListFilter {
+    fn apply(&self, backups: &mut Vec<BackupMetadata>) -> RumiResult<()> {
+        if let Some(kind) = &self.backup_type {
+            let wanted = match kind.as_str() {
+                "website" => BackupType::Website,
+                "config" => BackupType::Config,
+                "database" => BackupType::Database,
+                "chaindata" => BackupType::Chaindata,
+                other => {
+                    return Err(RumiError::Config(format!(
+                        "'{}' is not a backup type, use website, config, database or chaindata",
+                        other
+                    )))
+                }
+            };
+            backups.retain(|b| b.backup_type == wanted);
+        }
+        if let Some(since) = &self.since {
+            let since = parse_filter_time(since, false)?;
+            backups.retain(|b| created_at(b).is_some_and(|t| t >= since));
+        }
+        if let Some(until) = &self.until {
+            let until = parse_filter_time(until, true)?;
+            backups.retain(|b| created_at(b).is_some_and(|t| t <= until));
+        }
+        if let Some(min_size) = self.min_size {
+            backups.retain(|b| b.size_bytes >= min_size);
+        }
+        match self.sort.as_str() {
+            "date" => backups.sort_by(|a, b| a.created_at.cmp(&b.created_at)),
+            "size" => backups.sort_by_key(|b| std::cmp::Reverse(b.size_bytes)),
+            "name" => backups.sort_by(|a, b| a.deployment.cmp(&b.deployment)),
+            other => {
+                return Err(RumiError::Config(format!(
+                    "'{}' is not a sort order, use date, size or name",
+                    other
+                )))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The `backup list` command: answer from the local per-host cache when it is
+/// fresh enough, ssh only for the misses (or everything with --refresh).
+pub fn list_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    refresh: bool,
+    utc: bool,
+    filter: &ListFilter,
+) -> RumiResult<()> {
+    let mut hosts: Vec<SshConfig> = Vec::new();
+    for deployment in &config.deployments {
+        if name.is_some_and(|name| name != deployment.name) {
+            continue;
+        }
+        let ssh = config.ssh_for_deployment(deployment)?;
+        if !hosts.iter().any(|h| h.host == ssh.host) {
+            hosts.push(ssh.clone());
+        }
+    }
+    let mut backups = Vec::new();
+    let mut misses: Vec<SshConfig> = Vec::new();
+    for ssh in hosts {
+        match (!refresh).then(|| load_cached_index(&ssh.host)).flatten() {
+            Some(cached) => backups.extend(cached),
+            None => misses.push(ssh),
+        }
+    }
+    if !misses.is_empty() {
+        let mut pool = crate::session::SessionPool::new();
+        pool.connect_all(&misses)?;
+        for ssh in &misses {
+            let session = pool.get(ssh)?;
+            let manager = BackupManager::new(&session);
+            // cache the host's whole index, filters apply locally below
+            let index = manager.list_backups(None)?;
+            store_cached_index(&ssh.host, &index);
+            backups.extend(index);
+        }
+    }
+    if let Some(name) = name {
+        backups.retain(|b| b.deployment == name);
+    }
+    filter.apply(&mut backups)?;
+    print_backup_table(&backups, utc);
+    Ok(())
+}
+
+/// The `backup restore --local` command: find the backup by id on any of the
+/// deployment hosts, download archive and metadata into a local directory
+/// and unpack the archive there, so old versions can be inspected or diffed
+/// without touching the server they came from.
+pub fn restore_local_command(
+    config: &RumiConfig,
+    backup_id: &str,
+    local_dir: &Path,
+) -> RumiResult<()> {
+    let (_, archive) = fetch_backup(config, backup_id, local_dir)?;
+    extract_archive(&archive, local_dir)
+}
+
+/// The `backup download` command: fetch a backup's archive and metadata
+/// sidecar into a local directory without unpacking, so backups survive the
+/// host they were made on.
+pub fn download_command(
+    config: &RumiConfig,
+    backup_id: &str,
+    local_dir: &Path,
+) -> RumiResult<()> {
+    fetch_backup(config, backup_id, local_dir).map(|_| ())
+}
+
+/// Find a backup by id on whichever deployment host holds it and download
+/// archive plus metadata sidecar into a local directory.
+fn fetch_backup(
+    config: &RumiConfig,
+    backup_id: &str,
+    local_dir: &Path,
+) -> RumiResult<(BackupMetadata, PathBuf)> {
+    let mut hosts: Vec<SshConfig> = Vec::new();
+    for deployment in &config.deployments {
+        let ssh = config.ssh_for_deployment(deployment)?;
+        if !hosts.iter().any(|h| h.host == ssh.host) {
+            hosts.push(ssh.clone());
+        }
+    }
+    for ssh in &hosts {
+        let session = RumiSession::connect(ssh)?;
+        let manager = BackupManager::new(&session);
+        let Some(backup) = manager
+            .list_backups(None)?
+            .into_iter()
+            .find(|b| b.id == backup_id)
+        else {
+            continue;
+        };
+        std::fs::create_dir_all(local_dir)?;
+        let archive = manager.download(&backup, local_dir)?;
+        let metadata_path = local_dir.join(format!("{}.json", backup.id));
+        std::fs::write(&metadata_path, serde_json::to_string_pretty(&backup)?)?;
+        println!("downloaded {} from {}", archive.display(), ssh.host);
+        return Ok((backup, archive));
+    }
+    Err(RumiError::Config(format!(
+        "no backup with id {} on any deployment host",
+        backup_id
+    )))
+}
+
+/// The `backup upload` command: push a downloaded backup back into a
+/// deployment host's backup store, metadata sidecar and all, so a backup
+/// outliving its original server can be restored on the replacement. The
+/// sidecar written by `backup download` must sit next to the archive.
+pub fn upload_command(config: &RumiConfig, name: &str, archive: &Path) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let mut metadata = read_sidecar_metadata(archive)?;
+    // the store is keyed by deployment, re-home the record under this one
+    metadata.deployment = deployment.name.clone();
+    let archive_name = archive
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| RumiError::Config(format!("{} has no file name", archive.display())))?;
+    let backup_dir = format!("{}/{}", BACKUP_ROOT, deployment.name);
+    metadata.archive_path = format!("{}/{}", backup_dir, archive_name);
+
+    let session = RumiSession::connect(config.ssh_for_deployment(deployment)?)?;
+    let staging_path = format!("/tmp/rumi-upload-{}", archive_name);
+    let sftp = session.sftp()?;
+    crate::utils::upload_file(&sftp, archive, &staging_path)
+        .map_err(|e| RumiError::CommandFailed(format!("failed to upload {}: {}", archive.display(), e)))?;
+    session.execute_checked(&format!(
+        "sudo mkdir -p {} && sudo mv {} {}",
+        crate::session::quote_arg(&backup_dir),
+        crate::session::quote_arg(&staging_path),
+        crate::session::quote_arg(&metadata.archive_path)
+    ))?;
+    let manager = BackupManager::new(&session);
+    manager.write_metadata(&metadata)?;
+    invalidate_cache(session.host());
+    println!(
+        "backup {} uploaded to {} on {}",
+        metadata.id,
+        metadata.archive_path,
+        session.host()
+    );
+    Ok(())
+}
+
+/// The metadata sidecar next to a downloaded archive: `<id>.json` named
+/// after the archive's stem, or `<archive>.json`.
+fn read_sidecar_metadata(archive: &Path) -> RumiResult<BackupMetadata> {
+    let stem = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = stem.split('.').next().unwrap_or_default().to_string();
+    let candidates = [
+        archive.with_file_name(format!("{}.json", stem)),
+        PathBuf::from(format!("{}.json", archive.display())),
+    ];
+    for candidate in &candidates {
+        if let Ok(content) = std::fs::read_to_string(candidate) {
+            return serde_json::from_str(&content).map_err(|e| {
+                RumiError::Config(format!("{} is not backup metadata: {}", candidate.display(), e))
+            });
+        }
+    }
+    Err(RumiError::Config(format!(
+        "no metadata sidecar next to {} (expected {}.json, as written by `backup download`)",
+        archive.display(),
+        stem
+    )))
+}
+
+/// Unpack a downloaded backup next to itself: tarballs are extracted, plain
+/// gzipped dumps are gunzipped, anything else is left as downloaded.
+fn extract_archive(archive: &Path, local_dir: &Path) -> RumiResult<()> {
+    let name = archive.to_string_lossy();
+    let (program, args): (&str, Vec<String>) = if name.ends_with(".tar.gz") || name.ends_with(".tgz")
+    {
+        (
+            "tar",
+            vec![
+                "-xzf".to_string(),
+                name.to_string(),
+                "-C".to_string(),
+                local_dir.to_string_lossy().to_string(),
+            ],
+        )
+    } else if name.ends_with(".gz") {
+        ("gunzip", vec!["-kf".to_string(), name.to_string()])
+    } else {
+        println!("{} left as is, not an archive rumi knows", archive.display());
+        return Ok(());
+    };
+    let status = std::process::Command::new(program)
+        .args(&args)
+        .status()
+        .map_err(|e| RumiError::CommandFailed(format!("could not run {}: {}", program, e)))?;
+    if !status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "{} failed to unpack {}",
+            program,
+            archive.display()
+        )));
+    }
+    println!("extracted into {}", local_dir.display());
+    Ok(())
+}
+
+/// The `backup cleanup` command: apply the retention to every host (or the
+/// named deployment's host), a bounded number of hosts at a time, each over
+/// its own connection. Hosts shared by several deployments are visited once.
+pub fn cleanup_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    keep: usize,
+    json: bool,
+) -> RumiResult<()> {
+    let mut hosts: Vec<SshConfig> = Vec::new();
+    for deployment in &config.deployments {
+        if name.is_some_and(|name| name != deployment.name) {
+            continue;
+        }
+        let ssh = config.ssh_for_deployment(deployment)?;
+        if !hosts.iter().any(|h| h.host == ssh.host) {
+            hosts.push(ssh.clone());
+        }
+    }
+    if hosts.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments matched, nothing to clean up".to_string(),
+        ));
+    }
+    let filter = name.map(str::to_string);
+    let report = crate::fanout::Fanout::new()
+        .max_parallel(MAX_CONCURRENT_HOSTS)
+        .run(&hosts, move |ssh| {
+            let session = RumiSession::connect(ssh)?;
+            let manager = BackupManager::new(&session);
+            let (deleted, freed) = manager.cleanup(filter.as_deref(), keep)?;
+            invalidate_cache(&ssh.host);
+            Ok(format!("deleted {} backup(s), freed {} bytes", deleted, freed))
+        });
+    report.print(json);
+    report.into_result()
+}
+
+/// Print backups the way every list command does.
+pub fn print_backup_table(backups: &[BackupMetadata], utc: bool) {
+    println!(
+        "{:<38} {:<10} {:<20} {:<34} {:>12}",
+        "ID", "TYPE", "DEPLOYMENT", "CREATED", "SIZE"
+    );
+    for backup in backups {
+        println!(
+            "{:<38} {:<10} {:<20} {:<34} {:>12}",
+            backup.id,
+            match backup.backup_type {
+                BackupType::Website => "website",
+                BackupType::Config => "config",
+                BackupType::Database => "database",
+                BackupType::Chaindata => "chaindata",
+            },
+            backup.deployment,
+            crate::output::format_time_str(&backup.created_at, utc),
+            backup.size_bytes,
+        );
+    }
+}
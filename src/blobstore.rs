@@ -0,0 +1,364 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use openssl::hash::{Hasher, MessageDigest};
+
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::utils::upload_file;
+
+/// Where content-addressed blobs live on a host. Under the web root so
+/// hardlinks into release dirs stay on one filesystem.
+pub const BLOB_STORE: &str = "/var/www/.rumi-blobs";
+
+/// Only files at least this big go through the store; smaller ones are
+/// cheaper to upload than to hash and link.
+const MIN_BLOB_SIZE: u64 = 64 * 1024;
+
+/// Above this many files a folder goes over as one tar.gz instead of
+/// file-by-file sftp, which is painfully slow for node/webpack dists with
+/// thousands of tiny files. Overridable per config (0 disables the tar
+/// path), set once at startup like the command timeout.
+const DEFAULT_TAR_UPLOAD_THRESHOLD: u64 = 250;
+
+static TAR_UPLOAD_THRESHOLD: std::sync::atomic::AtomicU64 =
+    std::sync::atomic::AtomicU64::new(DEFAULT_TAR_UPLOAD_THRESHOLD);
+
+pub fn set_tar_upload_threshold(files: u64) {
+    TAR_UPLOAD_THRESHOLD.store(files, std::sync::atomic::Ordering::Relaxed);
+}
+
+fn tar_upload_threshold() -> Option<u64> {
+    match TAR_UPLOAD_THRESHOLD.load(std::sync::atomic::Ordering::Relaxed) {
+        0 => None,
+        files => Some(files),
+    }
+}
+
+/// How many sftp connections a folder upload spreads its files across.
+/// One (the default) keeps the serial path; set once at startup from
+/// --concurrency or the settings block, like the tar threshold.
+static UPLOAD_CONCURRENCY: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+
+pub fn set_upload_concurrency(connections: u64) {
+    UPLOAD_CONCURRENCY.store(connections.max(1), std::sync::atomic::Ordering::Relaxed);
+}
+
+fn upload_concurrency() -> u64 {
+    UPLOAD_CONCURRENCY.load(std::sync::atomic::Ordering::Relaxed).max(1)
+}
+
+fn count_files(path: &Path) -> RumiResult<u64> {
+    let mut count = 0;
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        if entry.path().is_dir() {
+            count += count_files(&entry.path())?;
+        } else {
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Through execute_command rather than a raw channel, so escalation
+/// rewriting and sudo password feeding apply here like everywhere else.
+fn exec(session: &RumiSession, command: &str) -> RumiResult<i32> {
+    Ok(session.execute_command(command)?.exit_code)
+}
+
+pub(crate) fn sha256_of(path: &Path) -> RumiResult<String> {
+    let mut hasher = Hasher::new(MessageDigest::sha256())
+        .map_err(|e| RumiError::Tls(format!("sha256 unavailable: {}", e)))?;
+    let mut file = File::open(path)?;
+    let mut buffer = [0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher
+            .update(&buffer[..read])
+            .map_err(|e| RumiError::Tls(format!("hashing {} failed: {}", path.display(), e)))?;
+    }
+    let digest = hasher
+        .finish()
+        .map_err(|e| RumiError::Tls(format!("hashing {} failed: {}", path.display(), e)))?;
+    Ok(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Upload a folder with cross-site dedup: every large file is stored once on
+/// the host under its sha256 and hardlinked into the destination. Blobs the
+/// host already has are not uploaded at all, so sites sharing fonts or
+/// vendor bundles only ever push them once. Folders over the tar threshold
+/// go as one archive instead; with --concurrency above one the files are
+/// spread over that many connections (both skip the dedup pass).
+pub fn upload_folder_deduped(
+    session: &RumiSession,
+    local_path: &Path,
+    remote_path: &str,
+) -> RumiResult<()> {
+    let sftp = session.sftp()?;
+    if let Some(threshold) = tar_upload_threshold() {
+        if count_files(local_path)? > threshold {
+            return upload_folder_tarball(session, &sftp, local_path, remote_path);
+        }
+    }
+    if upload_concurrency() > 1 {
+        return upload_folder_parallel(session, &sftp, local_path, remote_path);
+    }
+    if exec(session, &format!("sudo mkdir -p {}", BLOB_STORE))? != 0 {
+        return Err(RumiError::CommandFailed(format!(
+            "could not create the blob store at {}",
+            BLOB_STORE
+        )));
+    }
+    upload_dir(session, &sftp, local_path, remote_path)
+}
+
+/// A local file and the remote path it uploads to.
+type UploadJob = (std::path::PathBuf, String);
+
+/// Everything under a folder as (local file, its remote path), with the
+/// remote directories that have to exist first in creation order.
+fn collect_upload_plan(
+    local_path: &Path,
+    remote_path: &str,
+) -> RumiResult<(Vec<UploadJob>, Vec<String>)> {
+    let mut files = Vec::new();
+    let mut dirs = vec![remote_path.to_string()];
+    let mut pending = vec![(local_path.to_path_buf(), remote_path.to_string())];
+    while let Some((dir, remote_dir)) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let remote_entry = format!(
+                "{}/{}",
+                remote_dir,
+                entry.file_name().to_string_lossy()
+            );
+            if entry.path().is_dir() {
+                dirs.push(remote_entry.clone());
+                pending.push((entry.path(), remote_entry));
+            } else {
+                files.push((entry.path(), remote_entry));
+            }
+        }
+    }
+    dirs.sort();
+    Ok((files, dirs))
+}
+
+/// One file over an already open sftp channel, without the per-file print
+/// of utils::upload_file — the workers would shred the progress line.
+fn upload_one(sftp: &ssh2::Sftp, local: &Path, remote: &str) -> RumiResult<()> {
+    let mut reader = std::io::BufReader::new(File::open(local)?);
+    let remote_f = sftp
+        .open_mode(
+            Path::new(remote),
+            ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE,
+            crate::utils::local_mode(local, 0o644),
+            ssh2::OpenType::File,
+        )
+        .map_err(|e| {
+            RumiError::CommandFailed(format!("failed to open {} for writing: {}", remote, e))
+        })?;
+    let mut writer = std::io::BufWriter::new(remote_f);
+    std::io::copy(&mut reader, &mut writer)?;
+    std::io::Write::flush(&mut writer)?;
+    Ok(())
+}
+
+/// Upload the folder over several sftp connections at once. Each worker gets
+/// its own connection to the host (channels on one session share its lock,
+/// so extra channels alone would still upload serially) and pulls files off
+/// a shared queue; a progress line keeps an aggregate transfer rate.
+fn upload_folder_parallel(
+    session: &RumiSession,
+    sftp: &ssh2::Sftp,
+    local_path: &Path,
+    remote_path: &str,
+) -> RumiResult<()> {
+    let (files, dirs) = collect_upload_plan(local_path, remote_path)?;
+    // directories are cheap, create them all up front over the primary
+    // connection so the workers never race on a missing parent
+    for dir in &dirs {
+        sftp.mkdir(Path::new(dir), 0o755).ok();
+    }
+    let total_files = files.len();
+    let total_bytes: u64 = files
+        .iter()
+        .map(|(local, _)| fs::metadata(local).map(|m| m.len()).unwrap_or(0))
+        .sum();
+    let workers = (upload_concurrency() as usize).min(total_files.max(1));
+    let queue = std::sync::Arc::new(std::sync::Mutex::new(files));
+    let uploaded = std::sync::Arc::new(std::sync::atomic::AtomicU64::new(0));
+    let started = std::time::Instant::now();
+
+    let mut handles = Vec::new();
+    for _ in 0..workers {
+        let config = session.ssh_config().clone();
+        let queue = queue.clone();
+        let uploaded = uploaded.clone();
+        handles.push(std::thread::spawn(move || -> RumiResult<()> {
+            let session = RumiSession::connect(&config)?;
+            let sftp = session.sftp()?;
+            loop {
+                let next = queue.lock().expect("upload queue poisoned").pop();
+                let (local, remote) = match next {
+                    Some(job) => job,
+                    None => return Ok(()),
+                };
+                upload_one(&sftp, &local, &remote)?;
+                uploaded.fetch_add(
+                    fs::metadata(&local).map(|m| m.len()).unwrap_or(0),
+                    std::sync::atomic::Ordering::Relaxed,
+                );
+            }
+        }));
+    }
+    while handles.iter().any(|handle| !handle.is_finished()) {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let done = uploaded.load(std::sync::atomic::Ordering::Relaxed);
+        let rate = done as f64 / started.elapsed().as_secs_f64().max(0.001);
+        print!(
+            "\r{:.1} of {:.1} MB up, {:.1} MB/s over {} connections   ",
+            done as f64 / 1e6,
+            total_bytes as f64 / 1e6,
+            rate / 1e6,
+            workers
+        );
+        std::io::Write::flush(&mut std::io::stdout()).ok();
+    }
+    println!();
+    let mut first_error = None;
+    for handle in handles {
+        if let Err(e) = handle.join().expect("upload thread panicked") {
+            first_error.get_or_insert(e);
+        }
+    }
+    match first_error {
+        Some(error) => Err(error),
+        None => {
+            println!(
+                "uploaded {} files ({:.1} MB) in {:.1}s",
+                total_files,
+                total_bytes as f64 / 1e6,
+                started.elapsed().as_secs_f64()
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Pack the folder into a local tar.gz, upload the one archive and unpack
+/// it remotely. One sftp transfer instead of thousands; dedup is skipped,
+/// which is the better trade for folders this large.
+fn upload_folder_tarball(
+    session: &RumiSession,
+    sftp: &ssh2::Sftp,
+    local_path: &Path,
+    remote_path: &str,
+) -> RumiResult<()> {
+    let archive_name = format!("rumi-upload-{}.tar.gz", uuid::Uuid::new_v4());
+    let local_archive = std::env::temp_dir().join(&archive_name);
+    let tar = std::process::Command::new("tar")
+        .arg("-czf")
+        .arg(&local_archive)
+        .arg("-C")
+        .arg(local_path)
+        .arg(".")
+        .output()?;
+    if !tar.status.success() {
+        fs::remove_file(&local_archive).ok();
+        return Err(RumiError::CommandFailed(format!(
+            "tar -czf of {} failed: {}",
+            local_path.display(),
+            String::from_utf8_lossy(&tar.stderr).trim()
+        )));
+    }
+    let staging_path = format!("/tmp/{}", archive_name);
+    let upload = upload_file(sftp, &local_archive, &staging_path);
+    fs::remove_file(&local_archive).ok();
+    upload.map_err(|e| {
+        RumiError::CommandFailed(format!(
+            "failed to upload archive of {}: {}",
+            local_path.display(),
+            e
+        ))
+    })?;
+    let unpack = format!(
+        "sudo mkdir -p {0} && sudo tar -xzf {1} -C {0} && rm -f {1}",
+        crate::session::quote_arg(remote_path),
+        crate::session::quote_arg(&staging_path)
+    );
+    if exec(session, &unpack)? != 0 {
+        return Err(RumiError::CommandFailed(format!(
+            "could not unpack the release archive into {}",
+            remote_path
+        )));
+    }
+    Ok(())
+}
+
+fn upload_dir(
+    session: &RumiSession,
+    sftp: &ssh2::Sftp,
+    local_path: &Path,
+    remote_path: &str,
+) -> RumiResult<()> {
+    sftp.mkdir(Path::new(remote_path), 0o755).ok();
+    for entry in fs::read_dir(local_path)? {
+        let entry = entry?;
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let remote_file_path = format!("{}/{}", remote_path, file_name);
+        if path.is_dir() {
+            upload_dir(session, sftp, &path, &remote_file_path)?;
+        } else if entry.metadata()?.len() < MIN_BLOB_SIZE {
+            upload_file(sftp, &path, &remote_file_path).map_err(|e| {
+                RumiError::CommandFailed(format!("failed to upload {}: {}", path.display(), e))
+            })?;
+        } else {
+            link_blob(session, sftp, &path, &remote_file_path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Make sure the file's blob exists on the host (uploading it only when
+/// missing) and hardlink it to the destination.
+fn link_blob(
+    session: &RumiSession,
+    sftp: &ssh2::Sftp,
+    local_file: &Path,
+    remote_file: &str,
+) -> RumiResult<()> {
+    let hash = sha256_of(local_file)?;
+    let blob_path = format!("{}/{}", BLOB_STORE, hash);
+    if exec(session, &format!("test -f {}", blob_path))? != 0 {
+        let staging_path = format!("/tmp/rumi-blob-{}", hash);
+        upload_file(sftp, local_file, &staging_path).map_err(|e| {
+            RumiError::CommandFailed(format!("failed to upload {}: {}", local_file.display(), e))
+        })?;
+        if exec(
+            session,
+            &format!("sudo mv {} {} && sudo chmod 644 {}", staging_path, blob_path, blob_path),
+        )? != 0
+        {
+            return Err(RumiError::CommandFailed(format!(
+                "could not move blob into {}",
+                blob_path
+            )));
+        }
+    } else {
+        println!("Reusing blob for: {}", remote_file);
+    }
+    if exec(session, &format!("sudo ln -f {} {}", blob_path, remote_file))? != 0 {
+        return Err(RumiError::CommandFailed(format!(
+            "could not hardlink {} into place",
+            remote_file
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,349 @@
+//! Canary releases: run the new release beside the stable one and let nginx
+//! send a configurable share of traffic to it, until it is promoted to be
+//! the only release or aborted.
+
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::{
+    NGINX_WEB_CONFIG_PATH, SERVER_BIN_PATH, SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH,
+    WEB_FOLDER,
+};
+
+/// An in-flight canary as recorded on the host itself, so promote and abort
+/// work from any machine with the config.
+#[derive(Serialize, Deserialize)]
+struct CanaryState {
+    percent: u8,
+    /// The stable web root (websites) or the stable port (servers).
+    stable: String,
+    /// The canary web root (websites) or the canary port (servers).
+    canary: String,
+}
+
+fn state_path(name: &str) -> String {
+    format!("{}/.rumi-canary-{}.json", WEB_FOLDER, name)
+}
+
+fn load_state(session: &RumiSession, name: &str) -> RumiResult<Option<CanaryState>> {
+    let output =
+        session.execute_command(&format!("sudo cat {} 2>/dev/null", state_path(name)))?;
+    if !output.success() {
+        return Ok(None);
+    }
+    Ok(serde_json::from_str(&output.stdout).ok())
+}
+
+fn store_state(session: &RumiSession, name: &str, state: &CanaryState) -> RumiResult<()> {
+    let staging_path = format!("/tmp/rumi-canary-{}.json", name);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(serde_json::to_string_pretty(state)?.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!("sudo mv {} {}", staging_path, state_path(name)))?;
+    Ok(())
+}
+
+fn clear_state(session: &RumiSession, name: &str) -> RumiResult<()> {
+    session.execute_checked(&format!("sudo rm -f {}", state_path(name)))?;
+    Ok(())
+}
+
+/// Swap a freshly written nginx config in and reload, refusing configs nginx
+/// itself rejects.
+fn install_nginx_config(session: &RumiSession, domain: &str, content: &str) -> RumiResult<()> {
+    let staging_path = format!("/tmp/rumi-nginx-{}", domain);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(content.as_bytes())?;
+    drop(file);
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
+    session.execute_checked(&format!(
+        "sudo mv {} {} && sudo ln -sf {} /etc/nginx/sites-enabled/ && sudo nginx -t && sudo systemctl reload nginx",
+        staging_path, config_file_path, config_file_path
+    ))?;
+    Ok(())
+}
+
+/// The website split config: split_clients picks a web root per client, so
+/// `percent` of visitors see the canary while everyone else stays on stable.
+fn website_split_config(domain: &str, stable: &str, canary: &str, percent: u8) -> String {
+    let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
+    let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
+    format!(
+        r#"
+            split_clients "${{remote_addr}}" $rumi_canary_root {{
+                {percent}%    {canary};
+                *             {stable};
+            }}
+            server {{
+                 listen      80;
+                 listen      [::]:80;
+                 server_name {domain} www.{domain};
+                 return 301  https://$server_name$request_uri;
+            }}
+            server {{
+                 listen       443 ssl http2;
+                 listen       [::]:443 ssl http2;
+                 server_name  {domain} www.{domain};
+                 ssl_certificate {certificate_path};
+                 ssl_certificate_key {certificate_key_path};
+                 root $rumi_canary_root;
+                 index  index.html;
+                 location / {{
+                      root   $rumi_canary_root;
+                      index  index.html;
+                      try_files $uri $uri/ /index.html;
+                 }}
+            }}
+            "#
+    )
+}
+
+/// The server split config: a weighted upstream sends `percent` of requests
+/// to the canary instance on its own port.
+fn server_split_config(name: &str, domain: &str, stable: u16, canary: u16, percent: u8) -> String {
+    let stable_weight = 100 - percent;
+    format!(
+        r#"
+        upstream rumi_canary_{name} {{
+          server 127.0.0.1:{stable} weight={stable_weight};
+          server 127.0.0.1:{canary} weight={percent};
+        }}
+        server {{
+          listen 80;
+          listen [::]:80;
+          server_name {domain} www.{domain};
+
+          location ^~ / {{
+            proxy_http_version 1.1;
+            proxy_set_header Upgrade $http_upgrade;
+            proxy_set_header Connection "upgrade";
+            proxy_set_header X-Real-IP $remote_addr;
+            proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
+            proxy_set_header Host $http_host;
+            proxy_set_header X-NginX-Proxy true;
+            proxy_pass http://rumi_canary_{name}/;
+          }}
+        }}
+        "#
+    )
+}
+
+/// The `canary start` command: deploy the new release next to the stable one
+/// and split traffic. Websites get a split_clients web root, servers a
+/// weighted upstream with the canary binary running as its own unit.
+pub fn start_command(
+    config: &RumiConfig,
+    deployment: &DeploymentConfig,
+    percent: u8,
+) -> RumiResult<()> {
+    if !(1..=99).contains(&percent) {
+        return Err(RumiError::Config(
+            "the canary percentage must be between 1 and 99".to_string(),
+        ));
+    }
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    if load_state(&session, &deployment.name)?.is_some() {
+        return Err(RumiError::Config(format!(
+            "a canary is already running for '{}', promote or abort it first",
+            deployment.name
+        )));
+    }
+    match &deployment.deployment_type {
+        DeploymentType::Website { dist_path } => {
+            let stable = crate::release::current_web_root(&session, &deployment.domain)?;
+            let canary = format!("{}/{}_{}", WEB_FOLDER, deployment.domain, Uuid::new_v4());
+            // /var/www is root-owned; carve out a user-writable canary root
+            // for the upload, nginx only needs to read it
+            session.execute_checked(&format!(
+                "sudo mkdir -p {0} && sudo chown \"$(id -un)\" {0}",
+                crate::session::quote_arg(&canary)
+            ))?;
+            crate::blobstore::upload_folder_deduped(&session, Path::new(dist_path), &canary)
+                .map_err(|e| {
+                    RumiError::CommandFailed(format!("failed to upload canary release: {}", e))
+                })?;
+            install_nginx_config(
+                &session,
+                &deployment.domain,
+                &website_split_config(&deployment.domain, &stable, &canary, percent),
+            )?;
+            store_state(
+                &session,
+                &deployment.name,
+                &CanaryState {
+                    percent,
+                    stable,
+                    canary,
+                },
+            )?;
+        }
+        DeploymentType::Server {
+            binary_path,
+            port,
+            artifacts,
+            env,
+            env_file,
+            ..
+        } => {
+            let artifact =
+                crate::commands::servers::resolve_artifact(&session, binary_path, artifacts)?;
+            let canary_port = port + 1;
+            let canary_bin = format!("{}/{}-canary", SERVER_BIN_PATH, deployment.name);
+            let staging_path = format!("/tmp/rumi-bin-{}-canary", deployment.name);
+            let sftp = session.sftp()?;
+            crate::utils::upload_file(&sftp, Path::new(&artifact), &staging_path).map_err(
+                |e| RumiError::CommandFailed(format!("failed to upload {}: {}", artifact, e)),
+            )?;
+            session.execute_checked(&format!(
+                "sudo chmod 755 {} && sudo mv {} {}",
+                staging_path, staging_path, canary_bin
+            ))?;
+            // the canary shares the stable deployment's env file
+            let canary_env = (!env.is_empty() || env_file.is_some())
+                .then(|| crate::commands::servers::env_file_path(&deployment.name));
+            let unit = crate::utils::get_server_systemd_unit(
+                &format!("{}-canary", deployment.name),
+                &canary_bin,
+                canary_port,
+                canary_env.as_deref(),
+            );
+            let unit_staging = format!("/tmp/rumi-{}-canary.service", deployment.name);
+            let mut file = sftp.create(Path::new(&unit_staging))?;
+            file.write_all(unit.as_bytes())?;
+            drop(file);
+            session.execute_checked(&format!(
+                "sudo mv {} /etc/systemd/system/{}-canary.service && sudo systemctl daemon-reload && sudo systemctl enable --now {}-canary",
+                unit_staging, deployment.name, deployment.name
+            ))?;
+            install_nginx_config(
+                &session,
+                &deployment.domain,
+                &server_split_config(&deployment.name, &deployment.domain, *port, canary_port, percent),
+            )?;
+            store_state(
+                &session,
+                &deployment.name,
+                &CanaryState {
+                    percent,
+                    stable: port.to_string(),
+                    canary: canary_port.to_string(),
+                },
+            )?;
+        }
+        other => {
+            return Err(RumiError::Config(format!(
+                "canaries are for websites and servers, '{}' is a {}",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    }
+    println!(
+        "canary running for '{}': {}% of traffic on the new release",
+        deployment.name, percent
+    );
+    Ok(())
+}
+
+/// The `canary promote` command: the canary becomes the only release.
+pub fn promote_command(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let state = load_state(&session, &deployment.name)?.ok_or_else(|| {
+        RumiError::Config(format!("no canary running for '{}'", deployment.name))
+    })?;
+    match &deployment.deployment_type {
+        DeploymentType::Website { .. } => {
+            restore_website_config(&session, &deployment.domain, &state.canary)?;
+            // the old stable release folder stays behind for rollbacks
+        }
+        DeploymentType::Server { port, proxy, .. } => {
+            let stable_bin = format!("{}/{}", SERVER_BIN_PATH, deployment.name);
+            session.execute_checked(&format!(
+                "sudo systemctl disable --now {}-canary && sudo mv {}-canary {} && sudo rm -f /etc/systemd/system/{}-canary.service && sudo systemctl daemon-reload && sudo systemctl try-restart {}.service",
+                deployment.name, stable_bin, stable_bin, deployment.name, deployment.name
+            ))?;
+            install_nginx_config(
+                &session,
+                &deployment.domain,
+                &crate::utils::get_servers_nginx_config_file(
+                    &deployment.domain,
+                    *port,
+                    &proxy.clone().unwrap_or_default(),
+                    &deployment.nginx_extra_block()?,
+                ),
+            )?;
+        }
+        other => {
+            return Err(RumiError::Config(format!(
+                "canaries are for websites and servers, '{}' is a {}",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    }
+    clear_state(&session, &deployment.name)?;
+    println!("canary promoted, '{}' now serves only the new release", deployment.name);
+    Ok(())
+}
+
+/// The `canary abort` command: all traffic back on stable, canary removed.
+pub fn abort_command(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let state = load_state(&session, &deployment.name)?.ok_or_else(|| {
+        RumiError::Config(format!("no canary running for '{}'", deployment.name))
+    })?;
+    match &deployment.deployment_type {
+        DeploymentType::Website { .. } => {
+            restore_website_config(&session, &deployment.domain, &state.stable)?;
+            session.execute_checked(&format!("sudo rm -rf {}", state.canary))?;
+        }
+        DeploymentType::Server { port, proxy, .. } => {
+            session.execute_checked(&format!(
+                "sudo systemctl disable --now {}-canary && sudo rm -f {}/{}-canary /etc/systemd/system/{}-canary.service && sudo systemctl daemon-reload",
+                deployment.name, SERVER_BIN_PATH, deployment.name, deployment.name
+            ))?;
+            install_nginx_config(
+                &session,
+                &deployment.domain,
+                &crate::utils::get_servers_nginx_config_file(
+                    &deployment.domain,
+                    *port,
+                    &proxy.clone().unwrap_or_default(),
+                    &deployment.nginx_extra_block()?,
+                ),
+            )?;
+        }
+        other => {
+            return Err(RumiError::Config(format!(
+                "canaries are for websites and servers, '{}' is a {}",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    }
+    clear_state(&session, &deployment.name)?;
+    println!("canary aborted, '{}' serves the stable release again", deployment.name);
+    Ok(())
+}
+
+fn restore_website_config(session: &RumiSession, domain: &str, root: &str) -> RumiResult<()> {
+    let config = crate::utils::get_web_nginx_config_file(
+        domain,
+        &format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain),
+        &format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain),
+        root,
+        "",
+    );
+    install_nginx_config(session, domain, &config)
+}
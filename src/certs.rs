@@ -0,0 +1,361 @@
+//! Certificate issuance beyond `certbot --standalone`. The standalone
+//! authenticator binds port 80, which fails on a host that is already
+//! serving traffic, and http-01 can never issue wildcards. The dns-01
+//! challenge through the provider plugins handles both, reusing the dns
+//! block and credentials the dns commands already use.
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::RumiConfig;
+use crate::dns::DnsProviderKind;
+use crate::error::{RumiError, RumiResult};
+use crate::session::{quote_arg, CommandExecutor, RumiSession, SessionPool};
+
+/// How certbot proves ownership of the domain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Challenge {
+    /// certbot binds port 80 itself; only works while nothing serves on it.
+    #[serde(rename = "standalone")]
+    Standalone,
+    /// A TXT record through the configured dns provider; works behind a
+    /// running nginx and is the only way to issue wildcards.
+    #[serde(rename = "dns-01")]
+    Dns01,
+}
+
+impl std::str::FromStr for Challenge {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "standalone" => Ok(Challenge::Standalone),
+            "dns-01" | "dns01" => Ok(Challenge::Dns01),
+            other => Err(format!(
+                "unknown challenge '{}', expected standalone or dns-01",
+                other
+            )),
+        }
+    }
+}
+
+/// Where the cloudflare plugin's credentials file lives on the host,
+/// relative to the login user's home; root reads it through sudo.
+const CLOUDFLARE_CREDENTIALS_FILE: &str = ".rumi-certbot-cloudflare.ini";
+
+/// Obtain (or renew) the certificate for a domain on a host. Standalone
+/// covers the plain first-install case; dns-01 goes through the configured
+/// dns provider's certbot plugin and is required for `--wildcard`, which
+/// requests `*.domain` next to the apex instead of www.
+pub fn obtain_certificate(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    email: &str,
+    challenge: Challenge,
+    config: Option<&RumiConfig>,
+    wildcard: bool,
+) -> RumiResult<()> {
+    if wildcard && challenge != Challenge::Dns01 {
+        return Err(RumiError::Config(
+            "wildcard certificates need --challenge dns-01, http challenges cannot issue them"
+                .to_string(),
+        ));
+    }
+    let domains = if wildcard {
+        format!("-d {} -d {}", quote_arg(domain), quote_arg(&format!("*.{}", domain)))
+    } else {
+        format!("-d {} -d {}", quote_arg(domain), quote_arg(&format!("www.{}", domain)))
+    };
+    let tail = format!("{} --agree-tos --email {}", domains, quote_arg(email));
+    match challenge {
+        Challenge::Standalone => {
+            executor.execute_checked("sudo apt-get install -y certbot")?;
+            executor.execute_checked(&format!("sudo certbot certonly -y --standalone {}", tail))?;
+        }
+        Challenge::Dns01 => {
+            let provider = config.and_then(|c| c.dns.as_ref()).map(|dns| dns.provider);
+            match provider {
+                Some(DnsProviderKind::Cloudflare) => {
+                    executor.execute_checked(
+                        "sudo apt-get install -y certbot python3-certbot-dns-cloudflare",
+                    )?;
+                    install_cloudflare_credentials(executor, config)?;
+                    executor.execute_checked(&format!(
+                        "sudo certbot certonly -y --dns-cloudflare --dns-cloudflare-credentials {} {}",
+                        CLOUDFLARE_CREDENTIALS_FILE, tail
+                    ))?;
+                }
+                Some(DnsProviderKind::Route53) => {
+                    executor.execute_checked(
+                        "sudo apt-get install -y certbot python3-certbot-dns-route53",
+                    )?;
+                    // aws credentials from the local environment ride along;
+                    // without them the host's instance profile has to answer
+                    let mut env = String::new();
+                    for name in ["AWS_ACCESS_KEY_ID", "AWS_SECRET_ACCESS_KEY", "AWS_SESSION_TOKEN"]
+                    {
+                        if let Ok(value) = std::env::var(name) {
+                            env.push_str(&format!("{}={} ", name, quote_arg(&value)));
+                        }
+                    }
+                    executor.execute_checked(&format!(
+                        "sudo {}certbot certonly -y --dns-route53 {}",
+                        env, tail
+                    ))?;
+                }
+                None => {
+                    return Err(RumiError::Config(
+                        "dns-01 needs a dns block in the config naming the provider".to_string(),
+                    ))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Drop the cloudflare api token onto the host as the ini file the certbot
+/// plugin reads, owner-only. The token comes from CLOUDFLARE_API_TOKEN or
+/// the dns/cloudflare/api_token secret, like the dns commands.
+fn install_cloudflare_credentials(
+    executor: &dyn CommandExecutor,
+    config: Option<&RumiConfig>,
+) -> RumiResult<()> {
+    let token = std::env::var("CLOUDFLARE_API_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+        .or_else(|| {
+            config.and_then(|c| c.secrets.get("dns/cloudflare/api_token").cloned())
+        })
+        .ok_or_else(|| {
+            RumiError::Config(
+                "no cloudflare token: set CLOUDFLARE_API_TOKEN or the dns/cloudflare/api_token secret"
+                    .to_string(),
+            )
+        })?;
+    let token = crate::secrets::resolve(&token)?;
+    executor.create_remote_file(
+        CLOUDFLARE_CREDENTIALS_FILE,
+        format!("dns_cloudflare_api_token = {}\n", token).as_bytes(),
+    )?;
+    executor.execute_checked(&format!("chmod 600 {}", CLOUDFLARE_CREDENTIALS_FILE))?;
+    Ok(())
+}
+
+/// The deploy hook certbot runs after every successful renewal, so nginx
+/// picks up the new certificate without anyone ssh-ing in.
+const RENEW_HOOK_PATH: &str = "/etc/letsencrypt/renewal-hooks/deploy/rumi-reload-nginx.sh";
+
+/// The cron fallback for hosts whose certbot package ships no systemd timer.
+const RENEW_CRON_PATH: &str = "/etc/cron.d/rumi-certbot-renew";
+
+/// When the certificate for a deployment's domain expires on its host.
+fn certificate_expiry(
+    session: &RumiSession,
+    domain: &str,
+) -> RumiResult<chrono::DateTime<chrono::Utc>> {
+    let output = session.execute_command(&format!(
+        "sudo openssl x509 -enddate -noout -in {}",
+        quote_arg(&format!("{}/{}/fullchain.pem", crate::SSL_CERTIFICATE_PATH, domain))
+    ))?;
+    if !output.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "no certificate for {} on {}: {}",
+            domain,
+            session.host(),
+            output.stderr.trim()
+        )));
+    }
+    // openssl prints "notAfter=Jun  1 12:00:00 2026 GMT"; squeeze the
+    // double space single-digit days get before parsing
+    let raw = output
+        .stdout
+        .trim()
+        .strip_prefix("notAfter=")
+        .unwrap_or(output.stdout.trim())
+        .trim_end_matches(" GMT")
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let parsed = chrono::NaiveDateTime::parse_from_str(&raw, "%b %d %H:%M:%S %Y")
+        .map_err(|e| {
+            RumiError::CommandFailed(format!("unreadable expiry '{}' for {}: {}", raw, domain, e))
+        })?;
+    Ok(parsed.and_utc())
+}
+
+/// `cert status`: when each deployment's certificate expires, read off the
+/// hosts. Each host is connected once and reused across its deployments.
+pub fn status_command(config: &RumiConfig) -> RumiResult<()> {
+    if config.deployments.is_empty() {
+        println!("no deployments in the config, nothing to check");
+        return Ok(());
+    }
+    let mut pool = SessionPool::new();
+    println!("{:<20} {:<28} {:<22} {:<18} DAYS LEFT", "DEPLOYMENT", "DOMAIN", "HOST", "EXPIRES");
+    for deployment in &config.deployments {
+        let ssh = config.ssh_for_deployment(deployment)?;
+        let (expires, days) = match pool
+            .get(ssh)
+            .and_then(|session| certificate_expiry(&session, &deployment.domain))
+        {
+            Ok(expiry) => {
+                let days = (expiry - chrono::Utc::now()).num_days();
+                let days = if days < 14 {
+                    crate::output::red(days.to_string())
+                } else if days < 30 {
+                    crate::output::yellow(days.to_string())
+                } else {
+                    days.to_string()
+                };
+                (expiry.format("%Y-%m-%d %H:%M").to_string(), days)
+            }
+            Err(e) => (crate::output::red(format!("{}", e)), "-".to_string()),
+        };
+        println!(
+            "{:<20} {:<28} {:<22} {:<18} {}",
+            deployment.name, deployment.domain, ssh.host, expires, days
+        );
+    }
+    Ok(())
+}
+
+/// `cert renew`: renew now, for one deployment's domain or everything
+/// certbot manages on every host, then reload nginx. certbot itself skips
+/// certificates that are not close enough to expiry unless forced.
+pub fn renew_command(config: &RumiConfig, name: Option<&str>, force: bool) -> RumiResult<()> {
+    let mut pool = SessionPool::new();
+    let mut renewed_hosts: Vec<String> = Vec::new();
+    for deployment in targeted(config, name)? {
+        let ssh = config.ssh_for_deployment(deployment)?;
+        let session = pool.get(ssh)?;
+        let mut command = "sudo certbot renew".to_string();
+        if name.is_some() {
+            command.push_str(&format!(" --cert-name {}", quote_arg(&deployment.domain)));
+        } else if renewed_hosts.contains(&ssh.host) {
+            // an unscoped renew already covered every certificate on this host
+            continue;
+        }
+        if force {
+            command.push_str(" --force-renewal");
+        }
+        session.execute_checked(&command)?;
+        session.execute_checked("sudo systemctl reload nginx")?;
+        renewed_hosts.push(ssh.host.clone());
+        println!("[{}] renewal run finished", ssh.host);
+    }
+    Ok(())
+}
+
+/// `cert auto-renew`: make the hosts renew on their own. Installs the
+/// nginx-reload deploy hook, then enables the certbot systemd timer the
+/// debian package ships, falling back to a cron.d entry where there is none.
+pub fn auto_renew_command(config: &RumiConfig, name: Option<&str>) -> RumiResult<()> {
+    let mut pool = SessionPool::new();
+    let mut done_hosts: Vec<String> = Vec::new();
+    for deployment in targeted(config, name)? {
+        let ssh = config.ssh_for_deployment(deployment)?;
+        if done_hosts.contains(&ssh.host) {
+            continue;
+        }
+        let session = pool.get(ssh)?;
+        session.execute_checked(&format!(
+            "printf '#!/bin/sh\\nsystemctl reload nginx\\n' | sudo tee {0} > /dev/null && sudo chmod 755 {0}",
+            RENEW_HOOK_PATH
+        ))?;
+        let timer = session.execute_command("sudo systemctl enable --now certbot.timer")?;
+        if timer.success() {
+            println!("[{}] certbot.timer enabled, renewals reload nginx", ssh.host);
+        } else {
+            session.execute_checked(&format!(
+                "printf '0 3 * * * root certbot renew -q\\n' | sudo tee {} > /dev/null",
+                RENEW_CRON_PATH
+            ))?;
+            println!(
+                "[{}] no certbot.timer on this host, installed {} instead",
+                ssh.host, RENEW_CRON_PATH
+            );
+        }
+        done_hosts.push(ssh.host.clone());
+    }
+    Ok(())
+}
+
+/// The deployments a cert command operates on: the named one, or all of them.
+fn targeted<'a>(
+    config: &'a RumiConfig,
+    name: Option<&str>,
+) -> RumiResult<Vec<&'a crate::config::DeploymentConfig>> {
+    match name {
+        Some(name) => Ok(vec![config.find_deployment(name)?]),
+        None => {
+            if config.deployments.is_empty() {
+                return Err(RumiError::Config(
+                    "no deployments in the config, nothing to renew".to_string(),
+                ));
+            }
+            Ok(config.deployments.iter().collect())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::MockExecutor;
+
+    #[test]
+    fn challenge_parses_both_spellings() {
+        assert_eq!("standalone".parse::<Challenge>(), Ok(Challenge::Standalone));
+        assert_eq!("dns-01".parse::<Challenge>(), Ok(Challenge::Dns01));
+        assert_eq!("dns01".parse::<Challenge>(), Ok(Challenge::Dns01));
+        assert!("http-01".parse::<Challenge>().is_err());
+    }
+
+    #[test]
+    fn standalone_requests_apex_and_www() {
+        let executor = MockExecutor::new();
+        obtain_certificate(
+            &executor,
+            "example.com",
+            "ops@example.com",
+            Challenge::Standalone,
+            None,
+            false,
+        )
+        .unwrap();
+        let certbot = executor.executed().pop().unwrap();
+        assert!(certbot.contains("--standalone"));
+        assert!(certbot.contains("-d example.com -d www.example.com"));
+        assert!(certbot.contains("--email ops@example.com"));
+    }
+
+    #[test]
+    fn wildcard_requires_dns01() {
+        let executor = MockExecutor::new();
+        let error = obtain_certificate(
+            &executor,
+            "example.com",
+            "ops@example.com",
+            Challenge::Standalone,
+            None,
+            true,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("dns-01"));
+        assert!(executor.executed().is_empty());
+    }
+
+    #[test]
+    fn dns01_needs_a_configured_provider() {
+        let error = obtain_certificate(
+            &MockExecutor::new(),
+            "example.com",
+            "ops@example.com",
+            Challenge::Dns01,
+            None,
+            false,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("dns block"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::error::{RumiError, RumiResult};
+
+/// Set once at startup by --ci or CI env detection, read everywhere else.
+static CI_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Steps recorded during the run, printed as the end-of-run summary.
+static STEPS: Mutex<Vec<(String, u64, bool)>> = Mutex::new(Vec::new());
+
+/// The env var a pipeline puts the ssh private key into.
+pub const SSH_KEY_ENV: &str = "RUMI_SSH_KEY";
+
+/// Whether we are running inside a pipeline: the --ci flag, or the CI env
+/// var that GitHub Actions, GitLab CI and most others set.
+pub fn detect() -> bool {
+    std::env::var("CI").map(|v| v == "true" || v == "1").unwrap_or(false)
+}
+
+pub fn enable() {
+    CI_MODE.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    CI_MODE.load(Ordering::Relaxed)
+}
+
+/// Run one annotated step: machine-readable group markers around it in ci
+/// mode, and an entry in the end-of-run summary either way.
+pub fn step<T>(name: &str, run: impl FnOnce() -> RumiResult<T>) -> RumiResult<T> {
+    if enabled() {
+        println!("::group::{}", name);
+    }
+    let started = Instant::now();
+    let result = run();
+    let seconds = started.elapsed().as_secs();
+    if enabled() {
+        println!("::endgroup::");
+        if let Err(e) = &result {
+            println!("::error title={}::{}", name, e);
+        }
+    }
+    STEPS
+        .lock()
+        .unwrap()
+        .push((name.to_string(), seconds, result.is_ok()));
+    result
+}
+
+/// Print the concise summary a pipeline log ends with in ci mode.
+pub fn print_summary() {
+    if !enabled() {
+        return;
+    }
+    let steps = STEPS.lock().unwrap();
+    if steps.is_empty() {
+        return;
+    }
+    println!("--- rumi summary ---");
+    for (name, seconds, ok) in steps.iter() {
+        println!(
+            "{} {} ({}s)",
+            if *ok { "ok  " } else { "FAIL" },
+            name,
+            seconds
+        );
+    }
+}
+
+/// The dotenv artifact gitlab picks up for its environments/deployments ui.
+pub const GITLAB_ENV_FILE: &str = "rumi.env";
+
+/// Whether we are running inside a gitlab pipeline.
+pub fn detect_gitlab() -> bool {
+    std::env::var("GITLAB_CI").map(|v| v == "true").unwrap_or(false)
+}
+
+/// Write the dotenv artifact gitlab's environments ui reads: the url of the
+/// deployed domain and the release id. The job has to declare
+/// `artifacts: reports: dotenv: rumi.env` and set `environment: url:
+/// $RUMI_ENVIRONMENT_URL` for gitlab to pick it up.
+pub fn write_gitlab_env(domain: &str, release_id: &str) -> RumiResult<()> {
+    let content = format!(
+        "RUMI_ENVIRONMENT_URL=https://{}\nRUMI_RELEASE_ID={}\n",
+        domain, release_id
+    );
+    std::fs::write(GITLAB_ENV_FILE, content)?;
+    println!(
+        "wrote {} (environment url https://{}, release {})",
+        GITLAB_ENV_FILE, domain, release_id
+    );
+    Ok(())
+}
+
+/// In ci mode the ssh key comes from an env var, not a checked-out file:
+/// write it to a temp file with owner-only permissions and return the path.
+pub fn materialize_ssh_key() -> RumiResult<Option<PathBuf>> {
+    let Ok(key) = std::env::var(SSH_KEY_ENV) else {
+        return Ok(None);
+    };
+    let path = std::env::temp_dir().join(format!("rumi-ci-key-{}", std::process::id()));
+    std::fs::write(&path, key.trim_end().to_string() + "\n")?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(Some(path))
+}
+
+/// In ci mode secrets come strictly from the environment, never from the
+/// config file: "database/mydb/password" maps to RUMI_SECRET_DATABASE_MYDB_PASSWORD.
+pub fn secret_env_var(key: &str) -> String {
+    format!(
+        "RUMI_SECRET_{}",
+        key.replace(['/', '-'], "_").to_uppercase()
+    )
+}
+
+/// Look up a secret the ci way, with an error naming the missing variable.
+pub fn secret_from_env(key: &str) -> RumiResult<String> {
+    let var = secret_env_var(key);
+    std::env::var(&var).map_err(|_| {
+        RumiError::Config(format!(
+            "ci mode reads secrets from the environment only, set {} for '{}'",
+            var, key
+        ))
+    })
+}
+
+/// The `ci print-workflow` command: a ready-to-use GitHub Actions snippet.
+pub fn print_workflow_command() {
+    println!(
+        r#"# .github/workflows/deploy.yml
+name: deploy
+on:
+  push:
+    branches: [main]
+jobs:
+  deploy:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - name: install rumi2
+        run: cargo install rumi2
+      - name: deploy
+        env:
+          {ssh_key_env}: ${{{{ secrets.DEPLOY_SSH_KEY }}}}
+        run: |
+          rumi2 --ci hosting update \
+            --ssh_host ${{{{ vars.DEPLOY_HOST }}}} \
+            --ssh_user ${{{{ vars.DEPLOY_USER }}}} \
+            --ssh_cert_public_key "" --ssh_cert_private_key "" --ssh_password "" \
+            --domain ${{{{ vars.DEPLOY_DOMAIN }}}} \
+            --dist_path dist"#,
+        ssh_key_env = SSH_KEY_ENV
+    );
+}
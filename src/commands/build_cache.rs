@@ -0,0 +1,18 @@
+use crate::error::RumiResult;
+use crate::session::RumiSession;
+
+/// Where per-deployment build caches live on the remote host. Release dirs
+/// stay isolated; only the caches underneath here survive across releases.
+pub const BUILD_CACHE_ROOT: &str = "/var/cache/rumi";
+
+/// Make sure a deployment's cache dir for one tool (pip, npm, cargo, ...)
+/// exists and is writable by the connecting user, and return its path. The
+/// dir persists across releases so rebuilds hit warm caches.
+pub fn ensure(session: &RumiSession, deployment: &str, tool: &str) -> RumiResult<String> {
+    let cache_dir = format!("{}/{}/{}", BUILD_CACHE_ROOT, deployment, tool);
+    session.execute_checked(&format!(
+        "sudo mkdir -p {} && sudo chown $(whoami) {}",
+        cache_dir, cache_dir
+    ))?;
+    Ok(cache_dir)
+}
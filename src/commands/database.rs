@@ -0,0 +1,94 @@
+use crate::config::{DatabaseEngine, DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::secrets;
+use crate::session::RumiSession;
+
+/// Install the database server for a database deployment, create the
+/// database and user with a generated password (stored in the secrets
+/// layer), and configure what it listens on without opening it to the world.
+pub fn install_command(
+    session: &RumiSession,
+    config: &mut RumiConfig,
+    deployment: &DeploymentConfig,
+) -> RumiResult<()> {
+    let (engine, db_name, db_user, listen_address) = match &deployment.deployment_type {
+        DeploymentType::Database {
+            engine,
+            db_name,
+            db_user,
+            listen_address,
+        } => (*engine, db_name.clone(), db_user.clone(), listen_address.clone()),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a database",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+
+    let password = secrets::generate_password();
+    session.execute_checked("sudo apt-get update")?;
+    match engine {
+        DatabaseEngine::Postgres => {
+            session.execute_checked("sudo apt-get -y install postgresql")?;
+            session.execute_checked(&format!(
+                "sudo -u postgres psql -c \"CREATE USER {} WITH PASSWORD '{}'\"",
+                db_user, password
+            ))?;
+            session.execute_checked(&format!(
+                "sudo -u postgres psql -c \"CREATE DATABASE {} OWNER {}\"",
+                db_name, db_user
+            ))?;
+            if let Some(listen_address) = &listen_address {
+                session.execute_checked(&format!(
+                    "sudo -u postgres psql -c \"ALTER SYSTEM SET listen_addresses = '{}'\"",
+                    listen_address
+                ))?;
+                // scram auth for exactly this user and database, no trust lines
+                session.execute_checked(&format!(
+                    "sudo sh -c 'echo \"host {} {} 0.0.0.0/0 scram-sha-256\" >> $(sudo -u postgres psql -tAc \"SHOW hba_file\")'",
+                    db_name, db_user
+                ))?;
+            }
+            session.execute_checked("sudo systemctl restart postgresql")?;
+        }
+        DatabaseEngine::Mysql => {
+            session.execute_checked("sudo apt-get -y install mysql-server")?;
+            session.execute_checked(&format!(
+                "sudo mysql -e \"CREATE DATABASE IF NOT EXISTS {}\"",
+                db_name
+            ))?;
+            let host_part = if listen_address.is_some() { "%" } else { "localhost" };
+            session.execute_checked(&format!(
+                "sudo mysql -e \"CREATE USER IF NOT EXISTS '{}'@'{}' IDENTIFIED BY '{}'\"",
+                db_user, host_part, password
+            ))?;
+            session.execute_checked(&format!(
+                "sudo mysql -e \"GRANT ALL PRIVILEGES ON {}.* TO '{}'@'{}'; FLUSH PRIVILEGES\"",
+                db_name, db_user, host_part
+            ))?;
+            if let Some(listen_address) = &listen_address {
+                session.execute_checked(&format!(
+                    "sudo sh -c 'echo \"[mysqld]\nbind-address = {}\" > /etc/mysql/mysql.conf.d/rumi-bind.cnf'",
+                    listen_address
+                ))?;
+            }
+            session.execute_checked("sudo systemctl restart mysql")?;
+        }
+    }
+
+    secrets::store(
+        config,
+        &format!("database/{}/password", deployment.name),
+        password,
+    );
+    println!(
+        "database '{}' with user '{}' created on {}; password stored under secrets key database/{}/password",
+        db_name,
+        db_user,
+        session.host(),
+        deployment.name
+    );
+    Ok(())
+}
@@ -0,0 +1,107 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig, SshConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::secrets;
+use crate::session::RumiSession;
+
+/// Provision a fresh host as a docker host: engine and compose plugin,
+/// daemon.json with log rotation (and registry mirrors when configured),
+/// the deploy user in the docker group, and the base firewall rules. Other
+/// container deployments can assume this ran first.
+pub fn install_command(
+    session: &RumiSession,
+    ssh: &SshConfig,
+    deployment: &DeploymentConfig,
+) -> RumiResult<()> {
+    let registry_mirrors = match &deployment.deployment_type {
+        DeploymentType::DockerHost { registry_mirrors } => registry_mirrors,
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a docker host",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+
+    session.execute_checked("sudo apt-get update")?;
+    session.execute_checked("sudo apt-get -y install docker.io docker-compose-v2 ufw")?;
+
+    // json log driver with rotation so containers can't fill the disk
+    let mut daemon_config = serde_json::json!({
+        "log-driver": "json-file",
+        "log-opts": { "max-size": "10m", "max-file": "3" }
+    });
+    if !registry_mirrors.is_empty() {
+        daemon_config["registry-mirrors"] = serde_json::json!(registry_mirrors);
+    }
+    let sftp = session.sftp()?;
+    let staging_path = "/tmp/rumi-docker-daemon.json";
+    let mut file = sftp.create(Path::new(staging_path))?;
+    file.write_all(serde_json::to_string_pretty(&daemon_config)?.as_bytes())?;
+    drop(file);
+    crate::session::CommandBatch::new()
+        .step(
+            "install daemon.json",
+            &format!(
+                "sudo mkdir -p /etc/docker && sudo mv {} /etc/docker/daemon.json",
+                staging_path
+            ),
+        )
+        .step(
+            "add user to docker group",
+            &format!("sudo usermod -aG docker {}", ssh.user),
+        )
+        .step(
+            "enable service",
+            "sudo systemctl enable docker && sudo systemctl restart docker",
+        )
+        .run(session)?;
+
+    // ssh stays open, 80/443 for whatever the containers end up serving
+    crate::firewall::allow_ports(session, &["ssh", "80/tcp", "443/tcp"])?;
+
+    println!(
+        "docker host ready on {} (user '{}' added to the docker group, re-login required)",
+        session.host(),
+        ssh.user
+    );
+    Ok(())
+}
+
+/// Log the remote docker engine into a registry with credentials from the
+/// secrets layer ("registry/<host>/username" and ".../password"). The
+/// password travels over sftp into a root-only temp file and reaches docker
+/// via --password-stdin, so it never shows up in shell history or ps output.
+pub fn login_command(
+    session: &RumiSession,
+    config: &RumiConfig,
+    registry: &str,
+) -> RumiResult<()> {
+    let username = secrets::require(config, &format!("registry/{}/username", registry))?;
+    let password = secrets::require(config, &format!("registry/{}/password", registry))?;
+
+    let staging_path = format!("/tmp/rumi-registry-{}", std::process::id());
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(password.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!("chmod 600 {}", staging_path))?;
+    // docker hub is the cli default and rejects its own hostname as an arg
+    let registry_arg = if registry == "docker.io" { "" } else { registry };
+    let login = session.execute_command(&format!(
+        "docker login --username {} --password-stdin {} < {}; status=$?; rm -f {}; exit $status",
+        username, registry_arg, staging_path, staging_path
+    ))?;
+    if !login.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "docker login to {} failed: {}",
+            registry,
+            login.stderr.trim()
+        )));
+    }
+    println!("{} logged into {}", session.host(), registry);
+    Ok(())
+}
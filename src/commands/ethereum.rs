@@ -5,6 +5,182 @@ use crate::utils::{get_ethereum_nginx_config_file, get_genesis_file, get_startno
 use crate::ETH_GETH_NGINX_CONFIG_PATH;
 use ssh2::{Channel, Error, Session};
 
+/// The datadir used when the deployment does not name one: the historical
+/// relative path in the login user's home.
+pub const DEFAULT_DATADIR: &str = "node/data";
+
+/// Get the datadir ready before `geth init` touches it: optionally format
+/// (only while the device carries no filesystem) and mount a dedicated
+/// block device there, give it to the login user, and refuse to continue
+/// with less free space than the deployment demands — a synced chain that
+/// runs the disk full mid-way is much harder to clean up.
+pub fn prepare_datadir(
+    session: &crate::session::RumiSession,
+    datadir: &str,
+    device: Option<&str>,
+    min_free_gb: u64,
+) -> crate::error::RumiResult<()> {
+    use crate::error::RumiError;
+
+    // quoted once up front: every interpolation below is shell-safe data
+    let fstab_line = device.map(|device| format!("{} {} ext4 defaults,nofail 0 2", device, datadir));
+    let datadir = crate::session::quote_arg(datadir);
+    let datadir = datadir.as_str();
+    session.execute_checked(&format!(
+        "sudo mkdir -p {} && sudo chown -R $(whoami): {}",
+        datadir, datadir
+    ))?;
+    if let Some(device) = device {
+        let device = crate::session::quote_arg(device);
+        let device = device.as_str();
+        let fstab_line = crate::session::quote_arg(&fstab_line.unwrap_or_default());
+        crate::session::CommandBatch::new()
+            .step(
+                "format the data disk (skipped when it has a filesystem)",
+                &format!("sudo blkid {} >/dev/null || sudo mkfs.ext4 {}", device, device),
+            )
+            .step(
+                "mount the data disk",
+                &format!(
+                    "mountpoint -q {} || sudo mount {} {}",
+                    datadir, device, datadir
+                ),
+            )
+            .step(
+                "persist the mount across reboots",
+                &format!(
+                    "grep -qF {0} /etc/fstab || echo {0} | sudo tee -a /etc/fstab >/dev/null",
+                    fstab_line
+                ),
+            )
+            .step(
+                "own the mounted datadir",
+                &format!("sudo chown -R $(whoami): {}", datadir),
+            )
+            .run(session)?;
+    }
+    let output = session.execute_checked(&format!("df -BG --output=avail {} | tail -1", datadir))?;
+    let free_gb: u64 = output
+        .stdout
+        .trim()
+        .trim_end_matches('G')
+        .parse()
+        .map_err(|_| {
+            RumiError::CommandFailed(format!(
+                "could not read the free space at {}: {}",
+                datadir,
+                output.stdout.trim()
+            ))
+        })?;
+    if free_gb < min_free_gb {
+        return Err(RumiError::Config(format!(
+            "only {}G free at {}, the deployment demands {}G before geth init",
+            free_gb, datadir, min_free_gb
+        )));
+    }
+    Ok(())
+}
+
+/// Roll an ethereum node's chain back to a chaindata snapshot from the
+/// backup store: stop geth, swap the recorded tarball in under the datadir,
+/// start geth again and watch the block number long enough to see it move.
+/// The replaced chaindata stays next to the restored one until the next
+/// rollback, in case the snapshot turns out worse than the problem.
+pub fn rollback_command(
+    session: &crate::session::RumiSession,
+    deployment: &crate::config::DeploymentConfig,
+    backup_id: &str,
+) -> crate::error::RumiResult<()> {
+    use crate::config::DeploymentType;
+    use crate::error::RumiError;
+
+    let datadir = match &deployment.deployment_type {
+        DeploymentType::Ethereum { datadir, .. } => datadir
+            .clone()
+            .unwrap_or_else(|| DEFAULT_DATADIR.to_string()),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not an ethereum node",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    let manager = crate::backup::BackupManager::new(session);
+    let backup = manager
+        .list_backups(Some(&deployment.name))?
+        .into_iter()
+        .find(|b| b.id == backup_id)
+        .ok_or_else(|| {
+            RumiError::Config(format!(
+                "no backup {} recorded for {}",
+                backup_id, deployment.name
+            ))
+        })?;
+    if backup.backup_type != crate::backup::BackupType::Chaindata {
+        return Err(RumiError::Config(format!(
+            "backup {} is not a chaindata snapshot",
+            backup_id
+        )));
+    }
+    let datadir = crate::session::quote_arg(&datadir);
+    crate::session::CommandBatch::new()
+        .step(
+            "stop geth",
+            "sudo systemctl stop geth 2>/dev/null; sudo pkill -x geth; true",
+        )
+        .step(
+            "set the current chaindata aside",
+            &format!(
+                "sudo rm -rf {0}/geth/chaindata.rolled-back && [ ! -d {0}/geth/chaindata ] || sudo mv {0}/geth/chaindata {0}/geth/chaindata.rolled-back",
+                datadir
+            ),
+        )
+        .step(
+            "restore the snapshot",
+            &format!(
+                "sudo tar -xzf {} -C {}",
+                crate::session::quote_arg(&backup.archive_path),
+                datadir
+            ),
+        )
+        .step(
+            "own the restored chaindata",
+            &format!("sudo chown -R $(whoami): {}/geth/chaindata", datadir),
+        )
+        .step(
+            "start geth",
+            "sudo systemctl start geth 2>/dev/null; true",
+        )
+        .run(session)?;
+    let probe = format!("geth attach --exec eth.blockNumber {}/geth.ipc", datadir);
+    let first = session.execute_command(&probe)?;
+    if !first.success() {
+        println!(
+            "chaindata restored from backup {}, but no node answers at {}/geth.ipc — start geth yourself",
+            backup_id, datadir
+        );
+        return Ok(());
+    }
+    std::thread::sleep(std::time::Duration::from_secs(15));
+    let second = session.execute_checked(&probe)?;
+    let before: u64 = first.stdout.trim().parse().unwrap_or(0);
+    let after: u64 = second.stdout.trim().parse().unwrap_or(0);
+    if after > before {
+        println!(
+            "rolled back to backup {}, node is syncing again (block {} -> {})",
+            backup_id, before, after
+        );
+        Ok(())
+    } else {
+        Err(RumiError::CommandFailed(format!(
+            "chaindata restored but the block number is stuck at {}, check the geth logs",
+            after
+        )))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn install_command<'a>(
     chanel: &'a mut Channel,
     session: &'a Session,
@@ -14,6 +190,7 @@ pub fn install_command<'a>(
     ext_ip: &'a str,
     unlock_wallet_address: &'a str,
     ws_address_ip: &'a str,
+    datadir: &'a str,
 ) {
     let command = chanel.exec("sudo add-apt-repository -y ppa:ethereum/ethereum");
     let mut s = String::new();
@@ -29,10 +206,12 @@ pub fn install_command<'a>(
     assert!(command.is_ok(), "Failed to install nginx");
     let command = chanel.exec("sudo apt install -y certbot");
     assert!(command.is_ok(), "Failed to install certbot");
-    let cerbot_instruction = format!(
-        "sudo certbot certonly -y --standalone -d {} -d www.{}",
-        domain, domain
-    );
+    let cerbot_instruction = crate::session::CommandBuilder::new("sudo certbot certonly -y --standalone")
+        .word("-d")
+        .arg(domain)
+        .word("-d")
+        .arg(&format!("www.{}", domain))
+        .build();
     let command = chanel.exec(&cerbot_instruction);
     assert!(command.is_ok(), "Failed to get certificate");
 
@@ -51,13 +230,17 @@ pub fn install_command<'a>(
         .expect("failed to write password.sec");
 
     // create account
-    let command: Result<(), Error> =
-        chanel.exec("geth account new --datadir node/data  --password node/password.sec");
+    let command: Result<(), Error> = chanel.exec(&format!(
+        "geth account new --datadir {}  --password node/password.sec",
+        crate::session::quote_arg(datadir)
+    ));
     assert!(command.is_ok(), "Failed to create account");
 
     // init genesis file
-    let command: Result<(), Error> =
-        chanel.exec("geth init --datadir node/data  node/genesis.json");
+    let command: Result<(), Error> = chanel.exec(&format!(
+        "geth init --datadir {}  node/genesis.json",
+        crate::session::quote_arg(datadir)
+    ));
     assert!(command.is_ok(), "Failed to create genesis file");
 
     let sftp = session.sftp().expect("failed to get sftp");
@@ -99,6 +282,7 @@ pub fn install_command<'a>(
         ext_ip,
         unlock_wallet_address,
         ws_address_ip,
+        datadir,
     );
     let command: Result<(), Error> = chanel.exec(&start_command);
     assert!(command.is_ok(), "Failed to start geth");
@@ -0,0 +1,185 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::Command;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// The fields of a kubernetes deployment, pulled out of the enum variant.
+struct KubernetesSpec {
+    image: String,
+    container_port: u16,
+    replicas: u32,
+    namespace: String,
+    local_kubeconfig: bool,
+}
+
+fn kubernetes_spec(deployment: &DeploymentConfig) -> RumiResult<KubernetesSpec> {
+    match &deployment.deployment_type {
+        DeploymentType::Kubernetes {
+            image,
+            container_port,
+            replicas,
+            namespace,
+            local_kubeconfig,
+        } => Ok(KubernetesSpec {
+            image: image.clone(),
+            container_port: *container_port,
+            replicas: *replicas,
+            namespace: namespace.clone(),
+            local_kubeconfig: *local_kubeconfig,
+        }),
+        other => Err(RumiError::Config(format!(
+            "deployment '{}' is a {}, not a kubernetes app",
+            deployment.name,
+            other.kind()
+        ))),
+    }
+}
+
+/// Render the Deployment and Service manifests for a kubernetes deployment.
+/// Everything is derived from the rumi.json definition so the config file
+/// stays the single source of truth.
+pub fn render_manifests(deployment: &DeploymentConfig) -> RumiResult<String> {
+    let spec = kubernetes_spec(deployment)?;
+    let name = &deployment.name;
+    Ok(format!(
+        r#"apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: {name}
+  namespace: {namespace}
+  labels:
+    app: {name}
+    managed-by: rumi
+spec:
+  replicas: {replicas}
+  selector:
+    matchLabels:
+      app: {name}
+  template:
+    metadata:
+      labels:
+        app: {name}
+    spec:
+      containers:
+        - name: {name}
+          image: {image}
+          ports:
+            - containerPort: {port}
+---
+apiVersion: v1
+kind: Service
+metadata:
+  name: {name}
+  namespace: {namespace}
+  labels:
+    app: {name}
+    managed-by: rumi
+spec:
+  selector:
+    app: {name}
+  ports:
+    - port: 80
+      targetPort: {port}
+"#,
+        name = name,
+        namespace = spec.namespace,
+        replicas = spec.replicas,
+        image = spec.image,
+        port = spec.container_port,
+    ))
+}
+
+/// Run a kubectl command either against the local kubeconfig or over ssh on
+/// the control node, printing whatever kubectl prints.
+fn run_kubectl(
+    config: &RumiConfig,
+    deployment: &DeploymentConfig,
+    local: bool,
+    command: &str,
+) -> RumiResult<()> {
+    if local {
+        let output = Command::new("sh").arg("-c").arg(command).output()?;
+        print!("{}", String::from_utf8_lossy(&output.stdout));
+        if !output.status.success() {
+            return Err(RumiError::CommandFailed(format!(
+                "'{}' failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        return Ok(());
+    }
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let output = session.execute_checked(command)?;
+    print!("{}", output.stdout);
+    Ok(())
+}
+
+/// Deploy: render the manifests and `kubectl apply` them, then wait for the
+/// rollout. The manifest goes through a file so apply errors point at lines.
+pub fn deploy_command(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let spec = kubernetes_spec(deployment)?;
+    let manifest = render_manifests(deployment)?;
+    let manifest_path = format!("/tmp/rumi-k8s-{}.yaml", deployment.name);
+    if spec.local_kubeconfig {
+        std::fs::write(&manifest_path, &manifest)?;
+    } else {
+        let ssh = config.ssh_for_deployment(deployment)?;
+        let session = RumiSession::connect(ssh)?;
+        let sftp = session.sftp()?;
+        let mut file = sftp.create(Path::new(&manifest_path))?;
+        file.write_all(manifest.as_bytes())?;
+    }
+    run_kubectl(
+        config,
+        deployment,
+        spec.local_kubeconfig,
+        &format!("kubectl apply -f {} && rm {}", manifest_path, manifest_path),
+    )?;
+    run_kubectl(
+        config,
+        deployment,
+        spec.local_kubeconfig,
+        &format!(
+            "kubectl -n {} rollout status deployment/{}",
+            spec.namespace, deployment.name
+        ),
+    )?;
+    println!("deployed {} to namespace {}", deployment.name, spec.namespace);
+    Ok(())
+}
+
+/// Show the rollout status and the pods of a kubernetes deployment.
+pub fn status_command(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let spec = kubernetes_spec(deployment)?;
+    run_kubectl(
+        config,
+        deployment,
+        spec.local_kubeconfig,
+        &format!(
+            "kubectl -n {ns} rollout status deployment/{name} --timeout=1s; kubectl -n {ns} get pods -l app={name}",
+            ns = spec.namespace,
+            name = deployment.name
+        ),
+    )
+}
+
+/// Roll back to the previous revision with `kubectl rollout undo` and wait
+/// for the rollout to settle.
+pub fn rollback_command(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let spec = kubernetes_spec(deployment)?;
+    run_kubectl(
+        config,
+        deployment,
+        spec.local_kubeconfig,
+        &format!(
+            "kubectl -n {ns} rollout undo deployment/{name} && kubectl -n {ns} rollout status deployment/{name}",
+            ns = spec.namespace,
+            name = deployment.name
+        ),
+    )
+}
@@ -1,3 +1,12 @@
+pub mod build_cache;
+pub mod database;
+pub mod docker;
 pub mod ethereum;
+pub mod kubernetes;
+pub mod observability;
+pub mod php;
+pub mod python;
+pub mod redis;
 pub mod servers;
-pub mod websites;
\ No newline at end of file
+pub mod users;
+pub mod websites;
@@ -0,0 +1,131 @@
+use std::io::Write;
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::utils::{get_observability_nginx_config_file, get_prometheus_config_file};
+use crate::NGINX_WEB_CONFIG_PATH;
+
+/// Where the prometheus/grafana compose stack lives on the host.
+pub const OBSERVABILITY_STACK_PATH: &str = "/opt/rumi-observability";
+
+const DOCKER_COMPOSE_PROMETHEUS: &str = r#"
+  prometheus:
+    image: prom/prometheus:latest
+    restart: unless-stopped
+    network_mode: host
+    volumes:
+      - /opt/rumi-observability/prometheus.yml:/etc/prometheus/prometheus.yml
+      - prometheus-data:/prometheus
+"#;
+
+const DOCKER_COMPOSE_GRAFANA: &str = r#"
+  grafana:
+    image: grafana/grafana:latest
+    restart: unless-stopped
+    network_mode: host
+    volumes:
+      - grafana-data:/var/lib/grafana
+"#;
+
+/// The scrape targets prometheus should watch: node_exporter on every host
+/// rumi knows about, so existing deployments are registered automatically.
+fn scrape_targets(config: &RumiConfig) -> Vec<String> {
+    let mut targets = vec!["localhost:9100".to_string()];
+    for deployment in &config.deployments {
+        if let Ok(ssh) = config.ssh_for_deployment(deployment) {
+            let target = format!("{}:9100", ssh.host);
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+/// Install the observability stack on the deployment's host: node_exporter
+/// always, prometheus and grafana in docker when enabled, and an nginx vhost
+/// with basic auth in front of the dashboards.
+pub fn install_command(
+    session: &RumiSession,
+    config: &RumiConfig,
+    deployment: &DeploymentConfig,
+) -> RumiResult<()> {
+    let (with_prometheus, with_grafana) = match &deployment.deployment_type {
+        DeploymentType::Observability {
+            with_prometheus,
+            with_grafana,
+        } => (*with_prometheus, *with_grafana),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not an observability stack",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+
+    session.execute_checked("sudo apt-get update")?;
+    session.execute_checked("sudo apt-get -y install prometheus-node-exporter nginx apache2-utils")?;
+    session.execute_checked("sudo systemctl enable --now prometheus-node-exporter")?;
+
+    // basic auth in front of the dashboards, password printed once at the end
+    let dashboard_password = Uuid::new_v4().to_string();
+    session.execute_checked(&format!(
+        "sudo htpasswd -cb /etc/nginx/.rumi_observability_htpasswd rumi {}",
+        dashboard_password
+    ))?;
+
+    if with_prometheus || with_grafana {
+        session.execute_checked("sudo apt-get -y install docker.io docker-compose-v2")?;
+        session.execute_checked(&format!(
+            "sudo mkdir -p {} && sudo chown $(whoami) {}",
+            OBSERVABILITY_STACK_PATH, OBSERVABILITY_STACK_PATH
+        ))?;
+
+        let sftp = session.sftp()?;
+        let prometheus_config = get_prometheus_config_file(&scrape_targets(config));
+        let mut file = sftp.create(Path::new(&format!(
+            "{}/prometheus.yml",
+            OBSERVABILITY_STACK_PATH
+        )))?;
+        file.write_all(prometheus_config.as_bytes())?;
+
+        let mut compose = String::from("services:");
+        if with_prometheus {
+            compose.push_str(DOCKER_COMPOSE_PROMETHEUS);
+        }
+        if with_grafana {
+            compose.push_str(DOCKER_COMPOSE_GRAFANA);
+        }
+        compose.push_str("\nvolumes:\n  prometheus-data:\n  grafana-data:\n");
+        let mut file = sftp.create(Path::new(&format!(
+            "{}/docker-compose.yml",
+            OBSERVABILITY_STACK_PATH
+        )))?;
+        file.write_all(compose.as_bytes())?;
+
+        session.execute_checked(&format!(
+            "cd {} && sudo docker compose up -d",
+            OBSERVABILITY_STACK_PATH
+        ))?;
+    }
+
+    let nginx_config =
+        get_observability_nginx_config_file(&deployment.domain, with_prometheus, with_grafana);
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, deployment.domain);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&config_file_path))?;
+    file.write_all(nginx_config.as_bytes())?;
+    session.execute_checked(&format!(
+        "sudo ln -sf {} /etc/nginx/sites-enabled/ && sudo nginx -t && sudo systemctl reload nginx",
+        config_file_path
+    ))?;
+
+    println!("observability stack installed on {}", session.host());
+    println!("dashboard user: rumi, password: {}", dashboard_password);
+    Ok(())
+}
@@ -0,0 +1,96 @@
+use std::io::Write;
+use std::path::Path;
+
+use uuid::Uuid;
+
+use crate::config::{DeploymentConfig, DeploymentType};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::utils::{get_php_nginx_config_file, upload_folder};
+use crate::{NGINX_WEB_CONFIG_PATH, SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH, WEB_FOLDER};
+
+/// Deploy a php app or a fresh WordPress: php-fpm behind nginx with the
+/// fastcgi block, served over the certbot certificate like websites are.
+/// A database next to it comes from the database deployment type.
+pub fn install_command(
+    session: &RumiSession,
+    deployment: &DeploymentConfig,
+    ssl_email: &str,
+) -> RumiResult<()> {
+    let (app_path, wordpress) = match &deployment.deployment_type {
+        DeploymentType::Php {
+            app_path,
+            wordpress,
+        } => (app_path.clone(), *wordpress),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a php app",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    if !wordpress && app_path.is_none() {
+        return Err(RumiError::Config(format!(
+            "deployment '{}' needs either app_path or wordpress = true",
+            deployment.name
+        )));
+    }
+    let domain = &deployment.domain;
+
+    session.execute_checked("sudo apt-get update")?;
+    session.execute_checked(
+        "sudo apt-get -y install nginx certbot php-fpm php-mysql php-curl php-xml php-mbstring",
+    )?;
+    session.execute_checked(&format!(
+        "sudo certbot certonly -y --standalone -d {} -d www.{} --agree-tos --email {}",
+        domain, domain, ssl_email
+    ))?;
+
+    let web_root = format!("{}/{}_{}", WEB_FOLDER, domain, Uuid::new_v4());
+    if wordpress {
+        session.execute_checked(&format!(
+            "sudo mkdir -p {root} && sudo sh -c 'curl -sL https://wordpress.org/latest.tar.gz | tar xz -C {root} --strip-components=1'",
+            root = web_root
+        ))?;
+    } else {
+        session.execute_checked(&format!(
+            "sudo mkdir -p {} && sudo chown $(whoami) {}",
+            web_root, web_root
+        ))?;
+        let sftp = session.sftp()?;
+        upload_folder(
+            &sftp,
+            Path::new(app_path.as_deref().unwrap()),
+            &web_root,
+        )
+        .map_err(|e| RumiError::CommandFailed(format!("failed to upload app: {}", e)))?;
+    }
+    session.execute_checked(&format!("sudo chown -R www-data:www-data {}", web_root))?;
+
+    let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
+    let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
+    let nginx_config =
+        get_php_nginx_config_file(domain, &certificate_path, &certificate_key_path, &web_root);
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
+    let staging_path = format!("/tmp/rumi-nginx-{}", domain);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(nginx_config.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo mv {} {} && sudo ln -sf {} /etc/nginx/sites-enabled/ && sudo nginx -t && sudo systemctl reload nginx",
+        staging_path, config_file_path, config_file_path
+    ))?;
+    crate::firewall::allow_ports(session, &["80/tcp", "443/tcp"])?;
+
+    if wordpress {
+        println!(
+            "wordpress deployed to https://{}/ — finish the setup in the browser (a database from 'rumi2 database install' works as-is)",
+            domain
+        );
+    } else {
+        println!("php app deployed to https://{}/", domain);
+    }
+    Ok(())
+}
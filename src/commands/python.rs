@@ -0,0 +1,111 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{DeploymentConfig, DeploymentType};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::utils::{get_python_systemd_unit, get_servers_nginx_config_file, upload_folder};
+use crate::NGINX_WEB_CONFIG_PATH;
+
+/// Where python apps live on the remote host.
+pub const PYTHON_APP_ROOT: &str = "/srv";
+
+/// Deploy a python app: upload the source, build a venv with the
+/// requirements, run it under gunicorn/uvicorn as a systemd service and put
+/// nginx in front of it.
+pub fn install_command(
+    session: &RumiSession,
+    deployment: &DeploymentConfig,
+) -> RumiResult<()> {
+    let (app_path, entry, port, asgi) = match &deployment.deployment_type {
+        DeploymentType::Python {
+            app_path,
+            entry,
+            port,
+            asgi,
+        } => (app_path.clone(), entry.clone(), *port, *asgi),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a python app",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+
+    session.execute_checked("sudo apt-get update")?;
+    session.execute_checked("sudo apt-get -y install python3-venv python3-pip nginx")?;
+
+    let app_dir = format!("{}/{}", PYTHON_APP_ROOT, deployment.name);
+    session.execute_checked(&format!(
+        "sudo mkdir -p {} && sudo chown $(whoami) {}",
+        app_dir, app_dir
+    ))?;
+    let sftp = session.sftp()?;
+    let source_dir = format!("{}/app", app_dir);
+    upload_folder(&sftp, Path::new(&app_path), &source_dir)
+        .map_err(|e| RumiError::CommandFailed(format!("failed to upload app: {}", e)))?;
+
+    // the venv and pip cache survive across releases, so reinstalling only
+    // downloads what requirements.txt actually changed
+    let pip_cache = crate::commands::build_cache::ensure(session, &deployment.name, "pip")?;
+    let venv_dir = format!("{}/venv", app_dir);
+    session.execute_checked(&format!(
+        "[ -d {} ] || python3 -m venv {}",
+        venv_dir, venv_dir
+    ))?;
+    session.execute_checked(&format!(
+        "PIP_CACHE_DIR={} {}/bin/pip install -r {}/requirements.txt",
+        pip_cache, venv_dir, source_dir
+    ))?;
+    let server_package = if asgi { "uvicorn" } else { "gunicorn" };
+    session.execute_checked(&format!(
+        "PIP_CACHE_DIR={} {}/bin/pip install {}",
+        pip_cache, venv_dir, server_package
+    ))?;
+
+    let exec_start = if asgi {
+        format!(
+            "{}/bin/uvicorn {} --host 127.0.0.1 --port {}",
+            venv_dir, entry, port
+        )
+    } else {
+        format!(
+            "{}/bin/gunicorn {} --bind 127.0.0.1:{}",
+            venv_dir, entry, port
+        )
+    };
+    let unit = get_python_systemd_unit(&deployment.name, &source_dir, &exec_start);
+    let staging_path = format!("/tmp/rumi-{}.service", deployment.name);
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(unit.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo mv {} /etc/systemd/system/{}.service && sudo systemctl daemon-reload && sudo systemctl enable --now {}",
+        staging_path, deployment.name, deployment.name
+    ))?;
+
+    let nginx_config =
+        get_servers_nginx_config_file(
+            &deployment.domain,
+            port,
+            &Default::default(),
+            &deployment.nginx_extra_block()?,
+        );
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, deployment.domain);
+    let staging_path = format!("/tmp/rumi-nginx-{}", deployment.domain);
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(nginx_config.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo mv {} {} && sudo ln -sf {} /etc/nginx/sites-enabled/ && sudo nginx -t && sudo systemctl reload nginx",
+        staging_path, config_file_path, config_file_path
+    ))?;
+    crate::firewall::allow_ports(session, &["80/tcp", "443/tcp"])?;
+
+    println!(
+        "python app '{}' running as {}.service behind nginx on {}",
+        deployment.name, deployment.name, deployment.domain
+    );
+    Ok(())
+}
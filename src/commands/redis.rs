@@ -0,0 +1,77 @@
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::secrets;
+use crate::session::RumiSession;
+
+/// Install redis for a redis deployment: bind to localhost (or the
+/// configured private interface), set a generated requirepass kept in the
+/// secrets store, run it as a systemd service and optionally open the port.
+pub fn install_command(
+    session: &RumiSession,
+    config: &mut RumiConfig,
+    deployment: &DeploymentConfig,
+) -> RumiResult<()> {
+    let (bind_address, open_firewall) = match &deployment.deployment_type {
+        DeploymentType::Redis {
+            bind_address,
+            open_firewall,
+        } => (bind_address.clone(), *open_firewall),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a redis cache",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+
+    let password = secrets::generate_password();
+    // one script over one channel instead of a round trip per step
+    let mut batch = crate::session::CommandBatch::new()
+        .step("update apt", "sudo apt-get update")
+        .step("install redis", "sudo apt-get -y install redis-server");
+    if let Some(bind_address) = &bind_address {
+        batch = batch.step(
+            "bind private interface",
+            &format!(
+                "sudo sed -i 's/^bind .*/bind 127.0.0.1 {}/' /etc/redis/redis.conf",
+                bind_address
+            ),
+        );
+    }
+    batch
+        .step(
+            "set requirepass",
+            &format!(
+                "sudo sh -c 'echo \"requirepass {}\" >> /etc/redis/redis.conf'",
+                password
+            ),
+        )
+        .step(
+            "enable service",
+            "sudo systemctl enable redis-server && sudo systemctl restart redis-server",
+        )
+        .run(session)?;
+
+    if open_firewall {
+        if bind_address.is_none() {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' sets open_firewall without a bind_address, redis would stay unreachable",
+                deployment.name
+            )));
+        }
+        crate::firewall::allow_ports(session, &["6379/tcp"])?;
+    }
+
+    secrets::store(
+        config,
+        &format!("redis/{}/password", deployment.name),
+        password,
+    );
+    println!(
+        "redis installed on {}; password stored under secrets key redis/{}/password",
+        session.host(),
+        deployment.name
+    );
+    Ok(())
+}
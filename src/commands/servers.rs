@@ -13,32 +13,29 @@ pub fn install_command<'a>(
     app_name: &'a str,
     bin_path: &'a str,
     port: &'a i32,
+    ssl_email: &'a str,
 ) {
     ufw::install(session);
     nginx::install(session);
     certbot::install(session);
     ufw::allow_nginx_http(session);
-    certbot::get_ssl_certificate_for_domain(session, domain, "pondonda@gmail.com");
+    certbot::get_ssl_certificate_for_domain(session, domain, ssl_email);
 
     let app_release_path = format!("{}/{}", bin_path, app_name);
     let id = Uuid::new_v4();
-    let app_name_full = format!("{}_{}", id.to_string(), app_name);
+    let app_name_full = format!("{}_{}", id, app_name);
+    // uploaded to a user-writable staging path, then installed root-owned;
+    // /usr/local/bin never gets opened up to the deploy user
+    let staging_app_path = format!("/tmp/{}", app_name_full);
     let remote_app_release_path = format!("/usr/local/bin/{}", app_name_full);
 
-    nginx::enable_write_to_folders(session);
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo chmod 777 /usr/local/bin/");
-    assert!(command.is_ok(), "Failed to set permissions");
-    close_channel(&mut chanel);
-
     let mut local_file = File::open(app_release_path).expect("Failed to open app release file");
     let file_size = local_file
         .metadata()
         .expect("failed getting file meta data")
         .len();
     let mut remote_file = session
-        .scp_send(Path::new(&remote_app_release_path), 0o755, file_size, None)
+        .scp_send(Path::new(&staging_app_path), 0o755, file_size, None)
         .expect("Failed to create remote file");
     let mut buffer = Vec::new();
     local_file
@@ -52,9 +49,13 @@ pub fn install_command<'a>(
     remote_file.wait_close().expect("dsdsd");
 
     let mut chanel = new_channel(session);
-    let chmod_command = format!("sudo chmod +x {}", remote_app_release_path);
-    let command = chanel.exec(&chmod_command);
-    assert!(command.is_ok(), "Failed to set permissions");
+    let install_binary_command = format!(
+        "sudo install -o root -g root -m 755 {0} {1} && rm -f {0}",
+        crate::session::quote_arg(&staging_app_path),
+        crate::session::quote_arg(&remote_app_release_path)
+    );
+    let command = chanel.exec(&install_binary_command);
+    assert!(command.is_ok(), "Failed to install the binary");
     close_channel(&mut chanel);
 
     let mut chanel = new_channel(session);
@@ -66,15 +67,487 @@ pub fn install_command<'a>(
 
     ufw::allow_port(session, port);
     let sftp = session.sftp().expect("failed to get sftp");
-    let nginx_config = get_servers_nginx_config_file(&3000, domain, port);
+    let nginx_config = get_servers_nginx_config_file(domain, *port as u16, &Default::default(), "");
 
+    // staged under /tmp and moved with sudo, so sites-available stays root-owned
+    let staging_config_path = format!("/tmp/rumi-nginx-{}", domain);
     let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
-    let path = Path::new(&config_file_path);
     let mut file = sftp
-        .create(path)
+        .create(Path::new(&staging_config_path))
         .expect("failed to create nginx config file");
     file.write_all(nginx_config.as_bytes())
         .expect("failed to write nginx config file");
+    let mut chanel = new_channel(session);
+    let install_config_command = format!(
+        "sudo mv {0} {1} && sudo chown root:root {1}",
+        crate::session::quote_arg(&staging_config_path),
+        crate::session::quote_arg(&config_file_path)
+    );
+    let command = chanel.exec(&install_config_command);
+    assert!(command.is_ok(), "Failed to install the nginx config");
+    close_channel(&mut chanel);
     nginx::make_site_enabled(session, &config_file_path);
     nginx::restart(session)
 }
+
+/// Pick the binary to upload for a host: `uname -m` on the remote, looked up
+/// in the deployment's per-arch artifacts (exact machine name first, then a
+/// target triple starting with it). Hosts fall back to binary_path only when
+/// the map is empty; with a map, deploying a binary for the wrong
+/// architecture is refused instead of crash-looping the service. Entries
+/// that are urls are fetched into the local artifact cache first.
+pub fn resolve_artifact(
+    executor: &dyn crate::session::CommandExecutor,
+    binary_path: &str,
+    artifacts: &std::collections::HashMap<String, String>,
+) -> crate::error::RumiResult<String> {
+    if artifacts.is_empty() {
+        return crate::artifact::materialize(binary_path);
+    }
+    let machine = executor.execute_checked("uname -m")?.stdout.trim().to_string();
+    if let Some(path) = artifacts.get(&machine) {
+        return crate::artifact::materialize(path);
+    }
+    let triple_prefix = format!("{}-", machine);
+    if let Some(path) = artifacts
+        .iter()
+        .find(|(triple, _)| triple.starts_with(&triple_prefix))
+        .map(|(_, path)| path.clone())
+    {
+        return crate::artifact::materialize(&path);
+    }
+    let mut declared: Vec<&str> = artifacts.keys().map(String::as_str).collect();
+    declared.sort_unstable();
+    Err(crate::error::RumiError::Config(format!(
+        "no artifact for {} hosts, the config declares: {}",
+        machine,
+        declared.join(", ")
+    )))
+}
+
+/// Where a server deployment's environment file lives on the host.
+pub fn env_file_path(name: &str) -> String {
+    format!("/etc/{}.env", name)
+}
+
+/// The content of /etc/<name>.env: the configured variables in sorted
+/// order, then the lines of the local env_file verbatim. Values go in as
+/// written — systemd's env file syntax, not shell.
+pub fn render_env_file(
+    env: &std::collections::HashMap<String, String>,
+    env_file: Option<&str>,
+) -> crate::error::RumiResult<String> {
+    let mut keys: Vec<&String> = env.keys().collect();
+    keys.sort();
+    let mut content = String::new();
+    for key in keys {
+        content.push_str(&format!("{}={}\n", key, env[key]));
+    }
+    if let Some(path) = env_file {
+        let extra = std::fs::read_to_string(path).map_err(|e| {
+            crate::error::RumiError::Config(format!("could not read env_file {}: {}", path, e))
+        })?;
+        content.push_str(extra.trim_end());
+        content.push('\n');
+    }
+    Ok(content)
+}
+
+/// Where prior binary versions of a server deployment are kept on the
+/// host, one timestamped file per deploy, so `server rollback` can switch
+/// back without another upload.
+pub fn releases_path(name: &str) -> String {
+    format!("/usr/local/lib/{}/releases", name)
+}
+
+/// Deploy (or redeploy) a server binary: upload the artifact matching the
+/// remote architecture, swap it into place atomically, refresh the env
+/// file when one is configured and restart the deployment's systemd unit
+/// when one exists.
+pub fn deploy_command(
+    executor: &dyn crate::session::CommandExecutor,
+    deployment: &crate::config::DeploymentConfig,
+) -> crate::error::RumiResult<()> {
+    use crate::config::DeploymentType;
+    use crate::error::RumiError;
+
+    let (binary_path, port, proxy, artifacts, env, env_file) = match &deployment.deployment_type {
+        DeploymentType::Server {
+            binary_path,
+            port,
+            proxy,
+            artifacts,
+            env,
+            env_file,
+        } => (binary_path, *port, proxy, artifacts, env, env_file),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a server",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    let artifact = resolve_artifact(executor, binary_path, artifacts)?;
+    let staging_path = format!("/tmp/rumi-bin-{}", deployment.name);
+    executor.upload_file(Path::new(&artifact), &staging_path)?;
+    let remote_path = format!("{}/{}", crate::SERVER_BIN_PATH, deployment.name);
+    // each deploy keeps its binary in the release dir and repoints the
+    // /usr/local/bin symlink, so rolling back is a symlink flip away
+    let version = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+    let release_path = format!("{}/{}", releases_path(&deployment.name), version);
+    let staging_path = crate::session::quote_arg(&staging_path);
+    executor.execute_checked(&format!(
+        "sudo mkdir -p {0} && sudo chmod 755 {1} && sudo mv {1} {2} && sudo ln -sfn {2} {3}",
+        crate::session::quote_arg(&releases_path(&deployment.name)),
+        staging_path,
+        crate::session::quote_arg(&release_path),
+        crate::session::quote_arg(&remote_path)
+    ))?;
+    // refresh the env file before the restart picks it up; chmod 600 keeps
+    // secrets away from other users on the host
+    if !env.is_empty() || env_file.is_some() {
+        let content = render_env_file(env, env_file.as_deref())?;
+        let env_staging = format!("/tmp/rumi-env-{}", deployment.name);
+        executor.create_remote_file(&env_staging, content.as_bytes())?;
+        let remote_env = env_file_path(&deployment.name);
+        executor.execute_checked(&format!(
+            "sudo mv {0} {1} && sudo chown root:root {1} && sudo chmod 600 {1}",
+            crate::session::quote_arg(&env_staging),
+            crate::session::quote_arg(&remote_env)
+        ))?;
+    }
+    // regenerate the nginx config from the deployment's proxy settings, so a
+    // redeploy is also how changed proxy behavior reaches the host
+    let nginx_config = get_servers_nginx_config_file(
+        &deployment.domain,
+        port,
+        &proxy.clone().unwrap_or_default(),
+        &deployment.nginx_extra_block()?,
+    );
+    let nginx_staging = format!("/tmp/rumi-nginx-{}", deployment.domain);
+    executor.create_remote_file(&nginx_staging, nginx_config.as_bytes())?;
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, deployment.domain);
+    let config_file_path = crate::session::quote_arg(&config_file_path);
+    executor.execute_checked(&format!(
+        "sudo mv {} {} && sudo ln -sf {} /etc/nginx/sites-enabled/ && sudo nginx -t && sudo systemctl reload nginx",
+        crate::session::quote_arg(&nginx_staging),
+        config_file_path,
+        config_file_path
+    ))?;
+    // journaled before the restart, so a binary that crash-loops straight
+    // away still shows up as the deploy that went out
+    let dist_hash = crate::blobstore::sha256_of(Path::new(&artifact))
+        .ok()
+        .map(|hash| format!("sha256:{}", &hash[..12]));
+    crate::state::record(
+        executor,
+        crate::state::StateEntry::new(&deployment.name, "server")
+            .version(Some(version.clone()))
+            .dist_hash(dist_hash)
+            .nginx_config(Some(format!(
+                "{}/{}",
+                NGINX_WEB_CONFIG_PATH, deployment.domain
+            ))),
+    )?;
+    let restart = executor.execute(
+        &crate::session::CommandBuilder::new("sudo systemctl try-restart")
+            .arg(&format!("{}.service", deployment.name))
+            .build(),
+    )?;
+    if restart.success() {
+        println!("{} deployed to {} and restarted", artifact, remote_path);
+    } else {
+        println!(
+            "{} deployed to {}; no {}.service unit to restart, start it yourself",
+            artifact, remote_path, deployment.name
+        );
+    }
+    Ok(())
+}
+
+/// Take a server deployment off one host: stop and disable its unit, then
+/// remove the unit file, binary, kept releases, env file, nginx config and
+/// the firewall rule for its port. Only the nginx reload is checked — a
+/// half-installed server should still come off cleanly, so the removals
+/// are best effort.
+pub fn undeploy_command(
+    executor: &dyn crate::session::CommandExecutor,
+    deployment: &crate::config::DeploymentConfig,
+) -> crate::error::RumiResult<()> {
+    use crate::config::DeploymentType;
+    use crate::error::RumiError;
+
+    let port = match &deployment.deployment_type {
+        DeploymentType::Server { port, .. } => *port,
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not a server",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    let name = &deployment.name;
+    executor.execute(&format!(
+        "sudo systemctl disable --now {}.service",
+        crate::session::quote_arg(name)
+    ))?;
+    executor.execute(&format!(
+        "sudo rm -f /etc/systemd/system/{0}.service && sudo systemctl daemon-reload",
+        crate::session::quote_arg(name)
+    ))?;
+    executor.execute(&format!(
+        "sudo rm -f {} {} && sudo rm -rf {}",
+        crate::session::quote_arg(&format!("{}/{}", crate::SERVER_BIN_PATH, name)),
+        crate::session::quote_arg(&env_file_path(name)),
+        crate::session::quote_arg(&format!("/usr/local/lib/{}", name))
+    ))?;
+    executor.execute(&format!(
+        "sudo rm -f /etc/nginx/sites-enabled/{0} {1}/{0}",
+        crate::session::quote_arg(&deployment.domain),
+        NGINX_WEB_CONFIG_PATH
+    ))?;
+    executor.execute_checked("sudo systemctl reload nginx")?;
+    // the rule may predate ufw numbering changes or never have existed
+    executor.execute(&format!("sudo ufw delete allow {}", port))?;
+    println!("{} undeployed from {}", name, executor.host());
+    Ok(())
+}
+
+/// Point the binary symlink back at a previously deployed version and
+/// restart the unit. Versions are the release timestamps under the
+/// deployment's releases dir; `rumi2 history` lists them.
+pub fn rollback_command(
+    executor: &dyn crate::session::CommandExecutor,
+    deployment: &crate::config::DeploymentConfig,
+    version: &str,
+) -> crate::error::RumiResult<()> {
+    let name = &deployment.name;
+    let release_path = format!("{}/{}", releases_path(name), version);
+    let check = executor.execute(&format!(
+        "test -e {}",
+        crate::session::quote_arg(&release_path)
+    ))?;
+    if !check.success() {
+        let listing = executor.execute(&format!(
+            "ls -1 {}",
+            crate::session::quote_arg(&releases_path(name))
+        ))?;
+        let kept: Vec<&str> = listing.stdout.split_whitespace().collect();
+        return Err(crate::error::RumiError::Config(format!(
+            "no release {} of '{}' on {}, kept versions: {}",
+            version,
+            name,
+            executor.host(),
+            if kept.is_empty() {
+                "none".to_string()
+            } else {
+                kept.join(", ")
+            }
+        )));
+    }
+    executor.execute_checked(&format!(
+        "sudo ln -sfn {} {}",
+        crate::session::quote_arg(&release_path),
+        crate::session::quote_arg(&format!("{}/{}", crate::SERVER_BIN_PATH, name))
+    ))?;
+    crate::state::record(
+        executor,
+        crate::state::StateEntry::new(name, "server").version(Some(version.to_string())),
+    )?;
+    executor.execute_checked(&format!(
+        "sudo systemctl try-restart {}.service",
+        crate::session::quote_arg(name)
+    ))?;
+    println!("{} rolled back to {} on {}", name, version, executor.host());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{DeploymentConfig, DeploymentType};
+    use crate::session::MockExecutor;
+    use std::collections::HashMap;
+
+    fn server_deployment() -> DeploymentConfig {
+        DeploymentConfig {
+            name: "api".to_string(),
+            domain: "api.example.com".to_string(),
+            deployment_type: DeploymentType::Server {
+                binary_path: "target/release/api".to_string(),
+                port: 8080,
+                proxy: None,
+                artifacts: HashMap::new(),
+                env: HashMap::new(),
+                env_file: None,
+            },
+            ssh: None,
+            ssh_profile: None,
+            ssh_targets: Vec::new(),
+            repo: None,
+            project_path: None,
+            health_url: None,
+            expected_status: None,
+            health_check: None,
+            challenge: None,
+            ssl_email: None,
+            nginx_extra: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn resolve_artifact_falls_back_without_a_map() {
+        let executor = MockExecutor::new();
+        let artifact =
+            resolve_artifact(&executor, "target/release/api", &HashMap::new()).unwrap();
+        assert_eq!(artifact, "target/release/api");
+        // no uname round trip when there is nothing to choose between
+        assert!(executor.executed().is_empty());
+    }
+
+    #[test]
+    fn resolve_artifact_matches_the_remote_machine() {
+        let executor = MockExecutor::new().respond("uname -m", "x86_64\n", 0);
+        let mut artifacts = HashMap::new();
+        artifacts.insert("x86_64".to_string(), "bins/api-amd64".to_string());
+        artifacts.insert("aarch64".to_string(), "bins/api-arm64".to_string());
+        let artifact = resolve_artifact(&executor, "target/release/api", &artifacts).unwrap();
+        assert_eq!(artifact, "bins/api-amd64");
+    }
+
+    #[test]
+    fn resolve_artifact_accepts_target_triples() {
+        let executor = MockExecutor::new().respond("uname -m", "aarch64\n", 0);
+        let mut artifacts = HashMap::new();
+        artifacts.insert(
+            "aarch64-unknown-linux-gnu".to_string(),
+            "bins/api-arm64".to_string(),
+        );
+        let artifact = resolve_artifact(&executor, "target/release/api", &artifacts).unwrap();
+        assert_eq!(artifact, "bins/api-arm64");
+    }
+
+    #[test]
+    fn resolve_artifact_refuses_unknown_architectures() {
+        let executor = MockExecutor::new().respond("uname -m", "riscv64\n", 0);
+        let mut artifacts = HashMap::new();
+        artifacts.insert("x86_64".to_string(), "bins/api-amd64".to_string());
+        let error = resolve_artifact(&executor, "target/release/api", &artifacts).unwrap_err();
+        assert!(error.to_string().contains("riscv64"));
+    }
+
+    #[test]
+    fn deploy_stages_the_binary_and_regenerates_nginx() {
+        let executor = MockExecutor::new();
+        deploy_command(&executor, &server_deployment()).unwrap();
+        assert_eq!(
+            executor.uploads(),
+            vec![("target/release/api".to_string(), "/tmp/rumi-bin-api".to_string())]
+        );
+        let executed = executor.executed();
+        // the binary lands in the release dir and /usr/local/bin gets a symlink
+        assert!(executed.iter().any(|c| {
+            c.contains("sudo mv /tmp/rumi-bin-api /usr/local/lib/api/releases/")
+                && c.contains("sudo ln -sfn")
+                && c.contains("/usr/local/bin/api")
+        }));
+        assert!(executed.iter().any(|c| c.contains("sudo nginx -t")));
+        assert!(executed
+            .last()
+            .unwrap()
+            .contains("systemctl try-restart api.service"));
+        let written = executor.written();
+        assert_eq!(written[0].0, "/tmp/rumi-nginx-api.example.com");
+    }
+
+    #[test]
+    fn deploy_installs_the_env_file_with_tight_permissions() {
+        let mut deployment = server_deployment();
+        if let DeploymentType::Server { env, .. } = &mut deployment.deployment_type {
+            env.insert("DATABASE_URL".to_string(), "postgres://localhost/api".to_string());
+            env.insert("API_KEY".to_string(), "abc".to_string());
+        }
+        let executor = MockExecutor::new();
+        deploy_command(&executor, &deployment).unwrap();
+        let written = executor.written();
+        let (_, content) = written
+            .iter()
+            .find(|(path, _)| path == "/tmp/rumi-env-api")
+            .unwrap();
+        // sorted, one assignment per line
+        assert_eq!(
+            std::str::from_utf8(content).unwrap(),
+            "API_KEY=abc\nDATABASE_URL=postgres://localhost/api\n"
+        );
+        assert!(executor.executed().iter().any(|c| {
+            c.contains("sudo mv /tmp/rumi-env-api /etc/api.env")
+                && c.contains("sudo chmod 600 /etc/api.env")
+        }));
+    }
+
+    #[test]
+    fn deploy_without_env_leaves_no_env_file() {
+        let executor = MockExecutor::new();
+        deploy_command(&executor, &server_deployment()).unwrap();
+        assert!(!executor.written().iter().any(|(path, _)| path.contains("env")));
+    }
+
+    #[test]
+    fn undeploy_stops_the_unit_and_removes_everything_it_installed() {
+        let executor = MockExecutor::new();
+        undeploy_command(&executor, &server_deployment()).unwrap();
+        let executed = executor.executed();
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("sudo systemctl disable --now api.service")));
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("sudo rm -f /etc/systemd/system/api.service")));
+        assert!(executed.iter().any(|c| {
+            c.contains("sudo rm -f /usr/local/bin/api /etc/api.env")
+                && c.contains("sudo rm -rf /usr/local/lib/api")
+        }));
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("sudo rm -f /etc/nginx/sites-enabled/api.example.com")));
+        assert!(executed.iter().any(|c| c == "sudo ufw delete allow 8080"));
+    }
+
+    #[test]
+    fn rollback_flips_the_symlink_and_restarts() {
+        let executor = MockExecutor::new();
+        rollback_command(&executor, &server_deployment(), "20260101000000").unwrap();
+        let executed = executor.executed();
+        assert!(executed.iter().any(|c| c.contains(
+            "sudo ln -sfn /usr/local/lib/api/releases/20260101000000 /usr/local/bin/api"
+        )));
+        assert!(executed
+            .last()
+            .unwrap()
+            .contains("systemctl try-restart api.service"));
+    }
+
+    #[test]
+    fn rollback_refuses_versions_that_were_never_kept() {
+        let executor = MockExecutor::new()
+            .respond("test -e", "", 1)
+            .respond("ls -1", "20260101000000\n", 0);
+        let error =
+            rollback_command(&executor, &server_deployment(), "20251231000000").unwrap_err();
+        assert!(error.to_string().contains("20251231000000"));
+        // the message names what is actually on the host
+        assert!(error.to_string().contains("20260101000000"));
+    }
+
+    #[test]
+    fn deploy_refuses_non_server_deployments() {
+        let mut deployment = server_deployment();
+        deployment.deployment_type = DeploymentType::Website {
+            dist_path: "dist".to_string(),
+        };
+        let error = deploy_command(&MockExecutor::new(), &deployment).unwrap_err();
+        assert!(error.to_string().contains("not a server"));
+    }
+}
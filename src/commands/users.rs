@@ -0,0 +1,115 @@
+use std::io::Write;
+use std::path::Path;
+
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// The commands a deploy user is allowed to run with sudo: everything rumi
+/// itself invokes during installs, updates and log tailing, nothing more.
+const SUDOERS_COMMANDS: &[&str] = &[
+    "/usr/bin/apt-get",
+    "/usr/bin/systemctl",
+    "/usr/sbin/nginx",
+    "/usr/bin/certbot",
+    "/usr/sbin/ufw",
+    "/bin/mkdir",
+    "/bin/mv",
+    "/bin/ln",
+    "/bin/rm",
+    "/bin/cp",
+    "/bin/chown",
+    "/bin/chmod",
+    "/bin/sh",
+    "/usr/bin/tail",
+    "/usr/bin/journalctl",
+    "/usr/bin/stat",
+    "/usr/bin/tee",
+];
+
+fn sudoers_path(user: &str) -> String {
+    format!("/etc/sudoers.d/rumi-{}", user)
+}
+
+/// Create a dedicated non-root deploy user with a home, a shell and a
+/// sudoers entry limited to the commands rumi needs, so deployments can
+/// move off the root account.
+pub fn add_command(session: &RumiSession, user: &str) -> RumiResult<()> {
+    let exists = session
+        .execute_command(&format!("id -u {}", user))?
+        .success();
+    if exists {
+        println!("user {} already exists on {}", user, session.host());
+    } else {
+        session.execute_checked(&format!("sudo useradd -m -s /bin/bash {}", user))?;
+        println!("created user {} on {}", user, session.host());
+    }
+    session.execute_checked(&format!(
+        "sudo mkdir -p /home/{user}/.ssh && sudo touch /home/{user}/.ssh/authorized_keys && sudo chmod 700 /home/{user}/.ssh && sudo chmod 600 /home/{user}/.ssh/authorized_keys && sudo chown -R {user}:{user} /home/{user}/.ssh",
+        user = user
+    ))?;
+
+    let sudoers = format!(
+        "{} ALL=(ALL) NOPASSWD: {}\n",
+        user,
+        SUDOERS_COMMANDS.join(", ")
+    );
+    let staging_path = format!("/tmp/rumi-sudoers-{}", user);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(sudoers.as_bytes())?;
+    drop(file);
+    // visudo validates the staged file before it can break sudo for everyone
+    session.execute_checked(&format!("sudo visudo -cf {}", staging_path))?;
+    session.execute_checked(&format!(
+        "sudo install -m 440 -o root -g root {} {} && rm {}",
+        staging_path,
+        sudoers_path(user),
+        staging_path
+    ))?;
+    println!("sudoers entry installed at {}", sudoers_path(user));
+    Ok(())
+}
+
+/// Remove a deploy user, its home directory and its sudoers entry.
+pub fn remove_command(session: &RumiSession, user: &str) -> RumiResult<()> {
+    session.execute_checked(&format!("sudo rm -f {}", sudoers_path(user)))?;
+    session.execute_checked(&format!("sudo userdel -r {}", user))?;
+    println!("removed user {} from {}", user, session.host());
+    Ok(())
+}
+
+/// Append a public key to a user's authorized_keys, skipping it when the
+/// exact key is already there.
+pub fn authorize_key_command(
+    session: &RumiSession,
+    user: &str,
+    key_path: &Path,
+) -> RumiResult<()> {
+    let key = std::fs::read_to_string(key_path).map_err(|e| {
+        RumiError::Config(format!("could not read {}: {}", key_path.display(), e))
+    })?;
+    let key = key.trim();
+    if !key.starts_with("ssh-") && !key.starts_with("ecdsa-") {
+        return Err(RumiError::Config(format!(
+            "{} does not look like a public key",
+            key_path.display()
+        )));
+    }
+    let authorized_keys = format!("/home/{}/.ssh/authorized_keys", user);
+    let already_there = session
+        .execute_command(&format!(
+            "sudo grep -qF '{}' {}",
+            key, authorized_keys
+        ))?
+        .success();
+    if already_there {
+        println!("key already authorized for {}", user);
+        return Ok(());
+    }
+    session.execute_checked(&format!(
+        "echo '{}' | sudo tee -a {} > /dev/null",
+        key, authorized_keys
+    ))?;
+    println!("key from {} authorized for {}", key_path.display(), user);
+    Ok(())
+}
@@ -1,159 +1,601 @@
-use std::io::prelude::*;
 use std::path::Path;
-use uuid::Uuid;
-
-use ssh2::Session;
-use crate::{NGINX_WEB_CONFIG_PATH, WEB_FOLDER, SSL_CERTIFICATE_PATH, SSL_CERTIFICATE_KEY_PATH};
-use crate::utils::{get_web_nginx_config_file, upload_folder, new_channel, close_channel};
-
-
-pub fn install_command<'a>(session: &'a Session, domain: &'a str, dist_path: &'a str) {
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo apt update");
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    assert!(command.is_ok(), "Failed to update apt");
-    close_channel(&mut chanel);
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo apt-get -y install ufw");
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    assert!(command.is_ok(), "Failed to install ufw");
-    close_channel(&mut chanel);
-    
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo apt install -y nginx certbot");
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    assert!(command.is_ok(), "Failed to install nginx");
-    close_channel(&mut chanel);
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo ufw allow 'Nginx HTTP");
-    assert!(command.is_ok(), "Failed to allow Nginx HTTP");
-    close_channel(&mut chanel);
-
-    let cerbot_instruction = format!("sudo certbot certonly -y --standalone -d {} -d www.{} --agree-tos --email pondonda@gmail.com", domain, domain);
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec(&cerbot_instruction);
-    assert!(command.is_ok(), "Failed to create certificate");
-    close_channel(&mut chanel);
 
-    let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
-    let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
-
-    let random_uuid = Uuid::new_v4().to_string();
-    let web_folder_path = format!("{}/{}_{}", WEB_FOLDER, domain, random_uuid);
+use crate::error::RumiResult;
+use crate::session::{quote_arg, CommandExecutor};
+use crate::utils::{get_web_nginx_config_file, upload_folder};
+use crate::{NGINX_WEB_CONFIG_PATH, SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH, WEB_FOLDER};
+
+/// The `current` symlink nginx serves a domain from. Deploys upload a new
+/// timestamped release folder next to the old ones and switch this link,
+/// so going live is atomic and former releases stay for rollback until
+/// `hosting prune` removes them.
+pub fn site_current_path(domain: &str) -> String {
+    format!("{}/{}/current", WEB_FOLDER, domain)
+}
 
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo chmod 777 /var/www/ && sudo chmod 777 /etc/nginx/sites-available/ && sudo chmod 777 /etc/nginx/sites-enabled/");
-    assert!(command.is_ok(), "Failed to grant permissions");
-    close_channel(&mut chanel);
+/// A fresh timestamped release folder under the domain's releases dir.
+pub fn new_release_path(domain: &str) -> String {
+    format!(
+        "{}/{}/releases/{}",
+        WEB_FOLDER,
+        domain,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    )
+}
 
-    let sftp = session.sftp().expect("failed to get sftp");
+/// The per-release manifest of relative path → sha256, read by the next
+/// update to upload only what changed.
+pub const SYNC_MANIFEST_FILE: &str = ".rumi-manifest.json";
+
+/// Checksum every file under `root`, keyed by its release-relative path.
+fn local_manifest(
+    root: &Path,
+) -> RumiResult<std::collections::BTreeMap<String, String>> {
+    fn collect(
+        dir: &Path,
+        prefix: &str,
+        manifest: &mut std::collections::BTreeMap<String, String>,
+    ) -> RumiResult<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let rel = if prefix.is_empty() {
+                name
+            } else {
+                format!("{}/{}", prefix, name)
+            };
+            if entry.path().is_dir() {
+                collect(&entry.path(), &rel, manifest)?;
+            } else {
+                manifest.insert(rel, crate::blobstore::sha256_of(&entry.path())?);
+            }
+        }
+        Ok(())
+    }
+    let mut manifest = std::collections::BTreeMap::new();
+    collect(root, "", &mut manifest)?;
+    Ok(manifest)
+}
 
-    let dist_path = Path::new(&dist_path);
-    let upload = upload_folder(&sftp,  &dist_path, &web_folder_path);
-    assert!(upload.is_ok(), "Failed to upload folder");
+/// Leave the manifest in the release so the next update can delta against it.
+fn write_sync_manifest(
+    executor: &dyn CommandExecutor,
+    dist: &Path,
+    release_path: &str,
+) -> RumiResult<()> {
+    let manifest = local_manifest(dist)?;
+    executor.create_remote_file(
+        &format!("{}/{}", release_path, SYNC_MANIFEST_FILE),
+        serde_json::to_string(&manifest)?.as_bytes(),
+    )
+}
 
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo rm /etc/nginx/sites-enabled/default");
-    assert!(command.is_ok(), "Failed to remove default nginx config");
-    close_channel(&mut chanel);
+/// A user-writable folder the release is built in before promotion. It
+/// lives beside the releases dir rather than under /tmp, so the delta
+/// sync's hardlinks stay on one filesystem and promotion is a rename.
+fn stage_release(executor: &dyn CommandExecutor, domain: &str) -> RumiResult<String> {
+    let staging = format!(
+        "{}/{}/.staging-{}",
+        WEB_FOLDER,
+        domain,
+        uuid::Uuid::new_v4()
+    );
+    executor.execute_checked(&format!(
+        "sudo mkdir -p {0} {1} && sudo chown \"$(id -un)\" {1}",
+        quote_arg(&format!("{}/{}/releases", WEB_FOLDER, domain)),
+        quote_arg(&staging)
+    ))?;
+    Ok(staging)
+}
 
-    let nginx_config = get_web_nginx_config_file(domain, &certificate_path, &certificate_key_path, &web_folder_path);
+/// Move a staged release into the releases dir under the ownership nginx
+/// serves with; nothing below the web root stays writable by the deploy
+/// user.
+fn promote_release(
+    executor: &dyn CommandExecutor,
+    staging: &str,
+    release_path: &str,
+) -> RumiResult<()> {
+    executor.execute_checked(&format!(
+        "sudo mv {0} {1} && sudo chown -R www-data:www-data {1}",
+        quote_arg(staging),
+        quote_arg(release_path)
+    ))?;
+    Ok(())
+}
 
-    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
-    let path = Path::new(&config_file_path);
-    let mut file = sftp.create(path).expect("failed to create nginx config file");
-    file.write_all(nginx_config.as_bytes()).expect("failed to write nginx config file");
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec(format!("sudo ln -s {} /etc/nginx/sites-enabled/ && ls -a /etc/nginx/sites-enabled", config_file_path).as_str());
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    println!("ouptut : {:?}", s);
-    assert!(command.is_ok(), "Failed to allow port 80");
-    close_channel(&mut chanel);
-
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo ufw allow 80 && sudo ufw allow 443 && sudo systemctl restart nginx");
-    assert!(command.is_ok(), "Failed to restart nginx");
-    close_channel(&mut chanel);
+/// Build the new release out of the previous one, uploading only files whose
+/// checksum changed and dropping removed ones: the unchanged files are
+/// hardlinked across from `current`, so most updates move a handful of files
+/// instead of the whole dist. Returns false when there is nothing to sync
+/// against (first deploy, foreign layout, unreadable manifest), in which
+/// case the caller uploads everything.
+fn delta_sync(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    dist: &Path,
+    staging_path: &str,
+) -> RumiResult<bool> {
+    let current = site_current_path(domain);
+    let previous = executor.execute(&format!(
+        "cat {}",
+        quote_arg(&format!("{}/{}", current, SYNC_MANIFEST_FILE))
+    ))?;
+    if !previous.success() {
+        return Ok(false);
+    }
+    let previous: std::collections::BTreeMap<String, String> =
+        match serde_json::from_str(&previous.stdout) {
+            Ok(manifest) => manifest,
+            Err(_) => return Ok(false),
+        };
+    let local = local_manifest(dist)?;
+    // hardlink the previous release into the staging dir. The copy runs as
+    // root: the release files belong to www-data and protected_hardlinks
+    // forbids linking files you cannot write. Only the directories are
+    // handed back to the deploy user — the unlinks and uploads below need
+    // writable parents, while chowning the linked files would reach through
+    // the shared inodes into the live release. The metadata files are
+    // dropped because writing through their inodes would corrupt the
+    // previous release's records.
+    let seed = executor.execute(&format!(
+        "sudo cp -al {0}/. {1}/ && sudo rm -f {1}/{2} {1}/{3} && sudo find {1} -type d -exec chown \"$(id -un)\" {{}} +",
+        quote_arg(&current),
+        quote_arg(staging_path),
+        crate::release::RELEASE_METADATA_FILE,
+        SYNC_MANIFEST_FILE
+    ))?;
+    if !seed.success() {
+        return Ok(false);
+    }
+    let changed: Vec<&String> = local
+        .iter()
+        .filter(|(path, hash)| previous.get(*path) != Some(hash))
+        .map(|(path, _)| path)
+        .collect();
+    let removed: Vec<&String> = previous
+        .keys()
+        .filter(|path| !local.contains_key(*path))
+        .collect();
+    // unlink everything that changes or goes away, so the uploads create
+    // fresh inodes instead of writing through the hardlinks
+    if !changed.is_empty() || !removed.is_empty() {
+        let mut command = format!("cd {}", quote_arg(staging_path));
+        for path in changed.iter().chain(removed.iter()) {
+            command.push_str(&format!(" && rm -f {}", quote_arg(path)));
+        }
+        let dirs: std::collections::BTreeSet<&str> = changed
+            .iter()
+            .filter_map(|path| path.rsplit_once('/').map(|(dir, _)| dir))
+            .collect();
+        for dir in dirs {
+            command.push_str(&format!(" && mkdir -p {}", quote_arg(dir)));
+        }
+        executor.execute_checked(&command)?;
+    }
+    for path in &changed {
+        executor.upload_file(
+            &dist.join(path.as_str()),
+            &format!("{}/{}", staging_path, path),
+        )?;
+    }
+    println!(
+        "delta sync: {} uploaded, {} unchanged, {} removed",
+        changed.len(),
+        local.len() - changed.len(),
+        removed.len()
+    );
+    Ok(true)
 }
 
+/// Point `current` at a release: the new symlink is built aside and renamed
+/// over the old one, so nginx never sees a missing docroot in between.
+fn switch_current(executor: &dyn CommandExecutor, domain: &str, release_path: &str) -> RumiResult<()> {
+    let current = quote_arg(&site_current_path(domain));
+    executor.execute_checked(&format!(
+        "sudo mkdir -p {2} && sudo ln -sfn {0} {1}.tmp && sudo mv -T {1}.tmp {1}",
+        quote_arg(release_path),
+        current,
+        quote_arg(&format!("{}/{}", WEB_FOLDER, domain))
+    ))?;
+    Ok(())
+}
 
-pub fn update_command<'a>(session: &'a Session, domain: &'a str, dist_path: &'a str) {
+/// Write the domain's nginx server block and link it into sites-enabled.
+fn write_nginx_config(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    nginx_extras: &str,
+) -> RumiResult<()> {
     let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
     let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
+    let nginx_config = get_web_nginx_config_file(
+        domain,
+        &certificate_path,
+        &certificate_key_path,
+        &site_current_path(domain),
+        nginx_extras,
+    );
+    // staged under /tmp and moved with sudo: sites-available stays owned
+    // by root instead of being opened up to the deploy user
+    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
+    let staging = format!("/tmp/rumi-nginx-{}", domain);
+    executor.create_remote_file(&staging, nginx_config.as_bytes())?;
+    executor.execute_checked(&format!(
+        "sudo mv {0} {1} && sudo chown root:root {1}",
+        quote_arg(&staging),
+        quote_arg(&config_file_path)
+    ))?;
+    // best effort: the link is already there on every deploy after the first
+    executor.execute(&format!(
+        "sudo ln -s {} /etc/nginx/sites-enabled/",
+        quote_arg(&config_file_path)
+    ))?;
+    Ok(())
+}
 
-    let random_uuid = Uuid::new_v4().to_string();
-    let web_folder_path = format!("{}/{}_{}", WEB_FOLDER, domain, random_uuid);
-
-    let sftp = session.sftp().expect("failed to get sftp");
-
-    let dist_path = Path::new(&dist_path);
-    let upload = upload_folder(&sftp,  &dist_path, &web_folder_path);
-    assert!(upload.is_ok(), "Failed to upload folder");
-
-    let nginx_config = get_web_nginx_config_file(domain, &certificate_path, &certificate_key_path, &web_folder_path);
+pub fn install_command(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    dist_path: &str,
+    nginx_extras: &str,
+) -> RumiResult<()> {
+    executor.execute_checked("sudo apt update")?;
+    executor.execute_checked("sudo apt-get -y install ufw")?;
+    executor.execute_checked("sudo apt install -y nginx certbot")?;
+    executor.execute("sudo ufw allow 'Nginx HTTP'")?;
+
+    // the certificate was obtained by the caller (certs::obtain_certificate),
+    // which knows the challenge to use; here it only gets wired into nginx
+    let release_path = new_release_path(domain);
+    let staging = stage_release(executor, domain)?;
+    executor.upload_folder(Path::new(dist_path), &staging)?;
+    write_sync_manifest(executor, Path::new(dist_path), &staging)?;
+    promote_release(executor, &staging, &release_path)?;
+    switch_current(executor, domain, &release_path)?;
+
+    // may be gone already on a host that was installed before
+    executor.execute("sudo rm /etc/nginx/sites-enabled/default")?;
+    write_nginx_config(executor, domain, nginx_extras)?;
+
+    executor.execute_checked("sudo ufw allow 80 && sudo ufw allow 443 && sudo systemctl restart nginx")?;
+    Ok(())
+}
 
-    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
-    let path = Path::new(&config_file_path);
-    let mut file = sftp.create(path).expect("failed to create nginx config file");
-    file.write_all(nginx_config.as_bytes()).expect("failed to write nginx config file");
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec(format!("sudo ln -s {} /etc/nginx/sites-enabled/ && ls -a /etc/nginx/sites-enabled", config_file_path).as_str());
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    println!("ouptut : {:?}", s);
-    assert!(command.is_ok(), "Failed to allow port 80");
-    close_channel(&mut chanel);
+/// Upload a new release, switch `current` to it and reload nginx. Returns
+/// the release path for the caller's release records.
+pub fn update_command(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    dist_path: &str,
+    nginx_extras: &str,
+    full: bool,
+) -> RumiResult<String> {
+    let release_path = new_release_path(domain);
+    let staging = stage_release(executor, domain)?;
+    let dist = Path::new(dist_path);
+    if full || !delta_sync(executor, domain, dist, &staging)? {
+        executor.upload_folder(dist, &staging)?;
+    }
+    write_sync_manifest(executor, dist, &staging)?;
+    promote_release(executor, &staging, &release_path)?;
+    switch_current(executor, domain, &release_path)?;
+
+    write_nginx_config(executor, domain, nginx_extras)?;
 
     // reload nginx without downtime
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo systemctl reload nginx");
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    println!("ouptut : {:?}", s);
-    assert!(command.is_ok(), "Failed to reload nginx");
-    close_channel(&mut chanel);
+    executor.execute_checked("sudo systemctl reload nginx")?;
+    Ok(release_path)
 }
 
-
-pub fn rollback_command<'a>(session: &'a Session, domain: &'a str, version_name: &'a str) {
-    let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
-    let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
+pub fn rollback_command(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    version_name: &str,
+) -> RumiResult<()> {
     let web_folder_path = format!("{}/{}", WEB_FOLDER, version_name);
+    switch_current(executor, domain, &web_folder_path)?;
+    write_nginx_config(executor, domain, "")?;
+    executor.execute_checked("sudo systemctl reload nginx")?;
+    Ok(())
+}
 
-    let sftp = session.sftp().expect("failed to get sftp");
+/// Tear a website down: disable and delete its nginx configs, then archive
+/// the web folders aside — or delete them with purge. The certificate is
+/// only touched when asked, and removing the config entry is the caller's
+/// business, it owns the local config file.
+pub fn uninstall_command(
+    executor: &dyn CommandExecutor,
+    domain: &str,
+    purge: bool,
+    revoke_cert: bool,
+) -> RumiResult<()> {
+    // stop serving first so nothing references the folders while they move;
+    // both links may already be gone on a half-removed host
+    executor.execute(&format!(
+        "sudo rm -f /etc/nginx/sites-enabled/{}",
+        quote_arg(domain)
+    ))?;
+    executor.execute(&format!(
+        "sudo rm -f {}",
+        quote_arg(&format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain))
+    ))?;
+    executor.execute_checked("sudo systemctl reload nginx")?;
+    let site_root = format!("{}/{}", WEB_FOLDER, domain);
+    if purge {
+        // the timestamped layout and the old flat {domain}_{uuid} folders
+        executor.execute_checked(&format!("sudo rm -rf {}", quote_arg(&site_root)))?;
+        executor.execute(&format!("sudo sh -c 'rm -rf {}_*'", site_root))?;
+        println!("{} removed from {}", domain, executor.host());
+    } else {
+        let archive = format!(
+            "{}.uninstalled-{}",
+            site_root,
+            chrono::Utc::now().format("%Y%m%d%H%M%S")
+        );
+        executor.execute_checked(&format!(
+            "sudo mv {} {}",
+            quote_arg(&site_root),
+            quote_arg(&archive)
+        ))?;
+        println!(
+            "{} disabled on {}, files archived at {}",
+            domain,
+            executor.host(),
+            archive
+        );
+    }
+    if revoke_cert {
+        let revoked = executor.execute(&format!(
+            "sudo certbot revoke --cert-name {} --non-interactive --delete-after-revoke",
+            quote_arg(domain)
+        ))?;
+        if !revoked.success() {
+            println!(
+                "could not revoke the certificate for {}, it may already be gone: {}",
+                domain,
+                revoked.stderr.trim()
+            );
+        }
+    }
+    Ok(())
+}
 
-    let nginx_config = get_web_nginx_config_file(domain, &certificate_path, &certificate_key_path, &web_folder_path);
+/// Deploy to a shared host over sftp only: no sudo, packages, nginx or
+/// certbot. Uploads into a releases directory and switches a `current`
+/// symlink when the host lets us run commands, otherwise syncs straight
+/// into the remote path.
+pub fn sftp_deploy_command(
+    session: &crate::session::RumiSession,
+    deployment: &crate::config::DeploymentConfig,
+) -> crate::error::RumiResult<()> {
+    use crate::config::DeploymentType;
+    use crate::error::RumiError;
+
+    let (dist_path, remote_path) = match &deployment.deployment_type {
+        DeploymentType::SftpSite {
+            dist_path,
+            remote_path,
+        } => (dist_path.clone(), remote_path.clone()),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, not an sftp site",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    let sftp = session.sftp()?;
+    let release = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+    let release_path = format!("{}/releases/{}", remote_path, release);
+    sftp.mkdir(Path::new(&format!("{}/releases", remote_path)), 0o755).ok();
+
+    let upload = upload_folder(&sftp, Path::new(&dist_path), &release_path);
+    if upload.is_err() {
+        return Err(RumiError::CommandFailed(format!(
+            "failed to upload release to {}",
+            release_path
+        )));
+    }
+    // symlink switching needs shell access, which some shared hosts deny
+    let switched = session
+        .execute_command(&format!(
+            "ln -sfn {} {}",
+            quote_arg(&format!("releases/{}", release)),
+            quote_arg(&format!("{}/current", remote_path))
+        ))
+        .map(|output| output.success())
+        .unwrap_or(false);
+    if switched {
+        println!(
+            "release {} uploaded, {}/current now points at it",
+            release, remote_path
+        );
+    } else {
+        // no shell: sync the files straight into the remote path instead
+        let upload = upload_folder(&sftp, Path::new(&dist_path), &remote_path);
+        if upload.is_err() {
+            return Err(RumiError::CommandFailed(format!(
+                "failed to sync site into {}",
+                remote_path
+            )));
+        }
+        println!(
+            "no shell access on this host, site synced directly into {}",
+            remote_path
+        );
+    }
+    Ok(())
+}
 
-    let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, domain);
-    let path = Path::new(&config_file_path);
-    let mut file = sftp.create(path).expect("failed to create nginx config file");
-    file.write_all(nginx_config.as_bytes()).expect("failed to write nginx config file");
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec(format!("sudo ln -s {} /etc/nginx/sites-enabled/ && ls -a /etc/nginx/sites-enabled", config_file_path).as_str());
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    println!("ouptut : {:?}", s);
-    assert!(command.is_ok(), "Failed to allow port 80");
-    close_channel(&mut chanel);
-
-    let mut chanel = new_channel(session);
-    let command = chanel.exec("sudo systemctl reload nginx");
-    let mut s = String::new();
-    chanel.read_to_string(&mut s).unwrap();
-    println!("ouptut : {:?}", s);
-    assert!(command.is_ok(), "Failed to reload nginx");
-    close_channel(&mut chanel);
-
-    
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session::MockExecutor;
+
+    /// A real dist folder on disk, because update hashes the local files.
+    fn temp_dist() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("rumi-test-dist-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<html>hello</html>").unwrap();
+        dir
+    }
+
+    #[test]
+    fn update_uploads_a_release_and_switches_current() {
+        let executor = MockExecutor::new();
+        let dist = temp_dist();
+        let release =
+            update_command(&executor, "example.com", dist.to_str().unwrap(), "", false).unwrap();
+        assert!(release.starts_with("/var/www/example.com/releases/"));
+        // the dist lands in a user-writable staging dir, never in the
+        // root-owned releases tree directly
+        let uploads = executor.uploads();
+        assert_eq!(uploads[0].0, dist.display().to_string());
+        let staging = uploads[0].1.clone();
+        assert!(staging.starts_with("/var/www/example.com/.staging-"));
+        let executed = executor.executed();
+        assert!(executed
+            .iter()
+            .any(|c| c.contains(&format!("sudo mv {} {}", staging, release))
+                && c.contains("chown -R www-data:www-data")));
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("ln -sfn") && c.contains("/var/www/example.com/current")));
+        assert!(!executed.iter().any(|c| c.contains("777")));
+        assert_eq!(executed.last().unwrap(), "sudo systemctl reload nginx");
+    }
+
+    #[test]
+    fn update_writes_the_nginx_config_rooted_at_current() {
+        let executor = MockExecutor::new();
+        let dist = temp_dist();
+        update_command(&executor, "example.com", dist.to_str().unwrap(), "", false).unwrap();
+        let written = executor.written();
+        let (path, content) = written
+            .iter()
+            .find(|(path, _)| path.starts_with("/tmp/rumi-nginx-"))
+            .unwrap();
+        assert_eq!(path, "/tmp/rumi-nginx-example.com");
+        let content = std::str::from_utf8(content).unwrap();
+        assert!(content.contains("/var/www/example.com/current"));
+        assert!(content.contains("example.com"));
+        // staged, then moved into sites-available as root
+        assert!(executor.executed().iter().any(|c| c.contains(
+            "sudo mv /tmp/rumi-nginx-example.com /etc/nginx/sites-available/example.com"
+        )));
+    }
+
+    #[test]
+    fn update_leaves_a_manifest_in_the_release() {
+        let executor = MockExecutor::new();
+        let dist = temp_dist();
+        update_command(&executor, "example.com", dist.to_str().unwrap(), "", false).unwrap();
+        let written = executor.written();
+        // written into the staging dir, which is promoted wholesale
+        let (path, content) = written
+            .iter()
+            .find(|(path, _)| path.ends_with(SYNC_MANIFEST_FILE))
+            .unwrap();
+        assert!(path.starts_with("/var/www/example.com/.staging-"));
+        let manifest: std::collections::BTreeMap<String, String> =
+            serde_json::from_slice(content).unwrap();
+        assert!(manifest.contains_key("index.html"));
+    }
+
+    #[test]
+    fn update_syncs_only_changed_files_against_the_previous_manifest() {
+        let dist = temp_dist();
+        std::fs::write(dist.join("app.js"), "console.log(1)").unwrap();
+        let unchanged = crate::blobstore::sha256_of(&dist.join("app.js")).unwrap();
+        let previous = format!(
+            r#"{{"app.js":"{}","index.html":"stale","gone.txt":"whatever"}}"#,
+            unchanged
+        );
+        let executor = MockExecutor::new().respond("cat ", &previous, 0);
+        update_command(&executor, "example.com", dist.to_str().unwrap(), "", false).unwrap();
+        // only the stale file travels, as a single file into the staging dir
+        let uploads = executor.uploads();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].0, dist.join("index.html").display().to_string());
+        assert!(uploads[0].1.starts_with("/var/www/example.com/.staging-"));
+        assert!(uploads[0].1.ends_with("/index.html"));
+        let executed = executor.executed();
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("sudo cp -al") && c.contains("/var/www/example.com/current")));
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("rm -f index.html") && c.contains("rm -f gone.txt")));
+    }
+
+    #[test]
+    fn full_flag_skips_the_delta_and_uploads_everything() {
+        let dist = temp_dist();
+        let executor = MockExecutor::new().respond("cat ", r#"{"index.html":"stale"}"#, 0);
+        update_command(&executor, "example.com", dist.to_str().unwrap(), "", true).unwrap();
+        let uploads = executor.uploads();
+        assert_eq!(uploads.len(), 1);
+        assert_eq!(uploads[0].0, dist.display().to_string());
+        assert!(uploads[0].1.starts_with("/var/www/example.com/.staging-"));
+    }
+
+    #[test]
+    fn rollback_points_current_at_the_named_release() {
+        let executor = MockExecutor::new();
+        rollback_command(&executor, "example.com", "example.com/releases/20240101000000")
+            .unwrap();
+        assert!(executor.executed().iter().any(|c| {
+            c.contains("ln -sfn /var/www/example.com/releases/20240101000000")
+                && c.contains("/var/www/example.com/current")
+        }));
+    }
+
+    #[test]
+    fn uninstall_stops_serving_before_archiving_the_folders() {
+        let executor = MockExecutor::new();
+        uninstall_command(&executor, "example.com", false, false).unwrap();
+        let executed = executor.executed();
+        let reload = executed
+            .iter()
+            .position(|c| c == "sudo systemctl reload nginx")
+            .unwrap();
+        let archive = executed
+            .iter()
+            .position(|c| c.contains("sudo mv /var/www/example.com "))
+            .unwrap();
+        assert!(reload < archive);
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("rm -f /etc/nginx/sites-enabled/example.com")));
+        assert!(!executed.iter().any(|c| c.contains("certbot")));
+    }
+
+    #[test]
+    fn uninstall_purge_deletes_instead_of_archiving() {
+        let executor = MockExecutor::new();
+        uninstall_command(&executor, "example.com", true, true).unwrap();
+        let executed = executor.executed();
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("sudo rm -rf /var/www/example.com")));
+        assert!(!executed
+            .iter()
+            .any(|c| c.contains("sudo mv /var/www/example.com")));
+        assert!(executed
+            .iter()
+            .any(|c| c.contains("certbot revoke --cert-name example.com")));
+    }
+
+    #[test]
+    fn install_uploads_before_switching_and_restarts_nginx() {
+        let executor = MockExecutor::new();
+        let dist = temp_dist();
+        install_command(&executor, "example.com", dist.to_str().unwrap(), "").unwrap();
+        assert_eq!(executor.uploads().len(), 1);
+        let executed = executor.executed();
+        assert!(executed.iter().any(|c| c.starts_with("sudo apt update")));
+        assert!(!executed.iter().any(|c| c.contains("777")));
+        assert!(executed.last().unwrap().contains("systemctl restart nginx"));
+    }
 }
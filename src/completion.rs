@@ -0,0 +1,96 @@
+//! Shell completion with dynamic values. The printed script completes the
+//! subcommand names statically and calls back into `rumi2 complete-values`
+//! for the things that change per project: deployment names from the
+//! current config, configured ssh hosts, and backup ids from the local
+//! index cache (a possibly stale cache beats sshing mid-keystroke).
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+
+/// Print the completion script for a shell, to be eval'd or dropped into
+/// the shell's completion directory. zsh goes through bashcompinit, which
+/// keeps one script to maintain.
+pub fn print_script(shell: &str, subcommands: &[String]) -> RumiResult<()> {
+    let body = format!(
+        r#"_rumi2() {{
+    local cur prev
+    cur="${{COMP_WORDS[COMP_CWORD]}}"
+    prev="${{COMP_WORDS[COMP_CWORD-1]}}"
+    case "$prev" in
+        --name)
+            COMPREPLY=($(compgen -W "$(rumi2 complete-values --kind deployments 2>/dev/null)" -- "$cur"))
+            return;;
+        --backup-id)
+            COMPREPLY=($(compgen -W "$(rumi2 complete-values --kind backups 2>/dev/null)" -- "$cur"))
+            return;;
+        --ssh_host|--host)
+            COMPREPLY=($(compgen -W "$(rumi2 complete-values --kind hosts 2>/dev/null)" -- "$cur"))
+            return;;
+    esac
+    if [ "$COMP_CWORD" -eq 1 ]; then
+        COMPREPLY=($(compgen -W "{subcommands}" -- "$cur"))
+    fi
+}}
+complete -F _rumi2 rumi2"#,
+        subcommands = subcommands.join(" ")
+    );
+    match shell {
+        "bash" => println!("{}", body),
+        "zsh" => println!("autoload -U +X bashcompinit && bashcompinit\n{}", body),
+        other => {
+            return Err(RumiError::Config(format!(
+                "no completion script for '{}', use bash or zsh",
+                other
+            )))
+        }
+    }
+    Ok(())
+}
+
+/// Print the candidates for one value kind, one per line. A missing or
+/// unreadable config prints nothing — completion must never error at the
+/// prompt.
+pub fn print_candidates(config: Option<&RumiConfig>, kind: &str) -> RumiResult<()> {
+    match kind {
+        "deployments" => {
+            for deployment in config.map(|c| c.deployments.as_slice()).unwrap_or_default() {
+                println!("{}", deployment.name);
+            }
+        }
+        "hosts" => {
+            let mut hosts: Vec<&str> = Vec::new();
+            if let Some(config) = config {
+                if let Some(ssh) = &config.default_ssh {
+                    hosts.push(&ssh.host);
+                }
+                for ssh in config.ssh_profiles.values() {
+                    if !hosts.contains(&ssh.host.as_str()) {
+                        hosts.push(&ssh.host);
+                    }
+                }
+                for deployment in &config.deployments {
+                    if let Some(ssh) = &deployment.ssh {
+                        if !hosts.contains(&ssh.host.as_str()) {
+                            hosts.push(&ssh.host);
+                        }
+                    }
+                }
+            }
+            for host in hosts {
+                println!("{}", host);
+            }
+        }
+        "backups" => {
+            for id in crate::backup::cached_backup_ids() {
+                println!("{}", id);
+            }
+        }
+        other => {
+            return Err(RumiError::Config(format!(
+                "unknown completion kind '{}', use deployments, hosts or backups",
+                other
+            )))
+        }
+    }
+    Ok(())
+}
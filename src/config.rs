@@ -0,0 +1,835 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::alerts::{AlertRule, NotificationConfig};
+use crate::dns::DnsConfig;
+use crate::firewall::FirewallConfig;
+use crate::security::{ArtifactSigningSettings, Fail2banSettings};
+use crate::error::{RumiError, RumiResult};
+
+/// Default name of the config file, looked up in the current directory.
+pub const DEFAULT_CONFIG_FILE: &str = "rumi.json";
+
+/// How long a save waits for another rumi process to release the config.
+const LOCK_WAIT_SECS: u64 = 10;
+
+/// A lock file older than this belongs to a crashed process and is broken.
+const LOCK_STALE_SECS: u64 = 60;
+
+/// What the config file contained when it was loaded, for detecting writes
+/// by another rumi process between our load and our save.
+static LOADED_CONTENT: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// An advisory lock file next to the config, serializing saves between rumi
+/// processes (parallel ci jobs, a deploy while the listener runs). Released
+/// on drop; stale locks left by crashed processes are broken.
+struct ConfigLock {
+    path: PathBuf,
+}
+
+impl ConfigLock {
+    fn acquire(config_path: &Path) -> RumiResult<Self> {
+        let mut os_path = config_path.as_os_str().to_owned();
+        os_path.push(".lock");
+        let path = PathBuf::from(os_path);
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(LOCK_WAIT_SECS);
+        loop {
+            match fs::OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    use std::io::Write;
+                    let _ = write!(file, "{}", std::process::id());
+                    return Ok(ConfigLock { path });
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|meta| meta.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok())
+                        .is_some_and(|age| age.as_secs() > LOCK_STALE_SECS);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                        continue;
+                    }
+                    if std::time::Instant::now() >= deadline {
+                        return Err(RumiError::Config(format!(
+                            "another rumi process holds {}, remove it if no other rumi is running",
+                            path.display()
+                        )));
+                    }
+                    std::thread::sleep(std::time::Duration::from_millis(200));
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn default_ssh_port() -> u16 {
+    22
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_min_free_gb() -> u64 {
+    10
+}
+
+fn default_replicas() -> u32 {
+    1
+}
+
+fn default_namespace() -> String {
+    "default".to_string()
+}
+
+/// How privileged commands get elevated on a host. Command strings keep
+/// writing `sudo` as the marker for "needs privileges"; the session layer
+/// translates it according to this setting.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EscalationMethod {
+    #[default]
+    Sudo,
+    Doas,
+    /// The connection user already is root: the marker is stripped.
+    None,
+}
+
+/// The escalation block of an ssh profile.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EscalationConfig {
+    #[serde(default)]
+    pub method: EscalationMethod,
+    /// When non-empty, only these commands may be run with privileges.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowlist: Vec<String>,
+}
+
+/// Connection details for one server reachable over ssh.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshConfig {
+    pub host: String,
+    #[serde(default = "default_ssh_port")]
+    pub port: u16,
+    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_key_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private_key_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passphrase: Option<String>,
+    /// How privileged commands escalate on this host; sudo when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<EscalationConfig>,
+    /// Whether sudo on this host asks for a password. Commands then run
+    /// through `sudo -S` with the password fed on stdin, taken from
+    /// RUMI_SUDO_PASSWORD or prompted once per run.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub requires_sudo_password: bool,
+    /// Reach this host through a bastion: the name of an ssh profile, or a
+    /// plain "user@host:port" spec (user and port optional) authenticated
+    /// via the local agent. A profile with its own proxy_jump chains.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_jump: Option<String>,
+}
+
+/// Which database server a database deployment runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseEngine {
+    Postgres,
+    Mysql,
+}
+
+/// How nginx fronts a proxied deployment. Every field used to be a fixed
+/// value in the server template; the defaults keep that behavior.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProxyConfig {
+    /// Terminate tls on 443 with the domain's letsencrypt certificate and
+    /// redirect port 80, the way website deployments are served.
+    #[serde(default)]
+    pub ssl: bool,
+    /// Forward websocket Upgrade/Connection headers.
+    #[serde(default = "default_true")]
+    pub websockets: bool,
+    /// nginx client_max_body_size, e.g. "50m"; nginx's own default when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_body_size: Option<String>,
+    /// proxy_read_timeout and proxy_send_timeout, in seconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_secs: Option<u64>,
+}
+
+impl Default for ProxyConfig {
+    fn default() -> Self {
+        ProxyConfig {
+            ssl: false,
+            websockets: true,
+            max_body_size: None,
+            timeout_secs: None,
+        }
+    }
+}
+
+/// What kind of thing a deployment is, with its type specific fields.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DeploymentType {
+    Website {
+        dist_path: String,
+    },
+    Server {
+        binary_path: String,
+        port: u16,
+        /// How nginx proxies to the binary; the defaults reproduce the old
+        /// fixed template (websockets on, plain http, nginx limits).
+        #[serde(skip_serializing_if = "Option::is_none")]
+        proxy: Option<ProxyConfig>,
+        /// Alternative binaries keyed by architecture ("aarch64") or full
+        /// target triple ("aarch64-unknown-linux-gnu"); deploys pick the one
+        /// matching the remote's `uname -m`. Hosts fall back to binary_path
+        /// only when this map is empty.
+        #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+        artifacts: std::collections::HashMap<String, String>,
+        /// Environment variables for the binary, rendered into
+        /// /etc/<name>.env (mode 600) on deploy and loaded by the systemd
+        /// unit through EnvironmentFile.
+        #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+        env: std::collections::HashMap<String, String>,
+        /// A local env file whose lines go into /etc/<name>.env after the
+        /// env map, for secrets kept out of rumi.json. Later assignments
+        /// win in systemd, so the file overrides the map.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        env_file: Option<String>,
+    },
+    Ethereum {
+        network_id: u64,
+        unlock_wallet_address: String,
+        /// Where geth keeps the chain data, e.g. a mounted volume; the old
+        /// `node/data` in the login user's home when unset.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        datadir: Option<String>,
+        /// A block device to format (only while blank) and mount at the
+        /// datadir before init, for dedicated chain disks.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        datadir_device: Option<String>,
+        /// Refuse to init with fewer free gigabytes than this at the datadir.
+        #[serde(default = "default_min_free_gb")]
+        min_free_gb: u64,
+    },
+    /// An app running on a kubernetes cluster: manifests are rendered from
+    /// this definition and applied with kubectl, over ssh on a control node
+    /// or against the local kubeconfig.
+    Kubernetes {
+        image: String,
+        container_port: u16,
+        #[serde(default = "default_replicas")]
+        replicas: u32,
+        #[serde(default = "default_namespace")]
+        namespace: String,
+        /// Run kubectl locally instead of over ssh on the control node.
+        #[serde(default)]
+        local_kubeconfig: bool,
+    },
+    /// A website on shared hosting where only sftp is available: the dist is
+    /// synced to a remote directory, no packages, nginx or certbot involved.
+    SftpSite {
+        dist_path: String,
+        /// Where on the remote the site lives, e.g. "public_html".
+        remote_path: String,
+    },
+    /// A php app (or a fresh WordPress) served by php-fpm behind nginx.
+    Php {
+        /// Local path of the app source; ignored when wordpress is set.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        app_path: Option<String>,
+        /// Download and deploy the latest WordPress instead of an app_path.
+        #[serde(default)]
+        wordpress: bool,
+    },
+    /// A python app served by gunicorn (or uvicorn for asgi) behind nginx,
+    /// installed into a venv on the remote and run as a systemd service.
+    Python {
+        /// Local path of the app source, with a requirements.txt at its root.
+        app_path: String,
+        /// The app object, e.g. "myproject.wsgi:application" or "main:app".
+        entry: String,
+        port: u16,
+        /// Serve with uvicorn instead of gunicorn.
+        #[serde(default)]
+        asgi: bool,
+    },
+    /// A managed database server with one database and user, the password
+    /// generated at install time and kept in the secrets store.
+    Database {
+        engine: DatabaseEngine,
+        db_name: String,
+        db_user: String,
+        /// What the server binds to; stays on localhost when unset.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        listen_address: Option<String>,
+    },
+    /// A redis cache next to the app servers, password-protected with a
+    /// generated requirepass kept in the secrets store.
+    Redis {
+        /// What redis binds to; stays on localhost when unset.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        bind_address: Option<String>,
+        /// Open 6379 in ufw, only sensible together with bind_address on a
+        /// private interface.
+        #[serde(default)]
+        open_firewall: bool,
+    },
+    /// A host provisioned with docker engine and the compose plugin, ready
+    /// for container deployments.
+    DockerHost {
+        /// Registry mirrors written into /etc/docker/daemon.json.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        registry_mirrors: Vec<String>,
+    },
+    /// A host running node_exporter and optionally Prometheus and Grafana in
+    /// docker, scraping every other deployment.
+    Observability {
+        #[serde(default)]
+        with_prometheus: bool,
+        #[serde(default)]
+        with_grafana: bool,
+    },
+}
+
+impl DeploymentType {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeploymentType::Website { .. } => "website",
+            DeploymentType::Server { .. } => "server",
+            DeploymentType::Ethereum { .. } => "ethereum",
+            DeploymentType::Kubernetes { .. } => "kubernetes",
+            DeploymentType::SftpSite { .. } => "sftp_site",
+            DeploymentType::Php { .. } => "php",
+            DeploymentType::Python { .. } => "python",
+            DeploymentType::Database { .. } => "database",
+            DeploymentType::Redis { .. } => "redis",
+            DeploymentType::DockerHost { .. } => "docker_host",
+            DeploymentType::Observability { .. } => "observability",
+        }
+    }
+}
+
+/// Post-deploy verification: after hosting install/update and server
+/// deploy the endpoint is probed until it answers healthy, and the deploy
+/// fails when it never does within the retries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HealthCheckConfig {
+    /// A full url or a path on the deployment's domain; falls back to the
+    /// deployment's health_url, then "/".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// The status the probe must answer with; falls back to the
+    /// deployment's expected_status, then anything below 400.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expected_status: Option<u16>,
+    /// Per-probe timeout in seconds.
+    #[serde(default = "default_health_timeout_secs")]
+    pub timeout_secs: u64,
+    /// How many probes to attempt before giving the deploy up as failed.
+    #[serde(default = "default_health_retries")]
+    pub retries: u32,
+    /// Switch a website back to its previous release when the check fails;
+    /// other deployment kinds just fail the command.
+    #[serde(default)]
+    pub rollback: bool,
+}
+
+fn default_health_timeout_secs() -> u64 {
+    5
+}
+
+fn default_health_retries() -> u32 {
+    5
+}
+
+/// One deployment managed by rumi: a website, server binary or ethereum node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeploymentConfig {
+    pub name: String,
+    pub domain: String,
+    #[serde(flatten)]
+    pub deployment_type: DeploymentType,
+    /// Overrides the default ssh connection for this deployment.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh: Option<SshConfig>,
+    /// The name of an entry in ssh_profiles to connect through, for configs
+    /// where several deployments share a handful of hosts. An inline `ssh`
+    /// block wins over the profile.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssh_profile: Option<String>,
+    /// Profile names to deploy to all at once — the same site on several
+    /// servers behind a load balancer. When set, deploy commands visit every
+    /// target and this wins over ssh, ssh_profile and default_ssh.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub ssh_targets: Vec<String>,
+    /// The repository this deployment is built from, as "owner/name", used
+    /// by the webhook listener to map pushes onto deployments.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repo: Option<String>,
+    /// The local checkout the listener pulls and builds in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub project_path: Option<String>,
+    /// What to probe to decide whether this deployment is up: either a full
+    /// url or a path on the deployment's domain. Defaults to "/".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health_url: Option<String>,
+    /// The status code the health url must answer with, anything below 400
+    /// counts as healthy when unset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expected_status: Option<u16>,
+    /// Probed after deploys; the deploy fails (and optionally rolls back)
+    /// when the deployment never turns healthy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub health_check: Option<HealthCheckConfig>,
+    /// How certbot proves domain ownership for this deployment: standalone
+    /// (the default) or dns-01 through the configured dns provider, for
+    /// hosts already serving on port 80 and for wildcard certificates.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge: Option<crate::certs::Challenge>,
+    /// The let's encrypt account email for this deployment's certificates,
+    /// overriding settings.ssl_email.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssl_email: Option<String>,
+    /// Extra raw nginx directives injected verbatim into the generated
+    /// server block — custom headers, redirects, proxy_buffering off — so
+    /// one-off needs do not force a hand-maintained config. An entry of the
+    /// form "file:<path>" injects that local snippet file instead.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub nginx_extra: Vec<String>,
+}
+
+impl DeploymentConfig {
+    /// The extra directives as one pre-indented block ready for the nginx
+    /// templates, with "file:<path>" entries replaced by the file's content.
+    pub fn nginx_extra_block(&self) -> RumiResult<String> {
+        let mut block = String::new();
+        for entry in &self.nginx_extra {
+            let content = match entry.strip_prefix("file:") {
+                Some(path) => fs::read_to_string(crate::utils::expand_tilde(path))
+                    .map_err(|e| {
+                        RumiError::Config(format!("could not read nginx snippet {}: {}", path, e))
+                    })?,
+                None => entry.clone(),
+            };
+            for line in content.lines() {
+                block.push_str("          ");
+                block.push_str(line.trim_end());
+                block.push('\n');
+            }
+        }
+        Ok(block)
+    }
+}
+
+/// Cross-cutting knobs that are not tied to one deployment.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Settings {
+    /// Install fail2ban with these jails when provisioning hosts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fail2ban: Option<Fail2banSettings>,
+    /// Verify detached signatures on artifacts before deploying them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub artifact_signing: Option<ArtifactSigningSettings>,
+    /// Lock this config to non-mutating commands, for shared operator or
+    /// dashboard use. The --read-only flag does the same per invocation.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Write a timestamped command transcript here for every mutating run,
+    /// without needing --log-file each time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<String>,
+    /// Kill any remote command still running after this many seconds, so a
+    /// stuck apt or certbot fails the step instead of hanging the CLI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub command_timeout_secs: Option<u64>,
+    /// Refuse hosts missing from known_hosts instead of offering to accept
+    /// their fingerprint, for pipelines where nobody can answer the prompt.
+    #[serde(default)]
+    pub strict_host_key_checking: bool,
+    /// The let's encrypt account email certbot registers and sends expiry
+    /// warnings to. Required before anything obtains a certificate.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ssl_email: Option<String>,
+    /// Folders with more files than this upload as one tar.gz unpacked
+    /// remotely instead of file-by-file sftp; 0 turns the tar path off.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tar_upload_threshold: Option<u64>,
+    /// Spread folder uploads over this many sftp connections per host.
+    /// The --concurrency flag overrides it per invocation.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_concurrency: Option<u64>,
+}
+
+impl Settings {
+    fn is_default(&self) -> bool {
+        self.fail2ban.is_none()
+            && self.artifact_signing.is_none()
+            && !self.read_only
+            && self.log_dir.is_none()
+            && self.command_timeout_secs.is_none()
+            && !self.strict_host_key_checking
+            && self.ssl_email.is_none()
+            && self.tar_upload_threshold.is_none()
+            && self.upload_concurrency.is_none()
+    }
+}
+
+/// The rumi.json file: every deployment rumi knows about plus how to reach it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RumiConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_ssh: Option<SshConfig>,
+    /// Named ssh connections deployments can reference via ssh_profile,
+    /// managed with `config add-ssh --name` / `list-ssh` / `remove-ssh`.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub ssh_profiles: std::collections::HashMap<String, SshConfig>,
+    #[serde(default, skip_serializing_if = "Settings::is_default")]
+    pub settings: Settings,
+    #[serde(default)]
+    pub deployments: Vec<DeploymentConfig>,
+    /// Plain variables usable by deployments, e.g. imported from terraform
+    /// outputs.
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub variables: std::collections::HashMap<String, String>,
+    /// Values generated or stored by rumi, keyed like "database/<name>/password".
+    #[serde(default, skip_serializing_if = "std::collections::HashMap::is_empty")]
+    pub secrets: std::collections::HashMap<String, String>,
+    /// Which dns provider manages the deployed zones, for --manage-dns and
+    /// the dns commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dns: Option<DnsConfig>,
+    /// Hand-declared firewall rules on top of what the deployments derive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall: Option<FirewallConfig>,
+    /// Alert rules evaluated by `monitor check` and daemon mode.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub alerts: Vec<AlertRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notifications: Option<NotificationConfig>,
+}
+
+impl RumiConfig {
+    pub fn load_from_file(path: &Path) -> RumiResult<Self> {
+        let mut content = fs::read_to_string(path).map_err(|e| {
+            RumiError::Config(format!("could not read {}: {}", path.display(), e))
+        })?;
+        if crate::crypt::is_encrypted(&content) {
+            let passphrase = crate::crypt::passphrase()?;
+            content = crate::crypt::decrypt(&content, &passphrase)?;
+            crate::crypt::mark_loaded_encrypted();
+        }
+        let config: RumiConfig = serde_json::from_str(&content)?;
+        *LOADED_CONTENT.lock().unwrap() = Some(content);
+        // so a proxy_jump can name a profile from any later connect
+        crate::session::register_jump_profiles(&config.ssh_profiles);
+        Ok(config)
+    }
+
+    pub fn save_to_file(&self, path: &Path) -> RumiResult<()> {
+        let _lock = ConfigLock::acquire(path)?;
+        // under the lock: refuse to overwrite changes another process saved
+        // between our load and now, losing them silently
+        let loaded = LOADED_CONTENT.lock().unwrap().clone();
+        if let (Some(loaded), Ok(mut on_disk)) = (loaded, fs::read_to_string(path)) {
+            if crate::crypt::is_encrypted(&on_disk) {
+                on_disk = crate::crypt::decrypt(&on_disk, &crate::crypt::passphrase()?)?;
+            }
+            if on_disk != loaded {
+                return Err(RumiError::Config(format!(
+                    "{} changed on disk since it was loaded (another rumi process?), re-run the command",
+                    path.display()
+                )));
+            }
+        }
+        let plain = serde_json::to_string_pretty(self)?;
+        let mut content = plain.clone();
+        // a config that came off disk encrypted goes back encrypted
+        if crate::crypt::loaded_encrypted() {
+            content = crate::crypt::encrypt(&content, &crate::crypt::passphrase()?)?;
+        }
+        // write next to the config and rename, so readers never see a
+        // half-written file
+        let mut os_path = path.as_os_str().to_owned();
+        os_path.push(".tmp");
+        let staging = PathBuf::from(os_path);
+        fs::write(&staging, content)?;
+        fs::rename(&staging, path)?;
+        *LOADED_CONTENT.lock().unwrap() = Some(plain);
+        Ok(())
+    }
+
+    pub fn find_deployment(&self, name: &str) -> RumiResult<&DeploymentConfig> {
+        self.deployments
+            .iter()
+            .find(|d| d.name == name)
+            .ok_or_else(|| RumiError::DeploymentNotFound(name.to_string()))
+    }
+
+    /// The ssh connection to use for a deployment: its own inline override,
+    /// then the profile it names, then default_ssh.
+    pub fn ssh_for_deployment<'a>(
+        &'a self,
+        deployment: &'a DeploymentConfig,
+    ) -> RumiResult<&'a SshConfig> {
+        if let Some(ssh) = &deployment.ssh {
+            return Ok(ssh);
+        }
+        if let Some(profile) = &deployment.ssh_profile {
+            return self.ssh_profiles.get(profile).ok_or_else(|| {
+                RumiError::Config(format!(
+                    "deployment '{}' references ssh profile '{}' which does not exist, see `config list-ssh`",
+                    deployment.name, profile
+                ))
+            });
+        }
+        // single-host commands against a multi-target deployment pick the
+        // first target, the deploy commands visit them all
+        if let Some(target) = deployment.ssh_targets.first() {
+            return self.ssh_profiles.get(target).ok_or_else(|| {
+                RumiError::Config(format!(
+                    "deployment '{}' targets ssh profile '{}' which does not exist, see `config list-ssh`",
+                    deployment.name, target
+                ))
+            });
+        }
+        self.default_ssh.as_ref().ok_or_else(|| {
+            RumiError::Config(format!(
+                "deployment '{}' has no ssh config and no default_ssh is set",
+                deployment.name
+            ))
+        })
+    }
+
+    /// Every host a deployment targets: each named profile in ssh_targets,
+    /// or the single connection ssh_for_deployment resolves.
+    pub fn ssh_targets_for_deployment(
+        &self,
+        deployment: &DeploymentConfig,
+    ) -> RumiResult<Vec<SshConfig>> {
+        if deployment.ssh_targets.is_empty() {
+            return Ok(vec![self.ssh_for_deployment(deployment)?.clone()]);
+        }
+        deployment
+            .ssh_targets
+            .iter()
+            .map(|target| {
+                self.ssh_profiles.get(target).cloned().ok_or_else(|| {
+                    RumiError::Config(format!(
+                        "deployment '{}' targets ssh profile '{}' which does not exist, see `config list-ssh`",
+                        deployment.name, target
+                    ))
+                })
+            })
+            .collect()
+    }
+
+    /// The certbot account email for a deployment: its own override, then
+    /// settings.ssl_email. There is no built-in fallback — certificates
+    /// should never be registered to somebody else's address.
+    pub fn ssl_email_for_deployment(&self, deployment: &DeploymentConfig) -> RumiResult<String> {
+        deployment
+            .ssl_email
+            .clone()
+            .or_else(|| self.settings.ssl_email.clone())
+            .ok_or_else(|| {
+                RumiError::Config(format!(
+                    "no ssl email for deployment '{}': set settings.ssl_email or the deployment's ssl_email",
+                    deployment.name
+                ))
+            })
+    }
+}
+
+/// One problem `config validate` found, tied to the entry it concerns.
+pub struct ValidationProblem {
+    pub entry: String,
+    pub problem: String,
+}
+
+fn check_key_file(entry: &str, path: &str, problems: &mut Vec<ValidationProblem>) {
+    match fs::metadata(crate::utils::expand_tilde(path)) {
+        Err(_) => problems.push(ValidationProblem {
+            entry: entry.to_string(),
+            problem: format!("key file {} does not exist", path),
+        }),
+        #[cfg(unix)]
+        Ok(metadata) => {
+            use std::os::unix::fs::PermissionsExt;
+            // ssh itself refuses keys readable by group or others
+            if metadata.permissions().mode() & 0o077 != 0 {
+                problems.push(ValidationProblem {
+                    entry: entry.to_string(),
+                    problem: format!(
+                        "key file {} is readable by group/others, chmod 600 it",
+                        path
+                    ),
+                });
+            }
+        }
+        #[cfg(not(unix))]
+        Ok(_) => {}
+    }
+}
+
+/// The address old rumi versions hardcoded into every certbot call; configs
+/// that copied it from an example must not register certificates to it.
+const EXAMPLE_SSL_EMAIL: &str = "pondonda@gmail.com";
+
+/// Check everything about the config that can be checked without leaving the
+/// machine: duplicate names, deployments without a reachable ssh entry, key
+/// files that are missing or too open. Remote checks are `validate --remote`.
+pub fn validate_local(config: &RumiConfig) -> Vec<ValidationProblem> {
+    let mut problems = Vec::new();
+    let mut seen = std::collections::HashSet::new();
+    for deployment in &config.deployments {
+        if !seen.insert(deployment.name.as_str()) {
+            problems.push(ValidationProblem {
+                entry: deployment.name.clone(),
+                problem: "duplicate deployment name".to_string(),
+            });
+        }
+        if deployment.domain.is_empty() {
+            problems.push(ValidationProblem {
+                entry: deployment.name.clone(),
+                problem: "empty domain".to_string(),
+            });
+        }
+        if let Err(e) = config.ssh_for_deployment(deployment) {
+            problems.push(ValidationProblem {
+                entry: deployment.name.clone(),
+                problem: e.to_string(),
+            });
+        }
+        for target in &deployment.ssh_targets {
+            if !config.ssh_profiles.contains_key(target) {
+                problems.push(ValidationProblem {
+                    entry: deployment.name.clone(),
+                    problem: format!("ssh target '{}' is not a known ssh profile", target),
+                });
+            }
+        }
+        if deployment.ssl_email.as_deref() == Some(EXAMPLE_SSL_EMAIL) {
+            problems.push(ValidationProblem {
+                entry: deployment.name.clone(),
+                problem: "ssl_email is still the example address, put your own there".to_string(),
+            });
+        }
+    }
+    if config.settings.ssl_email.as_deref() == Some(EXAMPLE_SSL_EMAIL) {
+        problems.push(ValidationProblem {
+            entry: "settings".to_string(),
+            problem: "ssl_email is still the example address, put your own there".to_string(),
+        });
+    }
+    let mut ssh_entries: Vec<(String, &SshConfig)> = Vec::new();
+    if let Some(ssh) = &config.default_ssh {
+        ssh_entries.push(("default_ssh".to_string(), ssh));
+    }
+    for (name, ssh) in &config.ssh_profiles {
+        ssh_entries.push((format!("ssh profile '{}'", name), ssh));
+    }
+    for deployment in &config.deployments {
+        if let Some(ssh) = &deployment.ssh {
+            ssh_entries.push((deployment.name.clone(), ssh));
+        }
+    }
+    for (entry, ssh) in ssh_entries {
+        if let Some(path) = &ssh.private_key_path {
+            check_key_file(&entry, path, &mut problems);
+        }
+    }
+    problems
+}
+
+/// Import `terraform output -json` into the config: outputs named host/ip,
+/// user and ssh_port become the default ssh connection, every other string
+/// output lands in the variables map. Returns what was imported for display.
+pub fn import_terraform_outputs(
+    config: &mut RumiConfig,
+    path: &Path,
+) -> RumiResult<Vec<String>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| RumiError::Config(format!("could not read {}: {}", path.display(), e)))?;
+    let outputs: serde_json::Value = serde_json::from_str(&content)?;
+    let outputs = outputs.as_object().ok_or_else(|| {
+        RumiError::Config(format!(
+            "{} is not a terraform output file, expected a json object",
+            path.display()
+        ))
+    })?;
+
+    let mut imported = Vec::new();
+    let mut ssh = config.default_ssh.clone().unwrap_or(SshConfig {
+        host: String::new(),
+        port: default_ssh_port(),
+        user: "root".to_string(),
+        public_key_path: None,
+        private_key_path: None,
+        passphrase: None,
+        escalation: None,
+        requires_sudo_password: false,
+        proxy_jump: None,
+    });
+    let mut ssh_changed = false;
+    for (name, output) in outputs {
+        // terraform wraps each output in { "value": ..., "type": ... }
+        let value = output.get("value").unwrap_or(output);
+        let Some(value) = value.as_str().map(str::to_string).or_else(|| {
+            value.as_u64().map(|n| n.to_string())
+        }) else {
+            continue; // lists and maps stay in terraform
+        };
+        match name.as_str() {
+            "host" | "ip" | "server_ip" | "public_ip" => {
+                ssh.host = value.clone();
+                ssh_changed = true;
+                imported.push(format!("default_ssh.host = {}", value));
+            }
+            "user" | "ssh_user" => {
+                ssh.user = value.clone();
+                ssh_changed = true;
+                imported.push(format!("default_ssh.user = {}", value));
+            }
+            "ssh_port" => {
+                ssh.port = value.parse().map_err(|_| {
+                    RumiError::Config(format!("output ssh_port is not a port: {}", value))
+                })?;
+                ssh_changed = true;
+                imported.push(format!("default_ssh.port = {}", value));
+            }
+            _ => {
+                imported.push(format!("variables.{} = {}", name, value));
+                config.variables.insert(name.clone(), value);
+            }
+        }
+    }
+    if ssh_changed {
+        if ssh.host.is_empty() {
+            return Err(RumiError::Config(
+                "terraform outputs set an ssh user or port but no host".to_string(),
+            ));
+        }
+        config.default_ssh = Some(ssh);
+    }
+    Ok(imported)
+}
+
+/// Resolve the config path: the --config flag if given, rumi.json otherwise.
+pub fn resolve_config_path(flag: Option<PathBuf>) -> PathBuf {
+    flag.unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_FILE))
+}
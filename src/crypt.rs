@@ -0,0 +1,155 @@
+use std::io::BufRead;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use argon2::Argon2;
+use openssl::base64::{decode_block, encode_block};
+use openssl::symm::{decrypt_aead, encrypt_aead, Cipher};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RumiError, RumiResult};
+
+/// The env var a ci pipeline or shell can hand the master passphrase over in,
+/// instead of being prompted.
+pub const MASTER_KEY_ENV: &str = "RUMI_MASTER_KEY";
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The passphrase is asked for once per run, whichever command needs it first.
+static PASSPHRASE: Mutex<Option<String>> = Mutex::new(None);
+/// Whether the config came off disk encrypted, so saves re-encrypt it.
+static LOADED_ENCRYPTED: AtomicBool = AtomicBool::new(false);
+
+/// What an encrypted rumi.json holds instead of the config: the argon2id salt
+/// and the aes-256-gcm nonce, tag and ciphertext, all base64.
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    rumi_encrypted: u32,
+    salt: String,
+    nonce: String,
+    tag: String,
+    ciphertext: String,
+}
+
+/// Whether a config file's content is an encryption envelope rather than a
+/// plain config.
+pub fn is_encrypted(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content)
+        .ok()
+        .is_some_and(|v| v.get("rumi_encrypted").is_some())
+}
+
+pub fn loaded_encrypted() -> bool {
+    LOADED_ENCRYPTED.load(Ordering::Relaxed)
+}
+
+pub(crate) fn mark_loaded_encrypted() {
+    LOADED_ENCRYPTED.store(true, Ordering::Relaxed);
+}
+
+/// The master passphrase: RUMI_MASTER_KEY when set, an interactive prompt
+/// otherwise. Cached so a run that loads and saves only asks once.
+pub fn passphrase() -> RumiResult<String> {
+    let mut cached = PASSPHRASE.lock().unwrap();
+    if let Some(passphrase) = cached.as_ref() {
+        return Ok(passphrase.clone());
+    }
+    let passphrase = match std::env::var(MASTER_KEY_ENV) {
+        Ok(value) if !value.is_empty() => value,
+        _ => prompt("master passphrase")?,
+    };
+    if passphrase.is_empty() {
+        return Err(RumiError::Config(
+            "the master passphrase cannot be empty".to_string(),
+        ));
+    }
+    *cached = Some(passphrase.clone());
+    Ok(passphrase)
+}
+
+/// Prompt on stderr with terminal echo off, so the passphrase does not end up
+/// on screen or in scrollback. Also used for the sudo password prompt.
+pub(crate) fn prompt(label: &str) -> RumiResult<String> {
+    eprint!("{}: ", label);
+    let _ = std::process::Command::new("stty").arg("-echo").status();
+    let mut line = String::new();
+    let read = std::io::stdin().lock().read_line(&mut line);
+    let _ = std::process::Command::new("stty").arg("echo").status();
+    eprintln!();
+    read?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+/// argon2id with the crate's default parameters, 32 bytes out.
+fn derive_key(passphrase: &str, salt: &[u8]) -> RumiResult<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| RumiError::Config(format!("key derivation failed: {}", e)))?;
+    Ok(key)
+}
+
+/// Encrypt a config's json into the envelope written to disk.
+pub fn encrypt(plaintext: &str, passphrase: &str) -> RumiResult<String> {
+    let mut salt = [0u8; SALT_LEN];
+    openssl::rand::rand_bytes(&mut salt)
+        .map_err(|e| RumiError::Tls(format!("could not draw a salt: {}", e)))?;
+    let mut nonce = [0u8; NONCE_LEN];
+    openssl::rand::rand_bytes(&mut nonce)
+        .map_err(|e| RumiError::Tls(format!("could not draw a nonce: {}", e)))?;
+    let key = derive_key(passphrase, &salt)?;
+    let mut tag = [0u8; TAG_LEN];
+    let ciphertext = encrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        &[],
+        plaintext.as_bytes(),
+        &mut tag,
+    )
+    .map_err(|e| RumiError::Tls(format!("encryption failed: {}", e)))?;
+    let envelope = Envelope {
+        rumi_encrypted: 1,
+        salt: encode_block(&salt),
+        nonce: encode_block(&nonce),
+        tag: encode_block(&tag),
+        ciphertext: encode_block(&ciphertext),
+    };
+    Ok(serde_json::to_string_pretty(&envelope)?)
+}
+
+/// Decrypt an envelope back into the config's json. A wrong passphrase shows
+/// up as the aead tag failing to verify.
+pub fn decrypt(content: &str, passphrase: &str) -> RumiResult<String> {
+    let envelope: Envelope = serde_json::from_str(content)?;
+    if envelope.rumi_encrypted != 1 {
+        return Err(RumiError::Config(format!(
+            "unknown config encryption version {}",
+            envelope.rumi_encrypted
+        )));
+    }
+    let decode = |label: &str, value: &str| {
+        decode_block(value)
+            .map_err(|e| RumiError::Config(format!("corrupt {} in encrypted config: {}", label, e)))
+    };
+    let salt = decode("salt", &envelope.salt)?;
+    let nonce = decode("nonce", &envelope.nonce)?;
+    let tag = decode("tag", &envelope.tag)?;
+    let ciphertext = decode("ciphertext", &envelope.ciphertext)?;
+    let key = derive_key(passphrase, &salt)?;
+    let plaintext = decrypt_aead(
+        Cipher::aes_256_gcm(),
+        &key,
+        Some(&nonce),
+        &[],
+        &ciphertext,
+        &tag,
+    )
+    .map_err(|_| {
+        RumiError::Config("could not decrypt the config, wrong master passphrase?".to_string())
+    })?;
+    String::from_utf8(plaintext)
+        .map_err(|_| RumiError::Config("decrypted config is not valid utf-8".to_string()))
+}
@@ -0,0 +1,564 @@
+use std::net::{IpAddr, ToSocketAddrs, UdpSocket};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+use crate::http;
+
+/// How long --manage-dns waits for records to show up on public resolvers.
+pub const DEFAULT_PROPAGATION_TIMEOUT_SECS: u64 = 300;
+
+/// The public resolvers polled for propagation; all of them have to agree.
+const PUBLIC_RESOLVERS: &[&str] = &["8.8.8.8", "1.1.1.1"];
+
+/// Which dns provider manages the zones, set in the config's dns block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DnsProviderKind {
+    Cloudflare,
+    Route53,
+}
+
+/// The dns block of rumi.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DnsConfig {
+    pub provider: DnsProviderKind,
+}
+
+/// One record as rumi sees it, provider ids stay inside the providers.
+#[derive(Debug, Clone, Serialize)]
+pub struct DnsRecord {
+    /// Fully qualified name, e.g. "www.example.com".
+    pub name: String,
+    /// "A", "AAAA", "CNAME", "TXT", ...
+    pub record_type: String,
+    pub content: String,
+    pub ttl: u32,
+}
+
+/// What every dns backend has to offer: enough for record management during
+/// deploys, nothing zone-administrative.
+pub trait DnsProvider {
+    /// All records of the zone holding `domain`.
+    fn list_records(&self, domain: &str) -> RumiResult<Vec<DnsRecord>>;
+    /// Create the record, or update it when one with the same name and type
+    /// already exists.
+    fn upsert_record(&self, domain: &str, record: &DnsRecord) -> RumiResult<()>;
+}
+
+/// The registrable zone of a domain: the last two labels. Good enough for
+/// the tlds rumi deploys to; deeper zones can set records standalone.
+pub fn zone_of(domain: &str) -> String {
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() <= 2 {
+        domain.to_string()
+    } else {
+        labels[labels.len() - 2..].join(".")
+    }
+}
+
+/// Build the configured provider, with credentials from the environment or
+/// the secrets store.
+pub fn provider_from_config(config: &RumiConfig) -> RumiResult<Box<dyn DnsProvider>> {
+    let dns = config.dns.as_ref().ok_or_else(|| {
+        RumiError::Config(
+            "no dns block in the config, add {\"dns\": {\"provider\": \"cloudflare\"}}".to_string(),
+        )
+    })?;
+    match dns.provider {
+        DnsProviderKind::Cloudflare => Ok(Box::new(CloudflareProvider::from_config(config)?)),
+        DnsProviderKind::Route53 => Ok(Box::new(Route53Provider::new())),
+    }
+}
+
+/// Point the domain and its www alias at the server, creating or updating
+/// the A/AAAA records. Hostnames given as ssh host are resolved first.
+pub fn ensure_domain_records(
+    provider: &dyn DnsProvider,
+    domain: &str,
+    server: &str,
+) -> RumiResult<IpAddr> {
+    let ip = resolve_server_ip(server)?;
+    let record_type = match ip {
+        IpAddr::V4(_) => "A",
+        IpAddr::V6(_) => "AAAA",
+    };
+    for name in [domain.to_string(), format!("www.{}", domain)] {
+        let record = DnsRecord {
+            name: name.clone(),
+            record_type: record_type.to_string(),
+            content: ip.to_string(),
+            ttl: 300,
+        };
+        provider.upsert_record(domain, &record)?;
+        println!("{} {} -> {}", record_type, name, ip);
+    }
+    Ok(ip)
+}
+
+fn resolve_server_ip(server: &str) -> RumiResult<IpAddr> {
+    if let Ok(ip) = server.parse::<IpAddr>() {
+        return Ok(ip);
+    }
+    (server, 0)
+        .to_socket_addrs()
+        .map_err(|e| RumiError::Network(format!("could not resolve {}: {}", server, e)))?
+        .map(|addr| addr.ip())
+        .next()
+        .ok_or_else(|| RumiError::Network(format!("{} resolved to no addresses", server)))
+}
+
+/// Ask one public resolver for the addresses of a name, with a hand-built
+/// query on udp — the system resolver caches too aggressively for this.
+fn query_resolver(resolver: &str, domain: &str, want_v6: bool) -> RumiResult<Vec<IpAddr>> {
+    let mut query: Vec<u8> = vec![
+        0x52, 0x4d, // id "RM"
+        0x01, 0x00, // recursion desired
+        0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    for label in domain.trim_end_matches('.').split('.') {
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    let qtype: u16 = if want_v6 { 28 } else { 1 };
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes()); // class IN
+
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    socket.send_to(&query, (resolver, 53))?;
+    let mut response = [0u8; 512];
+    let read = socket
+        .recv(&mut response)
+        .map_err(|e| RumiError::Network(format!("no answer from {}: {}", resolver, e)))?;
+    let response = &response[..read];
+    if read < 12 || response[..2] != query[..2] {
+        return Err(RumiError::Network(format!(
+            "{} sent an invalid dns response",
+            resolver
+        )));
+    }
+    let answers = u16::from_be_bytes([response[6], response[7]]);
+
+    // skip the question section: name, type, class
+    let mut at = 12;
+    while at < read && response[at] != 0 {
+        at += response[at] as usize + 1;
+    }
+    at += 5;
+
+    let mut addresses = Vec::new();
+    for _ in 0..answers {
+        if at + 12 > read {
+            break;
+        }
+        // answer names are compression pointers in practice; a full name
+        // would start with a label length below 0xc0
+        at += if response[at] & 0xc0 == 0xc0 {
+            2
+        } else {
+            let mut len = 0;
+            while at + len < read && response[at + len] != 0 {
+                len += response[at + len] as usize + 1;
+            }
+            len + 1
+        };
+        let answer_type = u16::from_be_bytes([response[at], response[at + 1]]);
+        let rdlength = u16::from_be_bytes([response[at + 8], response[at + 9]]) as usize;
+        at += 10;
+        if at + rdlength > read {
+            break;
+        }
+        let rdata = &response[at..at + rdlength];
+        at += rdlength;
+        match (answer_type, rdlength) {
+            (1, 4) => addresses.push(IpAddr::from(<[u8; 4]>::try_from(rdata).unwrap())),
+            (28, 16) => addresses.push(IpAddr::from(<[u8; 16]>::try_from(rdata).unwrap())),
+            _ => {} // cname or other, keep scanning
+        }
+    }
+    Ok(addresses)
+}
+
+/// Poll the public resolvers until both the domain and its www alias resolve
+/// to the server ip everywhere, so certbot does not fail on a record that
+/// has not propagated yet. Prints progress since this can take minutes.
+pub fn wait_for_propagation(domain: &str, ip: IpAddr, timeout_secs: u64) -> RumiResult<()> {
+    let names = [domain.to_string(), format!("www.{}", domain)];
+    let started = Instant::now();
+    println!(
+        "waiting for {} to resolve to {} on {} (up to {}s)",
+        domain,
+        ip,
+        PUBLIC_RESOLVERS.join(" and "),
+        timeout_secs
+    );
+    loop {
+        let mut pending = Vec::new();
+        for name in &names {
+            for resolver in PUBLIC_RESOLVERS {
+                let seen = query_resolver(resolver, name, ip.is_ipv6()).unwrap_or_default();
+                if !seen.contains(&ip) {
+                    pending.push(format!("{} on {}", name, resolver));
+                }
+            }
+        }
+        if pending.is_empty() {
+            println!(
+                "records propagated after {}s",
+                started.elapsed().as_secs()
+            );
+            return Ok(());
+        }
+        if started.elapsed().as_secs() >= timeout_secs {
+            return Err(RumiError::Network(format!(
+                "records did not propagate within {}s, still missing: {}",
+                timeout_secs,
+                pending.join(", ")
+            )));
+        }
+        println!(
+            "  still waiting for {} ({}s elapsed)",
+            pending.join(", "),
+            started.elapsed().as_secs()
+        );
+        std::thread::sleep(Duration::from_secs(10));
+    }
+}
+
+/// The Cloudflare backend, talking to the v4 api with a scoped api token.
+pub struct CloudflareProvider {
+    api_token: String,
+}
+
+const CLOUDFLARE_API: &str = "https://api.cloudflare.com/client/v4";
+
+impl CloudflareProvider {
+    /// Token from CLOUDFLARE_API_TOKEN, falling back to the secrets store
+    /// under "dns/cloudflare/api_token".
+    pub fn from_config(config: &RumiConfig) -> RumiResult<Self> {
+        let api_token = std::env::var("CLOUDFLARE_API_TOKEN")
+            .ok()
+            .or_else(|| {
+                // ci mode takes secrets from the environment only
+                if crate::ci::enabled() {
+                    return None;
+                }
+                config.secrets.get("dns/cloudflare/api_token").cloned()
+            })
+            .ok_or_else(|| {
+                RumiError::Config(
+                    "no cloudflare token: set CLOUDFLARE_API_TOKEN or the dns/cloudflare/api_token secret"
+                        .to_string(),
+                )
+            })?;
+        // the stored value may itself be a vault:/ssm: reference
+        let api_token = crate::secrets::resolve(&api_token)?;
+        Ok(CloudflareProvider { api_token })
+    }
+
+    fn call(&self, method: &str, path: &str, body: Option<&str>) -> RumiResult<Value> {
+        let auth = format!("Bearer {}", self.api_token);
+        let (status, response) = http::request(
+            method,
+            &format!("{}{}", CLOUDFLARE_API, path),
+            &[
+                ("Authorization", &auth),
+                ("Content-Type", "application/json"),
+            ],
+            body,
+        )?;
+        let parsed: Value = serde_json::from_str(&response).map_err(|_| {
+            RumiError::Network(format!("cloudflare sent a non-json response ({})", status))
+        })?;
+        if status >= 400 || parsed["success"] == Value::Bool(false) {
+            return Err(RumiError::Network(format!(
+                "cloudflare {} {} failed ({}): {}",
+                method, path, status, parsed["errors"]
+            )));
+        }
+        Ok(parsed)
+    }
+
+    fn zone_id(&self, domain: &str) -> RumiResult<String> {
+        let zone = zone_of(domain);
+        let response = self.call("GET", &format!("/zones?name={}", zone), None)?;
+        response["result"][0]["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| {
+                RumiError::Config(format!("no cloudflare zone named {}", zone))
+            })
+    }
+}
+
+impl CloudflareProvider {
+    /// Purge the cdn cache for a domain: the listed urls, or everything in
+    /// the zone when none are given. Run after updates so visitors behind
+    /// the cdn see the new release.
+    pub fn purge_cache(&self, domain: &str, paths: &[String]) -> RumiResult<()> {
+        let zone_id = self.zone_id(domain)?;
+        let body = if paths.is_empty() {
+            serde_json::json!({ "purge_everything": true })
+        } else {
+            let files: Vec<String> = paths
+                .iter()
+                .map(|path| format!("https://{}{}", domain, path))
+                .collect();
+            serde_json::json!({ "files": files })
+        };
+        self.call(
+            "POST",
+            &format!("/zones/{}/purge_cache", zone_id),
+            Some(&serde_json::to_string(&body)?),
+        )?;
+        Ok(())
+    }
+}
+
+/// Purge the cdn cache after a deploy, for providers that are also a cdn.
+/// A no-op complaint for providers without one.
+pub fn purge_cdn_cache(config: &RumiConfig, domain: &str, paths: &[String]) -> RumiResult<()> {
+    let dns = config.dns.as_ref().ok_or_else(|| {
+        RumiError::Config(
+            "no dns block in the config, --purge-cdn needs a cloudflare provider".to_string(),
+        )
+    })?;
+    match dns.provider {
+        DnsProviderKind::Cloudflare => {
+            CloudflareProvider::from_config(config)?.purge_cache(domain, paths)?;
+            if paths.is_empty() {
+                println!("purged the whole cloudflare cache for {}", zone_of(domain));
+            } else {
+                println!("purged {} path(s) from the cloudflare cache", paths.len());
+            }
+            Ok(())
+        }
+        DnsProviderKind::Route53 => Err(RumiError::Config(
+            "route53 is not a cdn, --purge-cdn only works with cloudflare".to_string(),
+        )),
+    }
+}
+
+impl DnsProvider for CloudflareProvider {
+    fn list_records(&self, domain: &str) -> RumiResult<Vec<DnsRecord>> {
+        let zone_id = self.zone_id(domain)?;
+        let response = self.call(
+            "GET",
+            &format!("/zones/{}/dns_records?per_page=100", zone_id),
+            None,
+        )?;
+        let records = response["result"]
+            .as_array()
+            .map(|records| {
+                records
+                    .iter()
+                    .map(|r| DnsRecord {
+                        name: r["name"].as_str().unwrap_or_default().to_string(),
+                        record_type: r["type"].as_str().unwrap_or_default().to_string(),
+                        content: r["content"].as_str().unwrap_or_default().to_string(),
+                        ttl: r["ttl"].as_u64().unwrap_or(0) as u32,
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(records)
+    }
+
+    fn upsert_record(&self, domain: &str, record: &DnsRecord) -> RumiResult<()> {
+        let zone_id = self.zone_id(domain)?;
+        let existing = self.call(
+            "GET",
+            &format!(
+                "/zones/{}/dns_records?type={}&name={}",
+                zone_id, record.record_type, record.name
+            ),
+            None,
+        )?;
+        let body = serde_json::to_string(&serde_json::json!({
+            "type": record.record_type,
+            "name": record.name,
+            "content": record.content,
+            "ttl": record.ttl,
+        }))?;
+        match existing["result"][0]["id"].as_str() {
+            Some(record_id) => {
+                self.call(
+                    "PUT",
+                    &format!("/zones/{}/dns_records/{}", zone_id, record_id),
+                    Some(&body),
+                )?;
+            }
+            None => {
+                self.call(
+                    "POST",
+                    &format!("/zones/{}/dns_records", zone_id),
+                    Some(&body),
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The Route53 backend, going through the aws cli so credentials come from
+/// the usual environment variables or profile without rumi signing requests
+/// itself. Upserting TXT records makes DNS-01 certificate challenges work
+/// for zones hosted on AWS.
+pub struct Route53Provider;
+
+impl Route53Provider {
+    pub fn new() -> Self {
+        Route53Provider
+    }
+
+    fn aws(&self, args: &[&str]) -> RumiResult<Value> {
+        let output = std::process::Command::new("aws")
+            .args(args)
+            .arg("--output")
+            .arg("json")
+            .output()
+            .map_err(|e| {
+                RumiError::Config(format!(
+                    "could not run the aws cli (is it installed?): {}",
+                    e
+                ))
+            })?;
+        if !output.status.success() {
+            return Err(RumiError::CommandFailed(format!(
+                "aws {} failed: {}",
+                args.join(" "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        serde_json::from_slice(&output.stdout).map_err(RumiError::from)
+    }
+
+    fn zone_id(&self, domain: &str) -> RumiResult<String> {
+        let zone = format!("{}.", zone_of(domain));
+        let response = self.aws(&[
+            "route53",
+            "list-hosted-zones-by-name",
+            "--dns-name",
+            &zone,
+            "--max-items",
+            "1",
+        ])?;
+        let found = &response["HostedZones"][0];
+        if found["Name"].as_str() != Some(zone.as_str()) {
+            return Err(RumiError::Config(format!(
+                "no route53 hosted zone named {}",
+                zone.trim_end_matches('.')
+            )));
+        }
+        found["Id"]
+            .as_str()
+            .map(|id| id.trim_start_matches("/hostedzone/").to_string())
+            .ok_or_else(|| RumiError::Network("route53 zone has no id".to_string()))
+    }
+}
+
+impl Default for Route53Provider {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsProvider for Route53Provider {
+    fn list_records(&self, domain: &str) -> RumiResult<Vec<DnsRecord>> {
+        let zone_id = self.zone_id(domain)?;
+        let response = self.aws(&[
+            "route53",
+            "list-resource-record-sets",
+            "--hosted-zone-id",
+            &zone_id,
+        ])?;
+        let mut records = Vec::new();
+        for set in response["ResourceRecordSets"].as_array().unwrap_or(&vec![]) {
+            let name = set["Name"]
+                .as_str()
+                .unwrap_or_default()
+                .trim_end_matches('.')
+                .to_string();
+            let record_type = set["Type"].as_str().unwrap_or_default().to_string();
+            let ttl = set["TTL"].as_u64().unwrap_or(0) as u32;
+            for value in set["ResourceRecords"].as_array().unwrap_or(&vec![]) {
+                records.push(DnsRecord {
+                    name: name.clone(),
+                    record_type: record_type.clone(),
+                    content: value["Value"].as_str().unwrap_or_default().to_string(),
+                    ttl,
+                });
+            }
+        }
+        Ok(records)
+    }
+
+    fn upsert_record(&self, domain: &str, record: &DnsRecord) -> RumiResult<()> {
+        let zone_id = self.zone_id(domain)?;
+        // TXT record values have to be quoted in route53
+        let value = if record.record_type == "TXT" && !record.content.starts_with('"') {
+            format!("\"{}\"", record.content)
+        } else {
+            record.content.clone()
+        };
+        let batch = serde_json::to_string(&serde_json::json!({
+            "Changes": [{
+                "Action": "UPSERT",
+                "ResourceRecordSet": {
+                    "Name": record.name,
+                    "Type": record.record_type,
+                    "TTL": record.ttl,
+                    "ResourceRecords": [{ "Value": value }],
+                }
+            }]
+        }))?;
+        self.aws(&[
+            "route53",
+            "change-resource-record-sets",
+            "--hosted-zone-id",
+            &zone_id,
+            "--change-batch",
+            &batch,
+        ])?;
+        Ok(())
+    }
+}
+
+/// The `dns list` command: print every record of a zone.
+pub fn list_command(config: &RumiConfig, zone: &str) -> RumiResult<()> {
+    let provider = provider_from_config(config)?;
+    let records = provider.list_records(zone)?;
+    println!("{:<35} {:<8} {:<40} {:>6}", "NAME", "TYPE", "CONTENT", "TTL");
+    for record in records {
+        println!(
+            "{:<35} {:<8} {:<40} {:>6}",
+            record.name, record.record_type, record.content, record.ttl
+        );
+    }
+    Ok(())
+}
+
+/// The `dns set` command: create or update one record.
+pub fn set_command(
+    config: &RumiConfig,
+    name: &str,
+    record_type: &str,
+    content: &str,
+    ttl: u32,
+) -> RumiResult<()> {
+    let provider = provider_from_config(config)?;
+    let record = DnsRecord {
+        name: name.to_string(),
+        record_type: record_type.to_uppercase(),
+        content: content.to_string(),
+        ttl,
+    };
+    provider.upsert_record(name, &record)?;
+    println!("{} {} -> {}", record.record_type, record.name, record.content);
+    Ok(())
+}
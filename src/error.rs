@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// Errors that can happen while running rumi commands.
+#[derive(Debug)]
+pub enum RumiError {
+    Io(std::io::Error),
+    Ssh(ssh2::Error),
+    Config(String),
+    Network(String),
+    Tls(String),
+    Serialization(serde_json::Error),
+    CommandFailed(String),
+    DeploymentNotFound(String),
+}
+
+/// The remediation hint for a known remote failure, recognised from its
+/// stderr, so frequent problems come with what to do about them instead of
+/// raw output only.
+pub fn hint_for(stderr: &str) -> Option<&'static str> {
+    if stderr.contains("sudo: a password is required")
+        || stderr.contains("sudo: no tty present")
+    {
+        return Some(
+            "the remote user cannot sudo without a password; give it NOPASSWD in sudoers \
+             (users add does this) or set the host's escalation method accordingly",
+        );
+    }
+    if stderr.contains("too many certificates") || stderr.contains("rateLimited") {
+        return Some(
+            "let's encrypt rate limit hit for this domain; wait a week or test against \
+             the staging environment first",
+        );
+    }
+    if stderr.contains("DNS problem") || stderr.contains("NXDOMAIN") {
+        return Some(
+            "the domain does not resolve to this server yet; check the dns records \
+             (rumi2 dns list) and wait for propagation before retrying",
+        );
+    }
+    if stderr.contains("bind() to 0.0.0.0:80 failed") || stderr.contains("Address already in use")
+    {
+        return Some(
+            "port 80 is already taken on the host, usually by apache2 or a stray nginx; \
+             stop the other server before installing",
+        );
+    }
+    if stderr.contains("/var/lib/dpkg/lock") || stderr.contains("dpkg frontend lock") {
+        return Some(
+            "apt is busy (unattended-upgrades most likely); wait for it to finish and \
+             run the command again",
+        );
+    }
+    if stderr.contains("Permission denied") && stderr.contains("/var/www") {
+        return Some(
+            "the remote user cannot write to /var/www; deploys stage through /tmp and \
+             move with sudo, so check the host's escalation settings",
+        );
+    }
+    None
+}
+
+/// Append the matching hint, when there is one, to a command-failure message.
+pub(crate) fn command_failed(message: String) -> RumiError {
+    match hint_for(&message) {
+        Some(hint) => RumiError::CommandFailed(format!("{}\nhint: {}", message, hint)),
+        None => RumiError::CommandFailed(message),
+    }
+}
+
+impl fmt::Display for RumiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RumiError::Io(e) => write!(f, "io error: {}", e),
+            RumiError::Ssh(e) => write!(f, "ssh error: {}", e),
+            RumiError::Config(msg) => write!(f, "config error: {}", msg),
+            RumiError::Network(msg) => write!(f, "network error: {}", msg),
+            RumiError::Tls(msg) => write!(f, "tls error: {}", msg),
+            RumiError::Serialization(e) => write!(f, "serialization error: {}", e),
+            RumiError::CommandFailed(msg) => write!(f, "command failed: {}", msg),
+            RumiError::DeploymentNotFound(name) => {
+                write!(f, "no deployment named '{}' in the config", name)
+            }
+        }
+    }
+}
+
+impl std::error::Error for RumiError {}
+
+impl From<std::io::Error> for RumiError {
+    fn from(e: std::io::Error) -> Self {
+        RumiError::Io(e)
+    }
+}
+
+impl From<ssh2::Error> for RumiError {
+    fn from(e: ssh2::Error) -> Self {
+        RumiError::Ssh(e)
+    }
+}
+
+impl From<serde_json::Error> for RumiError {
+    fn from(e: serde_json::Error) -> Self {
+        RumiError::Serialization(e)
+    }
+}
+
+pub type RumiResult<T> = Result<T, RumiError>;
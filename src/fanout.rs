@@ -0,0 +1,163 @@
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::config::SshConfig;
+use crate::error::{RumiError, RumiResult};
+
+/// How many hosts run at once when no other bound is given.
+pub const DEFAULT_MAX_PARALLEL: usize = 4;
+
+/// Runs one job against many hosts, a bounded number at a time, and collects
+/// every outcome into a single report. Group commands (cleanup, multi-host
+/// deploys, bulk exec) share this instead of hand-rolling their thread pools.
+pub struct Fanout {
+    max_parallel: usize,
+    fail_fast: bool,
+    timeout: Option<Duration>,
+}
+
+impl Default for Fanout {
+    fn default() -> Self {
+        Fanout {
+            max_parallel: DEFAULT_MAX_PARALLEL,
+            fail_fast: false,
+            timeout: None,
+        }
+    }
+}
+
+impl Fanout {
+    pub fn new() -> Self {
+        Fanout::default()
+    }
+
+    pub fn max_parallel(mut self, max_parallel: usize) -> Self {
+        self.max_parallel = max_parallel.max(1);
+        self
+    }
+
+    /// Stop starting new hosts once one has failed. Hosts already running
+    /// still finish and appear in the report.
+    pub fn fail_fast(mut self) -> Self {
+        self.fail_fast = true;
+        self
+    }
+
+    /// Give up on a host after this long and report it as timed out.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Run the job against every host. The job returns a one-line detail for
+    /// the report on success.
+    pub fn run<F>(&self, hosts: &[SshConfig], job: F) -> FanoutReport
+    where
+        F: Fn(&SshConfig) -> RumiResult<String> + Send + Sync + 'static,
+    {
+        let job = Arc::new(job);
+        let mut reports = Vec::new();
+        for chunk in hosts.chunks(self.max_parallel) {
+            let mut pending = Vec::new();
+            for ssh in chunk {
+                let host = ssh.host.clone();
+                let ssh = ssh.clone();
+                let job = Arc::clone(&job);
+                let (tx, rx) = mpsc::channel();
+                let started = Instant::now();
+                std::thread::spawn(move || {
+                    let _ = tx.send(job(&ssh));
+                });
+                pending.push((host, started, rx));
+            }
+            let mut wave_failed = false;
+            for (host, started, rx) in pending {
+                let result = match self.timeout {
+                    Some(timeout) => rx.recv_timeout(timeout).unwrap_or_else(|_| {
+                        // the worker thread is abandoned, nothing to join
+                        Err(RumiError::Network(format!(
+                            "timed out after {}s",
+                            timeout.as_secs()
+                        )))
+                    }),
+                    None => rx.recv().expect("fan-out worker thread panicked"),
+                };
+                let report = HostReport {
+                    host,
+                    ok: result.is_ok(),
+                    detail: match result {
+                        Ok(detail) => detail,
+                        Err(e) => e.to_string(),
+                    },
+                    duration_secs: started.elapsed().as_secs_f64(),
+                };
+                wave_failed |= !report.ok;
+                reports.push(report);
+            }
+            if self.fail_fast && wave_failed {
+                break;
+            }
+        }
+        FanoutReport { reports }
+    }
+}
+
+/// One host's outcome in a fan-out run.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostReport {
+    pub host: String,
+    pub ok: bool,
+    pub detail: String,
+    pub duration_secs: f64,
+}
+
+/// Everything a fan-out run produced, printable as a table or as json.
+#[derive(Debug, Clone, Serialize)]
+pub struct FanoutReport {
+    pub reports: Vec<HostReport>,
+}
+
+impl FanoutReport {
+    pub fn failed(&self) -> bool {
+        self.reports.iter().any(|r| !r.ok)
+    }
+
+    pub fn print(&self, json: bool) {
+        if json {
+            println!("{}", serde_json::to_string_pretty(self).unwrap());
+            return;
+        }
+        println!("{:<25} {:<8} {:>8} DETAIL", "HOST", "STATUS", "TIME");
+        for report in &self.reports {
+            println!(
+                "{:<25} {} {:>7.1}s {}",
+                report.host,
+                if report.ok {
+                    crate::output::green(format!("{:<8}", "ok"))
+                } else {
+                    crate::output::red(format!("{:<8}", "failed"))
+                },
+                report.duration_secs,
+                report.detail
+            );
+        }
+    }
+
+    /// Turn the report into the command's exit outcome: an error listing the
+    /// failed hosts when any job failed.
+    pub fn into_result(self) -> RumiResult<()> {
+        if !self.failed() {
+            return Ok(());
+        }
+        let failed: Vec<String> = self
+            .reports
+            .iter()
+            .filter(|r| !r.ok)
+            .map(|r| format!("{}: {}", r.host, r.detail))
+            .collect();
+        Err(RumiError::CommandFailed(failed.join("; ")))
+    }
+}
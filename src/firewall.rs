@@ -0,0 +1,239 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{DatabaseEngine, DeploymentType, RumiConfig, SshConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// Extra rules declared by hand in the config's firewall block, each in
+/// "port/proto" form like "8080/tcp".
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FirewallConfig {
+    #[serde(default)]
+    pub extra_rules: Vec<String>,
+}
+
+/// One desired rule, derived from the deployments on a host.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FirewallRule {
+    pub port: u16,
+    /// "tcp" or "udp".
+    pub proto: String,
+    /// Which deployment wants this open, for the status output.
+    pub reason: String,
+}
+
+impl FirewallRule {
+    fn new(port: u16, proto: &str, reason: &str) -> Self {
+        FirewallRule {
+            port,
+            proto: proto.to_string(),
+            reason: reason.to_string(),
+        }
+    }
+
+    /// The "80/tcp" form ufw uses.
+    pub fn spec(&self) -> String {
+        format!("{}/{}", self.port, self.proto)
+    }
+}
+
+/// Every rule a host should have open, derived from the deployments that
+/// live on it plus the hand-declared extras. Ssh is always kept open so
+/// converging can never lock rumi out.
+pub fn desired_rules(config: &RumiConfig, ssh: &SshConfig) -> RumiResult<Vec<FirewallRule>> {
+    let mut rules = vec![FirewallRule::new(ssh.port, "tcp", "ssh")];
+    for deployment in &config.deployments {
+        if config.ssh_for_deployment(deployment)?.host != ssh.host {
+            continue;
+        }
+        let name = deployment.name.as_str();
+        match &deployment.deployment_type {
+            DeploymentType::Website { .. }
+            | DeploymentType::Server { .. }
+            | DeploymentType::Php { .. }
+            | DeploymentType::Python { .. }
+            | DeploymentType::DockerHost { .. }
+            | DeploymentType::Observability { .. } => {
+                rules.push(FirewallRule::new(80, "tcp", name));
+                rules.push(FirewallRule::new(443, "tcp", name));
+            }
+            DeploymentType::Ethereum { .. } => {
+                rules.push(FirewallRule::new(80, "tcp", name));
+                rules.push(FirewallRule::new(443, "tcp", name));
+                // geth p2p
+                rules.push(FirewallRule::new(30303, "tcp", name));
+                rules.push(FirewallRule::new(30303, "udp", name));
+            }
+            DeploymentType::Database {
+                engine,
+                listen_address,
+                ..
+            } => {
+                if listen_address.is_some() {
+                    let port = match engine {
+                        DatabaseEngine::Postgres => 5432,
+                        DatabaseEngine::Mysql => 3306,
+                    };
+                    rules.push(FirewallRule::new(port, "tcp", name));
+                }
+            }
+            DeploymentType::Redis { open_firewall, .. } => {
+                if *open_firewall {
+                    rules.push(FirewallRule::new(6379, "tcp", name));
+                }
+            }
+            DeploymentType::Kubernetes { .. } | DeploymentType::SftpSite { .. } => {}
+        }
+    }
+    if let Some(firewall) = &config.firewall {
+        for extra in &firewall.extra_rules {
+            let (port, proto) = extra.split_once('/').unwrap_or((extra.as_str(), "tcp"));
+            let port: u16 = port.parse().map_err(|_| {
+                RumiError::Config(format!("bad firewall rule '{}', expected port/proto", extra))
+            })?;
+            rules.push(FirewallRule::new(port, proto, "config"));
+        }
+    }
+    // several deployments wanting the same port collapse into one rule
+    let mut by_spec: BTreeMap<String, FirewallRule> = BTreeMap::new();
+    for rule in rules {
+        by_spec.entry(rule.spec()).or_insert(rule);
+    }
+    Ok(by_spec.into_values().collect())
+}
+
+/// The "port/proto" specs currently allowed on the remote ufw.
+pub fn current_rules(session: &RumiSession) -> RumiResult<Vec<String>> {
+    let output = session.execute_checked("sudo ufw status")?;
+    let mut specs = Vec::new();
+    for line in output.stdout.lines() {
+        let mut parts = line.split_whitespace();
+        let (Some(target), Some(action)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        if action != "ALLOW" || target.contains("(v6)") {
+            continue;
+        }
+        // bare port entries ("80") mean both protocols to ufw
+        if target.contains('/') {
+            specs.push(target.to_string());
+        } else if target.chars().all(|c| c.is_ascii_digit()) {
+            specs.push(format!("{}/tcp", target));
+            specs.push(format!("{}/udp", target));
+        } else {
+            // app profiles like "Nginx HTTP" from older installs
+            specs.push(target.to_string());
+        }
+    }
+    Ok(specs)
+}
+
+/// Allow a list of "port/proto" specs, the one helper every provisioning
+/// command goes through instead of inlining ufw invocations.
+pub fn allow_ports(session: &RumiSession, specs: &[&str]) -> RumiResult<()> {
+    for spec in specs {
+        session.execute_checked(&format!("sudo ufw allow {}", spec))?;
+    }
+    Ok(())
+}
+
+/// Converge a host's ufw onto the desired rules: install and enable ufw if
+/// needed, add what is missing, report what is extra without removing it.
+pub fn apply_host(config: &RumiConfig, ssh: &SshConfig, session: &RumiSession) -> RumiResult<()> {
+    let desired = desired_rules(config, ssh)?;
+    session.execute_checked("sudo apt-get -y install ufw > /dev/null 2>&1 || true")?;
+    // make sure ssh is allowed before enabling, then enable non-interactively
+    session.execute_checked(&format!("sudo ufw allow {}/tcp", ssh.port))?;
+    session.execute_checked("sudo ufw --force enable")?;
+
+    let current = current_rules(session)?;
+    let mut added = 0;
+    for rule in &desired {
+        if current.contains(&rule.spec()) {
+            continue;
+        }
+        session.execute_checked(&format!("sudo ufw allow {}", rule.spec()))?;
+        println!("{}: allowed {} ({})", ssh.host, rule.spec(), rule.reason);
+        added += 1;
+    }
+    let extra: Vec<&String> = current
+        .iter()
+        .filter(|spec| !desired.iter().any(|rule| &rule.spec() == *spec))
+        .collect();
+    if !extra.is_empty() {
+        println!(
+            "{}: {} rule(s) not derived from the config, left in place: {}",
+            ssh.host,
+            extra.len(),
+            extra
+                .iter()
+                .map(|s| s.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+    }
+    if added == 0 {
+        println!("{}: firewall already converged", ssh.host);
+    }
+    Ok(())
+}
+
+/// Show desired vs. present per host without changing anything.
+pub fn status_host(config: &RumiConfig, ssh: &SshConfig, session: &RumiSession) -> RumiResult<bool> {
+    let desired = desired_rules(config, ssh)?;
+    let current = current_rules(session)?;
+    let mut drift = false;
+    println!("{}:", ssh.host);
+    println!("  {:<12} {:<8} REASON", "RULE", "STATE");
+    for rule in &desired {
+        let present = current.contains(&rule.spec());
+        if !present {
+            drift = true;
+        }
+        println!(
+            "  {:<12} {} {}",
+            rule.spec(),
+            if present {
+                crate::output::green(format!("{:<8}", "ok"))
+            } else {
+                crate::output::red(format!("{:<8}", "missing"))
+            },
+            rule.reason
+        );
+    }
+    for spec in &current {
+        if !desired.iter().any(|rule| &rule.spec() == spec) {
+            drift = true;
+            println!("  {:<12} {:<8} not derived from the config", spec, "extra");
+        }
+    }
+    Ok(drift)
+}
+
+/// The hosts the firewall commands operate on: the named deployment's host,
+/// or every distinct host in the config.
+pub fn target_hosts(config: &RumiConfig, name: Option<&str>) -> RumiResult<Vec<SshConfig>> {
+    let mut hosts: Vec<SshConfig> = Vec::new();
+    match name {
+        Some(name) => {
+            let deployment = config.find_deployment(name)?;
+            hosts.push(config.ssh_for_deployment(deployment)?.clone());
+        }
+        None => {
+            for deployment in &config.deployments {
+                let ssh = config.ssh_for_deployment(deployment)?;
+                if !hosts.iter().any(|h| h.host == ssh.host) {
+                    hosts.push(ssh.clone());
+                }
+            }
+        }
+    }
+    if hosts.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments in the config, nothing to converge".to_string(),
+        ));
+    }
+    Ok(hosts)
+}
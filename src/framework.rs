@@ -0,0 +1,162 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use crate::error::{RumiError, RumiResult};
+
+/// Static-site generators rumi knows how to build and serve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framework {
+    Hugo,
+    Jekyll,
+    Astro,
+    NextExport,
+    Vite,
+}
+
+impl FromStr for Framework {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hugo" => Ok(Framework::Hugo),
+            "jekyll" => Ok(Framework::Jekyll),
+            "astro" => Ok(Framework::Astro),
+            "next" => Ok(Framework::NextExport),
+            "vite" => Ok(Framework::Vite),
+            other => Err(format!(
+                "unknown framework '{}', expected auto, hugo, jekyll, astro, next or vite",
+                other
+            )),
+        }
+    }
+}
+
+impl Framework {
+    /// Guess the framework from the files in a project directory.
+    pub fn detect(project_dir: &Path) -> Option<Framework> {
+        let has = |name: &str| project_dir.join(name).exists();
+        if has("hugo.toml") || has("config.toml") && has("content") {
+            return Some(Framework::Hugo);
+        }
+        if has("_config.yml") {
+            return Some(Framework::Jekyll);
+        }
+        if has("astro.config.mjs") || has("astro.config.ts") || has("astro.config.js") {
+            return Some(Framework::Astro);
+        }
+        if has("next.config.js") || has("next.config.mjs") || has("next.config.ts") {
+            return Some(Framework::NextExport);
+        }
+        if has("vite.config.js") || has("vite.config.ts") || has("vite.config.mjs") {
+            return Some(Framework::Vite);
+        }
+        None
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Framework::Hugo => "hugo",
+            Framework::Jekyll => "jekyll",
+            Framework::Astro => "astro",
+            Framework::NextExport => "next",
+            Framework::Vite => "vite",
+        }
+    }
+
+    /// The command that produces the production build.
+    pub fn build_command(&self) -> &'static str {
+        match self {
+            Framework::Hugo => "hugo --minify",
+            Framework::Jekyll => "bundle exec jekyll build",
+            Framework::Astro => "npm run build",
+            Framework::NextExport => "npm run build",
+            Framework::Vite => "npm run build",
+        }
+    }
+
+    /// Where that build command leaves the site.
+    pub fn output_dir(&self) -> &'static str {
+        match self {
+            Framework::Hugo => "public",
+            Framework::Jekyll => "_site",
+            Framework::Astro => "dist",
+            Framework::NextExport => "out",
+            Framework::Vite => "dist",
+        }
+    }
+
+    /// Extra nginx directives the framework's output expects: its 404 page
+    /// and long-lived caching for the hashed asset directory.
+    pub fn nginx_extras(&self) -> &'static str {
+        match self {
+            Framework::Hugo | Framework::Jekyll => {
+                r#"
+                 error_page 404 /404.html;
+"#
+            }
+            Framework::Astro | Framework::Vite => {
+                r#"
+                 error_page 404 /404.html;
+                 location /assets/ {
+                      expires 30d;
+                      add_header Cache-Control "public, immutable";
+                 }
+"#
+            }
+            Framework::NextExport => {
+                r#"
+                 error_page 404 /404.html;
+                 location /_next/static/ {
+                      expires 30d;
+                      add_header Cache-Control "public, immutable";
+                 }
+"#
+            }
+        }
+    }
+}
+
+/// Resolve "--framework auto" (or an explicit name) against a project dir.
+pub fn resolve(flag: &str, project_dir: &Path) -> RumiResult<Framework> {
+    if flag == "auto" {
+        Framework::detect(project_dir).ok_or_else(|| {
+            RumiError::Config(format!(
+                "could not detect a framework in {}, pass --framework explicitly",
+                project_dir.display()
+            ))
+        })
+    } else {
+        flag.parse().map_err(RumiError::Config)
+    }
+}
+
+/// Run the framework's build locally and return the output directory to
+/// upload instead of the project dir itself.
+pub fn build(framework: Framework, project_dir: &Path) -> RumiResult<PathBuf> {
+    println!(
+        "building {} project with '{}'",
+        framework.name(),
+        framework.build_command()
+    );
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(framework.build_command())
+        .current_dir(project_dir)
+        .status()?;
+    if !status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "'{}' failed in {}",
+            framework.build_command(),
+            project_dir.display()
+        )));
+    }
+    let output = project_dir.join(framework.output_dir());
+    if !output.is_dir() {
+        return Err(RumiError::CommandFailed(format!(
+            "build finished but {} does not exist",
+            output.display()
+        )));
+    }
+    Ok(output)
+}
@@ -0,0 +1,117 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+use openssl::ssl::{SslConnector, SslMethod};
+
+use crate::error::{RumiError, RumiResult};
+
+/// A minimal http(s) client for the handful of provider apis and webhooks
+/// rumi talks to: one request per connection, no redirects, no keep-alive.
+pub(crate) fn request(
+    method: &str,
+    url: &str,
+    headers: &[(&str, &str)],
+    body: Option<&str>,
+) -> RumiResult<(u16, String)> {
+    let (tls, rest) = match url.split_once("://") {
+        Some(("https", rest)) => (true, rest),
+        Some(("http", rest)) => (false, rest),
+        _ => {
+            return Err(RumiError::Config(format!(
+                "url '{}' must start with http:// or https://",
+                url
+            )))
+        }
+    };
+    let (host_port, path) = match rest.split_once('/') {
+        Some((host, path)) => (host, format!("/{}", path)),
+        None => (rest, "/".to_string()),
+    };
+    let (host, port) = match host_port.split_once(':') {
+        Some((host, port)) => (
+            host,
+            port.parse::<u16>()
+                .map_err(|_| RumiError::Config(format!("bad port in url '{}'", url)))?,
+        ),
+        None => (host_port, if tls { 443 } else { 80 }),
+    };
+    let mut request = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rumi2\r\nConnection: close\r\n",
+        method, path, host
+    );
+    for (name, value) in headers {
+        request.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(body) = body {
+        request.push_str(&format!("Content-Length: {}\r\n", body.len()));
+    }
+    request.push_str("\r\n");
+    if let Some(body) = body {
+        request.push_str(body);
+    }
+
+    let stream = TcpStream::connect((host, port))
+        .map_err(|e| RumiError::Network(format!("failed to connect to {}: {}", host, e)))?;
+    let response = if tls {
+        let connector = SslConnector::builder(SslMethod::tls())
+            .map_err(|e| RumiError::Tls(e.to_string()))?
+            .build();
+        let mut stream = connector
+            .connect(host, stream)
+            .map_err(|e| RumiError::Tls(format!("tls handshake with {} failed: {}", host, e)))?;
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        response
+    } else {
+        let mut stream = stream;
+        stream.write_all(request.as_bytes())?;
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        response
+    };
+
+    let response = String::from_utf8_lossy(&response).into_owned();
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| RumiError::Network(format!("{} sent an invalid response", host)))?;
+    let status: u16 = head
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| RumiError::Network(format!("{} sent an invalid response", host)))?;
+    let chunked = head
+        .lines()
+        .any(|l| l.to_ascii_lowercase().starts_with("transfer-encoding:") && l.contains("chunked"));
+    let body = if chunked { dechunk(body) } else { body.to_string() };
+    Ok((status, body))
+}
+
+/// Undo chunked transfer encoding; sizes are hex lines between the chunks.
+fn dechunk(body: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = body;
+    while let Some((size_line, after)) = rest.split_once("\r\n") {
+        let Ok(size) = usize::from_str_radix(size_line.trim(), 16) else {
+            break;
+        };
+        if size == 0 || after.len() < size {
+            break;
+        }
+        decoded.push_str(&after[..size]);
+        rest = after[size..].trim_start_matches("\r\n");
+    }
+    decoded
+}
+
+/// POST a json body and return the status code.
+pub(crate) fn post_json(url: &str, body: &str) -> RumiResult<u16> {
+    let (status, _) = request(
+        "POST",
+        url,
+        &[("Content-Type", "application/json")],
+        Some(body),
+    )?;
+    Ok(status)
+}
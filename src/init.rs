@@ -0,0 +1,133 @@
+use std::path::Path;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::framework::Framework;
+
+/// What `rumi2 init` figured out about the project in the current directory.
+enum ProjectKind {
+    /// A framework site built locally, deployed from its build output.
+    Framework(Framework),
+    /// A rust server binary, deployed from target/release.
+    RustBinary(String),
+    /// A plain static site, deployed from this directory.
+    StaticSite(String),
+}
+
+fn detect(project_dir: &Path) -> Option<ProjectKind> {
+    if let Some(framework) = Framework::detect(project_dir) {
+        return Some(ProjectKind::Framework(framework));
+    }
+    if project_dir.join("Cargo.toml").exists() {
+        return Some(ProjectKind::RustBinary(cargo_package_name(project_dir)?));
+    }
+    for dir in [".", "dist", "public", "build"] {
+        if project_dir.join(dir).join("index.html").exists() {
+            return Some(ProjectKind::StaticSite(dir.to_string()));
+        }
+    }
+    None
+}
+
+fn cargo_package_name(project_dir: &Path) -> Option<String> {
+    let manifest = std::fs::read_to_string(project_dir.join("Cargo.toml")).ok()?;
+    manifest
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("name").map(str::to_string))
+        .and_then(|rest| {
+            rest.split('"').nth(1).map(str::to_string)
+        })
+}
+
+/// The `init` command: look at the current directory, propose a deployment
+/// for what lives there, write a project-local rumi.json and print the exact
+/// commands that deploy it.
+pub fn init_command(config_path: &Path, domain: &str) -> RumiResult<()> {
+    if config_path.exists() {
+        return Err(RumiError::Config(format!(
+            "{} already exists, delete it first if you want to start over",
+            config_path.display()
+        )));
+    }
+    let project_dir = Path::new(".");
+    let kind = detect(project_dir).ok_or_else(|| {
+        RumiError::Config(
+            "could not tell what this project is: no framework markers, Cargo.toml or \
+             index.html found — write rumi.json by hand or run from the project root"
+                .to_string(),
+        )
+    })?;
+    let name = domain.split('.').next().unwrap_or(domain).to_string();
+    let (deployment_type, described, deploy_hint) = match kind {
+        ProjectKind::Framework(framework) => (
+            DeploymentType::Website {
+                dist_path: ".".to_string(),
+            },
+            format!("a {} site built locally", framework.name()),
+            format!(
+                "rumi2 hosting install --ssh_host <server> --ssh_user <user> \
+                 --ssh_cert_public_key <pub> --ssh_cert_private_key <key> --ssh_password '' \
+                 --domain {} --dist_path . --framework auto --version_id v1",
+                domain
+            ),
+        ),
+        ProjectKind::RustBinary(package) => (
+            DeploymentType::Server {
+                binary_path: format!("target/release/{}", package),
+                port: 8080,
+                proxy: None,
+                artifacts: Default::default(),
+                env: Default::default(),
+                env_file: None,
+            },
+            format!("a rust server binary ('{}')", package),
+            format!(
+                "cargo build --release, then deploy target/release/{} to your host",
+                package
+            ),
+        ),
+        ProjectKind::StaticSite(dir) => (
+            DeploymentType::Website {
+                dist_path: dir.clone(),
+            },
+            format!("a static site served from {}/", dir),
+            format!(
+                "rumi2 hosting install --ssh_host <server> --ssh_user <user> \
+                 --ssh_cert_public_key <pub> --ssh_cert_private_key <key> --ssh_password '' \
+                 --domain {} --dist_path {} --version_id v1",
+                domain, dir
+            ),
+        ),
+    };
+    let mut config = RumiConfig::default();
+    config.deployments.push(DeploymentConfig {
+        name: name.clone(),
+        domain: domain.to_string(),
+        deployment_type,
+        ssh: None,
+        ssh_profile: None,
+        ssh_targets: Vec::new(),
+        repo: None,
+        project_path: None,
+        health_url: None,
+        expected_status: None,
+        health_check: None,
+        challenge: None,
+        ssl_email: None,
+        nginx_extra: Vec::new(),
+    });
+    config.save_to_file(config_path)?;
+    println!("this looks like {}", described);
+    println!(
+        "wrote {} with deployment '{}' for {}",
+        config_path.display(),
+        name,
+        domain
+    );
+    println!();
+    println!("set the ssh connection the deployment will use:");
+    println!("  rumi2 config add-ssh --host <server> --user <user> --private_key_path ~/.ssh/id_ed25519");
+    println!("then deploy with:");
+    println!("  {}", deploy_hint);
+    Ok(())
+}
@@ -1,6 +1,34 @@
-use ssh2::Session;
-use std::net::TcpStream;
+pub mod alerts;
+pub mod artifact;
+pub mod backup;
+pub mod blobstore;
+pub mod canary;
+pub mod certs;
+pub mod ci;
 pub mod commands;
+pub mod completion;
+pub mod config;
+pub mod crypt;
+pub mod dns;
+pub mod error;
+pub mod fanout;
+pub mod firewall;
+pub mod framework;
+pub(crate) mod http;
+pub mod init;
+pub mod listen;
+pub mod logs;
+pub mod monitor;
+pub mod output;
+pub mod plan;
+pub mod release;
+pub mod render;
+pub mod secrets;
+pub mod security;
+pub mod session;
+pub mod shell;
+pub mod state;
+pub mod transcript;
 
 pub const SERVER_BIN_PATH: &str = "/usr/local/bin";
 pub const NGINX_WEB_CONFIG_PATH: &str = "/etc/nginx/sites-available"; // where to put the config files for websites that are available
@@ -10,32 +38,6 @@ pub const SSL_CERTIFICATE_PATH: &str = "/etc/letsencrypt/live"; // where to put
 pub const SSL_CERTIFICATE_KEY_PATH: &str = "/etc/letsencrypt/live"; // where to put the ssl certificate key
 pub const ETH_GETH_NGINX_CONFIG_PATH: &str = "/etc/nginx/conf.d/geth.conf"; // where to put the config file for ethereum
 
-pub struct Rumi2 {}
-
-impl Rumi2 {
-    pub fn start(
-        host: String,
-        username: String,
-        pubkeydata: String,
-        privatekeydata: String,
-        passphrase: String,
-    ) -> Session {
-        let tcp = TcpStream::connect(&format!("{host}:22")).expect("Failed to connect to tcp");
-        let mut session = Session::new().expect("Session could not be started");
-        session.set_tcp_stream(tcp);
-        session.handshake().expect("handshade didn't worked");
-        session
-            .userauth_pubkey_memory(
-                &username,
-                Some(&pubkeydata),
-                &privatekeydata,
-                Some(&passphrase),
-            )
-            .expect("Unable to authenticate with the given parameters");
-        session
-    }
-}
-
 pub mod ufw {
     use std::io::Read;
 
@@ -44,7 +46,7 @@ pub mod ufw {
 
     /// The install command for ufw
     ///
-    pub fn install<'a>(session: &'a Session) {
+    pub fn install(session: &Session) {
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo apt-get -y install ufw");
         let mut s = String::new();
@@ -53,14 +55,14 @@ pub mod ufw {
         close_channel(&mut chanel);
     }
 
-    pub fn allow_nginx_http<'a>(session: &'a Session) {
+    pub fn allow_nginx_http(session: &Session) {
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo ufw allow 'Nginx HTTP");
         assert!(command.is_ok(), "Failed to allow Nginx HTTP");
         close_channel(&mut chanel);
     }
 
-    pub fn allow_port_and_443<'a>(session: &'a Session) {
+    pub fn allow_port_and_443(session: &Session) {
         let mut chanel = new_channel(session);
         let command =
             chanel.exec("sudo ufw allow 80 && sudo ufw allow 443 && sudo systemctl restart nginx");
@@ -82,7 +84,7 @@ pub mod nginx {
     use ssh2::Session;
     use std::io::Read;
 
-    pub fn install<'a>(session: &'a Session) {
+    pub fn install(session: &Session) {
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo apt install -y nginx");
         let mut s = String::new();
@@ -91,13 +93,6 @@ pub mod nginx {
         close_channel(&mut chanel);
     }
 
-    pub fn enable_write_to_folders<'a>(session: &'a Session) {
-        let mut chanel = new_channel(session);
-        let command = chanel.exec("sudo chmod 777 /var/www/ && sudo chmod 777 /etc/nginx/sites-available/ && sudo chmod 777 /etc/nginx/sites-enabled/");
-        assert!(command.is_ok(), "Failed to grant permissions");
-        close_channel(&mut chanel);
-    }
-
     pub fn make_site_enabled<'a>(session: &'a Session, config_file_path: &'a str) {
         let mut chanel = new_channel(session);
         let command = chanel.exec(
@@ -114,14 +109,14 @@ pub mod nginx {
         close_channel(&mut chanel);
     }
 
-    pub fn remove_default_enable_folder<'a>(session: &'a Session) {
+    pub fn remove_default_enable_folder(session: &Session) {
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo rm /etc/nginx/sites-enabled/default");
         assert!(command.is_ok(), "Failed to remove default nginx config");
         close_channel(&mut chanel);
     }
 
-    pub fn restart<'a>(session: &'a Session) {
+    pub fn restart(session: &Session) {
         let mut chanel = new_channel(session);
         let command =
             chanel.exec("sudo ufw allow 80 && sudo ufw allow 443 && sudo systemctl restart nginx");
@@ -129,7 +124,7 @@ pub mod nginx {
         close_channel(&mut chanel);
     }
 
-    pub fn reload<'a>(session: &'a Session) {
+    pub fn reload(session: &Session) {
         // reload nginx without downtime
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo systemctl reload nginx");
@@ -146,7 +141,7 @@ pub mod certbot {
     use ssh2::Session;
     use std::io::Read;
 
-    pub fn install<'a>(session: &'a Session) {
+    pub fn install(session: &Session) {
         let mut chanel = new_channel(session);
         let command = chanel.exec("sudo apt install -y certbot");
         let mut s = String::new();
@@ -175,44 +170,114 @@ pub mod certbot {
 pub mod utils {
     use std::{
         fs::{self, File},
-        io::{Read, Write},
+        io::Write,
         path::Path,
     };
 
     use ssh2::{Channel, Session};
 
-    pub fn new_channel<'a>(session: &'a Session) -> Channel {
-        let channel = session.channel_session().unwrap();
-        channel
+    use crate::{SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH};
+
+    /// The local home directory: HOME on unix, USERPROFILE on windows.
+    pub fn home_dir() -> Option<std::path::PathBuf> {
+        std::env::var_os("HOME")
+            .or_else(|| std::env::var_os("USERPROFILE"))
+            .map(std::path::PathBuf::from)
+    }
+
+    /// Expand a leading ~/ against the local home, so a config written on
+    /// one operator's unix machine still finds the key from a windows one.
+    pub fn expand_tilde(path: &str) -> String {
+        match (path.strip_prefix("~/"), home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest).to_string_lossy().into_owned(),
+            _ => path.to_string(),
+        }
     }
 
-    pub fn close_channel<'a>(channel: &'a mut Channel) {
+    /// Join a path on the remote. Remote paths are plain strings joined with
+    /// '/' — never PathBuf, which would use '\' on a windows client.
+    pub fn remote_join(base: &str, name: &str) -> String {
+        format!("{}/{}", base.trim_end_matches('/'), name)
+    }
+
+    pub fn new_channel(session: &Session) -> Channel {
+        
+        session.channel_session().unwrap()
+    }
+
+    pub fn close_channel(channel: &mut Channel) {
         channel.wait_close().expect("closing channel failed");
     }
 
-    pub fn get_servers_nginx_config_file<'a>(
-        port: &'a i32,
-        domain: &'a str,
-        server_port: &'a i32,
+    pub fn get_servers_nginx_config_file(
+        domain: &str,
+        server_port: u16,
+        proxy: &crate::config::ProxyConfig,
+        extras: &str,
     ) -> String {
-        // the port nginx is listening doesnt change but the proxy_pass port can change has it depend
-        // on which server version is in production right now.
+        // the proxy_pass port can change as it depends on which server
+        // version is in production right now; everything else comes from the
+        // deployment's proxy settings
+        let mut location = String::from("            proxy_http_version 1.1;\n");
+        if proxy.websockets {
+            location.push_str(
+                "            proxy_set_header Upgrade $http_upgrade;\n\
+                 \x20           proxy_set_header Connection \"upgrade\";\n",
+            );
+        }
+        location.push_str(
+            "            proxy_set_header X-Real-IP $remote_addr;\n\
+             \x20           proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;\n\
+             \x20           proxy_set_header Host $http_host;\n\
+             \x20           proxy_set_header X-NginX-Proxy true;\n",
+        );
+        if let Some(secs) = proxy.timeout_secs {
+            location.push_str(&format!(
+                "            proxy_read_timeout {secs}s;\n\
+                 \x20           proxy_send_timeout {secs}s;\n"
+            ));
+        }
+        location.push_str(&format!(
+            "            proxy_pass http://127.0.0.1:{server_port}/;"
+        ));
+        let max_body = match &proxy.max_body_size {
+            Some(size) => format!("          client_max_body_size {size};\n"),
+            None => String::new(),
+        };
+        if !proxy.ssl {
+            return format!(
+                r#"
+        server {{
+          listen 80;
+          listen [::]:80;
+          server_name {domain} www.{domain};
+{max_body}{extras}
+          location ^~ / {{
+{location}
+          }}
+        }}
+        "#
+            );
+        }
+        let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
+        let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
         format!(
             r#"
         server {{
-          listen {port};
-          listen [::]:{port};
+          listen 80;
+          listen [::]:80;
           server_name {domain} www.{domain};
-
+          return 301 https://$server_name$request_uri;
+        }}
+        server {{
+          listen 443 ssl http2;
+          listen [::]:443 ssl http2;
+          server_name {domain} www.{domain};
+          ssl_certificate {certificate_path};
+          ssl_certificate_key {certificate_key_path};
+{max_body}{extras}
           location ^~ / {{
-            proxy_http_version 1.1;
-            proxy_set_header Upgrade $http_upgrade;
-            proxy_set_header Connection "upgrade";
-            proxy_set_header X-Real-IP $remote_addr;
-            proxy_set_header X-Forwarded-For $proxy_add_x_forwarded_for;
-            proxy_set_header Host $http_host;
-            proxy_set_header X-NginX-Proxy true;
-            proxy_pass http://127.0.0.1:{server_port}/;
+{location}
           }}
         }}
         "#
@@ -224,8 +289,10 @@ pub mod utils {
         ssl_fullchain_path: &'a str,
         ssl_pem_path: &'a str,
         website_dist_path: &'a str,
+        extras: &'a str,
     ) -> String {
         // https://medium.com/@kornchotpitakkul/deploy-a-node-js-and-vue-js-with-nginx-ssl-on-ubuntu-465f31216dc9
+        // extras carries framework or user specific directives for the 443 block
         format!(
             r#"
             server {{
@@ -242,6 +309,7 @@ pub mod utils {
                  ssl_certificate_key {ssl_pem_path};
                  root {website_dist_path};
                  index  index.html;
+{extras}
                  location / {{
                       root   {website_dist_path};
                       index  index.html;
@@ -296,9 +364,10 @@ pub mod utils {
         ext_ip: &'a str,
         unlock_wallet_address: &'a str,
         ws_address_ip: &'a str,
+        datadir: &'a str,
     ) -> String {
         format!(
-            r#"nohup geth --networkid {newtork_id}  --datadir data --nodiscover --http --http.port "8545"  --port "30303" --http.addr "{http_address_ip}"  --http.corsdomain "*" --nat any --http.api "eth,web3,personal,net,miner,admin" --http.vhosts "*" --nat extip:{ext_ip}  --unlock '{unlock_wallet_address}' --password './password.sec'  --mine --miner.threads 4  --ipcpath "./data/geth.ipc" --allow-insecure-unlock --miner.etherbase '{unlock_wallet_address}' --miner.gasprice 1  --syncmode full --ws --ws.addr "{ws_address_ip}"  --ws.api "eth,net,web3,admin" --ws.origins "*""#
+            r#"nohup geth --networkid {newtork_id}  --datadir {datadir} --nodiscover --http --http.port "8545"  --port "30303" --http.addr "{http_address_ip}"  --http.corsdomain "*" --nat any --http.api "eth,web3,personal,net,miner,admin" --http.vhosts "*" --nat extip:{ext_ip}  --unlock '{unlock_wallet_address}' --password './password.sec'  --mine --miner.threads 4  --ipcpath "{datadir}/geth.ipc" --allow-insecure-unlock --miner.etherbase '{unlock_wallet_address}' --miner.gasprice 1  --syncmode full --ws --ws.addr "{ws_address_ip}"  --ws.api "eth,net,web3,admin" --ws.origins "*""#
         )
     }
 
@@ -336,13 +405,182 @@ pub mod utils {
         )
     }
 
+    pub fn get_php_nginx_config_file(
+        domain: &str,
+        ssl_fullchain_path: &str,
+        ssl_pem_path: &str,
+        website_root: &str,
+    ) -> String {
+        format!(
+            r#"
+            server {{
+                 listen      80;
+                 listen      [::]:80;
+                 server_name {domain} www.{domain};
+                 return 301  https://$server_name$request_uri;
+            }}
+            server {{
+                 listen       443 ssl http2;
+                 listen       [::]:443 ssl http2;
+                 server_name  {domain} www.{domain};
+                 ssl_certificate {ssl_fullchain_path};
+                 ssl_certificate_key {ssl_pem_path};
+                 root {website_root};
+                 index  index.php index.html;
+                 location / {{
+                      try_files $uri $uri/ /index.php?$args;
+                 }}
+                 location ~ \.php$ {{
+                      include snippets/fastcgi-php.conf;
+                      fastcgi_pass unix:/run/php/php-fpm.sock;
+                 }}
+                 location ~ /\.ht {{
+                      deny all;
+                 }}
+            }}
+            "#
+        )
+    }
+
+    pub fn get_python_systemd_unit(name: &str, working_directory: &str, exec_start: &str) -> String {
+        format!(
+            r#"[Unit]
+Description={name} (deployed by rumi2)
+After=network.target
+
+[Service]
+WorkingDirectory={working_directory}
+ExecStart={exec_start}
+Restart=always
+
+[Install]
+WantedBy=multi-user.target
+"#
+        )
+    }
+
+    pub fn get_server_systemd_unit(
+        name: &str,
+        exec_start: &str,
+        port: u16,
+        env_file: Option<&str>,
+    ) -> String {
+        // the binary is told its port through the PORT convention, the only
+        // way two instances of the same server can listen side by side; the
+        // env file comes first so PORT still wins for the canary twin
+        let environment_file = match env_file {
+            Some(path) => format!("EnvironmentFile={path}\n"),
+            None => String::new(),
+        };
+        format!(
+            r#"[Unit]
+Description={name} (deployed by rumi2)
+After=network.target
+
+[Service]
+{environment_file}Environment=PORT={port}
+ExecStart={exec_start}
+Restart=always
+
+[Install]
+WantedBy=multi-user.target
+"#
+        )
+    }
+
+    pub fn get_observability_nginx_config_file(
+        domain: &str,
+        with_prometheus: bool,
+        with_grafana: bool,
+    ) -> String {
+        let mut locations = String::new();
+        if with_prometheus {
+            locations.push_str(
+                r#"
+              location /prometheus/ {
+                auth_basic "rumi observability";
+                auth_basic_user_file /etc/nginx/.rumi_observability_htpasswd;
+                proxy_pass http://127.0.0.1:9090/;
+              }
+"#,
+            );
+        }
+        if with_grafana {
+            locations.push_str(
+                r#"
+              location /grafana/ {
+                auth_basic "rumi observability";
+                auth_basic_user_file /etc/nginx/.rumi_observability_htpasswd;
+                proxy_set_header Host $http_host;
+                proxy_pass http://127.0.0.1:3000/;
+              }
+"#,
+            );
+        }
+        format!(
+            r#"
+            server {{
+              listen 80;
+              listen [::]:80;
+              server_name {domain} www.{domain};
+
+              location /metrics {{
+                auth_basic "rumi observability";
+                auth_basic_user_file /etc/nginx/.rumi_observability_htpasswd;
+                proxy_pass http://127.0.0.1:9100/metrics;
+              }}
+{locations}
+            }}
+            "#
+        )
+    }
+
+    pub fn get_prometheus_config_file(targets: &[String]) -> String {
+        let target_list = targets
+            .iter()
+            .map(|t| format!("\"{}\"", t))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!(
+            r#"global:
+  scrape_interval: 15s
+
+scrape_configs:
+  - job_name: "rumi_hosts"
+    static_configs:
+      - targets: [{target_list}]
+"#
+        )
+    }
+
+    /// The local mode bits to reproduce on the remote, or the default when
+    /// they cannot be read (or the client is not unix).
+    pub(crate) fn local_mode(path: &Path, default: i32) -> i32 {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            fs::metadata(path)
+                .map(|meta| (meta.permissions().mode() & 0o777) as i32)
+                .unwrap_or(default)
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = path;
+            default
+        }
+    }
+
+    /// Upload a local folder recursively. File modes are preserved, entries
+    /// go over in sorted name order (dotfiles like any other file), symlinks
+    /// to files are followed while directory symlinks and dangling links are
+    /// skipped — a directory link can loop the recursion forever.
     pub fn upload_folder(
         sftp: &ssh2::Sftp,
         local_path: &Path,
         remote_path: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
         // Create the remote directory
-        match sftp.mkdir(Path::new(remote_path), 0o755) {
+        match sftp.mkdir(Path::new(remote_path), local_mode(local_path, 0o755)) {
             Ok(_) => println!("Created directory: {}", remote_path),
             Err(e) => println!(
                 "Directory already exists or failed to create: {} - {}",
@@ -350,36 +588,62 @@ pub mod utils {
             ),
         }
 
-        // Iterate over the entries in the local directory
-        for entry in fs::read_dir(local_path)? {
-            let entry = entry?;
+        let mut entries: Vec<fs::DirEntry> =
+            fs::read_dir(local_path)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
             let path = entry.path();
-            let file_name = entry.file_name().into_string().unwrap();
-            let remote_file_path = format!("{}/{}", remote_path, file_name);
-
+            let file_name = entry.file_name().into_string().map_err(|name| {
+                format!("{:?} is not unicode, cannot build its remote path", name)
+            })?;
+            let remote_file_path = remote_join(remote_path, &file_name);
+
+            if entry.file_type()?.is_symlink() {
+                if path.is_dir() {
+                    println!("skipped directory symlink: {}", path.display());
+                    continue;
+                }
+                if !path.is_file() {
+                    println!("skipped dangling symlink: {}", path.display());
+                    continue;
+                }
+            }
             if path.is_dir() {
                 // Recursively upload directories
                 upload_folder(sftp, &path, &remote_file_path)?;
             } else {
-                // Upload files
-                upload_file(sftp, &path, &remote_file_path)?;
+                upload_file(sftp, &path, &remote_file_path)
+                    .map_err(|e| format!("uploading {}: {}", path.display(), e))?;
             }
         }
 
         Ok(())
     }
 
+    /// How much data is handed to libssh2 per write. Writes this large get
+    /// split into many sftp packets kept in flight together, instead of one
+    /// 32k packet per network round trip.
+    const UPLOAD_BUFFER_SIZE: usize = 512 * 1024;
+
     pub fn upload_file(
         sftp: &ssh2::Sftp,
         local_file: &Path,
         remote_file: &str,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let mut local_f = File::open(local_file)?;
-        let mut buffer = Vec::new();
-        local_f.read_to_end(&mut buffer)?;
-
-        let mut remote_f = sftp.create(Path::new(remote_file))?;
-        remote_f.write_all(&buffer)?;
+        // stream in chunks so a large binary is never fully in memory
+        let mut reader = std::io::BufReader::with_capacity(
+            UPLOAD_BUFFER_SIZE,
+            File::open(local_file)?,
+        );
+        let remote_f = sftp.open_mode(
+            Path::new(remote_file),
+            ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE,
+            local_mode(local_file, 0o644),
+            ssh2::OpenType::File,
+        )?;
+        let mut writer = std::io::BufWriter::with_capacity(UPLOAD_BUFFER_SIZE, remote_f);
+        std::io::copy(&mut reader, &mut writer)?;
+        writer.flush()?;
 
         println!("Uploaded file: {}", remote_file);
 
@@ -0,0 +1,252 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde_json::Value;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::framework;
+use crate::session::AsyncRumiSession;
+
+/// The port `rumi2 listen` binds by default.
+pub const DEFAULT_LISTEN_PORT: u16 = 9000;
+
+/// The `listen` command: a small http server accepting GitHub and GitLab
+/// push webhooks, verifying their signature and redeploying the mapped
+/// deployment — any box running rumi becomes a minimal deploy server.
+pub async fn listen_command(config: RumiConfig, port: u16, secret: String) -> RumiResult<()> {
+    let mapped: Vec<&str> = config
+        .deployments
+        .iter()
+        .filter_map(|d| d.repo.as_deref())
+        .collect();
+    if mapped.is_empty() {
+        return Err(RumiError::Config(
+            "no deployment has a repo set, nothing to map webhooks onto".to_string(),
+        ));
+    }
+    let listener = TcpListener::bind(("0.0.0.0", port))
+        .await
+        .map_err(|e| RumiError::Network(format!("could not bind port {}: {}", port, e)))?;
+    println!(
+        "listening on 0.0.0.0:{} for pushes to {}",
+        port,
+        mapped.join(", ")
+    );
+    let config = Arc::new(config);
+    let secret = Arc::new(secret);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        // one task per webhook — a deploy takes minutes, and awaiting it here
+        // would make every concurrent push time out at the sender
+        let (config, secret) = (Arc::clone(&config), Arc::clone(&secret));
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &config, &secret).await {
+                eprintln!("webhook from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    config: &RumiConfig,
+    secret: &str,
+) -> RumiResult<()> {
+    let (head, body) = read_request(&mut stream).await?;
+
+    let verified = if let Some(signature) = header(&head, "x-hub-signature-256") {
+        github_signature_valid(secret, &body, &signature)?
+    } else if let Some(token) = header(&head, "x-gitlab-token") {
+        token == secret
+    } else {
+        false
+    };
+    if !verified {
+        respond(&mut stream, 401, "bad or missing signature").await?;
+        return Err(RumiError::Network(
+            "rejected a payload with a bad or missing signature".to_string(),
+        ));
+    }
+
+    let payload: Value = serde_json::from_slice(&body)?;
+    // "owner/name" in both github and gitlab payloads
+    let repo = payload["repository"]["full_name"]
+        .as_str()
+        .or_else(|| payload["project"]["path_with_namespace"].as_str())
+        .unwrap_or_default()
+        .to_string();
+    let Some(deployment) = config
+        .deployments
+        .iter()
+        .find(|d| d.repo.as_deref() == Some(repo.as_str()))
+    else {
+        respond(&mut stream, 404, "no deployment mapped to this repository").await?;
+        return Err(RumiError::DeploymentNotFound(repo));
+    };
+
+    respond(&mut stream, 202, "deploy started").await?;
+    println!("push to {} -> redeploying {}", repo, deployment.name);
+    let result = redeploy(config, deployment).await;
+    match &result {
+        Ok(()) => println!("redeploy of {} finished", repo),
+        Err(e) => eprintln!("redeploy of {} failed: {}", repo, e),
+    }
+    result
+}
+
+/// Pull the checkout, rebuild when a framework is detectable and push the
+/// result to the server like `hosting update` would. The git pull and the
+/// build run on the blocking pool, the ssh half goes through
+/// [`AsyncRumiSession`] — the listener keeps accepting webhooks while a
+/// deploy is in flight.
+async fn redeploy(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<()> {
+    let dist_path = match &deployment.deployment_type {
+        DeploymentType::Website { dist_path } => dist_path.clone(),
+        other => {
+            return Err(RumiError::Config(format!(
+                "deployment '{}' is a {}, the listener only redeploys websites",
+                deployment.name,
+                other.kind()
+            )))
+        }
+    };
+    let project_path = deployment
+        .project_path
+        .clone()
+        .ok_or_else(|| {
+            RumiError::Config(format!(
+                "deployment '{}' has a repo but no project_path to pull into",
+                deployment.name
+            ))
+        })?;
+
+    let (dist, extras) = tokio::task::spawn_blocking(move || pull_and_build(&project_path, dist_path))
+        .await
+        .map_err(|e| RumiError::CommandFailed(format!("build task panicked: {}", e)))??;
+
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = AsyncRumiSession::connect(ssh).await?;
+    let domain = deployment.domain.clone();
+    session
+        .with_blocking(move |session| {
+            crate::commands::websites::update_command(session, &domain, &dist, extras, false)?;
+            Ok(())
+        })
+        .await
+}
+
+/// The local half of a redeploy: fast-forward the checkout and rebuild when a
+/// framework is detectable, returning the folder to upload and any nginx
+/// extras the framework wants.
+fn pull_and_build(project_path: &str, dist_path: String) -> RumiResult<(String, &'static str)> {
+    let pull = std::process::Command::new("git")
+        .args(["-C", project_path, "pull", "--ff-only"])
+        .output()?;
+    if !pull.status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "git pull in {} failed: {}",
+            project_path,
+            String::from_utf8_lossy(&pull.stderr).trim()
+        )));
+    }
+
+    let project_dir = Path::new(project_path);
+    match framework::Framework::detect(project_dir) {
+        Some(detected) => {
+            let output = framework::build(detected, project_dir)?;
+            Ok((output.to_string_lossy().into_owned(), detected.nginx_extras()))
+        }
+        None => Ok((dist_path, "")),
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> RumiResult<(String, Vec<u8>)> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 8192];
+    let head_end = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Err(RumiError::Network("request cut short".to_string()));
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(at) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break at;
+        }
+        if buffer.len() > 64 * 1024 {
+            return Err(RumiError::Network("request headers too large".to_string()));
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..head_end]).into_owned();
+    let content_length: usize = header(&head, "content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    if content_length > 4 * 1024 * 1024 {
+        return Err(RumiError::Network("request body too large".to_string()));
+    }
+    let mut body = buffer[head_end + 4..].to_vec();
+    while body.len() < content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+    }
+    body.truncate(content_length);
+    Ok((head, body))
+}
+
+fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        (key.trim().eq_ignore_ascii_case(name)).then(|| value.trim().to_string())
+    })
+}
+
+/// GitHub signs the raw body with hmac-sha256 of the shared secret.
+fn github_signature_valid(secret: &str, body: &[u8], signature: &str) -> RumiResult<bool> {
+    let key = PKey::hmac(secret.as_bytes()).map_err(|e| RumiError::Tls(e.to_string()))?;
+    let mut signer =
+        Signer::new(MessageDigest::sha256(), &key).map_err(|e| RumiError::Tls(e.to_string()))?;
+    signer
+        .update(body)
+        .map_err(|e| RumiError::Tls(e.to_string()))?;
+    let digest = signer
+        .sign_to_vec()
+        .map_err(|e| RumiError::Tls(e.to_string()))?;
+    let expected = format!(
+        "sha256={}",
+        digest.iter().map(|b| format!("{:02x}", b)).collect::<String>()
+    );
+    if expected.len() != signature.len() {
+        return Ok(false);
+    }
+    // constant-time compare so the signature can't be guessed byte by byte
+    Ok(openssl::memcmp::eq(
+        expected.as_bytes(),
+        signature.as_bytes(),
+    ))
+}
+
+async fn respond(stream: &mut TcpStream, status: u16, message: &str) -> RumiResult<()> {
+    let reason = match status {
+        202 => "Accepted",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        _ => "OK",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        message.len(),
+        message
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// How many lines of history a tail shows by default.
+pub const DEFAULT_TAIL_LINES: u32 = 100;
+
+/// Which log stream of a deployment to tail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogSource {
+    NginxAccess,
+    NginxError,
+    /// The deployment's own output: the nginx access log for websites, the
+    /// journald unit for servers, the geth nohup output for ethereum nodes.
+    App,
+}
+
+impl FromStr for LogSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nginx-access" => Ok(LogSource::NginxAccess),
+            "nginx-error" => Ok(LogSource::NginxError),
+            "app" => Ok(LogSource::App),
+            other => Err(format!(
+                "unknown log source '{}', expected nginx-access, nginx-error or app",
+                other
+            )),
+        }
+    }
+}
+
+/// Where the logs of one deployment live on the remote host.
+enum LogTarget {
+    File(String),
+    Journald(String),
+}
+
+fn log_target(deployment: &DeploymentConfig, source: LogSource) -> LogTarget {
+    match source {
+        LogSource::NginxAccess => LogTarget::File("/var/log/nginx/access.log".to_string()),
+        LogSource::NginxError => LogTarget::File("/var/log/nginx/error.log".to_string()),
+        LogSource::App => match &deployment.deployment_type {
+            DeploymentType::Kubernetes { .. } => {
+                LogTarget::Journald("kubelet".to_string())
+            }
+            DeploymentType::SftpSite { .. } => {
+                // shared hosts rarely expose logs; try the usual cpanel spot
+                LogTarget::File("access-logs/access.log".to_string())
+            }
+            DeploymentType::Website { .. } | DeploymentType::Php { .. } => {
+                LogTarget::File("/var/log/nginx/access.log".to_string())
+            }
+            DeploymentType::Server { .. } | DeploymentType::Python { .. } => {
+                LogTarget::Journald(deployment.name.clone())
+            }
+            DeploymentType::Ethereum { .. } => LogTarget::File("nohup.out".to_string()),
+            DeploymentType::Database { engine, .. } => LogTarget::Journald(
+                match engine {
+                    crate::config::DatabaseEngine::Postgres => "postgresql",
+                    crate::config::DatabaseEngine::Mysql => "mysql",
+                }
+                .to_string(),
+            ),
+            DeploymentType::Redis { .. } => LogTarget::Journald("redis-server".to_string()),
+            DeploymentType::DockerHost { .. } => LogTarget::Journald("docker".to_string()),
+            DeploymentType::Observability { .. } => {
+                LogTarget::Journald("prometheus-node-exporter".to_string())
+            }
+        },
+    }
+}
+
+/// Build the remote tail command for a deployment's log target.
+fn tail_command(
+    target: &LogTarget,
+    follow: bool,
+    since: Option<&str>,
+    lines: u32,
+) -> String {
+    match target {
+        LogTarget::File(path) => {
+            if since.is_some() {
+                eprintln!("note: --since only applies to journald sources, showing the last {} lines", lines);
+            }
+            format!(
+                "sudo tail -n {} {} {}",
+                lines,
+                if follow { "-f" } else { "" },
+                path
+            )
+        }
+        LogTarget::Journald(unit) => {
+            let mut command = format!("sudo journalctl -u {} -n {} --no-pager", unit, lines);
+            if let Some(since) = since {
+                command.push_str(&format!(" --since '-{}'", since));
+            }
+            if follow {
+                command.push_str(" -f");
+            }
+            command
+        }
+    }
+}
+
+/// Run the tail command on a session, printing every line as it arrives,
+/// prefixed with the host name when tailing several hosts at once.
+fn stream_logs(session: &RumiSession, command: &str, prefix: Option<&str>) -> RumiResult<()> {
+    let mut channel = session.session().channel_session()?;
+    channel.exec(command)?;
+    let mut buffer = [0u8; 8192];
+    let mut pending = String::new();
+    loop {
+        let read = match channel.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) => return Err(RumiError::Io(e)),
+        };
+        pending.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        while let Some(newline) = pending.find('\n') {
+            let line: String = pending.drain(..=newline).collect();
+            match prefix {
+                Some(prefix) => print!("[{}] {}", prefix, line),
+                None => print!("{}", line),
+            }
+        }
+    }
+    if !pending.is_empty() {
+        match prefix {
+            Some(prefix) => println!("[{}] {}", prefix, pending),
+            None => println!("{}", pending),
+        }
+    }
+    channel.wait_close()?;
+    Ok(())
+}
+
+/// One non-following tail over an existing session, for the interactive
+/// shell where the connection outlives the command.
+pub fn tail_once(
+    session: &RumiSession,
+    deployment: &DeploymentConfig,
+    source: LogSource,
+    lines: u32,
+) -> RumiResult<()> {
+    let command = tail_command(&log_target(deployment, source), false, None, lines);
+    stream_logs(session, &command, None)
+}
+
+/// The `hosting logs` command: tail a site's nginx access (or error) log.
+/// Sites with their own access_log file get tailed directly; otherwise the
+/// shared nginx log is tailed and grepped by the site's domain. --filter
+/// narrows further, e.g. to a status code or a path.
+pub fn hosting_logs_command(
+    config: &RumiConfig,
+    name: &str,
+    follow: bool,
+    error: bool,
+    filter: Option<&str>,
+    lines: u32,
+) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let kind = if error { "error" } else { "access" };
+    let site_log = format!("/var/log/nginx/{}.{}.log", deployment.domain, kind);
+    let shared_log = format!("/var/log/nginx/{}.log", kind);
+    let tail = format!("sudo tail -n {}{}", lines, if follow { " -f" } else { "" });
+    let mut command = format!(
+        "if sudo test -f {site}; then {tail} {site}; else {tail} {shared} | grep -F --line-buffered {domain}; fi",
+        site = site_log,
+        shared = shared_log,
+        tail = tail,
+        domain = crate::session::shell_quote(&deployment.domain),
+    );
+    if let Some(filter) = filter {
+        command.push_str(&format!(
+            " | grep -F --line-buffered {}",
+            crate::session::shell_quote(filter)
+        ));
+    }
+    let session = RumiSession::connect(config.ssh_for_deployment(deployment)?)?;
+    stream_logs(&session, &command, None)
+}
+
+/// The `logs` command: tail the right remote log for a deployment, or for all
+/// of them multiplexed with per-host prefixes when no name is given.
+pub fn logs_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    source: LogSource,
+    follow: bool,
+    since: Option<&str>,
+    lines: u32,
+) -> RumiResult<()> {
+    let deployments: Vec<&DeploymentConfig> = match name {
+        Some(name) => vec![config.find_deployment(name)?],
+        None => config.deployments.iter().collect(),
+    };
+    if deployments.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments in the config, add some to rumi.json first".to_string(),
+        ));
+    }
+    let multiplexed = deployments.len() > 1;
+    let mut handles = Vec::new();
+    for deployment in deployments {
+        let ssh = config.ssh_for_deployment(deployment)?.clone();
+        let command = tail_command(&log_target(deployment, source), follow, since, lines);
+        let prefix = multiplexed.then(|| format!("{}@{}", deployment.name, ssh.host));
+        handles.push(std::thread::spawn(move || -> RumiResult<()> {
+            let session = RumiSession::connect(&ssh)?;
+            stream_logs(&session, &command, prefix.as_deref())
+        }));
+    }
+    let mut failures = Vec::new();
+    for handle in handles {
+        if let Err(e) = handle.join().expect("log streaming thread panicked") {
+            failures.push(e.to_string());
+        }
+    }
+    if !failures.is_empty() {
+        return Err(RumiError::CommandFailed(failures.join("; ")));
+    }
+    Ok(())
+}
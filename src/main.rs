@@ -1,199 +1,2361 @@
-use clap::{arg, Command};
-use std::io::Error;
-
-fn cli() -> Command {
-    Command::new("run")
-        .about("Rumi2 cli to help publish new website to a server via ssh")
-        .author("Bourse Numerique D'Afrique <dev@boursenumeriquedafrique.com>")
-        .version("1.0")
-        .subcommand_required(true)
-        .arg_required_else_help(true)
-        .allow_external_subcommands(true)
-        .subcommand(
-            Command::new("hosting")
-                .about("Manage the hosting lifcycle of you website")
-                .subcommand_required(true)
-                .arg_required_else_help(true)
-                .allow_external_subcommands(true)
-                .subcommand(
-                    Command::new("install")
-                        .about("Install a website on a new server using a ssh connexion")
-                        .arg(arg!(--ssh_cert_public_key <SSH_CERT_PUBLIC_KEY> "the ssh public key"))
-                        .arg(arg!(--ssh_cert_private_key <SSH_CERT_PRIVATE_KEY> "the ssh private key"))
-                        .arg(arg!(--ssh_host <SSH_HOST> "the ssh host"))
-                        .arg(arg!(--ssh_user <SSH_USER> "the ssh user"))
-                        .arg(arg!(--ssh_password <SSH_PASSWORD> "the ssh password"))
-                        .arg(arg!(--domain <DOMAIN> "the url of the website"))
-                        .arg(arg!(--dist_path <DIST_PATH> "the url of the website"))
-                        .arg(arg!(--version_id <VERSION_ID> "the version id"))
-                        .arg_required_else_help(true),
-                )
-                .subcommand(
-                    Command::new("update")
-                        .about(
-                            "Update an existing website running on a server using a ssh connexion",
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+use clap::{Parser, Subcommand};
+
+use rumi2::config::{
+    resolve_config_path, DeploymentConfig, DeploymentType, RumiConfig, SshConfig,
+};
+use rumi2::error::RumiResult;
+use rumi2::monitor;
+
+#[derive(Parser)]
+#[command(
+    name = "rumi2",
+    about = "Rumi2 cli to help publish new website to a server via ssh",
+    author = "Bourse Numerique D'Afrique <dev@boursenumeriquedafrique.com>",
+    version = "1.0"
+)]
+struct Cli {
+    /// Path to the rumi config file (defaults to ./rumi.json)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Run non-interactively for pipelines: step annotations, secrets from
+    /// env only, ssh key from RUMI_SSH_KEY (auto-detected via the CI env var)
+    #[arg(long, global = true)]
+    ci: bool,
+    /// Write the gitlab dotenv artifact after deploys so the environments ui
+    /// reflects them (auto-detected via the GITLAB_CI env var)
+    #[arg(long, global = true)]
+    gitlab: bool,
+    /// Refuse anything that would change remote or config state; only
+    /// listing, status and monitoring commands run (also settable in the
+    /// config's settings block for shared configs)
+    #[arg(long, global = true)]
+    read_only: bool,
+    /// Append every remote command, its exit code, timing and output to this
+    /// file for after-the-fact diagnosis
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+    /// Skip ssh host key verification entirely — only for throwaway hosts,
+    /// this is what makes man-in-the-middle possible
+    #[arg(long, global = true)]
+    insecure_skip_host_key: bool,
+    /// Print every remote command and file the deploy would run instead of
+    /// connecting anywhere (hosting install/update, server deploy)
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// Echo remote command output line by line as it arrives, instead of
+    /// staying silent until each command finished
+    #[arg(long, short, global = true)]
+    verbose: bool,
+    /// Spread folder uploads over this many sftp connections per host,
+    /// overriding settings.upload_concurrency
+    #[arg(long, global = true)]
+    concurrency: Option<u64>,
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// Manage the hosting lifcycle of you website
+    Hosting {
+        #[command(subcommand)]
+        command: HostingCommands,
+    },
+    /// Deploy and manage server binaries behind nginx
+    Server {
+        #[command(subcommand)]
+        command: ServerCommands,
+    },
+    /// Route a share of traffic to a new release before committing to it
+    Canary {
+        #[command(subcommand)]
+        command: CanaryCommands,
+    },
+    /// Monitor the health of your deployments
+    Monitor {
+        #[command(subcommand)]
+        command: MonitorCommands,
+    },
+    /// Tail the remote logs of a deployment
+    Logs {
+        /// the deployment to tail (all deployments when omitted)
+        #[arg(long)]
+        name: Option<String>,
+        /// which log stream to tail
+        #[arg(long, default_value = "app")]
+        source: rumi2::logs::LogSource,
+        /// keep the stream open and follow new lines
+        #[arg(long)]
+        follow: bool,
+        /// how far back to look for journald sources, e.g. 1h or 30min
+        #[arg(long)]
+        since: Option<String>,
+        /// how many lines of history to show
+        #[arg(long, default_value_t = rumi2::logs::DEFAULT_TAIL_LINES)]
+        lines: u32,
+    },
+    /// Inspect and renew the let's encrypt certificates on the hosts
+    Cert {
+        #[command(subcommand)]
+        command: CertCommands,
+    },
+    /// Manage dns records through the configured provider
+    Dns {
+        #[command(subcommand)]
+        command: DnsCommands,
+    },
+    /// Deploy to a kubernetes cluster via kubectl
+    K8s {
+        #[command(subcommand)]
+        command: K8sCommands,
+    },
+    /// Accept push webhooks and redeploy the mapped deployments
+    Listen {
+        /// the port to listen on
+        #[arg(long, default_value_t = rumi2::listen::DEFAULT_LISTEN_PORT)]
+        port: u16,
+        /// the webhook secret shared with github/gitlab
+        #[arg(long)]
+        secret: String,
+    },
+    /// Provision and manage database servers
+    Database {
+        #[command(subcommand)]
+        command: DatabaseCommands,
+    },
+    /// Create and list backups on the deployment hosts
+    Backup {
+        #[command(subcommand)]
+        command: BackupCommands,
+    },
+    /// Manage ethereum nodes
+    Ethereum {
+        #[command(subcommand)]
+        command: EthereumCommands,
+    },
+    /// Deploy php apps and WordPress sites
+    Php {
+        #[command(subcommand)]
+        command: PhpCommands,
+    },
+    /// Deploy python wsgi/asgi apps
+    Python {
+        #[command(subcommand)]
+        command: PythonCommands,
+    },
+    /// Provision and manage redis caches
+    Redis {
+        #[command(subcommand)]
+        command: RedisCommands,
+    },
+    /// Provision and manage docker hosts
+    Docker {
+        #[command(subcommand)]
+        command: DockerCommands,
+    },
+    /// Deploy the observability stack (node_exporter, prometheus, grafana)
+    Observability {
+        #[command(subcommand)]
+        command: ObservabilityCommands,
+    },
+    /// Converge host firewalls onto the rules the deployments need
+    Firewall {
+        #[command(subcommand)]
+        command: FirewallCommands,
+    },
+    /// Helpers for running rumi inside ci pipelines
+    Ci {
+        #[command(subcommand)]
+        command: CiCommands,
+    },
+    /// Harden hosts and inspect their security state
+    Security {
+        #[command(subcommand)]
+        command: SecurityCommands,
+    },
+    /// Manage deploy users and their ssh keys on the hosts
+    Users {
+        #[command(subcommand)]
+        command: UsersCommands,
+    },
+    /// Manage the rumi config file
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+    /// Print what deploying an update would do, without connecting anywhere
+    Plan {
+        /// the deployment to plan
+        #[arg(long)]
+        name: String,
+        /// write the plan as an executable shell script instead of printing it
+        #[arg(long)]
+        export: Option<PathBuf>,
+    },
+    /// Render every file rumi would write on the host for a deployment
+    Render {
+        /// the deployment to render
+        #[arg(long)]
+        name: String,
+        /// write the files into this directory instead of printing them
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Print a shell completion script with dynamic name completion
+    Completion {
+        /// the shell to print a script for: bash or zsh
+        #[arg(long, default_value = "bash")]
+        shell: String,
+    },
+    /// Print completion candidates for one flag, used by the script
+    #[command(hide = true)]
+    CompleteValues {
+        /// what to complete: deployments, hosts or backups
+        #[arg(long)]
+        kind: String,
+    },
+    /// Every recorded deploy of a deployment, from the journal on its host
+    History {
+        /// the deployment to show
+        #[arg(long)]
+        name: String,
+        /// print raw utc timestamps for scripts
+        #[arg(long)]
+        utc: bool,
+    },
+    /// The most recent recorded deploy of a deployment
+    Status {
+        /// the deployment to show
+        #[arg(long)]
+        name: String,
+        /// print raw utc timestamps for scripts
+        #[arg(long)]
+        utc: bool,
+    },
+    /// An interactive prompt that keeps ssh sessions open between commands
+    Shell,
+    /// Detect what this project is and scaffold a rumi.json for deploying it
+    Init {
+        /// the domain the project will be served on
+        #[arg(long)]
+        domain: String,
+    },
+}
+
+#[derive(clap::Args)]
+struct SshArgs {
+    /// the ssh public key
+    #[arg(long = "ssh_cert_public_key")]
+    ssh_cert_public_key: String,
+    /// the ssh private key
+    #[arg(long = "ssh_cert_private_key")]
+    ssh_cert_private_key: String,
+    /// the ssh host; repeat the flag to target several hosts at once
+    #[arg(long = "ssh_host", required = true)]
+    ssh_host: Vec<String>,
+    /// the ssh port
+    #[arg(long = "ssh_port", default_value_t = 22)]
+    ssh_port: u16,
+    /// the ssh user
+    #[arg(long = "ssh_user")]
+    ssh_user: String,
+    /// the ssh password
+    #[arg(long = "ssh_password")]
+    ssh_password: String,
+}
+
+impl SshArgs {
+    /// The flags as an SshConfig, so the flag-driven commands go through the
+    /// same authentication chain as the config-driven ones (ci key from the
+    /// environment, key files, agent fallback).
+    fn to_ssh_config(&self) -> rumi2::config::SshConfig {
+        rumi2::config::SshConfig {
+            host: self.primary_host().to_string(),
+            port: self.ssh_port,
+            user: self.ssh_user.clone(),
+            public_key_path: Some(self.ssh_cert_public_key.clone()),
+            private_key_path: Some(self.ssh_cert_private_key.clone()),
+            passphrase: (!self.ssh_password.is_empty()).then(|| self.ssh_password.clone()),
+            escalation: None,
+            requires_sudo_password: false,
+            proxy_jump: None,
+        }
+    }
+
+    /// One SshConfig per --ssh_host, all sharing the same credentials.
+    fn to_ssh_configs(&self) -> Vec<rumi2::config::SshConfig> {
+        self.ssh_host
+            .iter()
+            .map(|host| {
+                let mut config = self.to_ssh_config();
+                config.host = host.clone();
+                config
+            })
+            .collect()
+    }
+
+    /// The first host, for the commands that only make sense on one.
+    fn primary_host(&self) -> &str {
+        &self.ssh_host[0]
+    }
+
+    fn start_session(&self) -> rumi2::error::RumiResult<rumi2::session::RumiSession> {
+        rumi2::session::RumiSession::connect(&self.to_ssh_config())
+    }
+}
+
+#[derive(Subcommand)]
+enum HostingCommands {
+    /// Install a website on a new server using a ssh connexion
+    Install {
+        #[command(flatten)]
+        ssh: SshArgs,
+        /// the url of the website
+        #[arg(long)]
+        domain: String,
+        /// the path of the website dist folder, or the project folder when
+        /// --framework is used
+        #[arg(long = "dist_path")]
+        dist_path: String,
+        /// the version id
+        #[arg(long = "version_id")]
+        version_id: String,
+        /// build the project first: auto, hugo, jekyll, astro, next or vite
+        #[arg(long)]
+        framework: Option<String>,
+        /// point the domain's dns records at the server before certbot runs
+        #[arg(long)]
+        manage_dns: bool,
+        /// how certbot proves domain ownership: standalone or dns-01
+        #[arg(long, default_value = "standalone")]
+        challenge: rumi2::certs::Challenge,
+        /// request a *.domain wildcard certificate (needs --challenge dns-01)
+        #[arg(long)]
+        wildcard: bool,
+        /// the let's encrypt account email (falls back to settings.ssl_email)
+        #[arg(long = "ssl_email")]
+        ssl_email: Option<String>,
+    },
+    /// Update an existing website running on a server using a ssh connexion
+    Update {
+        #[command(flatten)]
+        ssh: SshArgs,
+        /// the url of the website
+        #[arg(long)]
+        domain: String,
+        /// the path of the website dist folder, or the project folder when
+        /// --framework is used
+        #[arg(long = "dist_path")]
+        dist_path: String,
+        /// build the project first: auto, hugo, jekyll, astro, next or vite
+        #[arg(long)]
+        framework: Option<String>,
+        /// check out this branch, tag or commit in the project before building
+        #[arg(long = "ref")]
+        git_ref: Option<String>,
+        /// re-upload everything instead of syncing only changed files
+        #[arg(long)]
+        full: bool,
+        /// purge the cdn cache for the domain once the update is live
+        #[arg(long)]
+        purge_cdn: bool,
+        /// with --purge-cdn, only purge these paths instead of everything
+        #[arg(long)]
+        purge_path: Vec<String>,
+    },
+    /// Deploy an sftp_site deployment to a shared host (sftp only, no sudo)
+    SftpDeploy {
+        /// the sftp_site deployment to deploy
+        #[arg(long)]
+        name: String,
+    },
+    /// List the deployed releases of a website deployment with their git
+    /// revisions
+    Releases {
+        /// the website deployment to list releases of
+        #[arg(long)]
+        name: String,
+        /// print raw utc timestamps for scripts
+        #[arg(long)]
+        utc: bool,
+    },
+    /// Delete old release folders, keeping the newest ones and the one
+    /// nginx serves
+    Prune {
+        /// the website deployment to prune
+        #[arg(long)]
+        name: String,
+        /// how many releases to keep
+        #[arg(long, default_value_t = 5)]
+        keep: usize,
+    },
+    /// Tail a site's nginx logs over ssh
+    Logs {
+        /// the website deployment to tail
+        #[arg(long)]
+        name: String,
+        /// keep the stream open and follow new lines
+        #[arg(long)]
+        follow: bool,
+        /// tail the nginx error log instead of the access log
+        #[arg(long)]
+        error: bool,
+        /// only lines containing this text, e.g. a status code or a path
+        #[arg(long)]
+        filter: Option<String>,
+        /// how many lines of history to show
+        #[arg(long, default_value_t = rumi2::logs::DEFAULT_TAIL_LINES)]
+        lines: u32,
+    },
+    /// Show what exactly is running and who deployed it
+    Info {
+        /// the website deployment to inspect
+        #[arg(long)]
+        name: String,
+        /// print raw utc timestamps for scripts
+        #[arg(long)]
+        utc: bool,
+    },
+    /// Rollback to a former website version
+    Rollback {
+        #[command(flatten)]
+        ssh: SshArgs,
+        /// the url of the website
+        #[arg(long)]
+        domain: String,
+        /// the version id to roll back to
+        #[arg(long = "version_id")]
+        version_id: String,
+    },
+    /// Remove a website from its hosts: nginx configs, web folders and the
+    /// config entry
+    Uninstall {
+        /// the website deployment to remove
+        #[arg(long)]
+        name: String,
+        /// delete the web folders instead of archiving them aside
+        #[arg(long)]
+        purge: bool,
+        /// also revoke and delete the site's certificate
+        #[arg(long)]
+        revoke_cert: bool,
+        /// skip the confirmation prompt, for scripts
+        #[arg(long)]
+        yes: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServerCommands {
+    /// Upload the binary matching the host architecture and restart it
+    Deploy {
+        /// the server deployment to deploy
+        #[arg(long)]
+        name: String,
+    },
+    /// Stop the unit and remove the binary, its kept versions, nginx
+    /// config and firewall rule from the hosts
+    Undeploy {
+        /// the server deployment to remove
+        #[arg(long)]
+        name: String,
+        /// skip the confirmation prompt, for scripts
+        #[arg(long)]
+        yes: bool,
+    },
+    /// Switch the binary back to a previously deployed version
+    Rollback {
+        /// the server deployment to roll back
+        #[arg(long)]
+        name: String,
+        /// the release timestamp to switch back to, as listed by `history`
+        #[arg(long)]
+        version: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum CanaryCommands {
+    /// Deploy the new release beside the stable one with a traffic split
+    Start {
+        /// the deployment to canary
+        #[arg(long)]
+        name: String,
+        /// the share of traffic sent to the canary, 1 to 99
+        #[arg(long, default_value_t = 10)]
+        percent: u8,
+    },
+    /// Make the canary the only release
+    Promote {
+        /// the deployment whose canary to promote
+        #[arg(long)]
+        name: String,
+    },
+    /// Drop the canary and send all traffic back to the stable release
+    Abort {
+        /// the deployment whose canary to abort
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum MonitorCommands {
+    /// Probe every deployment over http/https and report status, latency and
+    /// tls validity
+    Check {
+        /// only check the deployment with this name
+        #[arg(long)]
+        name: Option<String>,
+        /// probe timeout in seconds
+        #[arg(long, default_value_t = monitor::DEFAULT_PROBE_TIMEOUT_SECS)]
+        timeout: u64,
+        /// print the results as json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Report tls certificate expiry for every deployed domain
+    Certs {
+        /// only check the deployment with this name
+        #[arg(long)]
+        name: Option<String>,
+        /// warn and exit non-zero when fewer days than this are left
+        #[arg(long, default_value_t = monitor::DEFAULT_CERT_WARN_DAYS)]
+        threshold: i64,
+        /// probe timeout in seconds
+        #[arg(long, default_value_t = monitor::DEFAULT_PROBE_TIMEOUT_SECS)]
+        timeout: u64,
+        /// read the certificate files over ssh instead of connecting to 443
+        #[arg(long)]
+        via_ssh: bool,
+        /// print the results as json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Gather cpu load, memory, disk usage and top processes from each host
+    Resources {
+        /// only check the host of the deployment with this name
+        #[arg(long, conflicts_with = "all")]
+        name: Option<String>,
+        /// check every host (the default when --name is not given)
+        #[arg(long)]
+        all: bool,
+        /// print the results as json instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Run checks on a schedule, alert on transitions and serve the latest
+    /// results on a local http endpoint
+    Daemon {
+        /// how often to run the checks, e.g. 60s, 5m or 1h
+        #[arg(long, default_value = "60s")]
+        interval: String,
+        /// address the results endpoint listens on
+        #[arg(long, default_value = "127.0.0.1:8737")]
+        listen: String,
+        /// where the check history ring buffer is stored
+        #[arg(long, default_value = "rumi-monitor-history.json")]
+        history_file: PathBuf,
+        /// probe timeout in seconds
+        #[arg(long, default_value_t = monitor::DEFAULT_PROBE_TIMEOUT_SECS)]
+        timeout: u64,
+    },
+    /// Load-test a deployment and compare latency against the last baseline
+    Bench {
+        /// the deployment to bench
+        #[arg(long)]
+        name: String,
+        /// total number of requests to send
+        #[arg(long, default_value_t = 200)]
+        requests: u32,
+        /// how many requests run in parallel
+        #[arg(long, default_value_t = 10)]
+        concurrency: u32,
+        /// per-request timeout in seconds
+        #[arg(long, default_value_t = monitor::DEFAULT_PROBE_TIMEOUT_SECS)]
+        timeout: u64,
+    },
+    /// Render a static status page from check results and deploy it
+    PublishStatus {
+        /// the website deployment that hosts the status page
+        #[arg(long)]
+        name: String,
+        /// probe timeout in seconds
+        #[arg(long, default_value_t = monitor::DEFAULT_PROBE_TIMEOUT_SECS)]
+        timeout: u64,
+    },
+}
+
+#[derive(Subcommand)]
+enum CertCommands {
+    /// Report when each deployment's certificate expires
+    Status,
+    /// Renew certificates now and reload nginx
+    Renew {
+        /// renew only this deployment's certificate (all when omitted)
+        #[arg(long)]
+        name: Option<String>,
+        /// renew even when certbot thinks it is not due yet
+        #[arg(long)]
+        force: bool,
+    },
+    /// Install a renewal timer with an nginx reload hook on the hosts
+    AutoRenew {
+        /// set up only this deployment's host (all hosts when omitted)
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DnsCommands {
+    /// List every record of the zone holding a domain
+    List {
+        /// the domain (or zone) to list
+        #[arg(long)]
+        zone: String,
+    },
+    /// Create or update one record
+    Set {
+        /// fully qualified record name, e.g. www.example.com
+        #[arg(long)]
+        name: String,
+        /// record type: A, AAAA, CNAME, TXT, ...
+        #[arg(long = "type")]
+        record_type: String,
+        /// record content, e.g. the server ip
+        #[arg(long)]
+        content: String,
+        /// record ttl in seconds
+        #[arg(long, default_value_t = 300)]
+        ttl: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum K8sCommands {
+    /// Render and apply the manifests of a kubernetes deployment
+    Deploy {
+        /// the kubernetes deployment to deploy
+        #[arg(long)]
+        name: String,
+    },
+    /// Print the rendered manifests without applying them
+    Render {
+        /// the kubernetes deployment to render
+        #[arg(long)]
+        name: String,
+    },
+    /// Show rollout status and pods of a kubernetes deployment
+    Status {
+        /// the kubernetes deployment to inspect
+        #[arg(long)]
+        name: String,
+    },
+    /// Roll back to the previous revision with rollout undo
+    Rollback {
+        /// the kubernetes deployment to roll back
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DatabaseCommands {
+    /// Install the database server of a database deployment and create its
+    /// database and user
+    Install {
+        /// the database deployment to provision
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupCommands {
+    /// Create a backup of a deployment
+    Create {
+        /// the deployment to back up
+        #[arg(long)]
+        name: String,
+    },
+    /// List the backups recorded on the deployment hosts
+    List {
+        /// only list backups of this deployment
+        #[arg(long)]
+        name: Option<String>,
+        /// ignore the local cache and fetch the index from every host
+        #[arg(long)]
+        refresh: bool,
+        /// print raw utc timestamps for scripts
+        #[arg(long)]
+        utc: bool,
+        /// only list this kind of backup: website, config or database
+        #[arg(long = "type")]
+        backup_type: Option<String>,
+        /// only backups created at or after this date (yyyy-mm-dd or rfc3339)
+        #[arg(long)]
+        since: Option<String>,
+        /// only backups created at or before this date (yyyy-mm-dd or rfc3339)
+        #[arg(long)]
+        until: Option<String>,
+        /// only backups of at least this many bytes
+        #[arg(long = "min-size")]
+        min_size: Option<u64>,
+        /// order the table by date, size or name
+        #[arg(long, default_value = "date")]
+        sort: String,
+    },
+    /// Download a backup and unpack it into a local directory
+    Restore {
+        /// the id of the backup to restore, from `backup list`
+        #[arg(long = "backup-id")]
+        backup_id: String,
+        /// the local directory to download and extract into
+        #[arg(long)]
+        local: PathBuf,
+    },
+    /// Download a backup's archive and metadata without unpacking them
+    Download {
+        /// the id of the backup to download, from `backup list`
+        #[arg(long = "backup-id")]
+        backup_id: String,
+        /// the local directory to download into
+        #[arg(long)]
+        out: PathBuf,
+    },
+    /// Push a downloaded backup back into a deployment host's backup store
+    Upload {
+        /// the deployment whose host receives the backup
+        #[arg(long)]
+        name: String,
+        /// the archive to upload, with its metadata sidecar next to it
+        #[arg(long)]
+        archive: PathBuf,
+    },
+    /// Delete old backups beyond the retention, a few hosts at a time
+    Cleanup {
+        /// only clean up this deployment's backups
+        #[arg(long)]
+        name: Option<String>,
+        /// how many backups to keep per deployment
+        #[arg(long, default_value_t = 5)]
+        keep: usize,
+        /// print the per-host report as json
+        #[arg(long)]
+        json: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum EthereumCommands {
+    /// Roll the chain back to a chaindata snapshot from the backup store
+    Rollback {
+        /// the ethereum deployment to roll back
+        #[arg(long)]
+        name: String,
+        /// the chaindata backup to restore, from `backup list`
+        #[arg(long = "backup-id")]
+        backup_id: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PhpCommands {
+    /// Install a php deployment: php-fpm, the app or WordPress, and nginx
+    Install {
+        /// the php deployment to install
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum PythonCommands {
+    /// Install a python deployment: venv, requirements, systemd and nginx
+    Install {
+        /// the python deployment to install
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RedisCommands {
+    /// Install redis on the host of a redis deployment
+    Install {
+        /// the redis deployment to provision
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum DockerCommands {
+    /// Install docker engine and compose on the host of a docker_host
+    /// deployment
+    Install {
+        /// the docker_host deployment to provision
+        #[arg(long)]
+        name: String,
+    },
+    /// Log the remote docker engine into a registry using stored credentials
+    Login {
+        /// the docker_host deployment whose engine logs in
+        #[arg(long)]
+        name: String,
+        /// the registry host, e.g. docker.io or ghcr.io
+        #[arg(long)]
+        registry: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ObservabilityCommands {
+    /// Install the stack on the host of an observability deployment
+    Install {
+        /// the observability deployment to install
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum FirewallCommands {
+    /// Open the rules the deployments need, reporting anything extra
+    Apply {
+        /// only converge the host of this deployment (all hosts when omitted)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Show desired vs. present rules per host without changing anything
+    Status {
+        /// only check the host of this deployment (all hosts when omitted)
+        #[arg(long)]
+        name: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CiCommands {
+    /// Print a ready-to-use GitHub Actions workflow for deploys
+    PrintWorkflow,
+}
+
+#[derive(Subcommand)]
+enum SecurityCommands {
+    /// Manage fail2ban on the deployment hosts
+    Fail2ban {
+        #[command(subcommand)]
+        command: Fail2banCommands,
+    },
+    /// Check a host for common issues and print a prioritized report
+    Audit {
+        /// the deployment whose host to audit
+        #[arg(long)]
+        name: String,
+    },
+    /// Disable password auth and root login, after a key-login safety check
+    HardenSsh {
+        /// the deployment whose host to harden
+        #[arg(long)]
+        name: String,
+        /// also move sshd to this port, updating ufw and the stored config
+        #[arg(long)]
+        port: Option<u16>,
+    },
+}
+
+#[derive(Subcommand)]
+enum Fail2banCommands {
+    /// Install fail2ban with the jails from the settings block
+    Install {
+        /// the deployment whose host to harden
+        #[arg(long)]
+        name: String,
+    },
+    /// Show every jail and its currently banned addresses
+    Status {
+        /// the deployment whose host to inspect
+        #[arg(long)]
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersCommands {
+    /// Create a non-root deploy user with a limited sudoers entry
+    Add {
+        /// the deployment whose host to create the user on
+        #[arg(long)]
+        name: String,
+        /// the user to create
+        #[arg(long)]
+        user: String,
+    },
+    /// Remove a deploy user, its home and its sudoers entry
+    Remove {
+        /// the deployment whose host to remove the user from
+        #[arg(long)]
+        name: String,
+        /// the user to remove
+        #[arg(long)]
+        user: String,
+    },
+    /// Add a public key to a user's authorized_keys
+    AuthorizeKey {
+        /// the deployment whose host holds the user
+        #[arg(long)]
+        name: String,
+        /// the user to authorize the key for
+        #[arg(long)]
+        user: String,
+        /// path to the local public key file
+        #[arg(long)]
+        key_path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Create an empty rumi config file
+    Init,
+    /// Print the current config
+    Show,
+    /// Rotate the ssh keypair: generate, install, verify, then retire the
+    /// old key
+    RotateKeys {
+        /// rotate the key of this deployment's ssh (default_ssh when omitted)
+        #[arg(long)]
+        name: Option<String>,
+        /// print the plan without running anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Check the config for problems, optionally probing every host
+    Validate {
+        /// also ssh into every host to verify authentication and sudo
+        #[arg(long)]
+        remote: bool,
+    },
+    /// Store a secret in the config, keyed like "registry/ghcr.io/password"
+    SetSecret {
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        value: String,
+    },
+    /// Encrypt the config file with a master passphrase
+    Encrypt,
+    /// Decrypt the config file back to plain json
+    Decrypt,
+    /// Import hosts and variables from terraform outputs
+    Import {
+        /// a `terraform output -json` file
+        #[arg(long)]
+        terraform_output: PathBuf,
+    },
+    /// Set the default ssh connection, or save a named profile with --name
+    AddSsh {
+        /// save as a named profile deployments can reference via ssh_profile
+        #[arg(long)]
+        name: Option<String>,
+        /// the ssh host
+        #[arg(long)]
+        host: String,
+        /// the ssh port
+        #[arg(long, default_value_t = 22)]
+        port: u16,
+        /// the ssh user
+        #[arg(long)]
+        user: String,
+        /// path to the public key file
+        #[arg(long)]
+        public_key_path: Option<String>,
+        /// path to the private key file
+        #[arg(long)]
+        private_key_path: Option<String>,
+        /// passphrase of the private key
+        #[arg(long)]
+        passphrase: Option<String>,
+        /// how privileged commands escalate: sudo, doas or none
+        #[arg(long, default_value = "sudo")]
+        escalation: String,
+        /// sudo on this host asks for a password, fed over stdin from
+        /// RUMI_SUDO_PASSWORD or an interactive prompt
+        #[arg(long)]
+        requires_sudo_password: bool,
+        /// reach the host through this bastion: an ssh profile name or a
+        /// user@host:port spec
+        #[arg(long)]
+        proxy_jump: Option<String>,
+    },
+    /// List the default ssh connection and every named profile
+    ListSsh,
+    /// Remove a named ssh profile
+    RemoveSsh {
+        /// the profile name as shown by list-ssh
+        #[arg(long)]
+        name: String,
+    },
+}
+
+/// Whether a command only reads state, so it may run in read-only mode.
+/// Anything that touches a host, dns zone or the config file counts as
+/// mutating.
+fn command_is_read_only(command: &Commands) -> bool {
+    match command {
+        Commands::Logs { .. } => true,
+        Commands::Monitor { command } => {
+            !matches!(command, MonitorCommands::PublishStatus { .. })
+        }
+        Commands::Hosting { command } => matches!(
+            command,
+            HostingCommands::Releases { .. }
+                | HostingCommands::Info { .. }
+                | HostingCommands::Logs { .. }
+        ),
+        Commands::Cert { command } => matches!(command, CertCommands::Status),
+        Commands::Dns { command } => matches!(command, DnsCommands::List { .. }),
+        Commands::K8s { command } => {
+            matches!(command, K8sCommands::Render { .. } | K8sCommands::Status { .. })
+        }
+        Commands::Backup { command } => matches!(
+            command,
+            BackupCommands::List { .. }
+                | BackupCommands::Restore { .. }
+                | BackupCommands::Download { .. }
+        ),
+        Commands::Firewall { command } => matches!(command, FirewallCommands::Status { .. }),
+        Commands::Ci { command } => matches!(command, CiCommands::PrintWorkflow),
+        Commands::Security { command } => match command {
+            SecurityCommands::Audit { .. } => true,
+            SecurityCommands::Fail2ban { command } => {
+                matches!(command, Fail2banCommands::Status { .. })
+            }
+            SecurityCommands::HardenSsh { .. } => false,
+        },
+        Commands::Config { command } => matches!(
+            command,
+            ConfigCommands::Show | ConfigCommands::Validate { .. } | ConfigCommands::ListSsh
+        ),
+        Commands::Plan { .. }
+        | Commands::Render { .. }
+        | Commands::History { .. }
+        | Commands::Status { .. }
+        | Commands::Completion { .. }
+        | Commands::CompleteValues { .. } => true,
+        Commands::Listen { .. }
+        | Commands::Database { .. }
+        | Commands::Ethereum { .. }
+        | Commands::Php { .. }
+        | Commands::Python { .. }
+        | Commands::Redis { .. }
+        | Commands::Docker { .. }
+        | Commands::Observability { .. }
+        | Commands::Users { .. }
+        | Commands::Server { .. }
+        | Commands::Canary { .. }
+        | Commands::Shell
+        | Commands::Init { .. } => false,
+    }
+}
+
+/// Provisioning commands harden the host afterwards when the settings block
+/// asks for it.
+fn harden_after_install(
+    config: &RumiConfig,
+    session: &rumi2::session::RumiSession,
+) -> RumiResult<()> {
+    if let Some(fail2ban) = &config.settings.fail2ban {
+        rumi2::security::install_fail2ban(session, fail2ban)?;
+    }
+    Ok(())
+}
+
+/// After a successful flag-driven install, persist the deployment into the
+/// config so the name-based commands (update, backup, monitor) find it.
+/// Run a deploy step on every --ssh_host in turn, reporting per host and
+/// failing the command if any host failed. Hosts behind one load balancer
+/// should all end up serving the same release, so the loop keeps going past
+/// a broken host and sums up the damage at the end.
+fn for_each_ssh_host<F>(ssh: &SshArgs, mut step: F) -> RumiResult<()>
+where
+    F: FnMut(&rumi2::session::RumiSession) -> RumiResult<()>,
+{
+    let configs = ssh.to_ssh_configs();
+    let single = configs.len() == 1;
+    let mut failed: Vec<String> = Vec::new();
+    for config in configs {
+        let result = rumi2::session::RumiSession::connect(&config)
+            .and_then(|session| step(&session));
+        match result {
+            Ok(()) => {
+                if !single {
+                    println!("[{}] done", config.host);
+                }
+            }
+            Err(e) => {
+                eprintln!("[{}] failed: {}", config.host, e);
+                failed.push(config.host);
+            }
+        }
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(rumi2::error::RumiError::CommandFailed(format!(
+            "failed on {}",
+            failed.join(", ")
+        )))
+    }
+}
+
+/// Already-registered domains are left alone.
+fn register_website_deployment(
+    config_path: &std::path::Path,
+    domain: &str,
+    dist_path: &str,
+    ssh: &SshArgs,
+) -> RumiResult<()> {
+    let mut config = RumiConfig::load_from_file(config_path).unwrap_or_default();
+    if config.deployments.iter().any(|d| d.domain == domain) {
+        return Ok(());
+    }
+    let ssh_config = SshConfig {
+        host: ssh.primary_host().to_string(),
+        port: 22,
+        user: ssh.ssh_user.clone(),
+        public_key_path: Some(ssh.ssh_cert_public_key.clone()),
+        private_key_path: Some(ssh.ssh_cert_private_key.clone()),
+        passphrase: (!ssh.ssh_password.is_empty()).then(|| ssh.ssh_password.clone()),
+        escalation: None,
+        requires_sudo_password: false,
+        proxy_jump: None,
+    };
+    // only record an override when the host differs from the default
+    let ssh_override = match &config.default_ssh {
+        Some(default) if default.host == ssh_config.host && default.user == ssh_config.user => {
+            None
+        }
+        _ => Some(ssh_config),
+    };
+    config.deployments.push(DeploymentConfig {
+        name: domain.to_string(),
+        domain: domain.to_string(),
+        deployment_type: DeploymentType::Website {
+            dist_path: dist_path.to_string(),
+        },
+        ssh: ssh_override,
+        ssh_profile: None,
+        ssh_targets: Vec::new(),
+        repo: None,
+        project_path: None,
+        health_url: None,
+        expected_status: None,
+        health_check: None,
+        challenge: None,
+        ssl_email: None,
+        nginx_extra: Vec::new(),
+    });
+    config.save_to_file(config_path)?;
+    println!(
+        "deployment '{}' registered in {}",
+        domain,
+        config_path.display()
+    );
+    Ok(())
+}
+
+/// The configured deployment for a domain, when the config has one — the
+/// hosting commands work from flags, so a config entry (and with it the
+/// post-deploy health check) is optional.
+fn deployment_for_domain(
+    config_path: &std::path::Path,
+    domain: &str,
+) -> Option<rumi2::config::DeploymentConfig> {
+    RumiConfig::load_from_file(config_path)
+        .ok()
+        .and_then(|config| config.deployments.iter().find(|d| d.domain == domain).cloned())
+}
+
+/// Deploy commands verify the artifact's detached signature first when the
+/// settings block configures signing. A missing rumi.json means nothing is
+/// configured and the artifact passes.
+fn verify_artifact_before_deploy(
+    config_path: &std::path::Path,
+    artifact: &str,
+) -> RumiResult<()> {
+    if !config_path.exists() {
+        return Ok(());
+    }
+    let config = RumiConfig::load_from_file(config_path)?;
+    if let Some(signing) = &config.settings.artifact_signing {
+        rumi2::security::verify_artifact(signing, std::path::Path::new(artifact))?;
+    }
+    Ok(())
+}
+
+/// When --framework is given, build the project locally and swap dist_path
+/// for the build output, along with the framework's nginx directives.
+fn resolve_framework(
+    framework: Option<String>,
+    dist_path: String,
+) -> RumiResult<(String, &'static str)> {
+    match framework {
+        Some(flag) => {
+            let project_dir = std::path::Path::new(&dist_path);
+            let framework = rumi2::framework::resolve(&flag, project_dir)?;
+            let output = rumi2::framework::build(framework, project_dir)?;
+            Ok((output.to_string_lossy().into_owned(), framework.nginx_extras()))
+        }
+        None => Ok((dist_path, "")),
+    }
+}
+
+fn run(cli: Cli) -> RumiResult<()> {
+    let config_path = resolve_config_path(cli.config);
+    if cli.ci || rumi2::ci::detect() {
+        rumi2::ci::enable();
+    }
+    let gitlab = cli.gitlab || rumi2::ci::detect_gitlab();
+    if let Some(log_file) = &cli.log_file {
+        rumi2::transcript::enable(log_file)?;
+    }
+    // the config only needs to be consulted for the read-only setting and
+    // the default transcript dir when the command would mutate something
+    if !command_is_read_only(&cli.command) {
+        let settings = config_path
+            .exists()
+            .then(|| RumiConfig::load_from_file(&config_path).map(|c| c.settings))
+            .and_then(Result::ok)
+            .unwrap_or_default();
+        if cli.read_only || settings.read_only {
+            return Err(rumi2::error::RumiError::Config(
+                "read-only mode: this command would change remote or config state".to_string(),
+            ));
+        }
+        if cli.log_file.is_none() {
+            if let Some(log_dir) = &settings.log_dir {
+                rumi2::transcript::enable_in_dir(log_dir)?;
+            }
+        }
+        if let Some(secs) = settings.command_timeout_secs {
+            rumi2::session::set_default_timeout_secs(secs);
+        }
+        if let Some(files) = settings.tar_upload_threshold {
+            rumi2::blobstore::set_tar_upload_threshold(files);
+        }
+        if let Some(connections) = settings.upload_concurrency {
+            rumi2::blobstore::set_upload_concurrency(connections);
+        }
+        if settings.strict_host_key_checking {
+            rumi2::session::set_host_key_policy(rumi2::session::HostKeyPolicy::Strict);
+        }
+    }
+    if cli.insecure_skip_host_key {
+        rumi2::session::set_host_key_policy(rumi2::session::HostKeyPolicy::Skip);
+    }
+    if cli.verbose {
+        rumi2::session::set_verbose();
+    }
+    if let Some(connections) = cli.concurrency {
+        rumi2::blobstore::set_upload_concurrency(connections);
+    }
+    let dry_run = cli.dry_run;
+    match cli.command {
+        Commands::Hosting { command } => match command {
+            HostingCommands::Install {
+                ssh,
+                domain,
+                dist_path,
+                version_id,
+                framework,
+                manage_dns,
+                challenge,
+                wildcard,
+                ssl_email,
+            } => {
+                // urls are fetched into the local artifact cache first
+                let dist_path = rumi2::artifact::materialize(&dist_path)?;
+                verify_artifact_before_deploy(&config_path, &dist_path)?;
+                // registered below as given, not as the framework build output
+                let dist_path_flag = dist_path.clone();
+                let (dist_path, nginx_extras) = resolve_framework(framework, dist_path)?;
+                if manage_dns && dry_run {
+                    println!(
+                        "dry run: would point {}'s dns records at {} first",
+                        domain,
+                        ssh.primary_host()
+                    );
+                } else if manage_dns {
+                    rumi2::ci::step("dns", || {
+                        let config = RumiConfig::load_from_file(&config_path)?;
+                        let provider = rumi2::dns::provider_from_config(&config)?;
+                        let ip = rumi2::dns::ensure_domain_records(
+                            provider.as_ref(),
+                            &domain,
+                            ssh.primary_host(),
+                        )?;
+                        rumi2::dns::wait_for_propagation(
+                            &domain,
+                            ip,
+                            rumi2::dns::DEFAULT_PROPAGATION_TIMEOUT_SECS,
                         )
-                        .arg(arg!(--ssh_cert_public_key <SSH_CERT_PUBLIC_KEY> "the ssh public key"))
-                        .arg(arg!(--ssh_cert_private_key <SSH_CERT_PRIVATE_KEY> "the ssh private key"))
-                        .arg(arg!(--ssh_host <SSH_HOST> "the ssh host"))
-                        .arg(arg!(--ssh_user <SSH_USER> "the ssh user"))
-                        .arg(arg!(--ssh_password <SSH_PASSWORD> "the ssh password"))
-                        .arg(arg!(--domain <DOMAIN> "the url of the website"))
-                        .arg(arg!(--dist_path <DIST_PATH> "the url of the website"))
-                        .arg_required_else_help(true),
-                )
-                .subcommand(
-                    Command::new("rollback")
-                        .about("Rollback to a former website version")
-                        .arg(arg!(--ssh_cert_public_key <SSH_CERT_PUBLIC_KEY> "the ssh public key"))
-                        .arg(arg!(--ssh_cert_private_key <SSH_CERT_PRIVATE_KEY> "the ssh private key"))
-                        .arg(arg!(--ssh_host <SSH_HOST> "the ssh host"))
-                        .arg(arg!(--ssh_user <SSH_USER> "the ssh user"))
-                        .arg(arg!(--ssh_password <SSH_PASSWORD> "the ssh password"))
-                        .arg(arg!(--domain <DOMAIN> "the url of the website"))
-                        .arg(arg!(--version_id <VERSION_ID> "the url of the website"))
-                        .arg_required_else_help(true),
-                ),
-        )
-}
-
-fn main() -> Result<(), Error> {
-    let matches = cli().get_matches();
-    match matches.subcommand() {
-        Some(("hosting", hosting_matches)) => match hosting_matches.subcommand() {
-            Some(("install", install_matches)) => {
-                use rumi2::commands::websites::install_command;
-
-                let ssh_cert_public_key = install_matches
-                    .get_one::<String>("ssh_cert_public_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PUBLIC_KEY parameter value is missing");
-                let ssh_cert_private_key = install_matches
-                    .get_one::<String>("ssh_cert_private_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PRIVATE_KEY parameter value is missing");
-                let ssh_host = install_matches
-                    .get_one::<String>("ssh_host")
-                    .map(|s| s.as_str())
-                    .expect("SSH_HOST parameter value is missing");
-                let ssh_user = install_matches
-                    .get_one::<String>("ssh_user")
-                    .map(|s| s.as_str())
-                    .expect("SSH_USER parameter value is missing");
-                let ssh_password = install_matches
-                    .get_one::<String>("ssh_password")
-                    .map(|s| s.as_str())
-                    .expect("SSH_PASSWORD parameter value is missing");
-                let domain = install_matches
-                    .get_one::<String>("domain")
-                    .map(|s| s.as_str())
-                    .expect("DOMAIN parameter value is missing");
-                let dist_path = install_matches
-                    .get_one::<String>("dist_path")
-                    .map(|s| s.as_str())
-                    .expect("DIST_PATH parameter value is missing");
-
-                let _version_id = install_matches
-                    .get_one::<String>("version_id")
-                    .map(|s| s.as_str())
-                    .expect("VERSION_ID paramer value is missing");
-
-                let session = rumi2::Rumi2::start(
-                    ssh_host.to_string(),
-                    ssh_user.to_string(),
-                    ssh_cert_public_key.to_string(),
-                    ssh_cert_private_key.to_string(),
-                    ssh_password.to_string(),
-                );
-                install_command(&session, domain, dist_path);
-            }
-
-            Some(("update", update_matches)) => {
-                use rumi2::commands::websites::update_command;
-
-                let ssh_cert_public_key = update_matches
-                    .get_one::<String>("ssh_cert_public_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PUBLIC_KEY parameter value is missing");
-                let ssh_cert_private_key = update_matches
-                    .get_one::<String>("ssh_cert_private_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PRIVATE_KEY parameter value is missing");
-                let ssh_host = update_matches
-                    .get_one::<String>("ssh_host")
-                    .map(|s| s.as_str())
-                    .expect("SSH_HOST parameter value is missing");
-                let ssh_user = update_matches
-                    .get_one::<String>("ssh_user")
-                    .map(|s| s.as_str())
-                    .expect("SSH_USER parameter value is missing");
-                let ssh_password = update_matches
-                    .get_one::<String>("ssh_password")
-                    .map(|s| s.as_str())
-                    .expect("SSH_PASSWORD parameter value is missing");
-                let domain = update_matches
-                    .get_one::<String>("domain")
-                    .map(|s| s.as_str())
-                    .expect("DOMAIN parameter value is missing");
-                let dist_path = update_matches
-                    .get_one::<String>("dist_path")
-                    .map(|s| s.as_str())
-                    .expect("DIST_PATH parameter value is missing");
-
-                let session = rumi2::Rumi2::start(
-                    ssh_host.to_string(),
-                    ssh_user.to_string(),
-                    ssh_cert_public_key.to_string(),
-                    ssh_cert_private_key.to_string(),
-                    ssh_password.to_string(),
+                    })?;
+                }
+                rumi2::ci::step("install", || {
+                    let config = config_path
+                        .exists()
+                        .then(|| RumiConfig::load_from_file(&config_path))
+                        .transpose()?;
+                    let ssl_email = ssl_email
+                        .clone()
+                        .or_else(|| {
+                            config.as_ref().and_then(|c| c.settings.ssl_email.clone())
+                        })
+                        .ok_or_else(|| {
+                            rumi2::error::RumiError::Config(
+                                "no ssl email: pass --ssl_email or set settings.ssl_email"
+                                    .to_string(),
+                            )
+                        })?;
+                    if dry_run {
+                        for target in ssh.to_ssh_configs() {
+                            let recorder =
+                                rumi2::session::RecordingExecutor::new(&target.host);
+                            rumi2::certs::obtain_certificate(
+                                &recorder,
+                                &domain,
+                                &ssl_email,
+                                challenge,
+                                config.as_ref(),
+                                wildcard,
+                            )?;
+                            rumi2::commands::websites::install_command(
+                                &recorder,
+                                &domain,
+                                &dist_path,
+                                nginx_extras,
+                            )?;
+                            recorder.print();
+                        }
+                        return Ok(());
+                    }
+                    for_each_ssh_host(&ssh, |session| {
+                        rumi2::certs::obtain_certificate(
+                            session,
+                            &domain,
+                            &ssl_email,
+                            challenge,
+                            config.as_ref(),
+                            wildcard,
+                        )?;
+                        rumi2::commands::websites::install_command(
+                            session,
+                            &domain,
+                            &dist_path,
+                            nginx_extras,
+                        )?;
+                        rumi2::state::record(
+                            session,
+                            rumi2::state::StateEntry::new(&domain, "website")
+                                .version(Some(version_id.clone()))
+                                .dist_hash(rumi2::release::checksum_summary(
+                                    std::path::Path::new(&dist_path),
+                                ))
+                                .nginx_config(Some(format!(
+                                    "{}/{}",
+                                    rumi2::NGINX_WEB_CONFIG_PATH,
+                                    domain
+                                ))),
+                        )
+                    })
+                })?;
+                if !dry_run {
+                    if let Some(deployment) = deployment_for_domain(&config_path, &domain) {
+                        rumi2::ci::step("health-check", || {
+                            rumi2::monitor::verify_after_deploy(&deployment)
+                        })?;
+                    }
+                    register_website_deployment(&config_path, &domain, &dist_path_flag, &ssh)?;
+                    if gitlab {
+                        rumi2::ci::write_gitlab_env(&domain, &version_id)?;
+                    }
+                }
+            }
+            HostingCommands::Update {
+                ssh,
+                domain,
+                dist_path,
+                framework,
+                git_ref,
+                full,
+                purge_cdn,
+                purge_path,
+            } => {
+                if let Some(git_ref) = &git_ref {
+                    rumi2::release::checkout_ref(std::path::Path::new(&dist_path), git_ref)?;
+                }
+                let dist_path = rumi2::artifact::materialize(&dist_path)?;
+                verify_artifact_before_deploy(&config_path, &dist_path)?;
+                let revision =
+                    rumi2::release::GitRevision::detect(std::path::Path::new(&dist_path));
+                let (dist_path, nginx_extras) = resolve_framework(framework, dist_path)?;
+                let deployment = deployment_for_domain(&config_path, &domain);
+                let wants_rollback = deployment
+                    .as_ref()
+                    .and_then(|d| d.health_check.as_ref())
+                    .map(|check| check.rollback)
+                    .unwrap_or(false);
+                // what `current` pointed at per host, for the auto-rollback
+                let mut previous_roots: std::collections::HashMap<String, String> =
+                    std::collections::HashMap::new();
+                rumi2::ci::step("update", || {
+                    if dry_run {
+                        for target in ssh.to_ssh_configs() {
+                            let recorder =
+                                rumi2::session::RecordingExecutor::new(&target.host);
+                            rumi2::commands::websites::update_command(
+                                &recorder,
+                                &domain,
+                                &dist_path,
+                                nginx_extras,
+                                full,
+                            )?;
+                            recorder.print();
+                        }
+                        return Ok(());
+                    }
+                    for_each_ssh_host(&ssh, |session| {
+                        if wants_rollback {
+                            if let Ok(previous) = rumi2::release::current_web_root(session, &domain)
+                            {
+                                previous_roots.insert(session.host().to_string(), previous);
+                            }
+                        }
+                        let release_path = rumi2::commands::websites::update_command(
+                            session,
+                            &domain,
+                            &dist_path,
+                            nginx_extras,
+                            full,
+                        )?;
+                        let metadata = rumi2::release::ReleaseMetadata::new(
+                            release_path,
+                            revision.clone(),
+                            std::path::Path::new(&dist_path),
+                        );
+                        rumi2::release::write_release_metadata(session, &metadata)?;
+                        rumi2::release::record_release(session, &domain, &metadata)?;
+                        rumi2::state::record(
+                            session,
+                            rumi2::state::StateEntry::new(&domain, "website")
+                                .version(
+                                    metadata
+                                        .release_path
+                                        .rsplit('/')
+                                        .next()
+                                        .map(str::to_string),
+                                )
+                                .dist_hash(metadata.checksum.clone())
+                                .nginx_config(Some(format!(
+                                    "{}/{}",
+                                    rumi2::NGINX_WEB_CONFIG_PATH,
+                                    domain
+                                ))),
+                        )
+                    })
+                })?;
+                if !dry_run {
+                    if let Some(deployment) = &deployment {
+                        let health = rumi2::ci::step("health-check", || {
+                            rumi2::monitor::verify_after_deploy(deployment)
+                        });
+                        if let Err(health_error) = health {
+                            if wants_rollback && !previous_roots.is_empty() {
+                                eprintln!(
+                                    "health check failed, switching back to the previous release"
+                                );
+                                for_each_ssh_host(&ssh, |session| {
+                                    let previous = match previous_roots.get(session.host()) {
+                                        Some(previous) => previous,
+                                        None => return Ok(()),
+                                    };
+                                    let version = previous
+                                        .strip_prefix(&format!("{}/", rumi2::WEB_FOLDER))
+                                        .unwrap_or(previous);
+                                    rumi2::commands::websites::rollback_command(
+                                        session, &domain, version,
+                                    )
+                                })?;
+                            }
+                            return Err(health_error);
+                        }
+                    }
+                }
+                if purge_cdn && !dry_run {
+                    rumi2::ci::step("purge-cdn", || {
+                        let config = RumiConfig::load_from_file(&config_path)?;
+                        rumi2::dns::purge_cdn_cache(&config, &domain, &purge_path)
+                    })?;
+                }
+                if gitlab && !dry_run {
+                    // updates have no explicit version id, stamp the release
+                    let release_id = chrono::Utc::now().format("%Y%m%d%H%M%S").to_string();
+                    rumi2::ci::write_gitlab_env(&domain, &release_id)?;
+                }
+            }
+            HostingCommands::SftpDeploy { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::websites::sftp_deploy_command(&session, deployment)?;
+            }
+            HostingCommands::Releases { name, utc } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                let releases = rumi2::release::list_releases(&session, &deployment.domain)?;
+                if releases.is_empty() {
+                    println!("no recorded releases for {}", deployment.domain);
+                } else {
+                    rumi2::release::print_release_table(&releases, utc);
+                }
+            }
+            HostingCommands::Prune { name, keep } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                let removed = rumi2::release::prune_releases(&session, &deployment.domain, keep)?;
+                if removed.is_empty() {
+                    println!("nothing to prune, {} release(s) or fewer", keep);
+                } else {
+                    for path in &removed {
+                        println!("removed {}", path);
+                    }
+                    println!("pruned {} release(s)", removed.len());
+                }
+            }
+            HostingCommands::Logs {
+                name,
+                follow,
+                error,
+                filter,
+                lines,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::logs::hosting_logs_command(
+                    &config,
+                    &name,
+                    follow,
+                    error,
+                    filter.as_deref(),
+                    lines,
+                )?;
+            }
+            HostingCommands::Info { name, utc } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                let metadata = rumi2::release::active_release(&session, &deployment.domain)?;
+                rumi2::release::print_release_info(&metadata, utc);
+            }
+            HostingCommands::Rollback {
+                ssh,
+                domain,
+                version_id,
+            } => {
+                let session = ssh.start_session()?;
+                rumi2::commands::websites::rollback_command(&session, &domain, &version_id)?;
+            }
+            HostingCommands::Uninstall {
+                name,
+                purge,
+                revoke_cert,
+                yes,
+            } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?.clone();
+                let domain = deployment.domain.clone();
+                let hosts = config.ssh_targets_for_deployment(&deployment)?;
+                if dry_run {
+                    for host in &hosts {
+                        let recorder = rumi2::session::RecordingExecutor::new(&host.host);
+                        rumi2::commands::websites::uninstall_command(
+                            &recorder,
+                            &domain,
+                            purge,
+                            revoke_cert,
+                        )?;
+                        recorder.print();
+                    }
+                    return Ok(());
+                }
+                if !yes {
+                    // a pipeline has nobody to answer, --yes is the way there
+                    eprint!(
+                        "this takes {} off {} host(s){}; continue? [y/N] ",
+                        domain,
+                        hosts.len(),
+                        if purge { " and deletes its files" } else { "" }
+                    );
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer)?;
+                    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                        println!("aborted, nothing was touched");
+                        return Ok(());
+                    }
+                }
+                for host in &hosts {
+                    let session = rumi2::session::RumiSession::connect(host)?;
+                    rumi2::commands::websites::uninstall_command(
+                        &session,
+                        &domain,
+                        purge,
+                        revoke_cert,
+                    )?;
+                }
+                config.deployments.retain(|d| d.name != name);
+                config.save_to_file(&config_path)?;
+                println!(
+                    "deployment '{}' removed from {}",
+                    name,
+                    config_path.display()
                 );
-                update_command(&session, domain, dist_path)
-            }
-
-            Some(("rollback", rollback_matches)) => {
-                use rumi2::commands::websites::rollback_command;
-
-                let ssh_cert_public_key = rollback_matches
-                    .get_one::<String>("ssh_cert_public_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PUBLIC_KEY parameter value is missing");
-                let ssh_cert_private_key = rollback_matches
-                    .get_one::<String>("ssh_cert_private_key")
-                    .map(|s| s.as_str())
-                    .expect("SSH_CERT_PRIVATE_KEY parameter value is missing");
-                let ssh_host = rollback_matches
-                    .get_one::<String>("ssh_host")
-                    .map(|s| s.as_str())
-                    .expect("SSH_HOST parameter value is missing");
-                let ssh_user = rollback_matches
-                    .get_one::<String>("ssh_user")
-                    .map(|s| s.as_str())
-                    .expect("SSH_USER parameter value is missing");
-                let ssh_password = rollback_matches
-                    .get_one::<String>("ssh_password")
-                    .map(|s| s.as_str())
-                    .expect("SSH_PASSWORD parameter value is missing");
-                let domain = rollback_matches
-                    .get_one::<String>("domain")
-                    .map(|s| s.as_str())
-                    .expect("DOMAIN parameter value is missing");
-                let version_id = rollback_matches
-                    .get_one::<String>("version_id")
-                    .map(|s| s.as_str())
-                    .expect("VERSION_ID parameter value is missing");
-
-                let session = rumi2::Rumi2::start(
-                    ssh_host.to_string(),
-                    ssh_user.to_string(),
-                    ssh_cert_public_key.to_string(),
-                    ssh_cert_private_key.to_string(),
-                    ssh_password.to_string(),
+            }
+        },
+        Commands::Monitor { command } => match command {
+            MonitorCommands::Check {
+                name,
+                timeout,
+                json,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                monitor::check_command(&config, name.as_deref(), timeout, json)?;
+            }
+            MonitorCommands::Certs {
+                name,
+                threshold,
+                timeout,
+                via_ssh,
+                json,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                monitor::certs_command(&config, name.as_deref(), threshold, timeout, via_ssh, json)?;
+            }
+            MonitorCommands::Resources { name, all: _, json } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                monitor::resources_command(&config, name.as_deref(), json)?;
+            }
+            MonitorCommands::Daemon {
+                interval,
+                listen,
+                history_file,
+                timeout,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let runtime = tokio::runtime::Runtime::new()?;
+                runtime.block_on(monitor::daemon_command(
+                    config,
+                    &interval,
+                    &listen,
+                    history_file,
+                    timeout,
+                ))?;
+            }
+            MonitorCommands::Bench {
+                name,
+                requests,
+                concurrency,
+                timeout,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                monitor::bench_command(&config, &name, requests, concurrency, timeout)?;
+            }
+            MonitorCommands::PublishStatus { name, timeout } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                monitor::publish_status_command(&config, &name, timeout)?;
+            }
+        },
+        Commands::Logs {
+            name,
+            source,
+            follow,
+            since,
+            lines,
+        } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            rumi2::logs::logs_command(&config, name.as_deref(), source, follow, since.as_deref(), lines)?;
+        }
+        Commands::Cert { command } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            match command {
+                CertCommands::Status => rumi2::certs::status_command(&config)?,
+                CertCommands::Renew { name, force } => {
+                    rumi2::certs::renew_command(&config, name.as_deref(), force)?
+                }
+                CertCommands::AutoRenew { name } => {
+                    rumi2::certs::auto_renew_command(&config, name.as_deref())?
+                }
+            }
+        }
+        Commands::Dns { command } => match command {
+            DnsCommands::List { zone } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::dns::list_command(&config, &zone)?;
+            }
+            DnsCommands::Set {
+                name,
+                record_type,
+                content,
+                ttl,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::dns::set_command(&config, &name, &record_type, &content, ttl)?;
+            }
+        },
+        Commands::K8s { command } => match command {
+            K8sCommands::Deploy { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                rumi2::commands::kubernetes::deploy_command(&config, deployment)?;
+            }
+            K8sCommands::Render { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                print!("{}", rumi2::commands::kubernetes::render_manifests(deployment)?);
+            }
+            K8sCommands::Status { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                rumi2::commands::kubernetes::status_command(&config, deployment)?;
+            }
+            K8sCommands::Rollback { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                rumi2::commands::kubernetes::rollback_command(&config, deployment)?;
+            }
+        },
+        Commands::Listen { port, secret } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            let runtime = tokio::runtime::Runtime::new()?;
+            runtime.block_on(rumi2::listen::listen_command(config, port, secret))?;
+        }
+        Commands::Database { command } => match command {
+            DatabaseCommands::Install { name } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?.clone();
+                let ssh = config.ssh_for_deployment(&deployment)?.clone();
+                let session = rumi2::session::RumiSession::connect(&ssh)?;
+                rumi2::commands::database::install_command(&session, &mut config, &deployment)?;
+                harden_after_install(&config, &session)?;
+                config.save_to_file(&config_path)?;
+            }
+        },
+        Commands::Backup { command } => match command {
+            BackupCommands::Create { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                let manager = rumi2::backup::BackupManager::new(&session);
+                let metadata = match &deployment.deployment_type {
+                    rumi2::config::DeploymentType::Ethereum { datadir, .. } => {
+                        let datadir = datadir
+                            .as_deref()
+                            .unwrap_or(rumi2::commands::ethereum::DEFAULT_DATADIR);
+                        manager.create_chaindata_backup(deployment, datadir)?
+                    }
+                    _ => manager.create_database_backup(deployment)?,
+                };
+                rumi2::backup::invalidate_cache(&ssh.host);
+                println!("backup {} created ({} bytes)", metadata.id, metadata.size_bytes);
+            }
+            BackupCommands::List {
+                name,
+                refresh,
+                utc,
+                backup_type,
+                since,
+                until,
+                min_size,
+                sort,
+            } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let filter = rumi2::backup::ListFilter {
+                    backup_type,
+                    since,
+                    until,
+                    min_size,
+                    sort,
+                };
+                rumi2::backup::list_command(&config, name.as_deref(), refresh, utc, &filter)?;
+            }
+            BackupCommands::Restore { backup_id, local } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::backup::restore_local_command(&config, &backup_id, &local)?;
+            }
+            BackupCommands::Download { backup_id, out } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::backup::download_command(&config, &backup_id, &out)?;
+            }
+            BackupCommands::Upload { name, archive } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::backup::upload_command(&config, &name, &archive)?;
+            }
+            BackupCommands::Cleanup { name, keep, json } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::backup::cleanup_command(&config, name.as_deref(), keep, json)?;
+            }
+        },
+        Commands::Ethereum { command } => match command {
+            EthereumCommands::Rollback { name, backup_id } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::ethereum::rollback_command(&session, deployment, &backup_id)?;
+                rumi2::backup::invalidate_cache(&ssh.host);
+            }
+        },
+        Commands::Php { command } => match command {
+            PhpCommands::Install { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                let ssl_email = config.ssl_email_for_deployment(deployment)?;
+                rumi2::commands::php::install_command(&session, deployment, &ssl_email)?;
+                harden_after_install(&config, &session)?;
+            }
+        },
+        Commands::Python { command } => match command {
+            PythonCommands::Install { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::python::install_command(&session, deployment)?;
+                harden_after_install(&config, &session)?;
+            }
+        },
+        Commands::Redis { command } => match command {
+            RedisCommands::Install { name } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?.clone();
+                let ssh = config.ssh_for_deployment(&deployment)?.clone();
+                let session = rumi2::session::RumiSession::connect(&ssh)?;
+                rumi2::commands::redis::install_command(&session, &mut config, &deployment)?;
+                harden_after_install(&config, &session)?;
+                config.save_to_file(&config_path)?;
+            }
+        },
+        Commands::Docker { command } => match command {
+            DockerCommands::Install { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::docker::install_command(&session, ssh, deployment)?;
+                harden_after_install(&config, &session)?;
+            }
+            DockerCommands::Login { name, registry } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::docker::login_command(&session, &config, &registry)?;
+            }
+        },
+        Commands::Observability { command } => match command {
+            ObservabilityCommands::Install { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                let session = rumi2::session::RumiSession::connect(ssh)?;
+                rumi2::commands::observability::install_command(&session, &config, deployment)?;
+                harden_after_install(&config, &session)?;
+            }
+        },
+        Commands::Firewall { command } => match command {
+            FirewallCommands::Apply { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let hosts = rumi2::firewall::target_hosts(&config, name.as_deref())?;
+                let mut pool = rumi2::session::SessionPool::new();
+                pool.connect_all(&hosts)?;
+                for ssh in &hosts {
+                    let session = pool.get(ssh)?;
+                    rumi2::firewall::apply_host(&config, ssh, &session)?;
+                }
+            }
+            FirewallCommands::Status { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let hosts = rumi2::firewall::target_hosts(&config, name.as_deref())?;
+                let mut pool = rumi2::session::SessionPool::new();
+                pool.connect_all(&hosts)?;
+                let mut drift = false;
+                for ssh in &hosts {
+                    let session = pool.get(ssh)?;
+                    drift |= rumi2::firewall::status_host(&config, ssh, &session)?;
+                }
+                if drift {
+                    return Err(rumi2::error::RumiError::Config(
+                        "firewall state drifted from the config, run firewall apply".to_string(),
+                    ));
+                }
+            }
+        },
+        Commands::Ci { command } => match command {
+            CiCommands::PrintWorkflow => rumi2::ci::print_workflow_command(),
+        },
+        Commands::Security { command } => match command {
+            SecurityCommands::Audit { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::security::audit(&config, &name)?;
+            }
+            SecurityCommands::HardenSsh { name, port } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::security::harden_ssh(&mut config, &name, port)?;
+                if port.is_some() {
+                    config.save_to_file(&config_path)?;
+                }
+            }
+            SecurityCommands::Fail2ban { command } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                match command {
+                    Fail2banCommands::Install { name } => {
+                        let deployment = config.find_deployment(&name)?;
+                        let ssh = config.ssh_for_deployment(deployment)?;
+                        let session = rumi2::session::RumiSession::connect(ssh)?;
+                        let fail2ban = config.settings.fail2ban.clone().unwrap_or_default();
+                        rumi2::security::install_fail2ban(&session, &fail2ban)?;
+                    }
+                    Fail2banCommands::Status { name } => {
+                        let deployment = config.find_deployment(&name)?;
+                        let ssh = config.ssh_for_deployment(deployment)?;
+                        let session = rumi2::session::RumiSession::connect(ssh)?;
+                        rumi2::security::fail2ban_status(&session)?;
+                    }
+                }
+            }
+        },
+        Commands::Users { command } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            let connect = |name: &str| -> RumiResult<rumi2::session::RumiSession> {
+                let deployment = config.find_deployment(name)?;
+                let ssh = config.ssh_for_deployment(deployment)?;
+                rumi2::session::RumiSession::connect(ssh)
+            };
+            match command {
+                UsersCommands::Add { name, user } => {
+                    rumi2::commands::users::add_command(&connect(&name)?, &user)?;
+                }
+                UsersCommands::Remove { name, user } => {
+                    rumi2::commands::users::remove_command(&connect(&name)?, &user)?;
+                }
+                UsersCommands::AuthorizeKey {
+                    name,
+                    user,
+                    key_path,
+                } => {
+                    rumi2::commands::users::authorize_key_command(
+                        &connect(&name)?,
+                        &user,
+                        &key_path,
+                    )?;
+                }
+            }
+        }
+        Commands::Config { command } => match command {
+            ConfigCommands::Init => {
+                let config = RumiConfig::default();
+                config.save_to_file(&config_path)?;
+                println!("created {}", config_path.display());
+            }
+            ConfigCommands::Show => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                println!("{}", serde_json::to_string_pretty(&config).unwrap());
+            }
+            ConfigCommands::Validate { remote } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let problems = rumi2::config::validate_local(&config);
+                for problem in &problems {
+                    println!("{:<20} {}", problem.entry, problem.problem);
+                }
+                if problems.is_empty() {
+                    println!(
+                        "config ok: {} deployment(s), local checks passed",
+                        config.deployments.len()
+                    );
+                }
+                if remote {
+                    let mut hosts: Vec<rumi2::config::SshConfig> = Vec::new();
+                    let mut entries = config.default_ssh.iter().collect::<Vec<_>>();
+                    entries.extend(config.ssh_profiles.values());
+                    entries.extend(config.deployments.iter().filter_map(|d| d.ssh.as_ref()));
+                    for ssh in entries {
+                        if !hosts
+                            .iter()
+                            .any(|h| h.host == ssh.host && h.user == ssh.user && h.port == ssh.port)
+                        {
+                            hosts.push(ssh.clone());
+                        }
+                    }
+                    let report = rumi2::fanout::Fanout::new().run(&hosts, |ssh| {
+                        let session = rumi2::session::RumiSession::connect(ssh)?;
+                        let sudo = session.execute_command("sudo -n true")?;
+                        if sudo.success() {
+                            Ok("authenticated, passwordless sudo works".to_string())
+                        } else {
+                            Err(rumi2::error::RumiError::CommandFailed(format!(
+                                "authenticated, but sudo -n failed: {}",
+                                sudo.stderr.trim()
+                            )))
+                        }
+                    });
+                    report.print(false);
+                    report.into_result()?;
+                }
+                if !problems.is_empty() {
+                    return Err(rumi2::error::RumiError::Config(
+                        "the config has problems, see above".to_string(),
+                    ));
+                }
+            }
+            ConfigCommands::RotateKeys { name, dry_run } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                rumi2::security::rotate_keys(&mut config, name.as_deref(), dry_run)?;
+                if !dry_run {
+                    config.save_to_file(&config_path)?;
+                }
+            }
+            ConfigCommands::SetSecret { key, value } => {
+                let mut config = RumiConfig::load_from_file(&config_path).unwrap_or_default();
+                rumi2::secrets::store(&mut config, &key, value);
+                config.save_to_file(&config_path)?;
+                println!("secret '{}' saved to {}", key, config_path.display());
+            }
+            ConfigCommands::Encrypt => {
+                // load decrypts as needed, so re-encrypting asks only once
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let passphrase = rumi2::crypt::passphrase()?;
+                let content = serde_json::to_string_pretty(&config)?;
+                std::fs::write(&config_path, rumi2::crypt::encrypt(&content, &passphrase)?)?;
+                println!("encrypted {}", config_path.display());
+            }
+            ConfigCommands::Decrypt => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let content = serde_json::to_string_pretty(&config)?;
+                std::fs::write(&config_path, content)?;
+                println!("decrypted {}", config_path.display());
+            }
+            ConfigCommands::Import { terraform_output } => {
+                let mut config = RumiConfig::load_from_file(&config_path).unwrap_or_default();
+                let imported =
+                    rumi2::config::import_terraform_outputs(&mut config, &terraform_output)?;
+                if imported.is_empty() {
+                    println!("nothing to import from {}", terraform_output.display());
+                } else {
+                    config.save_to_file(&config_path)?;
+                    for line in &imported {
+                        println!("{}", line);
+                    }
+                    println!("imported {} output(s) into {}", imported.len(), config_path.display());
+                }
+            }
+            ConfigCommands::AddSsh {
+                name,
+                host,
+                port,
+                user,
+                public_key_path,
+                private_key_path,
+                passphrase,
+                escalation,
+                requires_sudo_password,
+                proxy_jump,
+            } => {
+                let method = match escalation.as_str() {
+                    "sudo" => rumi2::config::EscalationMethod::Sudo,
+                    "doas" => rumi2::config::EscalationMethod::Doas,
+                    "none" => rumi2::config::EscalationMethod::None,
+                    other => {
+                        return Err(rumi2::error::RumiError::Config(format!(
+                            "unknown escalation '{}', expected sudo, doas or none",
+                            other
+                        )))
+                    }
+                };
+                let escalation = (method != rumi2::config::EscalationMethod::Sudo).then(|| {
+                    rumi2::config::EscalationConfig {
+                        method,
+                        allowlist: Vec::new(),
+                    }
+                });
+                let mut config = RumiConfig::load_from_file(&config_path).unwrap_or_default();
+                let ssh = SshConfig {
+                    host,
+                    port,
+                    user,
+                    public_key_path,
+                    private_key_path,
+                    passphrase,
+                    escalation,
+                    requires_sudo_password,
+                    proxy_jump,
+                };
+                match name {
+                    Some(name) => {
+                        let replaced = config.ssh_profiles.insert(name.clone(), ssh).is_some();
+                        config.save_to_file(&config_path)?;
+                        println!(
+                            "ssh profile '{}' {} in {}",
+                            name,
+                            if replaced { "updated" } else { "saved" },
+                            config_path.display()
+                        );
+                    }
+                    None => {
+                        config.default_ssh = Some(ssh);
+                        config.save_to_file(&config_path)?;
+                        println!("default ssh connection saved to {}", config_path.display());
+                    }
+                }
+            }
+            ConfigCommands::ListSsh => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let mut entries: Vec<(String, &SshConfig)> = Vec::new();
+                if let Some(ssh) = &config.default_ssh {
+                    entries.push(("(default)".to_string(), ssh));
+                }
+                let mut names: Vec<&String> = config.ssh_profiles.keys().collect();
+                names.sort();
+                for name in names {
+                    entries.push((name.clone(), &config.ssh_profiles[name]));
+                }
+                if entries.is_empty() {
+                    println!("no ssh connections configured, add one with `config add-ssh`");
+                    return Ok(());
+                }
+                println!("{:<20} {:<25} {:<12} USED BY", "NAME", "HOST", "USER");
+                for (name, ssh) in entries {
+                    let used_by = config
+                        .deployments
+                        .iter()
+                        .filter(|d| d.ssh_profile.as_deref() == Some(name.as_str()))
+                        .map(|d| d.name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    println!(
+                        "{:<20} {:<25} {:<12} {}",
+                        name,
+                        format!("{}:{}", ssh.host, ssh.port),
+                        ssh.user,
+                        used_by
+                    );
+                }
+            }
+            ConfigCommands::RemoveSsh { name } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                if config.ssh_profiles.remove(&name).is_none() {
+                    return Err(rumi2::error::RumiError::Config(format!(
+                        "no ssh profile named '{}', see `config list-ssh`",
+                        name
+                    )));
+                }
+                let orphaned: Vec<&str> = config
+                    .deployments
+                    .iter()
+                    .filter(|d| d.ssh_profile.as_deref() == Some(name.as_str()))
+                    .map(|d| d.name.as_str())
+                    .collect();
+                if !orphaned.is_empty() {
+                    return Err(rumi2::error::RumiError::Config(format!(
+                        "ssh profile '{}' is still used by {}, repoint them first",
+                        name,
+                        orphaned.join(", ")
+                    )));
+                }
+                config.save_to_file(&config_path)?;
+                println!("ssh profile '{}' removed", name);
+            }
+        },
+        Commands::Server { command } => match command {
+            ServerCommands::Deploy { name } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let hosts = config.ssh_targets_for_deployment(deployment)?;
+                if dry_run {
+                    for host in &hosts {
+                        let recorder = rumi2::session::RecordingExecutor::new(&host.host);
+                        rumi2::commands::servers::deploy_command(&recorder, deployment)?;
+                        recorder.print();
+                    }
+                } else if hosts.len() == 1 {
+                    let session = rumi2::session::RumiSession::connect(&hosts[0])?;
+                    rumi2::commands::servers::deploy_command(&session, deployment)?;
+                } else {
+                    let deployment = deployment.clone();
+                    let report = rumi2::fanout::Fanout::new().run(&hosts, move |ssh| {
+                        let session = rumi2::session::RumiSession::connect(ssh)?;
+                        rumi2::commands::servers::deploy_command(&session, &deployment)?;
+                        Ok("deployed".to_string())
+                    });
+                    report.print(false);
+                    report.into_result()?;
+                }
+                if !dry_run {
+                    rumi2::monitor::verify_after_deploy(deployment)?;
+                }
+            }
+            ServerCommands::Undeploy { name, yes } => {
+                let mut config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?.clone();
+                let hosts = config.ssh_targets_for_deployment(&deployment)?;
+                if dry_run {
+                    for host in &hosts {
+                        let recorder = rumi2::session::RecordingExecutor::new(&host.host);
+                        rumi2::commands::servers::undeploy_command(&recorder, &deployment)?;
+                        recorder.print();
+                    }
+                    return Ok(());
+                }
+                if !yes {
+                    // a pipeline has nobody to answer, --yes is the way there
+                    eprint!(
+                        "this stops {} and removes it from {} host(s); continue? [y/N] ",
+                        name,
+                        hosts.len()
+                    );
+                    let mut answer = String::new();
+                    std::io::stdin().read_line(&mut answer)?;
+                    if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                        println!("aborted, nothing was touched");
+                        return Ok(());
+                    }
+                }
+                for host in &hosts {
+                    let session = rumi2::session::RumiSession::connect(host)?;
+                    rumi2::commands::servers::undeploy_command(&session, &deployment)?;
+                }
+                config.deployments.retain(|d| d.name != name);
+                config.save_to_file(&config_path)?;
+                println!(
+                    "deployment '{}' removed from {}",
+                    name,
+                    config_path.display()
                 );
-                rollback_command(&session, domain, version_id);
             }
-            _ => unreachable!(),
+            ServerCommands::Rollback { name, version } => {
+                let config = RumiConfig::load_from_file(&config_path)?;
+                let deployment = config.find_deployment(&name)?;
+                let hosts = config.ssh_targets_for_deployment(deployment)?;
+                for host in &hosts {
+                    if dry_run {
+                        let recorder = rumi2::session::RecordingExecutor::new(&host.host);
+                        rumi2::commands::servers::rollback_command(
+                            &recorder, deployment, &version,
+                        )?;
+                        recorder.print();
+                    } else {
+                        let session = rumi2::session::RumiSession::connect(host)?;
+                        rumi2::commands::servers::rollback_command(
+                            &session, deployment, &version,
+                        )?;
+                    }
+                }
+            }
         },
-        _ => unreachable!(),
+        Commands::Canary { command } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            match command {
+                CanaryCommands::Start { name, percent } => {
+                    let deployment = config.find_deployment(&name)?;
+                    rumi2::canary::start_command(&config, deployment, percent)?;
+                }
+                CanaryCommands::Promote { name } => {
+                    let deployment = config.find_deployment(&name)?;
+                    rumi2::canary::promote_command(&config, deployment)?;
+                }
+                CanaryCommands::Abort { name } => {
+                    let deployment = config.find_deployment(&name)?;
+                    rumi2::canary::abort_command(&config, deployment)?;
+                }
+            }
+        }
+        Commands::Plan { name, export } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            let deployment = config.find_deployment(&name)?;
+            let plan = rumi2::plan::plan_deployment(&config, deployment)?;
+            match export {
+                Some(path) => plan.export(&path)?,
+                None => plan.print(),
+            }
+        }
+        Commands::Completion { shell } => {
+            use clap::CommandFactory;
+            let subcommands: Vec<String> = Cli::command()
+                .get_subcommands()
+                .filter(|c| !c.is_hide_set())
+                .map(|c| c.get_name().to_string())
+                .collect();
+            rumi2::completion::print_script(&shell, &subcommands)?;
+        }
+        Commands::CompleteValues { kind } => {
+            // completion must never prompt, so an encrypted config counts
+            // as no config here
+            let config = std::fs::read_to_string(&config_path)
+                .ok()
+                .filter(|content| !rumi2::crypt::is_encrypted(content))
+                .and_then(|content| serde_json::from_str::<RumiConfig>(&content).ok());
+            rumi2::completion::print_candidates(config.as_ref(), &kind)?;
+        }
+        Commands::Render { name, out } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            let deployment = config.find_deployment(&name)?;
+            rumi2::render::render_command(deployment, out.as_deref())?;
+        }
+        Commands::History { name, utc } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            rumi2::state::history_command(&config, &name, utc)?;
+        }
+        Commands::Status { name, utc } => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            rumi2::state::status_command(&config, &name, utc)?;
+        }
+        Commands::Shell => {
+            let config = RumiConfig::load_from_file(&config_path)?;
+            rumi2::shell::shell_command(&config)?;
+        }
+        Commands::Init { domain } => {
+            rumi2::init::init_command(&config_path, &domain)?;
+        }
     }
     Ok(())
 }
+
+fn main() -> ExitCode {
+    // trivial commands shouldn't pay for logger setup; the two long-running
+    // commands (listen, monitor daemon) build their tokio runtime themselves
+    if std::env::var_os("RUST_LOG").is_some() {
+        env_logger::init();
+    }
+    let cli = Cli::parse();
+    let result = run(cli);
+    rumi2::ci::print_summary();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
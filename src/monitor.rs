@@ -0,0 +1,1072 @@
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+use openssl::asn1::Asn1Time;
+use openssl::ssl::{SslConnector, SslMethod, SslStream};
+use openssl::x509::X509;
+use serde::Serialize;
+
+use crate::config::{DeploymentConfig, RumiConfig, SshConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::SSL_CERTIFICATE_PATH;
+
+/// How long we wait for a connection or a response before calling a host down.
+pub const DEFAULT_PROBE_TIMEOUT_SECS: u64 = 10;
+
+/// Warn when a certificate has fewer days left than this.
+pub const DEFAULT_CERT_WARN_DAYS: i64 = 14;
+
+/// The outcome of probing one deployment over http/https.
+#[derive(Debug, Clone, Serialize)]
+pub struct CheckResult {
+    pub name: String,
+    pub domain: String,
+    pub url: String,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+    /// Days until the tls certificate expires, when the probe went over https.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_days_left: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn connect(domain: &str, port: u16, timeout: Duration) -> RumiResult<TcpStream> {
+    let addr = format!("{}:{}", domain, port)
+        .to_socket_addrs()
+        .map_err(|e| RumiError::Network(format!("failed to resolve {}: {}", domain, e)))?
+        .next()
+        .ok_or_else(|| RumiError::Network(format!("no address found for {}", domain)))?;
+    let stream = TcpStream::connect_timeout(&addr, timeout)
+        .map_err(|e| RumiError::Network(format!("failed to connect to {}: {}", addr, e)))?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    Ok(stream)
+}
+
+/// Send a minimal HEAD request on an already connected stream and parse the
+/// status code out of the first response line.
+fn request_status<S: Read + Write>(stream: &mut S, domain: &str, path: &str) -> RumiResult<u16> {
+    let request = format!(
+        "HEAD {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: rumi2-monitor\r\nConnection: close\r\n\r\n",
+        path, domain
+    );
+    stream.write_all(request.as_bytes())?;
+    let mut response = Vec::new();
+    // servers close the connection after the response because of Connection: close
+    let _ = stream.read_to_end(&mut response);
+    let text = String::from_utf8_lossy(&response);
+    let status_line = text
+        .lines()
+        .next()
+        .ok_or_else(|| RumiError::Network(format!("{} sent an empty response", domain)))?;
+    status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+        .ok_or_else(|| {
+            RumiError::Network(format!("{} sent an invalid status line: {}", domain, status_line))
+        })
+}
+
+/// Open a tls connection to a domain.
+fn tls_connect(domain: &str, port: u16, timeout: Duration) -> RumiResult<SslStream<TcpStream>> {
+    let stream = connect(domain, port, timeout)?;
+    let connector = SslConnector::builder(SslMethod::tls())
+        .map_err(|e| RumiError::Tls(e.to_string()))?
+        .build();
+    connector
+        .connect(domain, stream)
+        .map_err(|e| RumiError::Tls(format!("tls handshake with {} failed: {}", domain, e)))
+}
+
+/// Days until a certificate expires, negative when it already did.
+fn certificate_days_left(cert: &X509) -> RumiResult<i64> {
+    let now = Asn1Time::days_from_now(0).map_err(|e| RumiError::Tls(e.to_string()))?;
+    let diff = now
+        .diff(cert.not_after())
+        .map_err(|e| RumiError::Tls(e.to_string()))?;
+    Ok(diff.days as i64)
+}
+
+fn peer_certificate(tls: &SslStream<TcpStream>, domain: &str) -> RumiResult<X509> {
+    tls.ssl()
+        .peer_certificate()
+        .ok_or_else(|| RumiError::Tls(format!("{} presented no certificate", domain)))
+}
+
+/// Probe a domain over https, returning status code and certificate days left.
+fn probe_https(domain: &str, port: u16, path: &str, timeout: Duration) -> RumiResult<(u16, i64)> {
+    let mut tls = tls_connect(domain, port, timeout)?;
+    let days_left = certificate_days_left(&peer_certificate(&tls, domain)?)?;
+    let status = request_status(&mut tls, domain, path)?;
+    Ok((status, days_left))
+}
+
+/// Probe a domain over plain http.
+fn probe_http(domain: &str, port: u16, path: &str, timeout: Duration) -> RumiResult<u16> {
+    let mut stream = connect(domain, port, timeout)?;
+    request_status(&mut stream, domain, path)
+}
+
+/// Where a deployment's health probe goes, derived from its health_url so
+/// "is it actually up" is defined once per deployment.
+struct ProbeTarget {
+    host: String,
+    port: Option<u16>,
+    path: String,
+    force_http: bool,
+}
+
+fn probe_target(deployment: &DeploymentConfig) -> ProbeTarget {
+    match &deployment.health_url {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => {
+            let force_http = url.starts_with("http://");
+            let rest = url.split_once("://").map(|(_, rest)| rest).unwrap_or("");
+            let (host_port, path) = match rest.split_once('/') {
+                Some((host_port, path)) => (host_port, format!("/{}", path)),
+                None => (rest, "/".to_string()),
+            };
+            let (host, port) = match host_port.split_once(':') {
+                Some((host, port)) => (host.to_string(), port.parse().ok()),
+                None => (host_port.to_string(), None),
+            };
+            ProbeTarget {
+                host,
+                port,
+                path,
+                force_http,
+            }
+        }
+        Some(path) => ProbeTarget {
+            host: deployment.domain.clone(),
+            port: None,
+            path: path.clone(),
+            force_http: false,
+        },
+        None => ProbeTarget {
+            host: deployment.domain.clone(),
+            port: None,
+            path: "/".to_string(),
+            force_http: false,
+        },
+    }
+}
+
+/// How long to wait between post-deploy probes, giving the service a
+/// moment to come up instead of burning the retries at once.
+const VERIFY_RETRY_DELAY: Duration = Duration::from_secs(3);
+
+/// The post-deploy phase: probe the deployment until it answers healthy,
+/// failing after the configured retries. Deployments without a
+/// health_check block skip this entirely; rolling back on failure is the
+/// caller's business, it knows what the previous release was.
+pub fn verify_after_deploy(deployment: &DeploymentConfig) -> RumiResult<()> {
+    let check = match &deployment.health_check {
+        Some(check) => check,
+        None => return Ok(()),
+    };
+    let mut probe = deployment.clone();
+    if check.url.is_some() {
+        probe.health_url = check.url.clone();
+    }
+    if check.expected_status.is_some() {
+        probe.expected_status = check.expected_status;
+    }
+    let retries = check.retries.max(1);
+    for attempt in 1..=retries {
+        let result = check_deployment(&probe, Duration::from_secs(check.timeout_secs));
+        if result.healthy {
+            println!(
+                "health check passed: {} answered {}",
+                result.url,
+                result.status.unwrap_or(0)
+            );
+            return Ok(());
+        }
+        let reason = result
+            .error
+            .or_else(|| result.status.map(|status| format!("status {}", status)))
+            .unwrap_or_else(|| "no answer".to_string());
+        println!(
+            "health check {}/{} failed: {} ({})",
+            attempt, retries, result.url, reason
+        );
+        if attempt < retries {
+            std::thread::sleep(VERIFY_RETRY_DELAY);
+        }
+    }
+    Err(RumiError::CommandFailed(format!(
+        "'{}' never became healthy within {} attempts",
+        deployment.name, retries
+    )))
+}
+
+/// Probe one deployment at its health url: https first (which also checks the
+/// certificate), falling back to plain http when the tls handshake fails.
+pub fn check_deployment(deployment: &DeploymentConfig, timeout: Duration) -> CheckResult {
+    let target = probe_target(deployment);
+    let start = Instant::now();
+    let (status, tls_days_left, error) = if target.force_http {
+        match probe_http(&target.host, target.port.unwrap_or(80), &target.path, timeout) {
+            Ok(status) => (Some(status), None, None),
+            Err(e) => (None, None, Some(e.to_string())),
+        }
+    } else {
+        match probe_https(&target.host, target.port.unwrap_or(443), &target.path, timeout) {
+            Ok((status, days)) => (Some(status), Some(days), None),
+            Err(https_err) => {
+                match probe_http(&target.host, target.port.unwrap_or(80), &target.path, timeout) {
+                    Ok(status) => (Some(status), None, Some(https_err.to_string())),
+                    Err(http_err) => (None, None, Some(http_err.to_string())),
+                }
+            }
+        }
+    };
+    let latency_ms = status.map(|_| start.elapsed().as_millis());
+    let healthy = match (status, deployment.expected_status) {
+        (Some(code), Some(expected)) => code == expected,
+        (Some(code), None) => code < 400,
+        (None, _) => false,
+    };
+    CheckResult {
+        name: deployment.name.clone(),
+        domain: deployment.domain.clone(),
+        url: format!(
+            "{}://{}{}",
+            if target.force_http { "http" } else { "https" },
+            target.host,
+            target.path
+        ),
+        healthy,
+        status,
+        latency_ms,
+        tls_days_left,
+        error,
+    }
+}
+
+/// Run checks for every deployment (or a single one) and return the results.
+pub fn run_checks(
+    config: &RumiConfig,
+    name: Option<&str>,
+    timeout: Duration,
+) -> RumiResult<Vec<CheckResult>> {
+    let deployments: Vec<&DeploymentConfig> = match name {
+        Some(name) => vec![config.find_deployment(name)?],
+        None => config.deployments.iter().collect(),
+    };
+    if deployments.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments in the config, add some to rumi.json first".to_string(),
+        ));
+    }
+    Ok(deployments
+        .into_iter()
+        .map(|d| check_deployment(d, timeout))
+        .collect())
+}
+
+/// Print results as an aligned table.
+pub fn print_check_table(results: &[CheckResult]) {
+    println!(
+        "{:<20} {:<30} {:<8} {:<8} {:<12} {:<10}",
+        "NAME", "DOMAIN", "HEALTHY", "STATUS", "LATENCY(MS)", "CERT DAYS"
+    );
+    for result in results {
+        println!(
+            "{:<20} {:<30} {} {:<8} {:<12} {:<10}",
+            result.name,
+            result.domain,
+            if result.healthy {
+                crate::output::green(format!("{:<8}", "up"))
+            } else {
+                crate::output::red(format!("{:<8}", "DOWN"))
+            },
+            result
+                .status
+                .map(|s| s.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            result
+                .latency_ms
+                .map(|l| l.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            result
+                .tls_days_left
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+        );
+        if let Some(error) = &result.error {
+            println!("  {}: {}", result.name, error);
+        }
+    }
+}
+
+/// Disk usage of one mount point on a host.
+#[derive(Debug, Clone, Serialize)]
+pub struct DiskUsage {
+    pub mount: String,
+    pub size_mb: u64,
+    pub used_mb: u64,
+    pub use_percent: String,
+}
+
+/// Resource usage of one host, gathered over ssh without any agent.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostResources {
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_average: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_total_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub memory_used_mb: Option<u64>,
+    pub disks: Vec<DiskUsage>,
+    pub top_processes: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+fn parse_free_output(output: &str) -> (Option<u64>, Option<u64>) {
+    for line in output.lines() {
+        if let Some(rest) = line.strip_prefix("Mem:") {
+            let fields: Vec<&str> = rest.split_whitespace().collect();
+            let total = fields.first().and_then(|f| f.parse().ok());
+            let used = fields.get(1).and_then(|f| f.parse().ok());
+            return (total, used);
+        }
+    }
+    (None, None)
+}
+
+fn parse_df_output(output: &str) -> Vec<DiskUsage> {
+    output
+        .lines()
+        .skip(1) // header
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 6 {
+                return None;
+            }
+            Some(DiskUsage {
+                mount: fields[5].to_string(),
+                size_mb: fields[1].parse().ok()?,
+                used_mb: fields[2].parse().ok()?,
+                use_percent: fields[4].to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Collect load, memory, disk and top processes from one host over a single
+/// ssh connection.
+fn gather_host_resources(ssh: &SshConfig) -> HostResources {
+    let mut resources = HostResources {
+        host: ssh.host.clone(),
+        load_average: None,
+        memory_total_mb: None,
+        memory_used_mb: None,
+        disks: Vec::new(),
+        top_processes: Vec::new(),
+        error: None,
+    };
+    let session = match RumiSession::connect(ssh) {
+        Ok(session) => session,
+        Err(e) => {
+            resources.error = Some(e.to_string());
+            return resources;
+        }
+    };
+    match session.execute_command("cat /proc/loadavg") {
+        Ok(output) => {
+            resources.load_average = Some(
+                output
+                    .stdout
+                    .split_whitespace()
+                    .take(3)
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            );
+        }
+        Err(e) => resources.error = Some(e.to_string()),
+    }
+    if let Ok(output) = session.execute_command("free -m") {
+        let (total, used) = parse_free_output(&output.stdout);
+        resources.memory_total_mb = total;
+        resources.memory_used_mb = used;
+    }
+    if let Ok(output) = session.execute_command("df -P -m -x tmpfs -x devtmpfs -x overlay") {
+        resources.disks = parse_df_output(&output.stdout);
+    }
+    if let Ok(output) = session.execute_command("ps aux --sort=-%cpu | head -6") {
+        resources.top_processes = output
+            .stdout
+            .lines()
+            .skip(1) // header
+            .map(|l| l.to_string())
+            .collect();
+    }
+    resources
+}
+
+fn print_resources(results: &[HostResources]) {
+    for resources in results {
+        println!("host: {}", resources.host);
+        if let Some(error) = &resources.error {
+            println!("  error: {}", error);
+            continue;
+        }
+        if let Some(load) = &resources.load_average {
+            println!("  load average: {}", load);
+        }
+        if let (Some(total), Some(used)) =
+            (resources.memory_total_mb, resources.memory_used_mb)
+        {
+            println!("  memory: {} / {} MB", used, total);
+        }
+        if !resources.disks.is_empty() {
+            println!("  {:<30} {:>10} {:>10} {:>6}", "MOUNT", "USED(MB)", "SIZE(MB)", "USE");
+            for disk in &resources.disks {
+                println!(
+                    "  {:<30} {:>10} {:>10} {:>6}",
+                    disk.mount, disk.used_mb, disk.size_mb, disk.use_percent
+                );
+            }
+        }
+        if !resources.top_processes.is_empty() {
+            println!("  top processes:");
+            for process in &resources.top_processes {
+                println!("    {}", process);
+            }
+        }
+    }
+}
+
+/// The `monitor resources` command: a quick fleet overview of CPU load,
+/// memory and disk, one ssh connection per host, gathered concurrently.
+pub fn resources_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    json: bool,
+) -> RumiResult<()> {
+    // deduplicate: several deployments usually share a host
+    let mut targets: Vec<SshConfig> = Vec::new();
+    match name {
+        Some(name) => {
+            let deployment = config.find_deployment(name)?;
+            targets.push(config.ssh_for_deployment(deployment)?.clone());
+        }
+        None => {
+            for deployment in &config.deployments {
+                let ssh = config.ssh_for_deployment(deployment)?;
+                if !targets.iter().any(|t| t.host == ssh.host) {
+                    targets.push(ssh.clone());
+                }
+            }
+        }
+    }
+    if targets.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments in the config, add some to rumi.json first".to_string(),
+        ));
+    }
+    let handles: Vec<_> = targets
+        .into_iter()
+        .map(|ssh| std::thread::spawn(move || gather_host_resources(&ssh)))
+        .collect();
+    let results: Vec<HostResources> = handles
+        .into_iter()
+        .map(|handle| handle.join().expect("resource gathering thread panicked"))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print_resources(&results);
+    }
+    let alerts = crate::alerts::evaluate_resource_rules(&config.alerts, &results);
+    if let Some(notifications) = &config.notifications {
+        if let Err(e) = crate::alerts::fire_webhooks(notifications, &alerts) {
+            eprintln!("warning: {}", e);
+        }
+    }
+    if !alerts.is_empty() {
+        return Err(RumiError::CommandFailed(format!(
+            "{} alert(s) fired: {}",
+            alerts.len(),
+            alerts
+                .iter()
+                .map(|a| a.message.as_str())
+                .collect::<Vec<_>>()
+                .join("; ")
+        )));
+    }
+    Ok(())
+}
+
+/// Render the latest check results as a small standalone status page.
+pub fn render_status_page(results: &[CheckResult]) -> String {
+    let rows: String = results
+        .iter()
+        .map(|result| {
+            format!(
+                "      <tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>\n",
+                if result.healthy { "up" } else { "down" },
+                result.name,
+                result.domain,
+                if result.healthy { "up" } else { "down" },
+                result
+                    .latency_ms
+                    .map(|l| format!("{} ms", l))
+                    .unwrap_or_else(|| "-".to_string()),
+                result
+                    .tls_days_left
+                    .map(|d| format!("{} days", d))
+                    .unwrap_or_else(|| "-".to_string()),
+            )
+        })
+        .collect();
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+  <head>
+    <meta charset="utf-8">
+    <meta http-equiv="refresh" content="300">
+    <title>Status</title>
+    <style>
+      body {{ font-family: sans-serif; margin: 2em auto; max-width: 50em; }}
+      table {{ border-collapse: collapse; width: 100%; }}
+      th, td {{ border: 1px solid #ddd; padding: 0.5em; text-align: left; }}
+      tr.up td {{ background: #e8f5e9; }}
+      tr.down td {{ background: #ffebee; }}
+      .generated {{ color: #777; font-size: 0.8em; }}
+    </style>
+  </head>
+  <body>
+    <h1>Service status</h1>
+    <table>
+      <tr><th>Name</th><th>Domain</th><th>State</th><th>Latency</th><th>Cert expires</th></tr>
+{rows}    </table>
+    <p class="generated">generated by rumi2 at {now}</p>
+  </body>
+</html>
+"#,
+        rows = rows,
+        now = chrono::Utc::now().to_rfc3339()
+    )
+}
+
+/// The `monitor publish-status` command: check every other deployment, render
+/// a static status page and push it through the normal website update flow of
+/// the named deployment.
+pub fn publish_status_command(config: &RumiConfig, name: &str, timeout_secs: u64) -> RumiResult<()> {
+    let status_site = config.find_deployment(name)?;
+    if !matches!(
+        status_site.deployment_type,
+        crate::config::DeploymentType::Website { .. }
+    ) {
+        return Err(RumiError::Config(format!(
+            "deployment '{}' is not a website, the status page needs one to be hosted on",
+            name
+        )));
+    }
+    let results: Vec<CheckResult> = config
+        .deployments
+        .iter()
+        .filter(|d| d.name != name) // the page shouldn't report on itself
+        .map(|d| check_deployment(d, Duration::from_secs(timeout_secs)))
+        .collect();
+    let page = render_status_page(&results);
+
+    let dist_dir = std::env::temp_dir().join(format!("rumi-status-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dist_dir)?;
+    std::fs::write(dist_dir.join("index.html"), page)?;
+
+    let ssh = config.ssh_for_deployment(status_site)?;
+    let session = RumiSession::connect(ssh)?;
+    crate::commands::websites::update_command(
+        &session,
+        &status_site.domain,
+        dist_dir.to_str().ok_or_else(|| {
+            RumiError::Config("temp dir path is not valid utf-8".to_string())
+        })?,
+        "",
+        false,
+    )?;
+    std::fs::remove_dir_all(&dist_dir).ok();
+    println!("status page published to https://{}/", status_site.domain);
+    Ok(())
+}
+
+/// How many check rounds the daemon keeps in its history file.
+pub const HISTORY_CAPACITY: usize = 1000;
+
+/// One round of checks as stored in the daemon's history ring buffer.
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct HistoryEntry {
+    pub checked_at: String,
+    pub results: Vec<StoredResult>,
+}
+
+/// The subset of a check result worth keeping around.
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct StoredResult {
+    pub name: String,
+    pub healthy: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latency_ms: Option<u128>,
+}
+
+/// Parse an interval like "60s", "5m" or "1h" into a duration.
+pub fn parse_interval(interval: &str) -> RumiResult<Duration> {
+    let (value, unit) = interval.split_at(interval.len().saturating_sub(1));
+    let seconds = match (value.parse::<u64>(), unit) {
+        (Ok(v), "s") => v,
+        (Ok(v), "m") => v * 60,
+        (Ok(v), "h") => v * 3600,
+        _ => interval.parse::<u64>().map_err(|_| {
+            RumiError::Config(format!(
+                "could not parse interval '{}', expected e.g. 60s, 5m or 1h",
+                interval
+            ))
+        })?,
+    };
+    Ok(Duration::from_secs(seconds.max(1)))
+}
+
+fn load_history(path: &std::path::Path) -> Vec<HistoryEntry> {
+    std::fs::read_to_string(path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default()
+}
+
+fn save_history(path: &std::path::Path, history: &[HistoryEntry]) -> RumiResult<()> {
+    std::fs::write(path, serde_json::to_string(history)?)?;
+    Ok(())
+}
+
+/// Answer every request on the local endpoint with the latest results.
+async fn serve_latest(
+    listener: tokio::net::TcpListener,
+    latest: std::sync::Arc<tokio::sync::RwLock<String>>,
+) {
+    loop {
+        let Ok((mut socket, _)) = listener.accept().await else {
+            continue;
+        };
+        let latest = latest.clone();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            let mut buffer = [0u8; 4096];
+            let _ = socket.read(&mut buffer).await; // request line and headers, ignored
+            let body = latest.read().await.clone();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+        });
+    }
+}
+
+/// The `monitor daemon` command: run checks on a schedule, keep a json ring
+/// buffer of history, alert on state transitions and serve the latest results
+/// on a local http endpoint.
+pub async fn daemon_command(
+    config: RumiConfig,
+    interval: &str,
+    listen: &str,
+    history_path: std::path::PathBuf,
+    timeout_secs: u64,
+) -> RumiResult<()> {
+    let interval = parse_interval(interval)?;
+    let listener = tokio::net::TcpListener::bind(listen)
+        .await
+        .map_err(|e| RumiError::Network(format!("could not listen on {}: {}", listen, e)))?;
+    log::info!(
+        "monitor daemon: checking every {}s, serving results on http://{}/",
+        interval.as_secs(),
+        listen
+    );
+    let latest = std::sync::Arc::new(tokio::sync::RwLock::new("[]".to_string()));
+    tokio::spawn(serve_latest(listener, latest.clone()));
+
+    let mut history = load_history(&history_path);
+    let mut down_streaks: std::collections::HashMap<String, u32> = std::collections::HashMap::new();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let check_config = config.clone();
+        let results = tokio::task::spawn_blocking(move || {
+            run_checks(&check_config, None, Duration::from_secs(timeout_secs))
+        })
+        .await
+        .expect("check task panicked")?;
+
+        for result in &results {
+            let streak = down_streaks.entry(result.name.clone()).or_insert(0);
+            if result.healthy {
+                if *streak > 0 {
+                    log::info!("{} recovered after {} failed check(s)", result.name, streak);
+                }
+                *streak = 0;
+            } else {
+                *streak += 1;
+                log::warn!("{} is down ({} consecutive)", result.name, streak);
+            }
+        }
+        // fire only on the transition, when a streak reaches the rule's length
+        let alerts = crate::alerts::evaluate_check_rules(&config.alerts, &results, |name| {
+            down_streaks.get(name).copied().unwrap_or(0)
+        })
+        .into_iter()
+        .filter(|alert| {
+            config.alerts.iter().any(|rule| match rule {
+                crate::alerts::AlertRule::SiteDown { consecutive_checks } => {
+                    alert.rule != "site_down"
+                        || down_streaks.get(&alert.subject).copied().unwrap_or(0)
+                            == *consecutive_checks
+                }
+                _ => true,
+            })
+        })
+        .collect::<Vec<_>>();
+        if let Some(notifications) = &config.notifications {
+            if let Err(e) = crate::alerts::fire_webhooks(notifications, &alerts) {
+                log::error!("{}", e);
+            }
+        }
+
+        *latest.write().await = serde_json::to_string(&results)?;
+        history.push(HistoryEntry {
+            checked_at: chrono::Utc::now().to_rfc3339(),
+            results: results
+                .iter()
+                .map(|r| StoredResult {
+                    name: r.name.clone(),
+                    healthy: r.healthy,
+                    status: r.status,
+                    latency_ms: r.latency_ms,
+                })
+                .collect(),
+        });
+        if history.len() > HISTORY_CAPACITY {
+            let excess = history.len() - HISTORY_CAPACITY;
+            history.drain(..excess);
+        }
+        if let Err(e) = save_history(&history_path, &history) {
+            log::error!("could not save history: {}", e);
+        }
+    }
+}
+
+/// A bench run is flagged as a regression when a percentile is this much
+/// slower than the recorded baseline.
+pub const BENCH_REGRESSION_FACTOR: f64 = 1.25;
+
+/// Latency percentiles of one bench run, also stored as the baseline for the
+/// next one.
+#[derive(Debug, Clone, Serialize, serde::Deserialize)]
+pub struct BenchBaseline {
+    pub recorded_at: String,
+    pub url: String,
+    pub requests: u32,
+    pub failed: u32,
+    pub p50_ms: u128,
+    pub p90_ms: u128,
+    pub p99_ms: u128,
+}
+
+fn percentile(sorted: &[u128], percent: f64) -> u128 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let index = ((sorted.len() as f64) * percent / 100.0).ceil() as usize;
+    sorted[index.min(sorted.len()) - 1]
+}
+
+fn bench_baseline_path(name: &str) -> std::path::PathBuf {
+    std::path::PathBuf::from(format!("rumi-bench-{}.json", name))
+}
+
+/// The `monitor bench` command: load-test the deployment's health url and
+/// compare latency percentiles against the previous run's baseline, failing
+/// when the new release is clearly slower.
+pub fn bench_command(
+    config: &RumiConfig,
+    name: &str,
+    requests: u32,
+    concurrency: u32,
+    timeout_secs: u64,
+) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let target = probe_target(deployment);
+    let timeout = Duration::from_secs(timeout_secs);
+    let concurrency = concurrency.clamp(1, requests.max(1));
+    let per_worker = requests.div_ceil(concurrency);
+
+    let mut handles = Vec::new();
+    for _ in 0..concurrency {
+        let host = target.host.clone();
+        let path = target.path.clone();
+        let port = target.port;
+        let force_http = target.force_http;
+        handles.push(std::thread::spawn(move || {
+            let mut latencies = Vec::new();
+            let mut failed = 0u32;
+            for _ in 0..per_worker {
+                let start = Instant::now();
+                let outcome = if force_http {
+                    probe_http(&host, port.unwrap_or(80), &path, timeout).map(|_| ())
+                } else {
+                    probe_https(&host, port.unwrap_or(443), &path, timeout).map(|_| ())
+                };
+                match outcome {
+                    Ok(()) => latencies.push(start.elapsed().as_millis()),
+                    Err(_) => failed += 1,
+                }
+            }
+            (latencies, failed)
+        }));
+    }
+    let mut latencies: Vec<u128> = Vec::new();
+    let mut failed = 0u32;
+    for handle in handles {
+        let (worker_latencies, worker_failed) = handle.join().expect("bench worker panicked");
+        latencies.extend(worker_latencies);
+        failed += worker_failed;
+    }
+    latencies.sort_unstable();
+
+    let run = BenchBaseline {
+        recorded_at: chrono::Utc::now().to_rfc3339(),
+        url: format!(
+            "{}://{}{}",
+            if target.force_http { "http" } else { "https" },
+            target.host,
+            target.path
+        ),
+        requests: latencies.len() as u32 + failed,
+        failed,
+        p50_ms: percentile(&latencies, 50.0),
+        p90_ms: percentile(&latencies, 90.0),
+        p99_ms: percentile(&latencies, 99.0),
+    };
+    println!("benched {} ({} requests, {} failed)", run.url, run.requests, run.failed);
+    println!(
+        "p50 {} ms, p90 {} ms, p99 {} ms",
+        run.p50_ms, run.p90_ms, run.p99_ms
+    );
+
+    let baseline_path = bench_baseline_path(name);
+    let baseline: Option<BenchBaseline> = std::fs::read_to_string(&baseline_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok());
+    let mut regressions = Vec::new();
+    if let Some(baseline) = &baseline {
+        println!(
+            "baseline from {}: p50 {} ms, p90 {} ms, p99 {} ms",
+            baseline.recorded_at, baseline.p50_ms, baseline.p90_ms, baseline.p99_ms
+        );
+        for (label, current, before) in [
+            ("p50", run.p50_ms, baseline.p50_ms),
+            ("p90", run.p90_ms, baseline.p90_ms),
+            ("p99", run.p99_ms, baseline.p99_ms),
+        ] {
+            if before > 0 && current as f64 > before as f64 * BENCH_REGRESSION_FACTOR {
+                regressions.push(format!("{} went from {} ms to {} ms", label, before, current));
+            }
+        }
+    }
+    std::fs::write(&baseline_path, serde_json::to_string_pretty(&run)?)?;
+    if !regressions.is_empty() {
+        return Err(RumiError::CommandFailed(format!(
+            "latency regression against baseline: {}",
+            regressions.join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// The certificate state of one deployment's domain.
+#[derive(Debug, Clone, Serialize)]
+pub struct CertResult {
+    pub name: String,
+    pub domain: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub not_after: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub days_left: Option<i64>,
+    pub expiring: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Fetch the certificate a domain presents over tls.
+fn certificate_via_tls(domain: &str, timeout: Duration) -> RumiResult<X509> {
+    let tls = tls_connect(domain, 443, timeout)?;
+    peer_certificate(&tls, domain)
+}
+
+/// Read the certificate file certbot wrote for a domain over ssh instead of
+/// connecting to port 443, useful when the site is firewalled off.
+fn certificate_via_ssh(session: &RumiSession, domain: &str) -> RumiResult<X509> {
+    let cert_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
+    let output = session.execute_checked(&format!("sudo cat {}", cert_path))?;
+    X509::from_pem(output.stdout.as_bytes())
+        .map_err(|e| RumiError::Tls(format!("could not parse {}: {}", cert_path, e)))
+}
+
+fn cert_result_for(
+    config: &RumiConfig,
+    deployment: &DeploymentConfig,
+    threshold: i64,
+    timeout: Duration,
+    via_ssh: bool,
+) -> CertResult {
+    let cert = if via_ssh {
+        config
+            .ssh_for_deployment(deployment)
+            .and_then(RumiSession::connect)
+            .and_then(|session| certificate_via_ssh(&session, &deployment.domain))
+    } else {
+        certificate_via_tls(&deployment.domain, timeout)
+    };
+    match cert.and_then(|cert| {
+        let days = certificate_days_left(&cert)?;
+        Ok((cert.not_after().to_string(), days))
+    }) {
+        Ok((not_after, days_left)) => CertResult {
+            name: deployment.name.clone(),
+            domain: deployment.domain.clone(),
+            not_after: Some(not_after),
+            days_left: Some(days_left),
+            expiring: days_left < threshold,
+            error: None,
+        },
+        Err(e) => CertResult {
+            name: deployment.name.clone(),
+            domain: deployment.domain.clone(),
+            not_after: None,
+            days_left: None,
+            expiring: true,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+fn print_cert_table(results: &[CertResult]) {
+    println!(
+        "{:<20} {:<30} {:<26} {:<10} {:<8}",
+        "NAME", "DOMAIN", "EXPIRES", "DAYS LEFT", "STATE"
+    );
+    for result in results {
+        println!(
+            "{:<20} {:<30} {:<26} {:<10} {}",
+            result.name,
+            result.domain,
+            result.not_after.as_deref().unwrap_or("-"),
+            result
+                .days_left
+                .map(|d| d.to_string())
+                .unwrap_or_else(|| "-".to_string()),
+            if result.expiring {
+                crate::output::yellow(format!("{:<8}", "EXPIRING"))
+            } else {
+                crate::output::green(format!("{:<8}", "ok"))
+            },
+        );
+        if let Some(error) = &result.error {
+            println!("  {}: {}", result.name, error);
+        }
+    }
+}
+
+/// The `monitor certs` command: report days-until-expiry for every deployed
+/// domain and fail when any certificate is under the threshold, so a silently
+/// broken renewal shows up before the site does.
+pub fn certs_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    threshold: i64,
+    timeout_secs: u64,
+    via_ssh: bool,
+    json: bool,
+) -> RumiResult<()> {
+    let timeout = Duration::from_secs(timeout_secs);
+    let deployments: Vec<&DeploymentConfig> = match name {
+        Some(name) => vec![config.find_deployment(name)?],
+        None => config.deployments.iter().collect(),
+    };
+    if deployments.is_empty() {
+        return Err(RumiError::Config(
+            "no deployments in the config, add some to rumi.json first".to_string(),
+        ));
+    }
+    let results: Vec<CertResult> = deployments
+        .into_iter()
+        .map(|d| cert_result_for(config, d, threshold, timeout, via_ssh))
+        .collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print_cert_table(&results);
+    }
+    let expiring: Vec<&CertResult> = results.iter().filter(|r| r.expiring).collect();
+    if !expiring.is_empty() {
+        return Err(RumiError::CommandFailed(format!(
+            "{} certificate(s) expiring or unreadable: {}",
+            expiring.len(),
+            expiring
+                .iter()
+                .map(|r| r.domain.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(())
+}
+
+/// The `monitor check` command: probe every deployment and print the outcome.
+/// Returns an error when any deployment is unhealthy so scripts can use the
+/// exit code.
+pub fn check_command(
+    config: &RumiConfig,
+    name: Option<&str>,
+    timeout_secs: u64,
+    json: bool,
+) -> RumiResult<()> {
+    let results = run_checks(config, name, Duration::from_secs(timeout_secs))?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        print_check_table(&results);
+    }
+    // a one-shot check counts every failure as a streak of one
+    let alerts = crate::alerts::evaluate_check_rules(&config.alerts, &results, |_| 1);
+    if let Some(notifications) = &config.notifications {
+        if let Err(e) = crate::alerts::fire_webhooks(notifications, &alerts) {
+            eprintln!("warning: {}", e);
+        }
+    }
+    let down: Vec<&CheckResult> = results.iter().filter(|r| !r.healthy).collect();
+    if !down.is_empty() {
+        return Err(RumiError::CommandFailed(format!(
+            "{} deployment(s) unhealthy: {}",
+            down.len(),
+            down.iter()
+                .map(|r| r.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,75 @@
+use std::io::IsTerminal;
+
+use chrono::{DateTime, Local, Utc};
+
+const RESET: &str = "\x1b[0m";
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+
+/// Color only when a human is looking: stdout is a terminal and the
+/// conventional NO_COLOR escape hatch is unset. Piped output stays plain so
+/// grep and cut keep working on the tables.
+fn color_enabled() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+fn paint(text: String, color: &str) -> String {
+    if color_enabled() {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text
+    }
+}
+
+/// A healthy status cell. Pad the text to its column width *before* coloring;
+/// the escape codes count towards {:<8} widths and would break the alignment.
+pub fn green(text: String) -> String {
+    paint(text, GREEN)
+}
+
+/// A failed status cell.
+pub fn red(text: String) -> String {
+    paint(text, RED)
+}
+
+/// A warning cell, used for certificates close to expiry.
+pub fn yellow(text: String) -> String {
+    paint(text, YELLOW)
+}
+
+/// How old a timestamp is, in the coarsest unit that still says something.
+pub fn relative_age(then: DateTime<Utc>) -> String {
+    let seconds = (Utc::now() - then).num_seconds();
+    if seconds < 0 {
+        return "in the future".to_string();
+    }
+    match seconds {
+        0..=59 => "just now".to_string(),
+        60..=3599 => format!("{} minutes ago", seconds / 60),
+        3600..=86399 => format!("{} hours ago", seconds / 3600),
+        _ => format!("{} days ago", seconds / 86400),
+    }
+}
+
+/// Render a timestamp the way the list commands show it: local time plus
+/// relative age for humans, the raw utc form with --utc for scripts.
+pub fn format_time(time: DateTime<Utc>, utc: bool) -> String {
+    if utc {
+        return time.to_rfc3339();
+    }
+    format!(
+        "{} ({})",
+        time.with_timezone(&Local).format("%Y-%m-%d %H:%M"),
+        relative_age(time)
+    )
+}
+
+/// Same for timestamps kept as rfc3339 strings; anything unparsable is shown
+/// as stored.
+pub fn format_time_str(time: &str, utc: bool) -> String {
+    match DateTime::parse_from_rfc3339(time) {
+        Ok(parsed) => format_time(parsed.with_timezone(&Utc), utc),
+        Err(_) => time.to_string(),
+    }
+}
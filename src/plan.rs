@@ -0,0 +1,195 @@
+use std::path::Path;
+
+use crate::config::{DeploymentConfig, DeploymentType, RumiConfig};
+use crate::error::{RumiError, RumiResult};
+use crate::{NGINX_WEB_CONFIG_PATH, SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH};
+
+/// The heredoc delimiter in exported scripts; payload lines never look
+/// like it.
+const HEREDOC_END: &str = "RUMI_EOF";
+
+/// One thing a deployment would do on the remote host.
+pub enum Step {
+    /// A shell command, exactly as the deploy would run it.
+    Run(String),
+    /// A file written over sftp; the export carries its payload inline.
+    Upload { remote_path: String, content: Vec<u8> },
+}
+
+/// Everything a deployment would do, in order, built without touching the
+/// network so it is safe to run against a production config.
+pub struct Plan {
+    pub deployment: String,
+    pub host: String,
+    steps: Vec<Step>,
+}
+
+impl Plan {
+    fn run(&mut self, command: impl Into<String>) {
+        self.steps.push(Step::Run(command.into()));
+    }
+
+    fn upload(&mut self, remote_path: String, content: Vec<u8>) {
+        self.steps.push(Step::Upload {
+            remote_path,
+            content,
+        });
+    }
+
+    /// List the steps the way a reviewer reads them: one line each, payloads
+    /// summarised by size.
+    pub fn print(&self) {
+        println!(
+            "plan for '{}' against {} ({} steps):",
+            self.deployment,
+            self.host,
+            self.steps.len()
+        );
+        for step in &self.steps {
+            match step {
+                Step::Run(command) => println!("  run    {}", command),
+                Step::Upload {
+                    remote_path,
+                    content,
+                } => println!("  upload {} ({} bytes)", remote_path, content.len()),
+            }
+        }
+    }
+
+    /// Write the plan as an executable shell script: commands verbatim, file
+    /// payloads as heredocs (base64 when the content is not clean text), so
+    /// the script can be reviewed or carried to an air-gapped server and run
+    /// there by hand.
+    pub fn export(&self, path: &Path) -> RumiResult<()> {
+        let mut script = String::new();
+        script.push_str("#!/bin/sh\n");
+        script.push_str(&format!(
+            "# generated by rumi2 plan for deployment '{}' against {}\n",
+            self.deployment, self.host
+        ));
+        script.push_str("set -eu\n\n");
+        for step in &self.steps {
+            match step {
+                Step::Run(command) => {
+                    script.push_str(command);
+                    script.push('\n');
+                }
+                Step::Upload {
+                    remote_path,
+                    content,
+                } => match clean_text(content) {
+                    Some(text) => {
+                        script.push_str(&format!("cat > '{}' <<'{}'\n", remote_path, HEREDOC_END));
+                        script.push_str(text);
+                        if !text.ends_with('\n') {
+                            script.push('\n');
+                        }
+                        script.push_str(HEREDOC_END);
+                        script.push('\n');
+                    }
+                    None => {
+                        script.push_str(&format!(
+                            "base64 -d > '{}' <<'{}'\n",
+                            remote_path, HEREDOC_END
+                        ));
+                        script.push_str(openssl::base64::encode_block(content).trim_end());
+                        script.push('\n');
+                        script.push_str(HEREDOC_END);
+                        script.push('\n');
+                    }
+                },
+            }
+            script.push('\n');
+        }
+        std::fs::write(path, script)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))?;
+        }
+        println!("plan written to {}", path.display());
+        Ok(())
+    }
+}
+
+/// Content that survives a plain heredoc unchanged: valid utf-8 with no line
+/// that would terminate it early. Everything else goes through base64.
+fn clean_text(content: &[u8]) -> Option<&str> {
+    let text = std::str::from_utf8(content).ok()?;
+    (!text.contains('\0') && !text.lines().any(|line| line == HEREDOC_END)).then_some(text)
+}
+
+/// Add an upload step per file under `local`, with the mkdirs the sftp
+/// upload would do implicitly.
+fn plan_folder(plan: &mut Plan, local: &Path, remote: &str) -> RumiResult<()> {
+    plan.run(format!("mkdir -p '{}'", remote));
+    for entry in std::fs::read_dir(local)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        let remote_path = format!("{}/{}", remote, name);
+        if entry.file_type()?.is_dir() {
+            plan_folder(plan, &entry.path(), &remote_path)?;
+        } else {
+            plan.upload(remote_path, std::fs::read(entry.path())?);
+        }
+    }
+    Ok(())
+}
+
+/// Build the plan of deploying an update of one deployment. Only the types
+/// whose deploys are pure command-and-upload sequences are plannable; the
+/// interactive ones (databases generating passwords, ethereum nodes) say so.
+pub fn plan_deployment(config: &RumiConfig, deployment: &DeploymentConfig) -> RumiResult<Plan> {
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let mut plan = Plan {
+        deployment: deployment.name.clone(),
+        host: ssh.host.clone(),
+        steps: Vec::new(),
+    };
+    match &deployment.deployment_type {
+        DeploymentType::Website { dist_path } => {
+            let web_folder_path = crate::commands::websites::new_release_path(&deployment.domain);
+            plan_folder(&mut plan, Path::new(dist_path), &web_folder_path)?;
+            plan.run(format!(
+                "sudo ln -sfn {} {}",
+                web_folder_path,
+                crate::commands::websites::site_current_path(&deployment.domain)
+            ));
+            let nginx_config = crate::utils::get_web_nginx_config_file(
+                &deployment.domain,
+                &format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, deployment.domain),
+                &format!(
+                    "{}/{}/privkey.pem",
+                    SSL_CERTIFICATE_KEY_PATH, deployment.domain
+                ),
+                &crate::commands::websites::site_current_path(&deployment.domain),
+                &deployment.nginx_extra_block()?,
+            );
+            let config_file_path = format!("{}/{}", NGINX_WEB_CONFIG_PATH, deployment.domain);
+            plan.upload(config_file_path.clone(), nginx_config.into_bytes());
+            plan.run(format!(
+                "sudo ln -s {} /etc/nginx/sites-enabled/",
+                config_file_path
+            ));
+            plan.run("sudo systemctl reload nginx");
+        }
+        DeploymentType::SftpSite {
+            dist_path,
+            remote_path,
+        } => {
+            let release = chrono::Utc::now().format("%Y%m%d%H%M%S");
+            let release_path = format!("{}/releases/{}", remote_path, release);
+            plan_folder(&mut plan, Path::new(dist_path), &release_path)?;
+            plan.run(format!("ln -sfn releases/{} {}/current", release, remote_path));
+        }
+        other => {
+            return Err(RumiError::Config(format!(
+                "plans are not implemented for {} deployments: their deploys \
+                 depend on remote state that a dry run cannot see",
+                other.kind()
+            )))
+        }
+    }
+    Ok(plan)
+}
@@ -0,0 +1,389 @@
+use std::io::Write;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+use crate::WEB_FOLDER;
+
+/// The metadata file dropped into every deployed release folder.
+pub const RELEASE_METADATA_FILE: &str = ".rumi-release.json";
+
+/// The authoritative registry on each host. Every deploy appends its release
+/// record here, so operators with separate local configs see the same
+/// history; the local config only says how to reach the host.
+pub const REGISTRY_ROOT: &str = "/var/lib/rumi";
+
+/// What was deployed: the git revision of the checkout at deploy time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GitRevision {
+    pub branch: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tag: Option<String>,
+    pub commit: String,
+}
+
+impl GitRevision {
+    /// Read branch, tag and commit from a checkout; None when the directory
+    /// is not a git repository (plain dist folders deploy fine without it).
+    pub fn detect(project_dir: &Path) -> Option<GitRevision> {
+        let git = |args: &[&str]| -> Option<String> {
+            let output = std::process::Command::new("git")
+                .arg("-C")
+                .arg(project_dir)
+                .args(args)
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        };
+        let commit = git(&["rev-parse", "HEAD"])?;
+        let branch = git(&["rev-parse", "--abbrev-ref", "HEAD"]).unwrap_or_default();
+        let tag = git(&["describe", "--tags", "--exact-match"]);
+        Some(GitRevision {
+            branch,
+            tag,
+            commit,
+        })
+    }
+}
+
+/// Check out a branch, tag or commit before building, for
+/// `hosting update --ref v1.2.3`.
+pub fn checkout_ref(project_dir: &Path, git_ref: &str) -> RumiResult<()> {
+    let fetch = std::process::Command::new("git")
+        .arg("-C")
+        .arg(project_dir)
+        .args(["fetch", "--tags", "--quiet"])
+        .output()?;
+    if !fetch.status.success() {
+        // offline checkouts of an already-fetched ref still work
+        eprintln!(
+            "note: git fetch failed ({}), trying the local ref",
+            String::from_utf8_lossy(&fetch.stderr).trim()
+        );
+    }
+    let checkout = std::process::Command::new("git")
+        .arg("-C")
+        .arg(project_dir)
+        .args(["checkout", "--quiet", git_ref])
+        .output()?;
+    if !checkout.status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "git checkout {} failed: {}",
+            git_ref,
+            String::from_utf8_lossy(&checkout.stderr).trim()
+        )));
+    }
+    println!("checked out {}", git_ref);
+    Ok(())
+}
+
+/// One deployed release as recorded on the server.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReleaseMetadata {
+    /// The web folder the release lives in.
+    pub release_path: String,
+    pub deployed_at: DateTime<Utc>,
+    /// Who ran the deploy, as user@host of the machine it ran on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployer: Option<String>,
+    /// The rumi2 version that performed the deploy.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rumi_version: Option<String>,
+    /// The local folder the release was uploaded from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_path: Option<String>,
+    /// A digest over the uploaded tree, e.g. "42 files, sha256:1f0a9c2b71d4",
+    /// so two releases can be compared without downloading either.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub revision: Option<GitRevision>,
+}
+
+impl ReleaseMetadata {
+    pub fn new(release_path: String, revision: Option<GitRevision>, source: &Path) -> Self {
+        ReleaseMetadata {
+            release_path,
+            deployed_at: Utc::now(),
+            deployer: Some(deployer()),
+            rumi_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            source_path: Some(source.display().to_string()),
+            checksum: checksum_summary(source),
+            revision,
+        }
+    }
+}
+
+/// user@host of the machine running the deploy, best effort.
+pub(crate) fn deployer() -> String {
+    let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_string());
+    match std::process::Command::new("hostname")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    {
+        Some(host) if !host.is_empty() => format!("{}@{}", user, host),
+        _ => user,
+    }
+}
+
+/// Hash every file under the source folder into one short digest. None when
+/// anything is unreadable; a release without a checksum beats a failed one.
+pub fn checksum_summary(source: &Path) -> Option<String> {
+    use openssl::hash::{Hasher, MessageDigest};
+
+    fn collect(dir: &Path, root: &Path, files: &mut Vec<(String, Vec<u8>)>) -> std::io::Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                collect(&entry.path(), root, files)?;
+            } else {
+                let path = entry.path();
+                let relative = path.strip_prefix(root).unwrap_or(&path).display().to_string();
+                files.push((relative, std::fs::read(&path)?));
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    collect(source, source, &mut files).ok()?;
+    // hash in path order so the digest is stable across directory walks
+    files.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut hasher = Hasher::new(MessageDigest::sha256()).ok()?;
+    for (path, content) in &files {
+        hasher.update(path.as_bytes()).ok()?;
+        hasher.update(b"\0").ok()?;
+        hasher.update(content).ok()?;
+    }
+    let digest = hasher.finish().ok()?;
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    Some(format!("{} files, sha256:{}", files.len(), &hex[..12]))
+}
+
+/// Drop the metadata file into a freshly uploaded release folder. Staged
+/// under /tmp and moved with sudo: the promoted release is owned by
+/// www-data, not the deploy user.
+pub fn write_release_metadata(
+    session: &RumiSession,
+    metadata: &ReleaseMetadata,
+) -> RumiResult<()> {
+    let staging_path = format!("/tmp/rumi-release-meta-{}", uuid::Uuid::new_v4());
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(serde_json::to_string_pretty(metadata)?.as_bytes())?;
+    drop(file);
+    let path = format!("{}/{}", metadata.release_path, RELEASE_METADATA_FILE);
+    session.execute_checked(&format!(
+        "sudo mv {0} {1} && sudo chown www-data:www-data {1}",
+        crate::session::quote_arg(&staging_path),
+        crate::session::quote_arg(&path)
+    ))?;
+    Ok(())
+}
+
+/// Append a release record to the host's registry journal, one json line per
+/// deploy. The journal survives release folders being pruned or rolled over,
+/// so the history stays complete for every operator.
+pub fn record_release(
+    session: &RumiSession,
+    domain: &str,
+    metadata: &ReleaseMetadata,
+) -> RumiResult<()> {
+    let staging_path = format!("/tmp/rumi-release-record-{}", domain);
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(&staging_path))?;
+    file.write_all(serde_json::to_string(metadata)?.as_bytes())?;
+    file.write_all(b"\n")?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo mkdir -p {0}/releases && sudo sh -c 'cat {1} >> {0}/releases/{2}.jsonl' && rm -f {1}",
+        REGISTRY_ROOT, staging_path, domain
+    ))?;
+    Ok(())
+}
+
+/// Every release a domain has on the server, newest first: the registry
+/// journal first, topped up with the metadata files still sitting in release
+/// folders for deploys older than the registry.
+pub fn list_releases(session: &RumiSession, domain: &str) -> RumiResult<Vec<ReleaseMetadata>> {
+    let journal = session.execute_command(&format!(
+        "sudo sh -c 'cat {}/releases/{}.jsonl 2>/dev/null'",
+        REGISTRY_ROOT, domain
+    ))?;
+    // both layouts: timestamped folders under releases/ and the old flat
+    // {domain}_{uuid} folders from before the symlink switch
+    let output = session.execute_command(&format!(
+        "sudo sh -c 'cat {0}/{1}/releases/*/{2} {0}/{1}_*/{2} 2>/dev/null'",
+        WEB_FOLDER, domain, RELEASE_METADATA_FILE
+    ))?;
+    let mut releases: Vec<ReleaseMetadata> =
+        serde_json::Deserializer::from_str(&journal.stdout)
+            .into_iter()
+            .filter_map(|release| release.ok())
+            .collect();
+    let mut seen: std::collections::HashSet<String> = releases
+        .iter()
+        .map(|release| release.release_path.clone())
+        .collect();
+    for release in serde_json::Deserializer::from_str(&output.stdout)
+        .into_iter::<ReleaseMetadata>()
+        .filter_map(|release| release.ok())
+    {
+        if seen.insert(release.release_path.clone()) {
+            releases.push(release);
+        }
+    }
+    releases.sort_by_key(|release| std::cmp::Reverse(release.deployed_at));
+    Ok(releases)
+}
+
+/// The web root the deployed nginx config serves right now — the active
+/// release. Refused while a canary splits traffic (the root is a variable).
+pub fn current_web_root(session: &RumiSession, domain: &str) -> RumiResult<String> {
+    let output = session.execute_checked(&format!(
+        "sudo cat {}/{}",
+        crate::NGINX_WEB_CONFIG_PATH,
+        domain
+    ))?;
+    output
+        .stdout
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("root "))
+        .map(|root| root.trim().trim_end_matches(';').to_string())
+        .filter(|root| !root.starts_with('$'))
+        .ok_or_else(|| {
+            RumiError::Config(format!(
+                "could not read the deployed web root for {}, is a canary running?",
+                domain
+            ))
+        })
+}
+
+/// The metadata of the release nginx serves right now.
+pub fn active_release(session: &RumiSession, domain: &str) -> RumiResult<ReleaseMetadata> {
+    let root = current_web_root(session, domain)?;
+    let output = session.execute_checked(&format!(
+        "sudo cat {}/{}",
+        root, RELEASE_METADATA_FILE
+    ))?;
+    serde_json::from_str(&output.stdout).map_err(|_| {
+        RumiError::Config(format!(
+            "{} has no readable release metadata, was it deployed before metadata existed?",
+            root
+        ))
+    })
+}
+
+/// Delete all but the newest `keep` release folders of a domain, never the
+/// one the `current` symlink points at. The registry journal is untouched,
+/// so the history outlives the folders. Returns the paths removed.
+pub fn prune_releases(
+    session: &RumiSession,
+    domain: &str,
+    keep: usize,
+) -> RumiResult<Vec<String>> {
+    let releases_dir = format!("{}/{}/releases", WEB_FOLDER, domain);
+    let listing = session.execute_command(&format!(
+        "ls -1 {} 2>/dev/null",
+        crate::session::quote_arg(&releases_dir)
+    ))?;
+    // timestamped names sort oldest first lexicographically
+    let mut names: Vec<&str> = listing
+        .stdout
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .collect();
+    names.sort_unstable();
+    if names.len() <= keep {
+        return Ok(Vec::new());
+    }
+    let current = session
+        .execute_command(&format!(
+            "readlink -f {}",
+            crate::session::quote_arg(&crate::commands::websites::site_current_path(domain))
+        ))?
+        .stdout
+        .trim()
+        .to_string();
+    let mut removed = Vec::new();
+    for name in &names[..names.len() - keep] {
+        let path = format!("{}/{}", releases_dir, name);
+        if path == current {
+            continue; // never pull the folder out from under nginx
+        }
+        session.execute_checked(&format!(
+            "sudo rm -rf {}",
+            crate::session::quote_arg(&path)
+        ))?;
+        removed.push(path);
+    }
+    Ok(removed)
+}
+
+/// Print one release's metadata in full, for `hosting info`.
+pub fn print_release_info(metadata: &ReleaseMetadata, utc: bool) {
+    println!("release:  {}", metadata.release_path);
+    println!(
+        "deployed: {}",
+        crate::output::format_time(metadata.deployed_at, utc)
+    );
+    if let Some(deployer) = &metadata.deployer {
+        println!("by:       {}", deployer);
+    }
+    if let Some(version) = &metadata.rumi_version {
+        println!("rumi:     {}", version);
+    }
+    if let Some(source) = &metadata.source_path {
+        println!("source:   {}", source);
+    }
+    if let Some(checksum) = &metadata.checksum {
+        println!("checksum: {}", checksum);
+    }
+    if let Some(revision) = &metadata.revision {
+        println!(
+            "git:      {} on {}{}",
+            &revision.commit[..revision.commit.len().min(12)],
+            revision.branch,
+            revision
+                .tag
+                .as_ref()
+                .map(|tag| format!(" (tag {})", tag))
+                .unwrap_or_default()
+        );
+    }
+}
+
+/// Print releases the way the other tables look.
+pub fn print_release_table(releases: &[ReleaseMetadata], utc: bool) {
+    println!(
+        "{:<45} {:<34} {:<15} {:<12} COMMIT",
+        "PATH", "DEPLOYED", "BRANCH", "TAG"
+    );
+    for release in releases {
+        let (branch, tag, commit) = match &release.revision {
+            Some(revision) => (
+                revision.branch.as_str(),
+                revision.tag.as_deref().unwrap_or("-"),
+                &revision.commit[..revision.commit.len().min(12)],
+            ),
+            None => ("-", "-", "-"),
+        };
+        println!(
+            "{:<45} {:<34} {:<15} {:<12} {}",
+            release.release_path,
+            crate::output::format_time(release.deployed_at, utc),
+            branch,
+            tag,
+            commit
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Render every file rumi would write on a host for a deployment — nginx
+//! site config, systemd unit, genesis.json, geth start script — to stdout
+//! or a local directory, without opening an ssh connection. The point is
+//! code review: the generated configs can be read (and diffed against a
+//! previous render) before the first deploy touches a machine.
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::config::{DeploymentConfig, DeploymentType};
+use crate::error::{RumiError, RumiResult};
+use crate::utils;
+use crate::{SSL_CERTIFICATE_KEY_PATH, SSL_CERTIFICATE_PATH, WEB_FOLDER};
+
+/// One file rumi would write on the host, named after its remote basename
+/// (e.g. "example.com.nginx", "api.service").
+pub struct RenderedFile {
+    pub name: String,
+    pub content: String,
+}
+
+/// Every generated file for a deployment. Values that only exist at deploy
+/// time — the per-release web root, generated passwords — are stood in by
+/// placeholders, which is fine for review: the structure around them is
+/// what changes by accident.
+pub fn render_deployment(deployment: &DeploymentConfig) -> RumiResult<Vec<RenderedFile>> {
+    let domain = &deployment.domain;
+    let certificate_path = format!("{}/{}/fullchain.pem", SSL_CERTIFICATE_PATH, domain);
+    let certificate_key_path = format!("{}/{}/privkey.pem", SSL_CERTIFICATE_KEY_PATH, domain);
+    let mut files = Vec::new();
+    match &deployment.deployment_type {
+        DeploymentType::Website { .. } => {
+            // nginx serves the `current` symlink, switched per release
+            let web_root = crate::commands::websites::site_current_path(domain);
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_web_nginx_config_file(
+                    domain,
+                    &certificate_path,
+                    &certificate_key_path,
+                    &web_root,
+                    &deployment.nginx_extra_block()?,
+                ),
+            });
+        }
+        DeploymentType::Server {
+            port,
+            proxy,
+            env,
+            env_file,
+            ..
+        } => {
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_servers_nginx_config_file(
+                    domain,
+                    *port,
+                    &proxy.clone().unwrap_or_default(),
+                    &deployment.nginx_extra_block()?,
+                ),
+            });
+            if !env.is_empty() || env_file.is_some() {
+                files.push(RenderedFile {
+                    name: format!("{}.env", deployment.name),
+                    content: crate::commands::servers::render_env_file(
+                        env,
+                        env_file.as_deref(),
+                    )?,
+                });
+            }
+        }
+        DeploymentType::Python {
+            app_path: _,
+            entry,
+            port,
+            asgi,
+        } => {
+            let app_dir = format!(
+                "{}/{}",
+                crate::commands::python::PYTHON_APP_ROOT,
+                deployment.name
+            );
+            let exec_start = if *asgi {
+                format!(
+                    "{}/venv/bin/uvicorn {} --host 127.0.0.1 --port {}",
+                    app_dir, entry, port
+                )
+            } else {
+                format!(
+                    "{}/venv/bin/gunicorn {} --bind 127.0.0.1:{}",
+                    app_dir, entry, port
+                )
+            };
+            files.push(RenderedFile {
+                name: format!("{}.service", deployment.name),
+                content: utils::get_python_systemd_unit(
+                    &deployment.name,
+                    &format!("{}/app", app_dir),
+                    &exec_start,
+                ),
+            });
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_servers_nginx_config_file(
+                    domain,
+                    *port,
+                    &Default::default(),
+                    &deployment.nginx_extra_block()?,
+                ),
+            });
+        }
+        DeploymentType::Ethereum {
+            network_id,
+            unlock_wallet_address,
+            datadir,
+            ..
+        } => {
+            let datadir = datadir
+                .as_deref()
+                .unwrap_or(crate::commands::ethereum::DEFAULT_DATADIR);
+            files.push(RenderedFile {
+                name: "genesis.json".to_string(),
+                content: utils::get_genesis_file(unlock_wallet_address, &(*network_id as i32)),
+            });
+            files.push(RenderedFile {
+                name: "startnode.sh".to_string(),
+                content: format!(
+                    "{}\n",
+                    utils::get_startnode_command(
+                        &(*network_id as i32),
+                        "<http_address_ip>",
+                        "<ext_ip>",
+                        unlock_wallet_address,
+                        "<ws_address_ip>",
+                        datadir,
+                    )
+                ),
+            });
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_ethereum_nginx_config_file(&80, domain),
+            });
+        }
+        DeploymentType::Php { .. } => {
+            let web_root = format!("{}/{}_<release>", WEB_FOLDER, domain);
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_php_nginx_config_file(
+                    domain,
+                    &certificate_path,
+                    &certificate_key_path,
+                    &web_root,
+                ),
+            });
+        }
+        DeploymentType::Kubernetes { .. } => {
+            files.push(RenderedFile {
+                name: format!("{}.yaml", deployment.name),
+                content: crate::commands::kubernetes::render_manifests(deployment)?,
+            });
+        }
+        DeploymentType::Observability {
+            with_prometheus,
+            with_grafana,
+        } => {
+            files.push(RenderedFile {
+                name: format!("{}.nginx", domain),
+                content: utils::get_observability_nginx_config_file(
+                    domain,
+                    *with_prometheus,
+                    *with_grafana,
+                ),
+            });
+            if *with_prometheus {
+                files.push(RenderedFile {
+                    name: "prometheus.yml".to_string(),
+                    content: utils::get_prometheus_config_file(&[format!("{}:9100", domain)]),
+                });
+            }
+        }
+        DeploymentType::SftpSite { .. }
+        | DeploymentType::Database { .. }
+        | DeploymentType::Redis { .. }
+        | DeploymentType::DockerHost { .. } => {
+            return Err(RumiError::Config(format!(
+                "a {} deployment generates no config files to render",
+                deployment.deployment_type.kind()
+            )));
+        }
+    }
+    Ok(files)
+}
+
+/// Print each rendered file to stdout under a `--- name ---` header, or
+/// write them into a directory for diffing.
+pub fn render_command(deployment: &DeploymentConfig, out: Option<&Path>) -> RumiResult<()> {
+    let files = render_deployment(deployment)?;
+    match out {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            for file in &files {
+                let path = dir.join(&file.name);
+                let mut f = std::fs::File::create(&path)?;
+                f.write_all(file.content.as_bytes())?;
+                println!("wrote {}", path.display());
+            }
+        }
+        None => {
+            for file in &files {
+                println!("--- {} ---", file.name);
+                println!("{}", file.content);
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,111 @@
+use uuid::Uuid;
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+
+/// Generate a password strong enough for a service account.
+pub fn generate_password() -> String {
+    // two uuids without dashes, 64 hex chars
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Store a generated secret in the config under a key like
+/// "database/mydb/password". The caller is responsible for saving the config.
+pub fn store(config: &mut RumiConfig, key: &str, value: String) {
+    config.secrets.insert(key.to_string(), value);
+}
+
+/// Look up a stored secret.
+pub fn get<'a>(config: &'a RumiConfig, key: &str) -> Option<&'a str> {
+    config.secrets.get(key).map(|s| s.as_str())
+}
+
+/// Look up a secret that has to exist, honoring ci mode where secrets come
+/// strictly from the environment. Values that are provider references like
+/// "vault:secret/prod/db#password" are resolved before being returned, so
+/// rumi.json never has to hold the real secret.
+pub fn require(config: &RumiConfig, key: &str) -> RumiResult<String> {
+    let value = if crate::ci::enabled() {
+        crate::ci::secret_from_env(key)?
+    } else {
+        get(config, key)
+            .map(str::to_string)
+            .ok_or_else(|| RumiError::Config(format!("no secret '{}' in the config", key)))?
+    };
+    resolve(&value)
+}
+
+/// Resolve a provider reference to its value; plain values pass through.
+///
+/// - "vault:secret/prod/db#password" — HashiCorp Vault over its http api,
+///   address and token from VAULT_ADDR / VAULT_TOKEN
+/// - "ssm:/prod/db/password" — AWS SSM Parameter Store via the aws cli,
+///   credentials from the usual env vars or profile
+pub fn resolve(value: &str) -> RumiResult<String> {
+    if let Some(reference) = value.strip_prefix("vault:") {
+        return resolve_vault(reference);
+    }
+    if let Some(parameter) = value.strip_prefix("ssm:") {
+        return resolve_ssm(parameter);
+    }
+    Ok(value.to_string())
+}
+
+fn resolve_vault(reference: &str) -> RumiResult<String> {
+    let (path, field) = reference.split_once('#').ok_or_else(|| {
+        RumiError::Config(format!(
+            "bad vault reference 'vault:{}', expected vault:path#field",
+            reference
+        ))
+    })?;
+    let address = std::env::var("VAULT_ADDR")
+        .map_err(|_| RumiError::Config("VAULT_ADDR is not set".to_string()))?;
+    let token = std::env::var("VAULT_TOKEN")
+        .map_err(|_| RumiError::Config("VAULT_TOKEN is not set".to_string()))?;
+    let url = format!("{}/v1/{}", address.trim_end_matches('/'), path);
+    let (status, body) = crate::http::request("GET", &url, &[("X-Vault-Token", &token)], None)?;
+    if status >= 400 {
+        return Err(RumiError::Network(format!(
+            "vault answered {} for {}",
+            status, path
+        )));
+    }
+    let parsed: serde_json::Value = serde_json::from_str(&body)?;
+    // kv v2 nests the fields one level deeper than kv v1
+    parsed["data"]["data"][field]
+        .as_str()
+        .or_else(|| parsed["data"][field].as_str())
+        .map(str::to_string)
+        .ok_or_else(|| {
+            RumiError::Config(format!("vault secret {} has no field '{}'", path, field))
+        })
+}
+
+fn resolve_ssm(parameter: &str) -> RumiResult<String> {
+    let output = std::process::Command::new("aws")
+        .args([
+            "ssm",
+            "get-parameter",
+            "--with-decryption",
+            "--name",
+            parameter,
+            "--output",
+            "json",
+        ])
+        .output()
+        .map_err(|e| {
+            RumiError::Config(format!("could not run the aws cli (is it installed?): {}", e))
+        })?;
+    if !output.status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "aws ssm get-parameter {} failed: {}",
+            parameter,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout)?;
+    parsed["Parameter"]["Value"]
+        .as_str()
+        .map(str::to_string)
+        .ok_or_else(|| RumiError::Network(format!("ssm parameter {} has no value", parameter)))
+}
@@ -0,0 +1,552 @@
+use std::io::Write;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+use crate::session::RumiSession;
+
+/// The fail2ban hardening block under settings in rumi.json.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fail2banSettings {
+    /// How long offenders stay banned, in fail2ban time form ("10m", "1h").
+    #[serde(default = "default_ban_time")]
+    pub ban_time: String,
+    /// The window failures are counted over.
+    #[serde(default = "default_find_time")]
+    pub find_time: String,
+    /// Failures within the window before a ban.
+    #[serde(default = "default_max_retry")]
+    pub max_retry: u32,
+    /// Also enable the nginx jails on hosts serving websites.
+    #[serde(default)]
+    pub nginx_jails: bool,
+}
+
+fn default_ban_time() -> String {
+    "10m".to_string()
+}
+
+fn default_find_time() -> String {
+    "10m".to_string()
+}
+
+fn default_max_retry() -> u32 {
+    5
+}
+
+impl Default for Fail2banSettings {
+    fn default() -> Self {
+        Fail2banSettings {
+            ban_time: default_ban_time(),
+            find_time: default_find_time(),
+            max_retry: default_max_retry(),
+            nginx_jails: false,
+        }
+    }
+}
+
+/// Install fail2ban on a host with the sshd jail (and optionally the nginx
+/// jails) configured from the settings block. Safe to run again: the
+/// jail.local is simply rewritten and fail2ban restarted.
+pub fn install_fail2ban(session: &RumiSession, settings: &Fail2banSettings) -> RumiResult<()> {
+    session.execute_checked("sudo apt-get update")?;
+    session.execute_checked("sudo apt-get -y install fail2ban")?;
+
+    let mut jail_local = format!(
+        "[DEFAULT]\nbantime = {}\nfindtime = {}\nmaxretry = {}\n\n[sshd]\nenabled = true\n",
+        settings.ban_time, settings.find_time, settings.max_retry
+    );
+    if settings.nginx_jails {
+        jail_local.push_str(
+            "\n[nginx-http-auth]\nenabled = true\n\n[nginx-botsearch]\nenabled = true\n",
+        );
+    }
+    let staging_path = "/tmp/rumi-jail.local";
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(staging_path))?;
+    file.write_all(jail_local.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo mv {} /etc/fail2ban/jail.local && sudo systemctl enable fail2ban && sudo systemctl restart fail2ban",
+        staging_path
+    ))?;
+    println!("fail2ban configured on {}", session.host());
+    Ok(())
+}
+
+/// The artifact signing block under settings in rumi.json. When present,
+/// deploy commands verify a detached signature sitting next to the artifact
+/// before anything is uploaded.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArtifactSigningSettings {
+    /// A minisign public key (the base64 line from the .pub file), checked
+    /// against `<artifact>.minisig`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub minisign_public_key: Option<String>,
+    /// Path to a gpg keyring holding the signing key, checked against
+    /// `<artifact>.sig` or `<artifact>.asc`. The default keyring is used
+    /// when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub gpg_keyring: Option<String>,
+    /// Refuse to deploy artifacts that carry no signature at all.
+    #[serde(default)]
+    pub required: bool,
+}
+
+/// Verify the detached signature next to an artifact: `<path>.minisig` is
+/// checked with minisign, `<path>.sig` / `<path>.asc` with gpg. An unsigned
+/// artifact only passes when the settings block does not mark signatures as
+/// required.
+pub fn verify_artifact(settings: &ArtifactSigningSettings, path: &Path) -> RumiResult<()> {
+    let minisig = signature_path(path, "minisig");
+    if minisig.exists() {
+        let key = settings.minisign_public_key.as_deref().ok_or_else(|| {
+            RumiError::Config(
+                "found a .minisig signature but no minisign_public_key is configured".to_string(),
+            )
+        })?;
+        let output = std::process::Command::new("minisign")
+            .arg("-Vm")
+            .arg(path)
+            .arg("-P")
+            .arg(key)
+            .arg("-x")
+            .arg(&minisig)
+            .output()
+            .map_err(|e| RumiError::CommandFailed(format!("failed to run minisign: {}", e)))?;
+        if !output.status.success() {
+            return Err(RumiError::CommandFailed(format!(
+                "minisign rejected {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        println!("minisign signature ok for {}", path.display());
+        return Ok(());
+    }
+    for extension in ["sig", "asc"] {
+        let sig = signature_path(path, extension);
+        if !sig.exists() {
+            continue;
+        }
+        let mut command = std::process::Command::new("gpg");
+        if let Some(keyring) = &settings.gpg_keyring {
+            command.arg("--no-default-keyring").arg("--keyring").arg(keyring);
+        }
+        let output = command
+            .arg("--verify")
+            .arg(&sig)
+            .arg(path)
+            .output()
+            .map_err(|e| RumiError::CommandFailed(format!("failed to run gpg: {}", e)))?;
+        if !output.status.success() {
+            return Err(RumiError::CommandFailed(format!(
+                "gpg rejected {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        println!("gpg signature ok for {}", path.display());
+        return Ok(());
+    }
+    if settings.required {
+        return Err(RumiError::Config(format!(
+            "no signature found next to {} and artifact signatures are required",
+            path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// "<path>.<extension>" with the extension appended, not replaced.
+fn signature_path(path: &Path, extension: &str) -> std::path::PathBuf {
+    let mut name = path
+        .file_name()
+        .map(|n| n.to_os_string())
+        .unwrap_or_default();
+    name.push(format!(".{}", extension));
+    path.with_file_name(name)
+}
+
+/// The `config rotate-keys` command: generate a fresh ed25519 keypair,
+/// install it on the remote, verify login with it, update the stored ssh
+/// config and only then drop the old key from authorized_keys. With
+/// --dry-run the plan is printed and nothing runs.
+pub fn rotate_keys(config: &mut RumiConfig, name: Option<&str>, dry_run: bool) -> RumiResult<()> {
+    let ssh = match name {
+        Some(name) => {
+            let deployment = config.find_deployment(name)?;
+            config.ssh_for_deployment(deployment)?.clone()
+        }
+        None => config.default_ssh.clone().ok_or_else(|| {
+            RumiError::Config("no default_ssh in the config to rotate".to_string())
+        })?,
+    };
+    let stamp = chrono::Utc::now().format("%Y%m%d");
+    let key_dir = dirs_ssh_dir()?;
+    let private_key_path = key_dir.join(format!("rumi_{}_{}", ssh.host, stamp));
+    let public_key_path = private_key_path.with_extension("pub");
+
+    if dry_run {
+        println!("plan for rotating the key of {}@{}:", ssh.user, ssh.host);
+        println!("  1. ssh-keygen -t ed25519 -f {}", private_key_path.display());
+        println!("  2. append the new public key to ~/.ssh/authorized_keys on {}", ssh.host);
+        println!("  3. open a verification connection using only the new key");
+        println!("  4. point the stored ssh config at the new keypair");
+        match &ssh.public_key_path {
+            Some(old) => println!("  5. remove the old key ({}) from authorized_keys", old),
+            None => println!("  5. (no old public key on record, nothing to remove)"),
+        }
+        return Ok(());
+    }
+
+    // 1. fresh keypair, no passphrase: rumi needs to use it unattended
+    let keygen = std::process::Command::new("ssh-keygen")
+        .args(["-t", "ed25519", "-N", "", "-q", "-f"])
+        .arg(&private_key_path)
+        .output()?;
+    if !keygen.status.success() {
+        return Err(RumiError::CommandFailed(format!(
+            "ssh-keygen failed: {}",
+            String::from_utf8_lossy(&keygen.stderr).trim()
+        )));
+    }
+    let new_public_key = std::fs::read_to_string(&public_key_path)?;
+    let new_public_key = new_public_key.trim();
+    println!("generated {}", private_key_path.display());
+
+    // 2. install it next to the existing keys
+    let session = RumiSession::connect(&ssh)?;
+    session.execute_checked(&format!(
+        "mkdir -p ~/.ssh && chmod 700 ~/.ssh && echo '{}' >> ~/.ssh/authorized_keys && chmod 600 ~/.ssh/authorized_keys",
+        new_public_key
+    ))?;
+
+    // 3. prove the new key alone gets us in before touching anything else
+    let mut rotated = ssh.clone();
+    rotated.private_key_path = Some(private_key_path.to_string_lossy().into_owned());
+    rotated.public_key_path = Some(public_key_path.to_string_lossy().into_owned());
+    rotated.passphrase = None;
+    let verify = RumiSession::connect(&rotated).map_err(|e| {
+        RumiError::Config(format!(
+            "login with the new key failed, old key left in place: {}",
+            e
+        ))
+    })?;
+    verify.execute_checked("true")?;
+    println!("verified login with the new key");
+
+    // 4. record the new keypair
+    let old_public_key = ssh
+        .public_key_path
+        .as_deref()
+        .and_then(|path| std::fs::read_to_string(path).ok());
+    match name {
+        Some(name) => {
+            let name = name.to_string();
+            if let Some(deployment) = config.deployments.iter_mut().find(|d| d.name == name) {
+                if deployment.ssh.is_some() {
+                    deployment.ssh = Some(rotated.clone());
+                } else {
+                    config.default_ssh = Some(rotated.clone());
+                }
+            }
+        }
+        None => config.default_ssh = Some(rotated.clone()),
+    }
+
+    // 5. retire the old key, over the already-verified new connection
+    match old_public_key {
+        Some(old_public_key) => {
+            verify.execute_checked(&format!(
+                "grep -vF '{}' ~/.ssh/authorized_keys > ~/.ssh/authorized_keys.rumi && mv ~/.ssh/authorized_keys.rumi ~/.ssh/authorized_keys && chmod 600 ~/.ssh/authorized_keys",
+                old_public_key.trim()
+            ))?;
+            println!("old key removed from authorized_keys on {}", ssh.host);
+        }
+        None => println!("no old public key on record, authorized_keys left as is"),
+    }
+    println!("key rotation for {}@{} done", ssh.user, ssh.host);
+    Ok(())
+}
+
+fn dirs_ssh_dir() -> RumiResult<std::path::PathBuf> {
+    let home = crate::utils::home_dir()
+        .ok_or_else(|| RumiError::Config("no home directory, cannot place the new key".to_string()))?;
+    let dir = home.join(".ssh");
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+/// The sshd drop-in the hardening writes; a separate file so it can be
+/// removed without touching the distro's sshd_config.
+const SSHD_DROPIN_PATH: &str = "/etc/ssh/sshd_config.d/60-rumi-hardening.conf";
+
+/// The `security harden-ssh` command: disable password auth and root login,
+/// optionally move sshd to another port. Refuses to proceed until a second,
+/// key-authenticated test connection succeeds, and verifies the new setup
+/// with yet another connection before updating the stored ssh config — so a
+/// typo here cannot lock anyone out.
+pub fn harden_ssh(config: &mut RumiConfig, name: &str, new_port: Option<u16>) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let ssh = config.ssh_for_deployment(deployment)?.clone();
+
+    // the sessions rumi opens only ever use key or agent auth, so a second
+    // successful connection proves key-based login works without passwords
+    let probe = RumiSession::connect(&ssh).map_err(|e| {
+        RumiError::Config(format!(
+            "refusing to harden {}: a key-authenticated test connection failed ({}). \
+             Fix key login first or you would be locked out",
+            ssh.host, e
+        ))
+    })?;
+    probe.execute_checked("true")?;
+    drop(probe);
+
+    let session = RumiSession::connect(&ssh)?;
+    // root keeps key login so a keyed root setup survives the hardening
+    let permit_root = if ssh.user == "root" {
+        "prohibit-password"
+    } else {
+        "no"
+    };
+    let mut dropin = format!(
+        "PasswordAuthentication no\nKbdInteractiveAuthentication no\nPermitRootLogin {}\n",
+        permit_root
+    );
+    if let Some(port) = new_port {
+        dropin.push_str(&format!("Port {}\n", port));
+    }
+    let staging_path = "/tmp/rumi-sshd-hardening.conf";
+    let sftp = session.sftp()?;
+    let mut file = sftp.create(Path::new(staging_path))?;
+    file.write_all(dropin.as_bytes())?;
+    drop(file);
+    session.execute_checked(&format!(
+        "sudo install -m 644 -o root -g root {} {} && rm {}",
+        staging_path, SSHD_DROPIN_PATH, staging_path
+    ))?;
+    // sshd -t catches a broken config before the restart can strand us
+    if let Err(e) = session.execute_checked("sudo sshd -t") {
+        session.execute_checked(&format!("sudo rm -f {}", SSHD_DROPIN_PATH))?;
+        return Err(RumiError::Config(format!(
+            "sshd rejected the hardened config, rolled the drop-in back: {}",
+            e
+        )));
+    }
+    if let Some(port) = new_port {
+        crate::firewall::allow_ports(&session, &[&format!("{}/tcp", port)])?;
+    }
+    session.execute_checked("sudo systemctl restart sshd || sudo systemctl restart ssh")?;
+
+    // prove the hardened daemon still lets us in before recording anything
+    let mut hardened_ssh = ssh.clone();
+    if let Some(port) = new_port {
+        hardened_ssh.port = port;
+    }
+    let verify = RumiSession::connect(&hardened_ssh).map_err(|e| {
+        RumiError::Network(format!(
+            "sshd restarted but a verification connection to {}:{} failed: {}. \
+             The previous port may still be open on existing sessions",
+            hardened_ssh.host, hardened_ssh.port, e
+        ))
+    })?;
+    verify.execute_checked("true")?;
+
+    if let Some(port) = new_port {
+        // record the new port on whichever config the deployment uses
+        let deployment = config.find_deployment(name)?;
+        let uses_override = deployment.ssh.is_some();
+        let name = name.to_string();
+        if uses_override {
+            if let Some(deployment) = config.deployments.iter_mut().find(|d| d.name == name) {
+                if let Some(ssh) = deployment.ssh.as_mut() {
+                    ssh.port = port;
+                }
+            }
+        } else if let Some(default_ssh) = config.default_ssh.as_mut() {
+            default_ssh.port = port;
+        }
+        println!("sshd on {} moved to port {}", hardened_ssh.host, port);
+    }
+    println!(
+        "ssh hardened on {}: password auth off, root login {}",
+        hardened_ssh.host, permit_root
+    );
+    Ok(())
+}
+
+/// How urgent one audit finding is; the report sorts by this.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    High,
+    Medium,
+    Low,
+}
+
+impl Severity {
+    fn label(&self) -> &'static str {
+        match self {
+            Severity::High => "HIGH",
+            Severity::Medium => "MEDIUM",
+            Severity::Low => "LOW",
+        }
+    }
+}
+
+/// One issue the audit found on a host.
+pub struct Finding {
+    pub severity: Severity,
+    pub title: String,
+    pub detail: String,
+}
+
+/// The `security audit` command: check a host for the common issues rumi
+/// setups run into and print a prioritized findings report. Exits non-zero
+/// when anything high-severity turns up.
+pub fn audit(config: &RumiConfig, name: &str) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let mut findings = Vec::new();
+
+    // world-writable web roots: older installs chmod 777 /var/www
+    if let Ok(output) = session.execute_command(
+        "find /var/www /etc/nginx/sites-available /etc/nginx/sites-enabled -maxdepth 1 -perm -o+w 2>/dev/null",
+    ) {
+        for path in output.stdout.lines().filter(|l| !l.trim().is_empty()) {
+            findings.push(Finding {
+                severity: Severity::High,
+                title: format!("{} is world-writable", path),
+                detail: "any local user can replace the served content; run chmod 755 on it"
+                    .to_string(),
+            });
+        }
+    }
+
+    // password ssh auth still on
+    if let Ok(output) = session.execute_command("sudo sshd -T 2>/dev/null | grep -i '^passwordauthentication'") {
+        if output.stdout.to_lowercase().contains("yes") {
+            findings.push(Finding {
+                severity: Severity::High,
+                title: "password ssh authentication is enabled".to_string(),
+                detail: "run 'rumi2 security harden-ssh' to switch to keys only".to_string(),
+            });
+        }
+    }
+
+    // firewall drift against what the deployments need
+    match crate::firewall::desired_rules(config, ssh) {
+        Ok(desired) => {
+            let current = crate::firewall::current_rules(&session).unwrap_or_default();
+            for rule in &desired {
+                if !current.contains(&rule.spec()) {
+                    findings.push(Finding {
+                        severity: Severity::Medium,
+                        title: format!("firewall rule {} is missing", rule.spec()),
+                        detail: format!(
+                            "{} needs it; run 'rumi2 firewall apply'",
+                            rule.reason
+                        ),
+                    });
+                }
+            }
+            for spec in &current {
+                if !desired.iter().any(|rule| &rule.spec() == spec) {
+                    findings.push(Finding {
+                        severity: Severity::Low,
+                        title: format!("firewall allows {} beyond the config", spec),
+                        detail: "close it or declare it under firewall.extra_rules".to_string(),
+                    });
+                }
+            }
+        }
+        Err(e) => eprintln!("note: could not derive firewall rules: {}", e),
+    }
+
+    // outdated nginx/openssl packages
+    if let Ok(output) = session.execute_command(
+        "apt list --upgradable 2>/dev/null | grep -E '^(nginx|openssl|libssl)'",
+    ) {
+        for line in output.stdout.lines().filter(|l| !l.trim().is_empty()) {
+            let package = line.split('/').next().unwrap_or(line);
+            findings.push(Finding {
+                severity: Severity::Medium,
+                title: format!("{} has a pending security-relevant upgrade", package),
+                detail: "run apt-get upgrade on the host".to_string(),
+            });
+        }
+    }
+
+    // unattended-upgrades not installed
+    if let Ok(output) = session.execute_command("dpkg -s unattended-upgrades 2>/dev/null | grep -q 'Status: install' && echo ok") {
+        if !output.stdout.contains("ok") {
+            findings.push(Finding {
+                severity: Severity::Low,
+                title: "unattended-upgrades is not installed".to_string(),
+                detail: "security patches wait for a manual apt run; apt-get install unattended-upgrades".to_string(),
+            });
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.severity);
+    if findings.is_empty() {
+        println!("{}: no findings", session.host());
+        return Ok(());
+    }
+    println!("{}: {} finding(s)", session.host(), findings.len());
+    for finding in &findings {
+        println!("  [{}] {}", finding.severity.label(), finding.title);
+        println!("         {}", finding.detail);
+    }
+    if findings.iter().any(|f| f.severity == Severity::High) {
+        return Err(RumiError::Config(
+            "the audit found high-severity issues".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// The `security fail2ban status` command: every jail with its currently
+/// banned addresses.
+pub fn fail2ban_status(session: &RumiSession) -> RumiResult<()> {
+    let overview = session.execute_checked("sudo fail2ban-client status")?;
+    let jails: Vec<String> = overview
+        .stdout
+        .lines()
+        .find_map(|line| line.split_once("Jail list:"))
+        .map(|(_, list)| {
+            list.split(',')
+                .map(|jail| jail.trim().to_string())
+                .filter(|jail| !jail.is_empty())
+                .collect()
+        })
+        .unwrap_or_default();
+    if jails.is_empty() {
+        println!("{}: fail2ban runs but has no jails", session.host());
+        return Ok(());
+    }
+    println!("{}:", session.host());
+    for jail in jails {
+        let status =
+            session.execute_checked(&format!("sudo fail2ban-client status {}", jail))?;
+        let banned = status
+            .stdout
+            .lines()
+            .find_map(|line| line.split_once("Banned IP list:"))
+            .map(|(_, list)| list.trim().to_string())
+            .unwrap_or_default();
+        println!(
+            "  {:<20} {}",
+            jail,
+            if banned.is_empty() {
+                "no current bans".to_string()
+            } else {
+                banned
+            }
+        );
+    }
+    Ok(())
+}
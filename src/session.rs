@@ -0,0 +1,1241 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use ssh2::Session;
+
+use crate::config::{EscalationConfig, EscalationMethod, SshConfig};
+use crate::error::{RumiError, RumiResult};
+
+/// The settings-level limit applied to every remote command, in seconds;
+/// zero means unlimited. Set once at startup from the settings block.
+static DEFAULT_TIMEOUT_SECS: AtomicU64 = AtomicU64::new(0);
+
+pub fn set_default_timeout_secs(secs: u64) {
+    DEFAULT_TIMEOUT_SECS.store(secs, Ordering::Relaxed);
+}
+
+fn default_timeout() -> Option<Duration> {
+    match DEFAULT_TIMEOUT_SECS.load(Ordering::Relaxed) {
+        0 => None,
+        secs => Some(Duration::from_secs(secs)),
+    }
+}
+
+/// The named ssh profiles of the loaded config, registered on load so a
+/// proxy_jump can name one from any connect call.
+static JUMP_PROFILES: std::sync::Mutex<Option<HashMap<String, SshConfig>>> =
+    std::sync::Mutex::new(None);
+
+pub fn register_jump_profiles(profiles: &HashMap<String, SshConfig>) {
+    *JUMP_PROFILES.lock().unwrap() = Some(profiles.clone());
+}
+
+fn jump_profile(name: &str) -> Option<SshConfig> {
+    JUMP_PROFILES.lock().unwrap().as_ref()?.get(name).cloned()
+}
+
+/// A proxy_jump value resolved to a connectable config: the name of an ssh
+/// profile when one matches, otherwise a "user@host:port" spec (user and
+/// port optional) that authenticates through the local agent.
+fn resolve_jump(spec: &str, target: &SshConfig) -> RumiResult<SshConfig> {
+    if let Some(profile) = jump_profile(spec) {
+        if profile.proxy_jump.as_deref() == Some(spec) {
+            return Err(RumiError::Config(format!(
+                "ssh profile '{}' names itself as its proxy_jump",
+                spec
+            )));
+        }
+        return Ok(profile);
+    }
+    let (user, host_port) = match spec.split_once('@') {
+        Some((user, rest)) => (user.to_string(), rest),
+        None => (target.user.clone(), spec),
+    };
+    let (host, port) = match host_port.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => {
+            (host.to_string(), port.parse().unwrap_or(22))
+        }
+        _ => (host_port.to_string(), 22),
+    };
+    Ok(SshConfig {
+        host,
+        port,
+        user,
+        public_key_path: None,
+        private_key_path: None,
+        passphrase: None,
+        escalation: None,
+        requires_sudo_password: false,
+        proxy_jump: None,
+    })
+}
+
+/// A live tunnel through a bastion. libssh2 cannot stack a session directly
+/// on a channel, so the target session handshakes over a loopback socket
+/// and a pump thread ferries the bytes into a direct-tcpip channel on the
+/// bastion; dropping the tunnel stops the pump and the bastion session
+/// with it.
+struct JumpTunnel {
+    stop: Arc<std::sync::atomic::AtomicBool>,
+    pump: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Drop for JumpTunnel {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(pump) = self.pump.take() {
+            let _ = pump.join();
+        }
+    }
+}
+
+/// Connect to the bastion, open a direct-tcpip channel to the target and
+/// start the pump. The returned stream is what the target session talks to.
+fn open_jump_tunnel(
+    jump: &SshConfig,
+    target_host: &str,
+    target_port: u16,
+) -> RumiResult<(TcpStream, JumpTunnel)> {
+    // a plain connect: a bastion with its own proxy_jump chains recursively
+    let bastion = RumiSession::connect(jump)?;
+    let channel = bastion
+        .session
+        .channel_direct_tcpip(target_host, target_port, None)
+        .map_err(|e| {
+            RumiError::Network(format!(
+                "{} could not open a tunnel to {}:{}: {}",
+                jump.host, target_host, target_port, e
+            ))
+        })?;
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    let addr = listener.local_addr()?;
+    let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let pump_stop = Arc::clone(&stop);
+    let pump = std::thread::spawn(move || pump_tunnel(bastion, channel, listener, pump_stop));
+    let stream = TcpStream::connect(addr)?;
+    Ok((
+        stream,
+        JumpTunnel {
+            stop,
+            pump: Some(pump),
+        },
+    ))
+}
+
+/// Copy bytes both ways between the loopback socket and the channel until
+/// either side closes. Both ends are polled non-blocking in one thread,
+/// because a libssh2 session must not be driven from two threads at once.
+fn pump_tunnel(
+    bastion: RumiSession,
+    mut channel: ssh2::Channel,
+    listener: std::net::TcpListener,
+    stop: Arc<std::sync::atomic::AtomicBool>,
+) {
+    let Ok((mut socket, _)) = listener.accept() else {
+        return;
+    };
+    if socket.set_nonblocking(true).is_err() {
+        return;
+    }
+    bastion.session.set_blocking(false);
+    let mut buf = [0u8; 16 * 1024];
+    while !stop.load(Ordering::Relaxed) {
+        let mut idle = true;
+        match socket.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if write_all_polling(&mut channel, &buf[..n]).is_err() {
+                    break;
+                }
+                idle = false;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+        match channel.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                if write_all_polling(&mut socket, &buf[..n]).is_err() {
+                    break;
+                }
+                idle = false;
+            }
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {}
+            Err(_) => break,
+        }
+        if idle {
+            std::thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
+
+/// write_all for a non-blocking writer: a WouldBlock is waited out instead
+/// of surfacing as an error.
+fn write_all_polling(writer: &mut dyn Write, mut buf: &[u8]) -> std::io::Result<()> {
+    while !buf.is_empty() {
+        match writer.write(buf) {
+            Ok(0) => return Err(std::io::ErrorKind::WriteZero.into()),
+            Ok(n) => buf = &buf[n..],
+            Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(2))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// The env var a pipeline or shell can hand the sudo password over in, for
+/// hosts whose ssh profile sets requires_sudo_password.
+pub const SUDO_PASSWORD_ENV: &str = "RUMI_SUDO_PASSWORD";
+
+/// The sudo password is asked for once per run and shared by every session,
+/// so parallel uploads and multi-host fanouts do not re-prompt.
+static SUDO_PASSWORD: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// The sudo password for a host that demands one: RUMI_SUDO_PASSWORD when
+/// set, an interactive echo-off prompt otherwise.
+fn resolve_sudo_password(host: &str) -> RumiResult<String> {
+    if let Ok(password) = std::env::var(SUDO_PASSWORD_ENV) {
+        if !password.is_empty() {
+            return Ok(password);
+        }
+    }
+    let mut cached = SUDO_PASSWORD.lock().unwrap();
+    if let Some(password) = cached.as_ref() {
+        return Ok(password.clone());
+    }
+    // a pipeline has nobody to answer the prompt
+    if crate::ci::enabled() {
+        return Err(RumiError::Config(format!(
+            "{} requires a sudo password and there is no one to ask in ci; set {}",
+            host, SUDO_PASSWORD_ENV
+        )));
+    }
+    let password = crate::crypt::prompt(&format!("sudo password for {}", host))?;
+    if password.is_empty() {
+        return Err(RumiError::Config(format!(
+            "{} requires a sudo password",
+            host
+        )));
+    }
+    *cached = Some(password.clone());
+    Ok(password)
+}
+
+/// Whether remote output is echoed line by line as it arrives, set once at
+/// startup from the --verbose flag.
+static VERBOSE: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+pub fn set_verbose() {
+    VERBOSE.store(true, Ordering::Relaxed);
+}
+
+fn verbose_enabled() -> bool {
+    VERBOSE.load(Ordering::Relaxed)
+}
+
+/// How connect treats a host whose key is not in known_hosts yet. Set once
+/// at startup from the settings block and the --insecure-skip-host-key flag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostKeyPolicy {
+    /// Show the fingerprint and ask before saving it — trust on first use.
+    Prompt,
+    /// Refuse unknown keys outright, for pipelines where nobody can answer.
+    Strict,
+    /// No verification at all.
+    Skip,
+}
+
+static HOST_KEY_POLICY: std::sync::atomic::AtomicU8 = std::sync::atomic::AtomicU8::new(0);
+
+pub fn set_host_key_policy(policy: HostKeyPolicy) {
+    let value = match policy {
+        HostKeyPolicy::Prompt => 0,
+        HostKeyPolicy::Strict => 1,
+        HostKeyPolicy::Skip => 2,
+    };
+    HOST_KEY_POLICY.store(value, Ordering::Relaxed);
+}
+
+fn host_key_policy() -> HostKeyPolicy {
+    match HOST_KEY_POLICY.load(Ordering::Relaxed) {
+        1 => HostKeyPolicy::Strict,
+        2 => HostKeyPolicy::Skip,
+        _ => HostKeyPolicy::Prompt,
+    }
+}
+
+/// The openssh-style fingerprint of the server's key: sha256, base64, no
+/// padding — what `ssh` itself prints on first connect.
+fn host_key_fingerprint(session: &Session) -> String {
+    match session.host_key_hash(ssh2::HashType::Sha256) {
+        Some(hash) => format!(
+            "SHA256:{}",
+            openssl::base64::encode_block(hash).trim_end_matches('=')
+        ),
+        None => "(unavailable)".to_string(),
+    }
+}
+
+/// Check the server's key against ~/.ssh/known_hosts, sharing trust with
+/// plain ssh. A changed key is always fatal; an unknown one is offered for
+/// acceptance or refused, depending on the policy.
+fn verify_host_key(session: &Session, host: &str, port: u16) -> RumiResult<()> {
+    if host_key_policy() == HostKeyPolicy::Skip {
+        return Ok(());
+    }
+    let known_hosts_path = crate::utils::home_dir()
+        .map(|home| home.join(".ssh").join("known_hosts"))
+        .ok_or_else(|| {
+            RumiError::Config("no home directory, cannot locate known_hosts".to_string())
+        })?;
+    let mut known_hosts = session.known_hosts()?;
+    if known_hosts_path.exists() {
+        known_hosts.read_file(&known_hosts_path, ssh2::KnownHostFileKind::OpenSSH)?;
+    }
+    let (key, key_type) = session.host_key().ok_or_else(|| {
+        RumiError::Network(format!("{} presented no host key", host))
+    })?;
+    match known_hosts.check_port(host, port, key) {
+        ssh2::CheckResult::Match => Ok(()),
+        ssh2::CheckResult::Mismatch => Err(RumiError::Network(format!(
+            "HOST KEY CHANGED for {}: the key {} does not match known_hosts — \
+             possible man-in-the-middle. If the host was genuinely reinstalled, \
+             remove its old line from {} and reconnect",
+            host,
+            host_key_fingerprint(session),
+            known_hosts_path.display()
+        ))),
+        ssh2::CheckResult::NotFound | ssh2::CheckResult::Failure => {
+            let fingerprint = host_key_fingerprint(session);
+            // a pipeline has nobody to answer the prompt, so ci implies strict
+            if host_key_policy() == HostKeyPolicy::Strict || crate::ci::enabled() {
+                return Err(RumiError::Network(format!(
+                    "unknown host key for {} ({}) and strict_host_key_checking is on; \
+                     add it to {} first",
+                    host,
+                    fingerprint,
+                    known_hosts_path.display()
+                )));
+            }
+            eprintln!("the authenticity of host '{}' can't be established", host);
+            eprintln!("key fingerprint is {}", fingerprint);
+            eprint!("accept and save to {}? [y/N] ", known_hosts_path.display());
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer)?;
+            if !matches!(answer.trim(), "y" | "Y" | "yes") {
+                return Err(RumiError::Network(format!(
+                    "host key for {} was not accepted",
+                    host
+                )));
+            }
+            // non-22 ports are keyed as [host]:port, like openssh does
+            let entry = if port == 22 {
+                host.to_string()
+            } else {
+                format!("[{}]:{}", host, port)
+            };
+            known_hosts.add(&entry, key, "added by rumi", key_type.into())?;
+            if let Some(parent) = known_hosts_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            known_hosts.write_file(&known_hosts_path, ssh2::KnownHostFileKind::OpenSSH)?;
+            Ok(())
+        }
+    }
+}
+
+/// Single-quote a command so it survives `sh -c` unchanged.
+pub(crate) fn shell_quote(command: &str) -> String {
+    format!("'{}'", command.replace('\'', "'\\''"))
+}
+
+/// Quote one user-supplied value for interpolation into a remote command.
+/// Clean words pass through bare so commands stay readable in transcripts;
+/// anything with spaces or shell metacharacters gets single-quoted, so a
+/// domain or path can never become shell syntax.
+pub fn quote_arg(value: &str) -> String {
+    let clean = !value.is_empty()
+        && value.bytes().all(|b| {
+            b.is_ascii_alphanumeric() || matches!(b, b'.' | b'/' | b'_' | b'-' | b':' | b'@' | b'=' | b'+' | b',')
+        });
+    if clean {
+        value.to_string()
+    } else {
+        shell_quote(value)
+    }
+}
+
+/// Builds a remote command word by word: the command itself goes in verbatim,
+/// user input goes through [`quote_arg`]. Deployment names, domains and paths
+/// come straight from the config, so every one of them is treated as data.
+#[derive(Debug, Default)]
+pub struct CommandBuilder {
+    parts: Vec<String>,
+}
+
+impl CommandBuilder {
+    /// Start from the fixed part of the command — program, subcommand, flags.
+    pub fn new(program: &str) -> Self {
+        CommandBuilder {
+            parts: vec![program.to_string()],
+        }
+    }
+
+    /// A word of the command itself, appended verbatim — a flag or keyword.
+    pub fn word(mut self, word: &str) -> Self {
+        self.parts.push(word.to_string());
+        self
+    }
+
+    /// A user-supplied value, quoted so it always arrives as one argument.
+    pub fn arg(mut self, value: &str) -> Self {
+        self.parts.push(quote_arg(value));
+        self
+    }
+
+    pub fn build(self) -> String {
+        self.parts.join(" ")
+    }
+}
+
+/// Wrap a command in coreutils `timeout` so the remote process itself is
+/// terminated (KILL five seconds after TERM), not just our channel.
+fn with_deadline(command: &str, timeout: Duration) -> String {
+    format!(
+        "timeout -k 5 {} sh -c {}",
+        timeout.as_secs(),
+        shell_quote(command)
+    )
+}
+
+/// The exit status coreutils `timeout` reports when the deadline fired.
+const TIMEOUT_EXIT_CODE: i32 = 124;
+
+/// What came back from running one remote command.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0
+    }
+}
+
+/// An authenticated ssh session to one host, built from an SshConfig.
+pub struct RumiSession {
+    session: Session,
+    host: String,
+    escalation: EscalationConfig,
+    /// Set when the host's sudoers asks for a password; fed to `sudo -S`
+    /// over the channel's stdin.
+    sudo_password: Option<String>,
+    /// Kept so helpers can open extra connections to the same host, e.g.
+    /// for parallel uploads.
+    config: SshConfig,
+    /// Keeps the bastion pump alive for sessions connected via proxy_jump.
+    _tunnel: Option<JumpTunnel>,
+}
+
+impl RumiSession {
+    pub fn connect(config: &SshConfig) -> RumiResult<Self> {
+        let (tcp, tunnel) = match &config.proxy_jump {
+            Some(spec) => {
+                let jump = resolve_jump(spec, config)?;
+                let (stream, tunnel) = open_jump_tunnel(&jump, &config.host, config.port)?;
+                (stream, Some(tunnel))
+            }
+            None => {
+                // ipv6 literals need brackets before the port
+                let addr = if config.host.contains(':') && !config.host.starts_with('[') {
+                    format!("[{}]:{}", config.host, config.port)
+                } else {
+                    format!("{}:{}", config.host, config.port)
+                };
+                let tcp = TcpStream::connect(&addr).map_err(|e| {
+                    RumiError::Network(format!("failed to connect to {}: {}", addr, e))
+                })?;
+                (tcp, None)
+            }
+        };
+        let mut session = Session::new()?;
+        session.set_tcp_stream(tcp);
+        session.handshake()?;
+        verify_host_key(&session, &config.host, config.port)?;
+        // ci pipelines hand the key over in an env var instead of a path
+        let ci_key = crate::ci::materialize_ssh_key()?;
+        let private_key_path = ci_key
+            .as_ref()
+            .map(|p| p.to_string_lossy().into_owned())
+            .or_else(|| config.private_key_path.clone())
+            .map(|p| crate::utils::expand_tilde(&p));
+        match &private_key_path {
+            Some(private_key_path) => {
+                let public_key_path = if ci_key.is_some() {
+                    None // let libssh2 derive it from the ci key
+                } else {
+                    config
+                        .public_key_path
+                        .as_deref()
+                        .map(crate::utils::expand_tilde)
+                };
+                session.userauth_pubkey_file(
+                    &config.user,
+                    public_key_path.as_deref().map(Path::new),
+                    Path::new(private_key_path),
+                    config.passphrase.as_deref(),
+                )?;
+            }
+            None => {
+                // no key configured, fall back to the local ssh agent
+                // (ssh-agent on unix, Pageant on windows)
+                session.userauth_agent(&config.user)?;
+            }
+        }
+        let sudo_password = config
+            .requires_sudo_password
+            .then(|| resolve_sudo_password(&config.host))
+            .transpose()?;
+        Ok(RumiSession {
+            session,
+            host: config.host.clone(),
+            escalation: config.escalation.clone().unwrap_or_default(),
+            sudo_password,
+            config: config.clone(),
+            _tunnel: tunnel,
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// The config this session was connected from.
+    pub fn ssh_config(&self) -> &SshConfig {
+        &self.config
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// Rewrite the `sudo` markers in a command according to the host's
+    /// escalation setting: left alone for sudo, swapped for doas, stripped
+    /// when the user already is root. The allowlist, when set, restricts
+    /// which commands may be elevated at all.
+    fn apply_escalation(&self, command: &str) -> RumiResult<String> {
+        let mut rewritten = String::with_capacity(command.len());
+        let mut rest = command;
+        let mut boundary = true; // a `sudo` token only counts at a word start
+        while let Some(at) = rest.find("sudo ") {
+            let token_ok = boundary && at == 0
+                || rest[..at]
+                    .chars()
+                    .next_back()
+                    .is_some_and(|c| !c.is_alphanumeric() && c != '_' && c != '-');
+            rewritten.push_str(&rest[..at]);
+            rest = &rest[at + 5..];
+            if !token_ok {
+                rewritten.push_str("sudo ");
+                continue;
+            }
+            if !self.escalation.allowlist.is_empty() {
+                let elevated = rest.split_whitespace().next().unwrap_or_default();
+                let elevated = elevated.rsplit('/').next().unwrap_or(elevated);
+                if !self.escalation.allowlist.iter().any(|c| c == elevated) {
+                    return Err(RumiError::Config(format!(
+                        "'{}' is not in the escalation allowlist for {}",
+                        elevated, self.host
+                    )));
+                }
+            }
+            match self.escalation.method {
+                // -S reads the password from stdin; the empty -p keeps the
+                // prompt out of the collected stderr
+                EscalationMethod::Sudo if self.sudo_password.is_some() => {
+                    rewritten.push_str("sudo -S -p '' ")
+                }
+                EscalationMethod::Sudo => rewritten.push_str("sudo "),
+                EscalationMethod::Doas => rewritten.push_str("doas "),
+                EscalationMethod::None => {}
+            }
+            boundary = false;
+        }
+        rewritten.push_str(rest);
+        Ok(rewritten)
+    }
+
+    /// Run a command on the remote host and collect its output and exit code.
+    /// The settings-level command timeout applies when one is configured.
+    pub fn execute_command(&self, command: &str) -> RumiResult<CommandOutput> {
+        self.execute_with_timeout(command, default_timeout())
+    }
+
+    /// Like execute_command but with an explicit time limit (None lifts even
+    /// the configured one, for commands that are legitimately slow). The
+    /// remote process is killed by `timeout`, and the libssh2 socket timeout
+    /// is armed a little above the deadline so a dead network cannot hang
+    /// the read either.
+    pub fn execute_with_timeout(
+        &self,
+        command: &str,
+        timeout: Option<Duration>,
+    ) -> RumiResult<CommandOutput> {
+        let escalated = self.apply_escalation(command)?;
+        let wrapped = match timeout {
+            Some(timeout) => with_deadline(&escalated, timeout),
+            None => escalated,
+        };
+        if let Some(timeout) = timeout {
+            let socket_ms = timeout.as_secs().saturating_mul(1000).saturating_add(30_000);
+            self.session
+                .set_timeout(socket_ms.min(u32::MAX as u64) as u32);
+        }
+        let result = self.exec_raw(&wrapped);
+        if timeout.is_some() {
+            self.session.set_timeout(0);
+        }
+        let output = result?;
+        if let Some(timeout) = timeout {
+            if output.exit_code == TIMEOUT_EXIT_CODE {
+                return Err(RumiError::CommandFailed(format!(
+                    "'{}' on {} timed out after {}s",
+                    command,
+                    self.host,
+                    timeout.as_secs()
+                )));
+            }
+        }
+        Ok(output)
+    }
+
+    /// Write the password once per `sudo -S` in the command: each sudo reads
+    /// exactly one line from the shared stdin pipe, so chained commands all
+    /// get theirs. No-op on hosts with passwordless sudo.
+    fn feed_sudo_password(&self, channel: &mut ssh2::Channel, command: &str) -> RumiResult<()> {
+        let Some(password) = &self.sudo_password else {
+            return Ok(());
+        };
+        let prompts = command.matches("sudo -S").count();
+        for _ in 0..prompts {
+            channel.write_all(format!("{}\n", password).as_bytes())?;
+        }
+        if prompts > 0 {
+            channel.send_eof()?;
+        }
+        Ok(())
+    }
+
+    fn exec_raw(&self, command: &str) -> RumiResult<CommandOutput> {
+        if verbose_enabled() {
+            // live echo instead of silence until the command finished
+            return self.exec_streaming(command, &mut |line, is_stderr| {
+                if is_stderr {
+                    eprintln!("  [{}] {}", self.host, line);
+                } else {
+                    println!("  [{}] {}", self.host, line);
+                }
+            });
+        }
+        let started = std::time::Instant::now();
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        self.feed_sudo_password(&mut channel, command)?;
+        let mut stdout = String::new();
+        channel.read_to_string(&mut stdout)?;
+        let mut stderr = String::new();
+        channel.stderr().read_to_string(&mut stderr)?;
+        channel.wait_close()?;
+        let exit_code = channel.exit_status()?;
+        crate::transcript::record(
+            &self.host,
+            command,
+            exit_code,
+            started.elapsed(),
+            &stdout,
+            &stderr,
+        );
+        Ok(CommandOutput {
+            stdout,
+            stderr,
+            exit_code,
+        })
+    }
+
+    /// Run a command handing each output line to `on_line` as it arrives
+    /// (second argument true for stderr lines), instead of buffering
+    /// everything until the command finished — for apt, tar and the like
+    /// that would otherwise sit silent for minutes. The full output is still
+    /// collected and returned. stdout streams live; stderr lines follow once
+    /// stdout closed, since the blocking channel is read one stream at a
+    /// time. Escalation and the settings-level timeout apply as usual.
+    pub fn execute_command_streaming(
+        &self,
+        command: &str,
+        on_line: &mut dyn FnMut(&str, bool),
+    ) -> RumiResult<CommandOutput> {
+        let escalated = self.apply_escalation(command)?;
+        let wrapped = match default_timeout() {
+            Some(timeout) => with_deadline(&escalated, timeout),
+            None => escalated,
+        };
+        self.exec_streaming(&wrapped, on_line)
+    }
+
+    fn exec_streaming(
+        &self,
+        command: &str,
+        on_line: &mut dyn FnMut(&str, bool),
+    ) -> RumiResult<CommandOutput> {
+        use std::io::BufRead;
+        let started = std::time::Instant::now();
+        let mut channel = self.session.channel_session()?;
+        channel.exec(command)?;
+        self.feed_sudo_password(&mut channel, command)?;
+        let mut stdout = String::new();
+        {
+            let mut reader = std::io::BufReader::new(&mut channel);
+            let mut line = String::new();
+            while reader.read_line(&mut line)? != 0 {
+                on_line(line.trim_end_matches(['\n', '\r']), false);
+                stdout.push_str(&line);
+                line.clear();
+            }
+        }
+        let mut stderr = String::new();
+        {
+            let mut reader = std::io::BufReader::new(channel.stderr());
+            let mut line = String::new();
+            while reader.read_line(&mut line)? != 0 {
+                on_line(line.trim_end_matches(['\n', '\r']), true);
+                stderr.push_str(&line);
+                line.clear();
+            }
+        }
+        channel.wait_close()?;
+        let exit_code = channel.exit_status()?;
+        crate::transcript::record(
+            &self.host,
+            command,
+            exit_code,
+            started.elapsed(),
+            &stdout,
+            &stderr,
+        );
+        Ok(CommandOutput {
+            stdout,
+            stderr,
+            exit_code,
+        })
+    }
+
+    /// Like execute_command but returns an error when the command exits
+    /// non-zero, with stderr in the message.
+    pub fn execute_checked(&self, command: &str) -> RumiResult<CommandOutput> {
+        let output = self.execute_command(command)?;
+        if !output.success() {
+            return Err(crate::error::command_failed(format!(
+                "'{}' on {} exited with {}: {}",
+                command,
+                self.host,
+                output.exit_code,
+                output.stderr.trim()
+            )));
+        }
+        Ok(output)
+    }
+
+    pub fn sftp(&self) -> RumiResult<ssh2::Sftp> {
+        Ok(self.session.sftp()?)
+    }
+}
+
+/// How a deploy's remote work is carried out. Live deploys go through
+/// RumiSession; --dry-run swaps in a RecordingExecutor that writes the plan
+/// down instead of touching the host. A command module written against this
+/// trait works with either without knowing which it got.
+pub trait CommandExecutor {
+    /// The host this executor stands for, for per-host reporting.
+    fn host(&self) -> &str;
+    /// Run one remote command and collect its output and exit code.
+    fn execute(&self, command: &str) -> RumiResult<CommandOutput>;
+    /// Upload one local file to a remote path.
+    fn upload_file(&self, local: &Path, remote_path: &str) -> RumiResult<()>;
+    /// Upload a local folder recursively.
+    fn upload_folder(&self, local: &Path, remote_path: &str) -> RumiResult<()>;
+    /// Write a remote file from content built locally (nginx configs,
+    /// systemd units), creating or truncating it.
+    fn create_remote_file(&self, remote_path: &str, content: &[u8]) -> RumiResult<()>;
+    /// Whether a remote path exists.
+    fn exists(&self, remote_path: &str) -> RumiResult<bool>;
+
+    /// Like execute but failing the step when the command exits non-zero,
+    /// with stderr in the message.
+    fn execute_checked(&self, command: &str) -> RumiResult<CommandOutput> {
+        let output = self.execute(command)?;
+        if !output.success() {
+            return Err(crate::error::command_failed(format!(
+                "'{}' on {} exited with {}: {}",
+                command,
+                self.host(),
+                output.exit_code,
+                output.stderr.trim()
+            )));
+        }
+        Ok(output)
+    }
+}
+
+impl CommandExecutor for RumiSession {
+    fn host(&self) -> &str {
+        self.host()
+    }
+
+    fn execute(&self, command: &str) -> RumiResult<CommandOutput> {
+        self.execute_command(command)
+    }
+
+    fn upload_file(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        let sftp = self.sftp()?;
+        crate::utils::upload_file(&sftp, local, remote_path).map_err(|e| {
+            crate::error::command_failed(format!(
+                "failed to upload {} to {}: {}",
+                local.display(),
+                remote_path,
+                e
+            ))
+        })
+    }
+
+    fn upload_folder(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        crate::blobstore::upload_folder_deduped(self, local, remote_path)
+    }
+
+    fn create_remote_file(&self, remote_path: &str, content: &[u8]) -> RumiResult<()> {
+        let sftp = self.sftp()?;
+        let mut file = sftp.create(Path::new(remote_path))?;
+        file.write_all(content)?;
+        Ok(())
+    }
+
+    fn exists(&self, remote_path: &str) -> RumiResult<bool> {
+        Ok(self
+            .execute_command(&format!("test -e {}", quote_arg(remote_path)))?
+            .success())
+    }
+}
+
+/// One thing a dry run recorded in place of doing it.
+enum PlannedAction {
+    Run(String),
+    Upload { remote_path: String, local: String },
+    Write { remote_path: String, content: Vec<u8> },
+    Check(String),
+}
+
+/// A CommandExecutor that records what a deploy would do instead of doing
+/// it, for --dry-run. Commands answer success with empty output and `exists`
+/// answers no, so the recorded plan follows each deploy's fresh-host path;
+/// steps that branch on real remote state cannot be foreseen beyond that.
+pub struct RecordingExecutor {
+    host: String,
+    actions: std::cell::RefCell<Vec<PlannedAction>>,
+}
+
+impl RecordingExecutor {
+    pub fn new(host: &str) -> Self {
+        RecordingExecutor {
+            host: host.to_string(),
+            actions: std::cell::RefCell::new(Vec::new()),
+        }
+    }
+
+    fn record(&self, action: PlannedAction) {
+        self.actions.borrow_mut().push(action);
+    }
+
+    /// List what was recorded the way a reviewer reads it: one line per
+    /// action, written file contents inline when they are printable text.
+    pub fn print(&self) {
+        let actions = self.actions.borrow();
+        println!("dry run, this would happen on {}:", self.host);
+        for action in actions.iter() {
+            match action {
+                PlannedAction::Run(command) => println!("  + run    {}", command),
+                PlannedAction::Upload { remote_path, local } => {
+                    println!("  + upload {}  (from {})", remote_path, local)
+                }
+                PlannedAction::Write {
+                    remote_path,
+                    content,
+                } => match std::str::from_utf8(content) {
+                    Ok(text) if !text.contains('\0') => {
+                        println!("  + write  {}:", remote_path);
+                        for line in text.lines() {
+                            println!("      | {}", line);
+                        }
+                    }
+                    _ => println!("  + write  {} ({} bytes)", remote_path, content.len()),
+                },
+                PlannedAction::Check(path) => println!("  + stat   {}", path),
+            }
+        }
+        println!("{} actions, none executed", actions.len());
+    }
+}
+
+impl CommandExecutor for RecordingExecutor {
+    fn host(&self) -> &str {
+        &self.host
+    }
+
+    fn execute(&self, command: &str) -> RumiResult<CommandOutput> {
+        self.record(PlannedAction::Run(command.to_string()));
+        Ok(CommandOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+        })
+    }
+
+    fn upload_file(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        self.record(PlannedAction::Upload {
+            remote_path: remote_path.to_string(),
+            local: local.display().to_string(),
+        });
+        Ok(())
+    }
+
+    fn upload_folder(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        // walk the local folder the way the upload would, so the plan names
+        // every file that would go over
+        let mut entries: Vec<std::fs::DirEntry> =
+            std::fs::read_dir(local)?.collect::<Result<_, _>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let name = entry.file_name();
+            let remote_path = format!("{}/{}", remote_path, name.to_string_lossy());
+            if entry.file_type()?.is_dir() {
+                self.upload_folder(&entry.path(), &remote_path)?;
+            } else {
+                self.upload_file(&entry.path(), &remote_path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_remote_file(&self, remote_path: &str, content: &[u8]) -> RumiResult<()> {
+        self.record(PlannedAction::Write {
+            remote_path: remote_path.to_string(),
+            content: content.to_vec(),
+        });
+        Ok(())
+    }
+
+    fn exists(&self, remote_path: &str) -> RumiResult<bool> {
+        self.record(PlannedAction::Check(remote_path.to_string()));
+        Ok(false)
+    }
+}
+
+/// A CommandExecutor for tests: every action is recorded for assertions and
+/// command outputs can be staged ahead of time, so the command modules can
+/// be exercised without a live server.
+#[derive(Default)]
+pub struct MockExecutor {
+    /// Responses staged with `respond`; the first whose needle is contained
+    /// in the command wins, anything else answers success with no output.
+    responses: Vec<(String, CommandOutput)>,
+    executed: std::cell::RefCell<Vec<String>>,
+    uploads: std::cell::RefCell<Vec<(String, String)>>,
+    written: std::cell::RefCell<Vec<(String, Vec<u8>)>>,
+    existing: Vec<String>,
+}
+
+impl MockExecutor {
+    pub fn new() -> Self {
+        MockExecutor::default()
+    }
+
+    /// Stage the output of every command containing `needle`.
+    pub fn respond(mut self, needle: &str, stdout: &str, exit_code: i32) -> Self {
+        self.responses.push((
+            needle.to_string(),
+            CommandOutput {
+                stdout: stdout.to_string(),
+                stderr: String::new(),
+                exit_code,
+            },
+        ));
+        self
+    }
+
+    /// Mark a remote path as existing for `exists`.
+    pub fn with_existing(mut self, remote_path: &str) -> Self {
+        self.existing.push(remote_path.to_string());
+        self
+    }
+
+    /// Every command that was executed, in order.
+    pub fn executed(&self) -> Vec<String> {
+        self.executed.borrow().clone()
+    }
+
+    /// Every (local, remote) pair uploaded, files and folders alike.
+    pub fn uploads(&self) -> Vec<(String, String)> {
+        self.uploads.borrow().clone()
+    }
+
+    /// Every remote file written from local content, with its payload.
+    pub fn written(&self) -> Vec<(String, Vec<u8>)> {
+        self.written.borrow().clone()
+    }
+}
+
+impl CommandExecutor for MockExecutor {
+    fn host(&self) -> &str {
+        "mock-host"
+    }
+
+    fn execute(&self, command: &str) -> RumiResult<CommandOutput> {
+        self.executed.borrow_mut().push(command.to_string());
+        for (needle, output) in &self.responses {
+            if command.contains(needle.as_str()) {
+                return Ok(output.clone());
+            }
+        }
+        Ok(CommandOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_code: 0,
+        })
+    }
+
+    fn upload_file(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        self.uploads
+            .borrow_mut()
+            .push((local.display().to_string(), remote_path.to_string()));
+        Ok(())
+    }
+
+    fn upload_folder(&self, local: &Path, remote_path: &str) -> RumiResult<()> {
+        self.uploads
+            .borrow_mut()
+            .push((local.display().to_string(), remote_path.to_string()));
+        Ok(())
+    }
+
+    fn create_remote_file(&self, remote_path: &str, content: &[u8]) -> RumiResult<()> {
+        self.written
+            .borrow_mut()
+            .push((remote_path.to_string(), content.to_vec()));
+        Ok(())
+    }
+
+    fn exists(&self, remote_path: &str) -> RumiResult<bool> {
+        Ok(self.existing.iter().any(|path| path == remote_path))
+    }
+}
+
+/// Groups consecutive non-interactive steps into one generated script run
+/// over a single channel, instead of paying a channel open/close round trip
+/// per step. The script echoes a sentinel with each step's exit status, so
+/// failures are still reported per step.
+#[derive(Default)]
+pub struct CommandBatch {
+    steps: Vec<(String, String)>,
+}
+
+impl CommandBatch {
+    pub fn new() -> Self {
+        CommandBatch::default()
+    }
+
+    pub fn step(mut self, label: &str, command: &str) -> Self {
+        self.steps.push((label.to_string(), command.to_string()));
+        self
+    }
+
+    /// A step with its own deadline, overriding the settings-level timeout
+    /// for just that step (the batch runs as one remote script, so the limit
+    /// is applied inside it).
+    pub fn step_with_timeout(mut self, label: &str, command: &str, secs: u64) -> Self {
+        self.steps.push((
+            label.to_string(),
+            with_deadline(command, Duration::from_secs(secs)),
+        ));
+        self
+    }
+
+    /// Run every step in order over one channel, stopping at the first
+    /// failure and naming the step that caused it.
+    pub fn run(self, session: &RumiSession) -> RumiResult<()> {
+        if self.steps.is_empty() {
+            return Ok(());
+        }
+        let mut script = String::new();
+        for (index, (_, command)) in self.steps.iter().enumerate() {
+            script.push_str(command);
+            script.push_str(&format!(
+                "\n__rumi_status=$?\necho \"__rumi_step__ {} $__rumi_status\"\n[ $__rumi_status -eq 0 ] || exit $__rumi_status\n",
+                index
+            ));
+        }
+        let output = session.execute_command(&script)?;
+        // the step that ran last is the one to blame when the batch failed
+        let mut last_reported: Option<(usize, i32)> = None;
+        for line in output.stdout.lines() {
+            if let Some(rest) = line.strip_prefix("__rumi_step__ ") {
+                if let Some((index, status)) = rest.split_once(' ') {
+                    if let (Ok(index), Ok(status)) = (index.parse(), status.parse()) {
+                        last_reported = Some((index, status));
+                    }
+                }
+            }
+        }
+        if output.exit_code == 0 {
+            return Ok(());
+        }
+        // a step's own output can contain a line that parses like a sentinel
+        // (catting a log, say), so an index read back from stdout is never
+        // trusted to be in range
+        let step_name = |index: usize| self.steps.get(index).map(|(name, _)| name.as_str());
+        let failed = match last_reported {
+            Some((index, TIMEOUT_EXIT_CODE)) => match step_name(index) {
+                Some(name) => format!("step '{}' timed out", name),
+                None => "batch failed".to_string(),
+            },
+            Some((index, status)) if status != 0 => match step_name(index) {
+                Some(name) => format!("step '{}' exited with {}", name, status),
+                None => format!("batch failed with {}", status),
+            },
+            // a step died before its sentinel (killed, connection cut)
+            Some((index, _)) => match step_name(index + 1) {
+                Some(name) => format!("step '{}' died", name),
+                None => "batch failed".to_string(),
+            },
+            None => format!("step '{}' died", self.steps[0].0),
+        };
+        Err(crate::error::command_failed(format!(
+            "{} on {}: {}",
+            failed,
+            session.host,
+            output.stderr.trim()
+        )))
+    }
+}
+
+/// Sessions cached per host and opened on demand, so commands that iterate
+/// deployments connect once per host at most — and not at all to hosts they
+/// end up skipping.
+#[derive(Default)]
+pub struct SessionPool {
+    sessions: HashMap<String, Arc<RumiSession>>,
+}
+
+impl SessionPool {
+    pub fn new() -> Self {
+        SessionPool::default()
+    }
+
+    fn key(config: &SshConfig) -> String {
+        format!("{}@{}:{}", config.user, config.host, config.port)
+    }
+
+    /// The session for a host, connecting on first use and reusing it after.
+    pub fn get(&mut self, config: &SshConfig) -> RumiResult<Arc<RumiSession>> {
+        let key = Self::key(config);
+        if !self.sessions.contains_key(&key) {
+            let session = RumiSession::connect(config)?;
+            self.sessions.insert(key.clone(), Arc::new(session));
+        }
+        Ok(self.sessions[&key].clone())
+    }
+
+    /// Open every connection not yet in the pool concurrently, so a command
+    /// that will visit all hosts pays one handshake round-trip instead of one
+    /// per host. The first failure is returned once all threads finished.
+    pub fn connect_all(&mut self, configs: &[SshConfig]) -> RumiResult<()> {
+        let mut handles = Vec::new();
+        for config in configs {
+            let key = Self::key(config);
+            if self.sessions.contains_key(&key) || handles.iter().any(|(k, _)| *k == key) {
+                continue;
+            }
+            let config = config.clone();
+            handles.push((key, std::thread::spawn(move || RumiSession::connect(&config))));
+        }
+        let mut first_error = None;
+        for (key, handle) in handles {
+            match handle.join().expect("connection thread panicked") {
+                Ok(session) => {
+                    self.sessions.insert(key, Arc::new(session));
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An async face over [`RumiSession`] for the code paths that already live
+/// inside a tokio runtime — the webhook listener and the monitor daemon.
+/// ssh2 stays blocking underneath; every call is moved onto tokio's blocking
+/// pool so a slow host or a long upload never stalls the reactor threads
+/// serving other connections. Cloning is cheap and shares the connection.
+#[derive(Clone)]
+pub struct AsyncRumiSession {
+    inner: Arc<RumiSession>,
+}
+
+impl AsyncRumiSession {
+    pub async fn connect(config: &SshConfig) -> RumiResult<Self> {
+        let config = config.clone();
+        let inner = tokio::task::spawn_blocking(move || RumiSession::connect(&config))
+            .await
+            .map_err(|e| RumiError::CommandFailed(format!("ssh task panicked: {}", e)))??;
+        Ok(AsyncRumiSession {
+            inner: Arc::new(inner),
+        })
+    }
+
+    /// Run arbitrary blocking work against the underlying session on the
+    /// blocking pool — batches, uploads, the legacy `&ssh2::Session` helpers.
+    pub async fn with_blocking<T, F>(&self, work: F) -> RumiResult<T>
+    where
+        F: FnOnce(&RumiSession) -> RumiResult<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let session = self.inner.clone();
+        tokio::task::spawn_blocking(move || work(&session))
+            .await
+            .map_err(|e| RumiError::CommandFailed(format!("ssh task panicked: {}", e)))?
+    }
+
+    pub async fn execute_command(&self, command: &str) -> RumiResult<CommandOutput> {
+        let command = command.to_string();
+        self.with_blocking(move |session| session.execute_command(&command))
+            .await
+    }
+
+    pub async fn execute_checked(&self, command: &str) -> RumiResult<CommandOutput> {
+        let command = command.to_string();
+        self.with_blocking(move |session| session.execute_checked(&command))
+            .await
+    }
+}
@@ -0,0 +1,119 @@
+use std::io::{BufRead, Write};
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+use crate::session::SessionPool;
+
+const HELP: &str = "\
+commands:
+  status                    list the deployments and their hosts
+  exec <name> <command>     run a command on a deployment's host
+  logs <name> [lines]       tail a deployment's log (no follow)
+  deploy <name>             sftp-deploy a deployment
+  backups [name]            list backups (cached index)
+  help                      this text
+  exit                      leave the shell";
+
+/// The `shell` command: a prompt where commands run against ssh sessions
+/// that stay open between them, so a burst of operations against the same
+/// servers pays the connection latency once.
+pub fn shell_command(config: &RumiConfig) -> RumiResult<()> {
+    let mut pool = SessionPool::new();
+    let stdin = std::io::stdin();
+    println!("rumi shell, 'help' for commands, 'exit' to leave");
+    loop {
+        print!("rumi> ");
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line)? == 0 {
+            break; // eof
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(()),
+            ["exit"] | ["quit"] => break,
+            ["help"] => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            ["status"] => {
+                status(config);
+                Ok(())
+            }
+            ["exec", name, command @ ..] if !command.is_empty() => {
+                exec(config, &mut pool, name, &command.join(" "))
+            }
+            ["logs", name] => logs(config, &mut pool, name, crate::logs::DEFAULT_TAIL_LINES),
+            ["logs", name, lines] => match lines.parse() {
+                Ok(lines) => logs(config, &mut pool, name, lines),
+                Err(_) => Err(RumiError::Config(format!("'{}' is not a line count", lines))),
+            },
+            ["deploy", name] => deploy(config, &mut pool, name),
+            ["backups"] => crate::backup::list_command(
+                config,
+                None,
+                false,
+                false,
+                &crate::backup::ListFilter::default(),
+            ),
+            ["backups", name] => crate::backup::list_command(
+                config,
+                Some(name),
+                false,
+                false,
+                &crate::backup::ListFilter::default(),
+            ),
+            _ => Err(RumiError::Config(
+                "unknown command, 'help' lists what the shell understands".to_string(),
+            )),
+        };
+        if let Err(e) = result {
+            eprintln!("error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+fn status(config: &RumiConfig) {
+    println!(
+        "{:<20} {:<14} {:<30} HOST",
+        "NAME", "TYPE", "DOMAIN"
+    );
+    for deployment in &config.deployments {
+        let host = config
+            .ssh_for_deployment(deployment)
+            .map(|ssh| ssh.host.clone())
+            .unwrap_or_else(|_| "-".to_string());
+        println!(
+            "{:<20} {:<14} {:<30} {}",
+            deployment.name,
+            deployment.deployment_type.kind(),
+            deployment.domain,
+            host
+        );
+    }
+}
+
+fn exec(config: &RumiConfig, pool: &mut SessionPool, name: &str, command: &str) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let session = pool.get(config.ssh_for_deployment(deployment)?)?;
+    let output = session.execute_command(command)?;
+    print!("{}", output.stdout);
+    eprint!("{}", output.stderr);
+    if !output.success() {
+        println!("(exit {})", output.exit_code);
+    }
+    Ok(())
+}
+
+fn logs(config: &RumiConfig, pool: &mut SessionPool, name: &str, lines: u32) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let session = pool.get(config.ssh_for_deployment(deployment)?)?;
+    crate::logs::tail_once(&session, deployment, crate::logs::LogSource::App, lines)
+}
+
+fn deploy(config: &RumiConfig, pool: &mut SessionPool, name: &str) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let session = pool.get(config.ssh_for_deployment(deployment)?)?;
+    crate::commands::websites::sftp_deploy_command(&session, deployment)
+}
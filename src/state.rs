@@ -0,0 +1,170 @@
+//! The per-host deployment journal: every deploy appends what went out —
+//! version, dist hash, who ran it, when and which nginx config — to one
+//! json file under the registry root, so `rumi2 history` and `rumi2
+//! status` can answer "what is running there" from the host itself,
+//! independent of whoever's local config did the deploying.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::config::RumiConfig;
+use crate::error::{RumiError, RumiResult};
+use crate::session::{CommandExecutor, RumiSession};
+
+/// Where the journal lives, next to the release registry.
+pub const STATE_FILE: &str = "/var/lib/rumi/state.json";
+
+/// One deploy as recorded in the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StateEntry {
+    pub name: String,
+    /// What kind of deployment went out ("website", "server", ...).
+    pub kind: String,
+    /// The version that went out: a --version_id, release timestamp or tag.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// A digest of what was uploaded, e.g. "42 files, sha256:1f0a9c2b71d4".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dist_hash: Option<String>,
+    /// Who ran the deploy, as user@host of the machine it ran on.
+    pub deployer: String,
+    pub deployed_at: DateTime<Utc>,
+    /// The nginx config the deploy wrote, when it wrote one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nginx_config: Option<String>,
+}
+
+impl StateEntry {
+    pub fn new(name: &str, kind: &str) -> Self {
+        StateEntry {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            version: None,
+            dist_hash: None,
+            deployer: crate::release::deployer(),
+            deployed_at: Utc::now(),
+            nginx_config: None,
+        }
+    }
+
+    pub fn version(mut self, version: Option<String>) -> Self {
+        self.version = version;
+        self
+    }
+
+    pub fn dist_hash(mut self, dist_hash: Option<String>) -> Self {
+        self.dist_hash = dist_hash;
+        self
+    }
+
+    pub fn nginx_config(mut self, nginx_config: Option<String>) -> Self {
+        self.nginx_config = nginx_config;
+        self
+    }
+}
+
+/// The whole journal on a host, oldest entry first. A missing file is an
+/// empty journal, not an error.
+pub fn load(executor: &dyn CommandExecutor) -> RumiResult<Vec<StateEntry>> {
+    let output = executor.execute(&format!("sudo sh -c 'cat {} 2>/dev/null'", STATE_FILE))?;
+    if output.stdout.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(&output.stdout).map_err(|_| {
+        RumiError::Config(format!(
+            "{} on {} is not a readable deployment journal",
+            STATE_FILE,
+            executor.host()
+        ))
+    })
+}
+
+/// Append one entry: read, extend and write the whole file back through a
+/// staging path, so the journal stays a single valid json document. An
+/// unreadable journal is started over rather than failing the deploy that
+/// just succeeded.
+pub fn record(executor: &dyn CommandExecutor, entry: StateEntry) -> RumiResult<()> {
+    let mut entries = load(executor).unwrap_or_default();
+    entries.push(entry);
+    let staging_path = format!("/tmp/rumi-state-{}", uuid::Uuid::new_v4());
+    executor.create_remote_file(
+        &staging_path,
+        serde_json::to_string_pretty(&entries)?.as_bytes(),
+    )?;
+    executor.execute_checked(&format!(
+        "sudo mkdir -p {} && sudo mv {} {}",
+        crate::release::REGISTRY_ROOT,
+        crate::session::quote_arg(&staging_path),
+        STATE_FILE
+    ))?;
+    Ok(())
+}
+
+/// The `history` command: every recorded deploy of one deployment, newest
+/// first.
+pub fn history_command(config: &RumiConfig, name: &str, utc: bool) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let mut entries: Vec<StateEntry> = load(&session)?
+        .into_iter()
+        .filter(|entry| entry.name == name)
+        .collect();
+    if entries.is_empty() {
+        println!(
+            "no recorded deploys for '{}' on {}, was it last deployed before the journal existed?",
+            name,
+            session.host()
+        );
+        return Ok(());
+    }
+    entries.reverse();
+    println!(
+        "{:<34} {:<18} {:<10} {:<24} DIST",
+        "DEPLOYED", "VERSION", "KIND", "BY"
+    );
+    for entry in &entries {
+        println!(
+            "{:<34} {:<18} {:<10} {:<24} {}",
+            crate::output::format_time(entry.deployed_at, utc),
+            entry.version.as_deref().unwrap_or("-"),
+            entry.kind,
+            entry.deployer,
+            entry.dist_hash.as_deref().unwrap_or("-")
+        );
+    }
+    Ok(())
+}
+
+/// The `status` command: the most recent recorded deploy of one deployment.
+pub fn status_command(config: &RumiConfig, name: &str, utc: bool) -> RumiResult<()> {
+    let deployment = config.find_deployment(name)?;
+    let ssh = config.ssh_for_deployment(deployment)?;
+    let session = RumiSession::connect(ssh)?;
+    let entry = load(&session)?
+        .into_iter()
+        .rfind(|entry| entry.name == name)
+        .ok_or_else(|| {
+            RumiError::Config(format!(
+                "no recorded deploys for '{}' on {}, was it last deployed before the journal existed?",
+                name,
+                session.host()
+            ))
+        })?;
+    println!("deployment: {} ({})", entry.name, entry.kind);
+    if let Some(version) = &entry.version {
+        println!("version:    {}", version);
+    }
+    println!(
+        "deployed:   {}",
+        crate::output::format_time(entry.deployed_at, utc)
+    );
+    println!("by:         {}", entry.deployer);
+    if let Some(dist_hash) = &entry.dist_hash {
+        println!("dist:       {}", dist_hash);
+    }
+    if let Some(nginx_config) = &entry.nginx_config {
+        println!("nginx:      {}", nginx_config);
+    }
+    Ok(())
+}
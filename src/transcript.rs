@@ -0,0 +1,84 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::error::{RumiError, RumiResult};
+
+/// How much of each stream makes it into the transcript.
+const MAX_OUTPUT_CHARS: usize = 2000;
+
+/// The open transcript file, when --log-file (or the settings default dir)
+/// asked for one. Everything the session layer runs is appended here.
+static LOG: Mutex<Option<File>> = Mutex::new(None);
+
+/// Start writing the transcript to an explicit file.
+pub fn enable(path: &Path) -> RumiResult<()> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .map_err(|e| RumiError::Config(format!("could not open {}: {}", path.display(), e)))?;
+    *LOG.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+/// Start a fresh timestamped transcript under the settings' log directory
+/// and say where it went.
+pub fn enable_in_dir(dir: &str) -> RumiResult<()> {
+    std::fs::create_dir_all(dir)
+        .map_err(|e| RumiError::Config(format!("could not create {}: {}", dir, e)))?;
+    let path = PathBuf::from(dir).join(format!(
+        "rumi-{}.log",
+        chrono::Utc::now().format("%Y%m%d-%H%M%S")
+    ));
+    enable(&path)?;
+    println!("transcript: {}", path.display());
+    Ok(())
+}
+
+fn truncated(stream: &str) -> &str {
+    match stream.char_indices().nth(MAX_OUTPUT_CHARS) {
+        Some((index, _)) => &stream[..index],
+        None => stream,
+    }
+}
+
+/// Append one remote command's outcome. Failing to write never fails the
+/// command itself, the transcript is diagnostics only.
+pub fn record(
+    host: &str,
+    command: &str,
+    exit_code: i32,
+    duration: Duration,
+    stdout: &str,
+    stderr: &str,
+) {
+    let mut log = LOG.lock().unwrap();
+    let Some(file) = log.as_mut() else {
+        return;
+    };
+    let mut entry = format!(
+        "[{}] {}$ {}\n  exit {} in {:.2}s\n",
+        chrono::Utc::now().to_rfc3339(),
+        host,
+        command,
+        exit_code,
+        duration.as_secs_f64()
+    );
+    for (label, stream) in [("stdout", stdout), ("stderr", stderr)] {
+        let stream = stream.trim_end();
+        if stream.is_empty() {
+            continue;
+        }
+        let cut = truncated(stream);
+        for line in cut.lines() {
+            entry.push_str(&format!("  {}> {}\n", label, line));
+        }
+        if cut.len() < stream.len() {
+            entry.push_str(&format!("  {}> [... truncated]\n", label));
+        }
+    }
+    let _ = file.write_all(entry.as_bytes());
+}